@@ -3,7 +3,7 @@
 /// An enum representing possible actions
 ///
 /// This includes basic movement, opening a default file, entering different modes, etc.
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum ActionType {
     // Basic Movement Controls
     MoveRight,
@@ -23,6 +23,27 @@ pub enum ActionType {
     Cancel,
 }
 
+impl ActionType {
+    /// Find the action with the variant's name, as written in a keymap config
+    pub fn from_name(name: &str) -> Option<ActionType> {
+        Some(match name {
+            "MoveRight" => ActionType::MoveRight,
+            "MoveLeft" => ActionType::MoveLeft,
+            "MoveUp" => ActionType::MoveUp,
+            "MoveDown" => ActionType::MoveDown,
+            "EnterNormal" => ActionType::EnterNormal,
+            "EnterInsert" => ActionType::EnterInsert,
+            "EnterJump" => ActionType::EnterJump,
+            "EnterCommand" => ActionType::EnterCommand,
+            "EnterFind" => ActionType::EnterFind,
+            "EnterOpen" => ActionType::EnterOpen,
+            "InsertChar" => ActionType::InsertChar,
+            "Cancel" => ActionType::Cancel,
+            _ => return None,
+        })
+    }
+}
+
 pub struct Action {
     pub action_type: ActionType,
     pub action_param: ActionParam,
@@ -0,0 +1,444 @@
+use std::ops::Range;
+use std::path::PathBuf;
+use regex::{Regex, RegexBuilder};
+
+/// Names of every command understood by the parser, used for completion
+pub const COMMAND_NAMES: [&str; 26] = [
+    "b", "bn", "bp", "e", "e!", "earlier", "fold", "later", "new", "q", "q!", "qa", "qa!", "r", "recover",
+    "retab", "s", "set", "spellgood", "stats", "unfold", "view", "w", "w!", "wa", "wq",
+];
+
+/// A line given in a command's range
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LineAddress {
+    /// The cursor's line, `.`
+    Current,
+    /// The last line of the buffer, `$`
+    Last,
+    /// A line number, counting from 1
+    Number(usize),
+}
+
+/// The lines a command applies to, like `%`, `.`, `5` or `2,$`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LineRange {
+    pub start: LineAddress,
+    pub end: LineAddress,
+}
+
+impl LineRange {
+    /// Every line of the buffer, `%`
+    pub fn all() -> LineRange {
+        LineRange { start: LineAddress::Number(1), end: LineAddress::Last }
+    }
+
+    /// Just the cursor's line, `.`
+    pub fn current() -> LineRange {
+        LineRange { start: LineAddress::Current, end: LineAddress::Current }
+    }
+
+    /// Split a range off the front of the input, returning it (if there was one) and the
+    /// rest of the input
+    pub fn parse_prefix(input: &str) -> Result<(Option<LineRange>, &str), String> {
+        if let Some(rest) = input.strip_prefix('%') {
+            return Ok((Some(LineRange::all()), rest));
+        }
+        let (start, rest) = match parse_address(input)? {
+            (Some(start), rest) => (start, rest),
+            (None, rest) => return Ok((None, rest)),
+        };
+        let (end, rest) = match rest.strip_prefix(',') {
+            Some(after_comma) => match parse_address(after_comma)? {
+                (Some(end), rest) => (end, rest),
+                (None, _) => return Err(format!("Missing end of range: {input}")),
+            },
+            None => (start, rest),
+        };
+        Ok((Some(LineRange { start, end }), rest))
+    }
+
+    /// The zero based rows covered, given the cursor's row and the buffer's length.
+    /// Errors if the range runs backwards or past the end of the buffer.
+    pub fn rows(&self, current_row: usize, num_lines: usize) -> Result<Range<usize>, String> {
+        let row = |address: LineAddress| match address {
+            LineAddress::Current => current_row,
+            LineAddress::Last => num_lines.saturating_sub(1),
+            LineAddress::Number(line) => line.saturating_sub(1),
+        };
+        let (start, end) = (row(self.start), row(self.end));
+        if start > end {
+            return Err("Backwards range given".to_string());
+        }
+        if end >= num_lines {
+            return Err("Invalid range".to_string());
+        }
+        Ok(start..end + 1)
+    }
+}
+
+/// Parse a single line address from the front of the input
+fn parse_address(input: &str) -> Result<(Option<LineAddress>, &str), String> {
+    if let Some(rest) = input.strip_prefix('.') {
+        return Ok((Some(LineAddress::Current), rest));
+    }
+    if let Some(rest) = input.strip_prefix('$') {
+        return Ok((Some(LineAddress::Last), rest));
+    }
+    let digits = input.len() - input.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    if digits == 0 {
+        return Ok((None, input));
+    }
+    let line = input[..digits].parse::<usize>().map_err(|_| format!("Invalid line number: {}", &input[..digits]))?;
+    Ok((Some(LineAddress::Number(line)), &input[digits..]))
+}
+
+/// A substitution, `:[range]s/pattern/replacement/[flags]`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Substitute {
+    /// Lines searched, the cursor's line by default
+    pub range: LineRange,
+    /// Regular expression to replace
+    pub pattern: String,
+    /// Replacement text, converted to the regex crate's syntax (`${1}` for a group)
+    pub replacement: String,
+    /// Replace every match on a line rather than just the first, `g`
+    pub global: bool,
+    /// Match regardless of case, `i`
+    pub ignore_case: bool,
+    /// Ask before making each replacement, `c`
+    pub confirm: bool,
+}
+
+impl Substitute {
+    /// Parse a substitution from the text following `s`, which starts with the delimiter.
+    /// The delimiter can appear in the pattern or replacement escaped with a backslash.
+    pub fn parse(range: LineRange, input: &str) -> Result<Substitute, String> {
+        let delimiter = match input.chars().next() {
+            Some(delimiter) if !delimiter.is_alphanumeric() && !delimiter.is_whitespace() && delimiter != '\\' => delimiter,
+            _ => return Err("Substitute needs a delimiter, like :s/pattern/replacement/".to_string()),
+        };
+        let (pattern, rest) = split_delimited(&input[delimiter.len_utf8()..], delimiter);
+        if pattern.is_empty() {
+            return Err("No pattern given".to_string());
+        }
+        let (replacement, flags) = rest.map(|rest| split_delimited(rest, delimiter)).unwrap_or_default();
+        let mut substitute = Substitute {
+            range,
+            pattern,
+            replacement: convert_replacement(&replacement),
+            global: false,
+            ignore_case: false,
+            confirm: false,
+        };
+        for flag in flags.unwrap_or("").trim().chars() {
+            match flag {
+                'g' => substitute.global = true,
+                'i' => substitute.ignore_case = true,
+                'c' => substitute.confirm = true,
+                _ => return Err(format!("Unknown substitute flag: {flag}")),
+            }
+        }
+        Ok(substitute)
+    }
+
+    /// Compile the pattern, taking the ignore case flag into account
+    pub fn regex(&self) -> Result<Regex, String> {
+        RegexBuilder::new(&self.pattern)
+            .case_insensitive(self.ignore_case)
+            .build()
+            .map_err(|error| format!("Invalid pattern: {error}"))
+    }
+}
+
+/// Split the input at the first delimiter not escaped with a backslash, unescaping any
+/// escaped delimiters before it. The rest is None if there was no delimiter.
+fn split_delimited(input: &str, delimiter: char) -> (String, Option<&str>) {
+    let mut part = String::new();
+    let mut chars = input.char_indices();
+    while let Some((index, c)) = chars.next() {
+        if c == delimiter {
+            return (part, Some(&input[index + c.len_utf8()..]));
+        }
+        if c == '\\' {
+            match chars.next() {
+                Some((_, next)) if next == delimiter => part.push(next),
+                Some((_, next)) => {
+                    part.push(c);
+                    part.push(next);
+                }
+                None => part.push(c),
+            }
+            continue;
+        }
+        part.push(c);
+    }
+    (part, None)
+}
+
+/// Convert a vim style replacement, where `&` is the whole match and `\1` a group, into the
+/// regex crate's syntax
+fn convert_replacement(replacement: &str) -> String {
+    let mut converted = String::new();
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => converted.push_str("${0}"),
+            '$' => converted.push_str("$$"),
+            '\\' => match chars.next() {
+                Some(digit) if digit.is_ascii_digit() => converted.push_str(&format!("${{{digit}}}")),
+                Some('$') => converted.push_str("$$"),
+                Some(next) => converted.push(next),
+                None => converted.push(c),
+            },
+            _ => converted.push(c),
+        }
+    }
+    converted
+}
+
+/// Parse an optional count argument, which defaults to 1
+fn parse_count(argument: &str) -> Result<usize, String> {
+    if argument.is_empty() {
+        return Ok(1);
+    }
+    argument.parse::<usize>().map_err(|_| format!("Invalid count: {argument}"))
+}
+
+/// An enum representing commands which can be entered in command mode
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    /// Write the current buffer to its file, or to the path if one is given, even if it is
+    /// read-only when forced
+    Write { path: Option<PathBuf>, force: bool },
+    /// Write every modified buffer to its file
+    WriteAll,
+    /// Write the current buffer, then close its screen
+    WriteQuit,
+    /// Close the current screen, even if it is modified when forced
+    Quit { force: bool },
+    /// Close every screen and the editor, even if they are modified when forced
+    QuitAll { force: bool },
+    /// Open the file at the path in the current screen, or reload the current file from disk
+    /// without one. Unsaved changes are only thrown away when forced.
+    Edit { path: Option<PathBuf>, force: bool },
+    /// Open the file at the path in the current screen, read-only
+    View(PathBuf),
+    /// Switch to the screen at the index (counting from zero), given as `:b 1` for the first
+    Buffer(usize),
+    /// Switch to the next screen, wrapping around to the first
+    BufferNext,
+    /// Switch to the previous screen, wrapping around to the last
+    BufferPrevious,
+    /// Open a new screen showing the welcome screen
+    New,
+    /// Add a word to the spell checker's personal dictionary
+    SpellGood(String),
+    /// Fold the indented region following the cursor's line
+    Fold,
+    /// Expand the fold containing the cursor's line
+    Unfold,
+    /// Convert indentation to spaces or tabs (following expand_tab), optionally with a
+    /// different tab width
+    Retab(Option<usize>),
+    /// Run a shell command, inserting its output below the cursor's line (`:r !command`)
+    ReadShell(String),
+    /// Replace lines with the output of a shell command given them as input (`:.!command`)
+    Filter(LineRange, String),
+    /// Go back a number of edits in the order they were made, across undo branches
+    Earlier(usize),
+    /// Go forward a number of edits in the order they were made, across undo branches
+    Later(usize),
+    /// Replace matches of a pattern within a range of lines
+    Substitute(Substitute),
+    /// Replace the text with that kept in the swap file by an earlier session
+    Recover,
+    /// Change a setting, like `:set wrap`, `:set nowrap` or `:set tab_width=8`
+    Set(String),
+    /// Report the number of lines, words, graphemes and bytes in the buffer
+    Stats,
+}
+
+impl Command {
+    /// Parse the text entered in command mode (with or without the leading `:`) into a Command
+    pub fn parse(input: &str) -> Result<Command, String> {
+        let input = input.trim();
+        let input = input.strip_prefix(':').unwrap_or(input);
+        let (range, input) = LineRange::parse_prefix(input)?;
+        if let Some(rest) = input.strip_prefix('s') {
+            if rest.starts_with(|c: char| !c.is_alphanumeric() && !c.is_whitespace()) {
+                return Substitute::parse(range.unwrap_or(LineRange::current()), rest).map(Command::Substitute);
+            }
+        }
+        if let Some(range) = range {
+            return match input.strip_prefix('!') {
+                Some(command) if !command.trim().is_empty() => Ok(Command::Filter(range, command.trim().to_string())),
+                Some(_) => Err("No shell command given".to_string()),
+                None => Err(format!("Not an editor command: {input}")),
+            };
+        }
+        let (name, argument) = match input.split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, argument.trim()),
+            None => (input, ""),
+        };
+        match (name, argument) {
+            ("w" | "w!", path) => Ok(Command::Write {
+                path: if path.is_empty() { None } else { Some(PathBuf::from(path)) },
+                force: name == "w!",
+            }),
+            ("wa", "") => Ok(Command::WriteAll),
+            ("wq", "") => Ok(Command::WriteQuit),
+            ("q", "") => Ok(Command::Quit { force: false }),
+            ("q!", "") => Ok(Command::Quit { force: true }),
+            ("qa", "") => Ok(Command::QuitAll { force: false }),
+            ("qa!", "") => Ok(Command::QuitAll { force: true }),
+            ("e" | "e!", path) => Ok(Command::Edit {
+                path: if path.is_empty() { None } else { Some(PathBuf::from(path)) },
+                force: name == "e!",
+            }),
+            ("view", "") => Err("No file given".to_string()),
+            ("view", path) => Ok(Command::View(PathBuf::from(path))),
+            ("b" | "buffer", "") => Err("No screen number given".to_string()),
+            ("b" | "buffer", number) => match number.parse::<usize>() {
+                Ok(number) if number > 0 => Ok(Command::Buffer(number - 1)),
+                _ => Err(format!("Invalid screen number: {number}")),
+            },
+            ("bn" | "bnext", "") => Ok(Command::BufferNext),
+            ("bp" | "bprevious", "") => Ok(Command::BufferPrevious),
+            ("new", "") => Ok(Command::New),
+            ("fold", "") => Ok(Command::Fold),
+            ("recover", "") => Ok(Command::Recover),
+            ("stats", "") => Ok(Command::Stats),
+            ("set", "") => Err("No option given".to_string()),
+            ("set", assignment) => Ok(Command::Set(assignment.to_string())),
+            ("unfold", "") => Ok(Command::Unfold),
+            ("retab", "") => Ok(Command::Retab(None)),
+            ("retab", width) => match width.parse::<usize>() {
+                Ok(width) if width > 0 => Ok(Command::Retab(Some(width))),
+                _ => Err(format!("Invalid tab width: {width}")),
+            },
+            ("r", argument) => match argument.strip_prefix('!') {
+                Some(command) if !command.trim().is_empty() => Ok(Command::ReadShell(command.trim().to_string())),
+                _ => Err("Only reading a shell command's output (:r !command) is supported".to_string()),
+            },
+            ("earlier", count) => parse_count(count).map(Command::Earlier),
+            ("later", count) => parse_count(count).map(Command::Later),
+            ("spellgood", "") => Err("No word given".to_string()),
+            ("spellgood", word) => Ok(Command::SpellGood(word.to_string())),
+            _ => Err(format!("Not an editor command: {input}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_write_all() {
+        assert_eq!(Command::parse(":wa"), Ok(Command::WriteAll));
+        assert_eq!(Command::parse("wa"), Ok(Command::WriteAll));
+        assert!(Command::parse(":notacommand").is_err());
+    }
+
+    #[test]
+    fn parse_quit() {
+        assert_eq!(Command::parse(":q"), Ok(Command::Quit { force: false }));
+        assert_eq!(Command::parse(":qa!"), Ok(Command::QuitAll { force: true }));
+        assert_eq!(Command::parse(":wq"), Ok(Command::WriteQuit));
+        assert_eq!(Command::parse(":w!"), Ok(Command::Write { path: None, force: true }));
+        assert_eq!(Command::parse(":w out.txt"), Ok(Command::Write { path: Some(PathBuf::from("out.txt")), force: false }));
+    }
+
+    #[test]
+    fn parse_edit() {
+        assert_eq!(Command::parse(":e src/main.rs"), Ok(Command::Edit { path: Some(PathBuf::from("src/main.rs")), force: false }));
+        // Without a path, the current file is reloaded
+        assert_eq!(Command::parse(":e"), Ok(Command::Edit { path: None, force: false }));
+        assert_eq!(Command::parse(":e!"), Ok(Command::Edit { path: None, force: true }));
+    }
+
+    #[test]
+    fn parse_screen_switching() {
+        assert_eq!(Command::parse(":b 2"), Ok(Command::Buffer(1)));
+        assert!(Command::parse(":b 0").is_err());
+        assert!(Command::parse(":b").is_err());
+        assert_eq!(Command::parse(":bn"), Ok(Command::BufferNext));
+        assert_eq!(Command::parse(":bprevious"), Ok(Command::BufferPrevious));
+        assert_eq!(Command::parse(":new"), Ok(Command::New));
+    }
+
+    #[test]
+    fn parse_retab() {
+        assert_eq!(Command::parse(":retab"), Ok(Command::Retab(None)));
+        assert_eq!(Command::parse(":retab 8"), Ok(Command::Retab(Some(8))));
+        assert!(Command::parse(":retab 0").is_err());
+        assert!(Command::parse(":retab x").is_err());
+    }
+
+    #[test]
+    fn parse_recover() {
+        assert_eq!(Command::parse(":recover"), Ok(Command::Recover));
+        assert!(Command::parse(":recover now").is_err());
+    }
+
+    #[test]
+    fn parse_set() {
+        assert_eq!(Command::parse(":set nowrap"), Ok(Command::Set("nowrap".to_string())));
+        assert!(Command::parse(":set").is_err());
+    }
+
+    #[test]
+    fn parse_shell_commands() {
+        assert_eq!(Command::parse(":r !ls -l"), Ok(Command::ReadShell("ls -l".to_string())));
+        assert!(Command::parse(":r file.txt").is_err());
+        assert_eq!(Command::parse(":.!sort"), Ok(Command::Filter(LineRange::current(), "sort".to_string())));
+        assert_eq!(Command::parse(":%!sort -r"), Ok(Command::Filter(LineRange::all(), "sort -r".to_string())));
+        let range = LineRange { start: LineAddress::Number(2), end: LineAddress::Last };
+        assert_eq!(Command::parse(":2,$!sort"), Ok(Command::Filter(range, "sort".to_string())));
+        assert!(Command::parse(":.!").is_err());
+    }
+
+    #[test]
+    fn parse_earlier_later() {
+        assert_eq!(Command::parse(":earlier"), Ok(Command::Earlier(1)));
+        assert_eq!(Command::parse(":earlier 5"), Ok(Command::Earlier(5)));
+        assert_eq!(Command::parse(":later 2"), Ok(Command::Later(2)));
+        assert!(Command::parse(":later x").is_err());
+    }
+
+    #[test]
+    fn parse_substitute() {
+        let substitute = |range, pattern: &str, replacement: &str| Substitute {
+            range,
+            pattern: pattern.to_string(),
+            replacement: replacement.to_string(),
+            global: false,
+            ignore_case: false,
+            confirm: false,
+        };
+        assert_eq!(Command::parse(":s/cat/dog/"), Ok(Command::Substitute(substitute(LineRange::current(), "cat", "dog"))));
+        assert_eq!(Command::parse(":s/cat"), Ok(Command::Substitute(substitute(LineRange::current(), "cat", ""))));
+        let mut global = substitute(LineRange::all(), "a/b", "c/d");
+        global.global = true;
+        global.ignore_case = true;
+        assert_eq!(Command::parse(":%s/a\\/b/c\\/d/gi"), Ok(Command::Substitute(global)));
+        let range = LineRange { start: LineAddress::Number(2), end: LineAddress::Last };
+        let mut confirm = substitute(range, r"(\w+)\.", "${1}: ${0} $$5");
+        confirm.confirm = true;
+        assert_eq!(Command::parse(r":2,$s#(\w+)\.#\1: & $5#c"), Ok(Command::Substitute(confirm)));
+        assert!(Command::parse(":s//x/").is_err());
+        assert!(Command::parse(":s/a/b/z").is_err());
+        assert!(Command::parse(":spellgood word").is_ok());
+    }
+
+    #[test]
+    fn line_range_rows() {
+        assert_eq!(LineRange::all().rows(3, 10), Ok(0..10));
+        assert_eq!(LineRange::current().rows(3, 10), Ok(3..4));
+        let (range, rest) = LineRange::parse_prefix("2,.s").unwrap();
+        assert_eq!(rest, "s");
+        assert_eq!(range.unwrap().rows(4, 10), Ok(1..5));
+        assert!(range.unwrap().rows(0, 10).is_err());
+        assert!(LineRange::parse_prefix("5").unwrap().0.unwrap().rows(0, 3).is_err());
+    }
+}
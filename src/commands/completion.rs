@@ -0,0 +1,64 @@
+use std::fs::read_dir;
+use std::path::{Path, PathBuf};
+use crate::commands::command::COMMAND_NAMES;
+
+/// Find the command names which start with the partially typed name
+pub fn complete_command(prefix: &str) -> Vec<&'static str> {
+    COMMAND_NAMES.iter().copied().filter(|name| name.starts_with(prefix)).collect()
+}
+
+/// Find the filesystem entries which could complete a partially typed path, sorted by name.
+/// Hidden entries are only offered when the typed name starts with a `.`
+pub fn complete_path(prefix: &str) -> Vec<PathBuf> {
+    let (dir, name) = match prefix.rfind('/') {
+        Some(idx) => (&prefix[..=idx], &prefix[idx + 1..]),
+        None => ("", prefix),
+    };
+    let search_dir = if dir.is_empty() { Path::new(".") } else { Path::new(dir) };
+    let entries = match read_dir(search_dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut matches: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().to_str().map(|s| s.to_string()))
+        .filter(|entry_name| entry_name.starts_with(name))
+        .filter(|entry_name| !entry_name.starts_with('.') || name.starts_with('.'))
+        .map(|entry_name| PathBuf::from(format!("{dir}{entry_name}")))
+        .collect();
+    matches.sort();
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, remove_dir_all, File};
+
+    #[test]
+    fn complete_command_names() {
+        assert_eq!(complete_command("q"), vec!["q", "q!", "qa", "qa!"]);
+        assert_eq!(complete_command("wa"), vec!["wa"]);
+        assert!(complete_command("x").is_empty());
+    }
+
+    #[test]
+    fn complete_path_in_directory() {
+        let dir = std::env::temp_dir().join(format!("trout_complete_path_{}", std::process::id()));
+        create_dir_all(dir.join("partial_dir")).unwrap();
+        File::create(dir.join("partial.txt")).unwrap();
+        File::create(dir.join("part.rs")).unwrap();
+        File::create(dir.join("other.txt")).unwrap();
+        File::create(dir.join(".partial_hidden")).unwrap();
+        let dir_str = format!("{}/", dir.display());
+
+        let matches = complete_path(&format!("{dir_str}parti"));
+        assert_eq!(matches, vec![dir.join("partial.txt"), dir.join("partial_dir")]);
+        let matches = complete_path(&format!("{dir_str}o"));
+        assert_eq!(matches, vec![dir.join("other.txt")]);
+        assert_eq!(complete_path(&format!("{dir_str}.part")), vec![dir.join(".partial_hidden")]);
+        assert!(complete_path(&format!("{dir_str}missing")).is_empty());
+        assert_eq!(complete_path(&dir_str).len(), 4);
+        remove_dir_all(&dir).unwrap();
+    }
+}
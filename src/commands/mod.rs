@@ -1 +1,4 @@
-pub(crate) mod actions;
\ No newline at end of file
+pub(crate) mod actions;
+pub(crate) mod command;
+pub(crate) mod completion;
+pub(crate) mod shell;
\ No newline at end of file
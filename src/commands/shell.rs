@@ -0,0 +1,60 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Run a command with the system shell, optionally writing input to its stdin, and return
+/// what it wrote to stdout. A failure to start, or a non-zero exit, gives an error
+/// describing it (using stderr when the command wrote any).
+pub fn run_shell(command: &str, input: Option<&str>) -> Result<String, String> {
+    let mut process = shell_command(command)
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|error| format!("Failed to run {command}: {error}"))?;
+    if let (Some(input), Some(mut stdin)) = (input, process.stdin.take()) {
+        // Write from another thread, so a command producing lots of output can't deadlock
+        let input = input.to_string();
+        std::thread::spawn(move || {
+            let _ = stdin.write_all(input.as_bytes());
+        });
+    }
+    let output = process.wait_with_output()
+        .map_err(|error| format!("Failed to run {command}: {error}"))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = match stderr.trim() {
+            "" => output.status.to_string(),
+            stderr => stderr.to_string(),
+        };
+        return Err(format!("{command}: {reason}"));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+#[cfg(unix)]
+fn shell_command(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(windows)]
+fn shell_command(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn output_and_failure() {
+        assert_eq!(run_shell("echo hello", None), Ok("hello\n".to_string()));
+        assert_eq!(run_shell("sort", Some("b\na\n")), Ok("a\nb\n".to_string()));
+        assert_eq!(run_shell("echo oops >&2; exit 3", None), Err("echo oops >&2; exit 3: oops".to_string()));
+        assert!(run_shell("exit 1", None).unwrap_err().contains("exit status: 1"));
+    }
+}
@@ -1,9 +1,18 @@
-use crate::view::screen::Screen;
+use crate::view::screen::{MessageKind, Screen, ScreenAction};
+use std::cell::RefCell;
+use std::io::Error;
 use std::path::{Path, PathBuf};
 
 use std::panic::{set_hook, take_hook};
+use std::rc::Rc;
 use crate::view::screen::Mode;
 use crate::terminal::controls::Terminal;
+use crate::commands::command::Command;
+use crate::commands::shell::run_shell;
+use crate::input::keymap::KeyMap;
+use crate::input::macros::Macros;
+use crate::textbuffer::buffer::{remove_swap_files, Buffer};
+use crate::textbuffer::registers::Registers;
 
 /// Main editor struct, which manages the user facing behavior
 pub(crate) struct Editor {
@@ -11,31 +20,395 @@ pub(crate) struct Editor {
     should_quit: bool,
     mode: Mode,
     current_screen: usize,
+    /// Registers shared by every screen, so text can be yanked in one and pasted in another
+    registers: Rc<RefCell<Registers>>,
+    /// Macros recorded in any screen, which can be played in the others
+    macros: Rc<RefCell<Macros>>,
+    /// Keybindings, read from the user's keymap config at startup and shared by every screen
+    key_map: Rc<KeyMap>,
+    /// Why the keymap config couldn't be loaded, shown on the first screen opened
+    config_error: Option<String>,
 }
 
 impl Editor {
-    pub fn new(path: Option<&Path>)-> Editor{
+    /// Create the editor, opening the path, or a buffer of piped input (which must be read
+    /// before the terminal enters raw mode), or the welcome screen when given neither.
+    /// The opened buffer is read-only if readonly is set.
+    pub fn new(path: Option<&Path>, input: Option<Buffer>, readonly: bool)-> Editor{
         // Ensure that on panic, the terminal shuts down nicely
         let current_hook = take_hook();
         set_hook(Box::new(move |panic_info| {
+            remove_swap_files();
             let _ = Terminal::terminate();
             current_hook(panic_info);
         }));
         // Create a default terminal session, entering raw mode, on an alternate screen, and clearing it
         _=Terminal::initialize();
-        Editor {
+        let (key_map, config_error) = match KeyMap::load() {
+            Ok(key_map) => (key_map, None),
+            Err(error) => (KeyMap::default(), Some(format!("keymap config not loaded, {error}"))),
+        };
+        let mut editor = Editor {
             screens: Vec::new(),
             should_quit: false,
             mode: Mode::Normal,
             current_screen: 0,
+            registers: Rc::new(RefCell::new(Registers::new())),
+            macros: Rc::new(RefCell::new(Macros::new())),
+            key_map: Rc::new(key_map),
+            config_error,
+        };
+        match (path, input) {
+            (_, Some(buffer)) => {
+                let mut screen = Screen::default();
+                screen.buffer = buffer;
+                editor.add_screen(screen);
+            }
+            (Some(path), None) => editor.open_file(path.to_path_buf()),
+            (None, None) => editor.add_screen(Screen::welcome()),
+        }
+        if readonly {
+            if let Some(screen) = editor.screens.get_mut(editor.current_screen) {
+                screen.buffer.readonly = true;
+            }
+        }
+        editor
+    }
+
+    /// Run the editor until its last screen is closed. The current screen handles input
+    /// until it needs the editor to act, then the editor carries out the action and hands
+    /// back to whichever screen is current. The terminal is restored on the way out.
+    pub fn run(&mut self)->Result<(), Error>{
+        while !self.should_quit && self.current_screen < self.screens.len() {
+            let action = self.screens[self.current_screen].run();
+            self.handle_action(action);
+        }
+        Terminal::terminate()
+    }
+
+    /// Carry out an action returned by the current screen
+    fn handle_action(&mut self, action: EditorAction){
+        match action {
+            EditorAction::ChangeScreen(index) => {
+                if let Err(error) = self.change_screen(index) {
+                    self.show_error(&error);
+                }
+            }
+            EditorAction::NextScreen => self.cycle_screen(true),
+            EditorAction::PreviousScreen => self.cycle_screen(false),
+            EditorAction::NewScreen(path) => self.open_file(path),
+            EditorAction::NewWelcomeScreen => self.add_screen(Screen::welcome()),
+            EditorAction::QuitScreen => self.quit_current(),
+            EditorAction::QuitEditor => {
+                if (0..self.screens.len()).all(|index| self.confirm_quit(index)) {
+                    self.quit_all();
+                }
+            }
+            EditorAction::ExecuteCommand(command) => {
+                if let Err(error) = self.execute_command(command) {
+                    self.show_error(&error);
+                }
+            }
+        }
+    }
+
+    /// Report an error on the current screen
+    fn show_error(&mut self, error: &str){
+        if let Some(screen) = self.screens.get_mut(self.current_screen) {
+            screen.set_message(error, MessageKind::Error);
+        }
+    }
+
+    /// Make the screen at the index current
+    fn change_screen(&mut self, index: usize)->Result<(), String>{
+        if index >= self.screens.len() {
+            return Err(format!("No screen {}", index + 1));
+        }
+        self.current_screen = index;
+        Ok(())
+    }
+
+    /// Make the next screen current, or the previous one, wrapping around at either end
+    fn cycle_screen(&mut self, forward: bool){
+        let count = self.screens.len();
+        if count == 0 {
+            return;
         }
+        let step = if forward { 1 } else { count - 1 };
+        self.current_screen = (self.current_screen + step) % count;
     }
 
+    /// Open a screen for the file, or a file browser if the path is a directory
     pub fn open_file(&mut self, file_path:PathBuf){
-        self.screens.push(Screen::default());
-        self.current_screen = self.screens.len()-1;
+        if file_path.is_dir() {
+            self.add_screen(Screen::browser(file_path));
+            return;
+        }
+        self.add_screen(Screen::default());
         self.screens[self.current_screen].load_file(file_path);
     }
+
+    /// Add a screen sharing the editor's registers and keybindings, and make it the current one
+    fn add_screen(&mut self, mut screen: Screen){
+        screen.registers = Rc::clone(&self.registers);
+        screen.macros = Rc::clone(&self.macros);
+        screen.key_map = Rc::clone(&self.key_map);
+        if let Some(error) = self.config_error.take() {
+            screen.set_message(&error, MessageKind::Error);
+        }
+        self.screens.push(screen);
+        self.current_screen = self.screens.len()-1;
+    }
+
+    /// Run a command entered in command mode, returning a message describing any failure
+    pub fn execute_command(&mut self, command: Command) -> Result<(), String> {
+        match command {
+            Command::Write { path, force } => {
+                self.write_current(path, force)
+            }
+            Command::WriteQuit => {
+                self.write_current(None, false)?;
+                self.quit_current();
+                Ok(())
+            }
+            Command::Quit { force } => {
+                if force || self.confirm_quit(self.current_screen) {
+                    self.quit_current();
+                }
+                Ok(())
+            }
+            Command::QuitAll { force } => {
+                if force || (0..self.screens.len()).all(|index| self.confirm_quit(index)) {
+                    self.quit_all();
+                }
+                Ok(())
+            }
+            Command::WriteAll => {
+                let errors = self.write_all();
+                if errors.is_empty() {
+                    return Ok(());
+                }
+                let report: Vec<String> = errors.iter()
+                    .map(|(path, error)| format!("{}: {}", path.display(), error))
+                    .collect();
+                Err(format!("Failed to write {} file(s): {}", errors.len(), report.join("; ")))
+            }
+            Command::Edit { path, force } => {
+                // Directories are browsed in a screen of their own
+                let screen = match self.screens.get_mut(self.current_screen) {
+                    Some(screen) if !path.as_ref().is_some_and(|path| path.is_dir()) => screen,
+                    _ => {
+                        if let Some(path) = path {
+                            self.open_file(path);
+                        }
+                        return Ok(());
+                    }
+                };
+                if screen.buffer.modified && !force {
+                    return Err("No write since last change (add ! to override)".to_string());
+                }
+                match path {
+                    Some(path) => screen.edit_file(path),
+                    None => screen.reload_file(),
+                }.map_err(|error| error.to_string())
+            }
+            Command::View(path) => {
+                self.execute_command(Command::Edit { path: Some(path), force: false })?;
+                if let Some(screen) = self.screens.get_mut(self.current_screen) {
+                    screen.buffer.readonly = true;
+                }
+                Ok(())
+            }
+            Command::Buffer(index) => self.change_screen(index),
+            Command::BufferNext | Command::BufferPrevious => {
+                self.cycle_screen(command == Command::BufferNext);
+                Ok(())
+            }
+            Command::New => {
+                self.add_screen(Screen::welcome());
+                Ok(())
+            }
+            Command::Fold | Command::Unfold => {
+                let screen = match self.screens.get_mut(self.current_screen) {
+                    Some(screen) => screen,
+                    None => return Ok(()),
+                };
+                let row = screen.text_position.row;
+                let changed = if command == Command::Fold {
+                    screen.fold_at(row)
+                } else {
+                    screen.unfold_at(row)
+                }.map_err(|error| error.to_string())?;
+                if changed { Ok(()) } else { Err("No fold found".to_string()) }
+            }
+            Command::Retab(width) => {
+                if let Some(screen) = self.screens.get_mut(self.current_screen) {
+                    let width = width.unwrap_or(screen.settings.tab_width);
+                    screen.buffer.retab(screen.settings.expand_tab, width);
+                    screen.buffer.commit_undo();
+                }
+                Ok(())
+            }
+            Command::ReadShell(command) => {
+                let output = run_shell(&command, None)?;
+                if let Some(screen) = self.screens.get_mut(self.current_screen) {
+                    let below = (screen.text_position.row + 1).min(screen.buffer.num_lines);
+                    screen.buffer.replace_lines(below..below, &output);
+                    screen.buffer.commit_undo();
+                }
+                Ok(())
+            }
+            Command::Filter(range, command) => {
+                let screen = match self.screens.get_mut(self.current_screen) {
+                    Some(screen) => screen,
+                    None => return Ok(()),
+                };
+                let rows = range.rows(screen.text_position.row, screen.buffer.num_lines)?;
+                let mut input: String = screen.buffer.text.range(rows.clone())
+                    .map(|line| line.text.as_str())
+                    .collect::<Vec<&str>>()
+                    .join("\n");
+                input.push('\n');
+                let output = run_shell(&command, Some(&input))?;
+                screen.buffer.replace_lines(rows.clone(), &output);
+                screen.buffer.commit_undo();
+                screen.text_position.row = rows.start.min(screen.buffer.num_lines.saturating_sub(1));
+                screen.text_position.grapheme = 0;
+                screen.text_position.byte = 0;
+                screen.scroll_into_view().map_err(|error| error.to_string())
+            }
+            Command::Earlier(count) | Command::Later(count) => {
+                let screen = match self.screens.get_mut(self.current_screen) {
+                    Some(screen) => screen,
+                    None => return Ok(()),
+                };
+                if matches!(command, Command::Earlier(_)) {
+                    screen.earlier(count)
+                } else {
+                    screen.later(count)
+                }.map_err(|error| error.to_string())
+            }
+            Command::Substitute(substitute) => {
+                let screen = match self.screens.get_mut(self.current_screen) {
+                    Some(screen) => screen,
+                    None => return Ok(()),
+                };
+                let rows = substitute.range.rows(screen.text_position.row, screen.buffer.num_lines)?;
+                let regex = substitute.regex()?;
+                if !rows.clone().any(|row| regex.is_match(&screen.buffer.text[row].text)) {
+                    return Err(format!("Pattern not found: {}", substitute.pattern));
+                }
+                if substitute.confirm {
+                    screen.confirm_substitute(rows, &regex, &substitute.replacement, substitute.global, |screen| {
+                        screen.draw();
+                        Terminal::read_answer()
+                    });
+                    screen.buffer.commit_undo();
+                    return screen.scroll_into_view().map_err(|error| error.to_string());
+                }
+                screen.buffer.replace_all(rows, &regex, &substitute.replacement, substitute.global);
+                screen.buffer.commit_undo();
+                // The line under the cursor may have become shorter
+                screen.move_start_line().map_err(|error| error.to_string())
+            }
+            Command::Recover => {
+                let screen = match self.screens.get_mut(self.current_screen) {
+                    Some(screen) => screen,
+                    None => return Ok(()),
+                };
+                screen.buffer.recover_swap()
+                    .map_err(|error| format!("Failed to recover swap file: {error}"))?;
+                screen.text_position.row = screen.text_position.row.min(screen.buffer.num_lines.saturating_sub(1));
+                screen.move_start_line().map_err(|error| error.to_string())
+            }
+            Command::Stats => {
+                if let Some(screen) = self.screens.get_mut(self.current_screen) {
+                    screen.show_stats();
+                }
+                Ok(())
+            }
+            Command::Set(assignment) => {
+                match self.screens.get_mut(self.current_screen) {
+                    Some(screen) => screen.set_option(&assignment),
+                    None => Ok(()),
+                }
+            }
+            Command::SpellGood(word) => {
+                let checker = self.screens.get_mut(self.current_screen)
+                    .and_then(|screen| screen.spell_checker.as_mut())
+                    .ok_or("Spell checking is not enabled".to_string())?;
+                checker.add_word(&word).map_err(|error| error.to_string())
+            }
+        }
+    }
+
+    /// Write the buffer of the current screen to its file, or to the path if one is given,
+    /// even if it is read-only when forced
+    fn write_current(&mut self, path: Option<PathBuf>, force: bool) -> Result<(), String> {
+        match self.screens.get_mut(self.current_screen) {
+            None => Ok(()),
+            Some(screen) => match path {
+                Some(path) => screen.write_file_as(path, force),
+                None => screen.write_file(force),
+            }.map_err(|error| {
+                if screen.buffer.path.as_os_str().is_empty() {
+                    error.to_string()
+                } else {
+                    format!("{}: {}", screen.buffer.path.display(), error)
+                }
+            }),
+        }
+    }
+
+    /// Close the current screen, quitting the editor if it was the last one
+    fn quit_current(&mut self) {
+        if self.current_screen < self.screens.len() {
+            self.screens.remove(self.current_screen).buffer.remove_swap();
+        }
+        self.current_screen = self.current_screen.min(self.screens.len().saturating_sub(1));
+        if self.screens.is_empty() {
+            self.should_quit = true;
+        }
+    }
+
+    /// Ask whether to save the changes to a screen's buffer before it is closed, showing the
+    /// screen while asking. Returns whether to go ahead with closing it.
+    fn confirm_quit(&mut self, index: usize) -> bool {
+        let Some(screen) = self.screens.get_mut(index) else { return true };
+        if !screen.buffer.modified {
+            return true;
+        }
+        self.current_screen = index;
+        let action = self.screens[index].confirm_quit(|screen| {
+            screen.draw();
+            Terminal::read_answer()
+        });
+        matches!(action, ScreenAction::QuitScreen)
+    }
+
+    /// Close every screen and quit the editor
+    fn quit_all(&mut self) {
+        for screen in self.screens.iter_mut() {
+            screen.buffer.remove_swap();
+        }
+        self.screens.clear();
+        self.should_quit = true;
+    }
+
+    /// Write every modified buffer to its file. Every buffer is attempted, and the
+    /// errors for any which failed to save are returned along with their paths
+    pub fn write_all(&mut self) -> Vec<(PathBuf, Error)> {
+        let mut errors: Vec<(PathBuf, Error)> = Vec::new();
+        for screen in self.screens.iter_mut() {
+            if !screen.buffer.modified {
+                continue;
+            }
+            if let Err(error) = screen.write_file(false) {
+                errors.push((screen.buffer.path.clone(), error));
+            }
+        }
+        errors
+    }
 }
 
 /// Enum used for telling the editor what to do next, returned from a mode's run method
@@ -44,10 +417,275 @@ pub enum EditorAction {
     ChangeScreen(usize),
     /// Open a new screen with the provided path
     NewScreen(PathBuf),
+    /// Change to the screen after the current one, wrapping around to the first
+    NextScreen,
+    /// Change to the screen before the current one, wrapping around to the last
+    PreviousScreen,
     /// Open a new welcome screen
     NewWelcomeScreen,
     /// Quit the current screen (closing it without saving)
     QuitScreen,
+    /// Close every screen and the editor
+    QuitEditor,
+    /// Run a command entered in command mode
+    ExecuteCommand(Command),
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::textbuffer::buffer::Buffer;
+    use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+    use std::fs::{create_dir_all, read_to_string, remove_dir_all};
+
+    fn test_editor() -> Editor {
+        Editor {
+            screens: Vec::new(),
+            should_quit: false,
+            mode: Mode::Normal,
+            current_screen: 0,
+            registers: Rc::new(RefCell::new(Registers::new())),
+            macros: Rc::new(RefCell::new(Macros::new())),
+            key_map: Rc::new(KeyMap::default()),
+            config_error: None,
+        }
+    }
+
+    #[test]
+    fn write_all_reports_failures() {
+        let dir = std::env::temp_dir().join(format!("trout_write_all_{}", std::process::id()));
+        create_dir_all(&dir).unwrap();
+        let good_path = dir.join("good.txt");
+        // The parent directory doesn't exist, so this file can't be written
+        let bad_path = dir.join("missing").join("bad.txt");
+
+        let mut editor = test_editor();
+        for path in [bad_path.clone(), good_path.clone()] {
+            let mut screen = Screen::default();
+            screen.buffer = Buffer::from_string("hello");
+            screen.buffer.path = path;
+            screen.buffer.modified = true;
+            editor.screens.push(screen);
+        }
+
+        let errors = editor.write_all();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, bad_path);
+        assert_eq!(read_to_string(&good_path).unwrap(), "hello\n");
+        assert!(editor.screens[0].buffer.modified);
+        assert!(!editor.screens[1].buffer.modified);
 
+        editor.screens[1].buffer.modified = true;
+        assert!(editor.execute_command(Command::WriteAll).is_err());
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn run_until_last_screen_quits() {
+        let mut editor = test_editor();
+        editor.add_screen(Screen::default());
+        editor.add_screen(Screen::default());
+        let quit = Event::Key(KeyEvent::new(KeyCode::Char('q'), KeyModifiers::NONE));
+        Terminal::capture(|| Terminal::script_input(vec![quit.clone(), quit], || editor.run().unwrap()));
+        assert!(editor.screens.is_empty());
+        assert!(editor.should_quit);
+    }
+
+    #[test]
+    fn command_line_switches_screens() {
+        let mut editor = test_editor();
+        editor.add_screen(Screen::default());
+        editor.add_screen(Screen::default());
+        editor.current_screen = 0;
+        let command = |text: &str| {
+            let mut events = vec![Event::Key(KeyEvent::from(KeyCode::Char('c')))];
+            events.extend(text.chars().map(|c| Event::Key(KeyEvent::from(KeyCode::Char(c)))));
+            events.push(Event::Key(KeyEvent::from(KeyCode::Enter)));
+            events
+        };
+        // Run the current screen until it hands an action to the editor
+        let run_command = |editor: &mut Editor, text: &str| {
+            Terminal::capture(|| Terminal::script_input(command(text), || {
+                let action = editor.screens[editor.current_screen].run();
+                editor.handle_action(action);
+            }));
+        };
+        run_command(&mut editor, "bn");
+        assert_eq!(editor.current_screen, 1);
+        assert_eq!(editor.screens[0].mode, Mode::Normal);
+        run_command(&mut editor, "bp");
+        assert_eq!(editor.current_screen, 0);
+        run_command(&mut editor, "new");
+        assert_eq!(editor.screens.len(), 3);
+        assert_eq!(editor.current_screen, 2);
+        assert!(editor.screens[2].welcome_screen);
+        run_command(&mut editor, "b 2");
+        assert_eq!(editor.current_screen, 1);
+        run_command(&mut editor, "qa");
+        assert!(editor.screens.is_empty());
+        assert!(editor.should_quit);
+    }
+
+    #[test]
+    fn quitting_asks_about_each_modified_buffer() {
+        let mut editor = test_editor();
+        for text in ["one", "two", "three"] {
+            let mut screen = Screen::default();
+            screen.buffer = Buffer::from_string(text);
+            editor.add_screen(screen);
+        }
+        editor.screens[0].buffer.modified = true;
+        editor.screens[2].buffer.modified = true;
+        // Discarding the first buffer's changes but cancelling on the last keeps every screen
+        let answers = vec![Event::Key(KeyEvent::from(KeyCode::Char('n'))), Event::Key(KeyEvent::from(KeyCode::Esc))];
+        Terminal::capture(|| Terminal::script_input(answers, || editor.execute_command(Command::QuitAll { force: false }).unwrap()));
+        assert_eq!(editor.screens.len(), 3);
+        assert_eq!(editor.current_screen, 2);
+        assert!(!editor.should_quit);
+        editor.execute_command(Command::Quit { force: true }).unwrap();
+        assert_eq!(editor.screens.len(), 2);
+        editor.execute_command(Command::QuitAll { force: true }).unwrap();
+        assert!(editor.should_quit);
+    }
+
+    #[test]
+    fn handle_screen_actions() {
+        let dir = std::env::temp_dir().join(format!("trout_screen_actions_{}", std::process::id()));
+        create_dir_all(&dir).unwrap();
+        let mut editor = test_editor();
+        editor.add_screen(Screen::default());
+        editor.handle_action(EditorAction::NewScreen(dir.join("new.txt")));
+        assert_eq!(editor.current_screen, 1);
+        assert_eq!(editor.screens[1].buffer.path, dir.join("new.txt"));
+        editor.handle_action(EditorAction::ChangeScreen(0));
+        assert_eq!(editor.current_screen, 0);
+        editor.handle_action(EditorAction::ChangeScreen(5));
+        assert_eq!(editor.current_screen, 0);
+        assert!(editor.screens[0].message.take().is_some());
+        editor.handle_action(EditorAction::PreviousScreen);
+        assert_eq!(editor.current_screen, 1);
+        editor.handle_action(EditorAction::NextScreen);
+        assert_eq!(editor.current_screen, 0);
+        // Commands which fail report why on the current screen
+        editor.handle_action(EditorAction::ExecuteCommand(Command::Fold));
+        assert!(editor.screens[0].message.is_some());
+        editor.handle_action(EditorAction::QuitScreen);
+        assert_eq!(editor.screens.len(), 1);
+        assert!(!editor.should_quit);
+        editor.handle_action(EditorAction::QuitEditor);
+        assert!(editor.screens.is_empty());
+        assert!(editor.should_quit);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn edit_reloads_and_opens_files() {
+        let dir = std::env::temp_dir().join(format!("trout_edit_{}", std::process::id()));
+        create_dir_all(&dir).unwrap();
+        let first = dir.join("first.txt");
+        let second = dir.join("second.txt");
+        std::fs::write(&first, "one\ntwo\nthree\n").unwrap();
+        std::fs::write(&second, "other\n").unwrap();
+        let mut editor = test_editor();
+        editor.open_file(first.clone());
+        let screen = &mut editor.screens[0];
+        screen.buffer.finish_loading();
+        screen.text_position.row = 2;
+        screen.delete_current_line().unwrap();
+        screen.insert_char('x').unwrap();
+        // Reloading would lose the edits, unless forced
+        assert!(editor.execute_command(Command::parse(":e").unwrap()).is_err());
+        assert!(editor.execute_command(Command::parse(":e second.txt").unwrap()).is_err());
+        editor.execute_command(Command::parse(":e!").unwrap()).unwrap();
+        let text: Vec<&str> = editor.screens[0].buffer.text.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(text, ["one", "two", "three"]);
+        assert!(!editor.screens[0].buffer.modified);
+        assert!(editor.screens[0].text_position.row < 3);
+        editor.screens[0].set_mark('a');
+        editor.screens[0].push_jump();
+        // A clean buffer is replaced by the other file, in the same screen, without the first
+        // file's marks and jumps
+        editor.execute_command(Command::Edit { path: Some(second.clone()), force: false }).unwrap();
+        assert_eq!(editor.screens.len(), 1);
+        editor.screens[0].buffer.finish_loading();
+        assert_eq!(editor.screens[0].buffer.path, second);
+        assert_eq!(editor.screens[0].buffer.text[0].text, "other");
+        assert_eq!(editor.screens[0].text_position.row, 0);
+        assert!(editor.screens[0].marks.is_empty());
+        assert!(editor.screens[0].jump_list.is_empty());
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn open_directory_as_browser() {
+        let dir = std::env::temp_dir().join(format!("trout_open_dir_{}", std::process::id()));
+        create_dir_all(&dir).unwrap();
+        let mut editor = test_editor();
+        editor.open_file(dir.clone());
+        assert_eq!(editor.screens.len(), 1);
+        assert_eq!(editor.screens[0].mode, Mode::Open);
+        assert_eq!(editor.screens[0].browse_directory, Some(dir.clone()));
+        assert_eq!(editor.screens[0].buffer.path, PathBuf::new());
+        // The same happens through :e
+        editor.execute_command(Command::Edit { path: Some(dir.clone()), force: false }).unwrap();
+        assert_eq!(editor.current_screen, 1);
+        assert_eq!(editor.screens[1].mode, Mode::Open);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shell_commands() {
+        let mut editor = test_editor();
+        let mut screen = Screen::default();
+        screen.buffer = Buffer::from_string("pear\ncherry\napple\nend");
+        editor.screens.push(screen);
+        editor.execute_command(Command::parse(":1,3!sort").unwrap()).unwrap();
+        let text: Vec<&str> = editor.screens[0].buffer.text.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(text, ["apple", "cherry", "pear", "end"]);
+
+        editor.screens[0].text_position.row = 3;
+        editor.execute_command(Command::parse(":.!tr a-z A-Z").unwrap()).unwrap();
+        assert_eq!(editor.screens[0].buffer.text[3].text, "END");
+
+        editor.screens[0].text_position.row = 0;
+        editor.execute_command(Command::parse(":r !printf 'one\\ntwo\\n'").unwrap()).unwrap();
+        let text: Vec<&str> = editor.screens[0].buffer.text.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(text, ["apple", "one", "two", "cherry", "pear", "END"]);
+
+        // A failing command leaves the buffer alone
+        assert!(editor.execute_command(Command::parse(":%!exit 1").unwrap()).is_err());
+        assert_eq!(editor.screens[0].buffer.num_lines, 6);
+    }
+
+    #[test]
+    fn substitute() {
+        let mut editor = test_editor();
+        let mut screen = Screen::default();
+        screen.buffer = Buffer::from_string("one fish two fish\nred Fish\nblue fish");
+        editor.screens.push(screen);
+        editor.execute_command(Command::parse(":1,2s/fish/cat/g").unwrap()).unwrap();
+        let text: Vec<&str> = editor.screens[0].buffer.text.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(text, ["one cat two cat", "red Fish", "blue fish"]);
+        editor.execute_command(Command::parse(":%s/(\\w+) fish/& and \\1/i").unwrap()).unwrap();
+        let text: Vec<&str> = editor.screens[0].buffer.text.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(text, ["one cat two cat", "red Fish and red", "blue fish and blue"]);
+        assert!(editor.execute_command(Command::parse(":s/missing/x/").unwrap()).is_err());
+    }
+
+    #[test]
+    fn registers_shared_between_screens() {
+        let dir = std::env::temp_dir().join(format!("trout_registers_{}", std::process::id()));
+        create_dir_all(&dir).unwrap();
+        let mut editor = test_editor();
+        editor.open_file(dir.join("first.txt"));
+        editor.open_file(dir.join("second.txt"));
+        editor.screens[0].buffer = Buffer::from_string("shared line");
+        editor.screens[0].selected_register = Some('q');
+        editor.screens[0].yank_current_line();
+        editor.screens[1].selected_register = Some('q');
+        editor.screens[1].paste(false).unwrap();
+        assert_eq!(editor.screens[1].buffer.text[0].text, "shared line");
+        remove_dir_all(&dir).unwrap();
+    }
+}
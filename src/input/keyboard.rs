@@ -4,13 +4,26 @@ use crate::input::keymap::KeyMap;
 use crate::commands::actions::{ActionType, ActionParam, Action};
 use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers, ModifierKeyCode, read};
 
+/// Maximum number of keys held in the input buffer before it is cleared
+const MAX_INPUT_TOKENS: usize = 16;
+
 /// Handles keypress events
 struct KeyReader {
     key_map: KeyMap,
     input_buffer: String,
+    /// Number of keys currently held in the input buffer
+    input_tokens: usize,
 }
 
 impl KeyReader {
+    pub fn new() -> KeyReader {
+        Self {
+            key_map: KeyMap::default(),
+            input_buffer: String::new(),
+            input_tokens: 0,
+        }
+    }
+
     pub fn read_input(&mut self, key_event: KeyEvent, mode: Mode) -> Option<Action> {
         match mode {
             Mode::Normal => {self.normal_mode(key_event)}
@@ -20,6 +33,7 @@ impl KeyReader {
             Mode::Find => {self.find_mode(key_event)}
             Mode::Open => {self.open_mode(key_event)}
             Mode::Select => {self.select_mode(key_event)}
+            Mode::Search => {self.search_mode(key_event)}
         }
     }
 
@@ -85,11 +99,24 @@ impl KeyReader {
             }
         }
 
+        self.input_tokens += 1;
+
         let (num, command_str) = Self::strip_digits(&self.input_buffer);
 
         match self.key_map.normal.get(command_str) {
-            None => None,
-            Some(&action_type)=> Some(Action{action_type, action_param:ActionParam::Repeat(num)})
+            None => {
+                // Clear the buffer if it can no longer complete a binding, or has grown
+                // too long
+                let dead_end = !self.key_map.normal.keys().any(|key| key.starts_with(command_str));
+                if dead_end || self.input_tokens >= MAX_INPUT_TOKENS {
+                    self.clear_input_buffer();
+                }
+                None
+            },
+            Some(&action_type)=> {
+                self.clear_input_buffer();
+                Some(Action{action_type, action_param:ActionParam::Repeat(num)})
+            }
         }
     }
 
@@ -105,8 +132,11 @@ impl KeyReader {
 
     fn select_mode(&mut self, key_event: KeyEvent)-> Option<Action>{None}
 
+    fn search_mode(&mut self, key_event: KeyEvent)-> Option<Action>{None}
+
     fn clear_input_buffer(&mut self){
         self.input_buffer = "".to_string();
+        self.input_tokens = 0;
     }
 
     fn strip_digits(in_string: &str) -> (u16, &str) {
@@ -120,7 +150,12 @@ impl KeyReader {
 
         let mut digits = String::new();
         in_string[..index].clone_into(&mut digits);
-        let parsed_digits: u16 = digits.parse().expect("Failed to parse into unsigned integer");
+        // No digits means no repeat count, and too many saturates the count
+        let parsed_digits: u16 = if digits.is_empty() {
+            0
+        } else {
+            digits.parse().unwrap_or(u16::MAX)
+        };
 
 
         return match in_string.strip_prefix(&digits) {
@@ -141,4 +176,22 @@ mod tests {
         assert_eq!(res_digit, 123u16);
         assert_eq!(res_str, "jlk");
     }
+
+    #[test]
+    fn input_buffer_is_capped() {
+        let mut reader = KeyReader::new();
+        for _ in 0..100 {
+            let action = reader.read_input(KeyEvent::new(KeyCode::Char('7'), KeyModifiers::NONE),
+                                           Mode::Normal);
+            assert!(action.is_none());
+            assert!(reader.input_tokens < MAX_INPUT_TOKENS);
+            assert!(reader.input_buffer.len() < MAX_INPUT_TOKENS);
+        }
+        for _ in 0..100 {
+            let action = reader.read_input(KeyEvent::new(KeyCode::Char('z'), KeyModifiers::NONE),
+                                           Mode::Normal);
+            assert!(action.is_none());
+            assert!(reader.input_buffer.is_empty());
+        }
+    }
 }
\ No newline at end of file
@@ -1,4 +1,8 @@
 use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+use crossterm::event::{KeyCode, KeyModifiers};
+use toml::Spanned;
 use crate::commands::actions::ActionType;
 
 pub struct KeyMap {
@@ -12,7 +16,7 @@ pub struct KeyMap {
 }
 
 impl KeyMap {
-    fn default()-> KeyMap {
+    pub fn default()-> KeyMap {
         let mut normal:HashMap<String, ActionType> = HashMap::new();
         let mut insert:HashMap<String, ActionType> = HashMap::new();
         let mut jump:HashMap<String, ActionType> = HashMap::new();
@@ -35,4 +39,134 @@ impl KeyMap {
             normal, insert, jump, command, find, open, select
         }
     }
-}
\ No newline at end of file
+
+    /// The name a key is bound with in a config, such as `w`, `W`, `Space` or `Ctrl-w`, for
+    /// the keys which can be bound
+    pub fn key_name(code: KeyCode, modifiers: KeyModifiers) -> Option<String> {
+        let prefix = match modifiers {
+            KeyModifiers::NONE | KeyModifiers::SHIFT => "",
+            KeyModifiers::CONTROL => "Ctrl-",
+            KeyModifiers::ALT => "Alt-",
+            _ => return None,
+        };
+        let key = match code {
+            KeyCode::Char(' ') => "Space".to_string(),
+            KeyCode::Char(c) => c.to_string(),
+            KeyCode::Enter => "Enter".to_string(),
+            KeyCode::Esc => "Escape".to_string(),
+            KeyCode::Tab => "Tab".to_string(),
+            KeyCode::Backspace => "Backspace".to_string(),
+            KeyCode::Delete => "Delete".to_string(),
+            KeyCode::F(key) => format!("Fn{key}"),
+            _ => return None,
+        };
+        Some(format!("{prefix}{key}"))
+    }
+
+    /// Where the user's keymap config is looked for, `~/.config/trout/keymap.toml`
+    pub fn config_path() -> Option<PathBuf> {
+        std::env::var_os("HOME")
+            .map(|home| PathBuf::from(home).join(".config").join("trout").join("keymap.toml"))
+    }
+
+    /// Load the user's keymap config if there is one, otherwise the default bindings
+    pub fn load() -> Result<KeyMap, String> {
+        match KeyMap::config_path() {
+            Some(path) if path.is_file() => KeyMap::from_config(&path),
+            _ => Ok(KeyMap::default()),
+        }
+    }
+
+    /// Read keybindings from a TOML file, merged over the defaults
+    pub fn from_config(path: &Path) -> Result<KeyMap, String> {
+        let text = read_to_string(path).map_err(|error| format!("{}: {error}", path.display()))?;
+        KeyMap::from_toml(&text).map_err(|error| format!("{}: {error}", path.display()))
+    }
+
+    /// Parse keybindings from TOML text, with a table mapping a key to the name of an
+    /// action, e.g.
+    ///
+    /// ```toml
+    /// [normal]
+    /// "h" = "MoveLeft"
+    /// ```
+    ///
+    /// Bindings in the text replace the default binding for the same key. Normal mode looks
+    /// its keys up in these bindings before its built in commands. The other modes don't look
+    /// up bindings yet, so a table for one of them is refused rather than silently ignored.
+    pub fn from_toml(text: &str) -> Result<KeyMap, String> {
+        let config: HashMap<Spanned<String>, HashMap<String, Spanned<String>>> =
+            toml::from_str(text).map_err(|error| error.to_string())?;
+        let line_of = |offset: usize| text[..offset].lines().count().max(1);
+        let mut key_map = KeyMap::default();
+        for (mode, bindings) in config {
+            let line = line_of(mode.span().start);
+            match mode.get_ref().as_str() {
+                "normal" => {}
+                name if MODE_NAMES.contains(&name) => {
+                    return Err(format!("line {line}: keys can only be bound in `normal` mode, not `{name}`"));
+                }
+                name => return Err(format!("line {line}: unknown mode `{name}`")),
+            }
+            for (key, action) in bindings {
+                let line = line_of(action.span().start);
+                let action_type = ActionType::from_name(action.get_ref()).ok_or_else(|| {
+                    format!("line {line}: unknown action `{}` for key `{key}`", action.get_ref())
+                })?;
+                key_map.normal.insert(key, action_type);
+            }
+        }
+        Ok(key_map)
+    }
+}
+
+/// Names of the modes, as used for their tables in a config
+const MODE_NAMES: [&str; 7] = ["normal", "insert", "jump", "command", "find", "open", "select"];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn override_default_binding() {
+        let key_map = KeyMap::from_toml("[normal]\n\"w\" = \"EnterInsert\"\n\"h\" = \"MoveLeft\"\n").unwrap();
+        assert_eq!(key_map.normal["w"], ActionType::EnterInsert);
+        assert_eq!(key_map.normal["h"], ActionType::MoveLeft);
+        // Bindings the config doesn't mention keep their defaults
+        assert_eq!(key_map.normal["a"], ActionType::MoveLeft);
+        assert_eq!(key_map.insert["Escape"], ActionType::EnterNormal);
+    }
+
+    #[test]
+    fn refuse_other_modes() {
+        let error = KeyMap::from_toml("[normal]\n\"h\" = \"MoveLeft\"\n\n[select]\n\"Escape\" = \"Cancel\"\n").err().unwrap();
+        assert_eq!(error, "line 4: keys can only be bound in `normal` mode, not `select`");
+    }
+
+    #[test]
+    fn names_keys_as_bound() {
+        assert_eq!(KeyMap::key_name(KeyCode::Char('w'), KeyModifiers::NONE), Some("w".to_string()));
+        assert_eq!(KeyMap::key_name(KeyCode::Char('W'), KeyModifiers::SHIFT), Some("W".to_string()));
+        assert_eq!(KeyMap::key_name(KeyCode::Char(' '), KeyModifiers::NONE), Some("Space".to_string()));
+        assert_eq!(KeyMap::key_name(KeyCode::Char('w'), KeyModifiers::CONTROL), Some("Ctrl-w".to_string()));
+        assert_eq!(KeyMap::key_name(KeyCode::Up, KeyModifiers::NONE), None);
+    }
+
+    #[test]
+    fn unknown_action_names_line() {
+        let error = KeyMap::from_toml("[normal]\n\"w\" = \"MoveUp\"\n\"x\" = \"Explode\"\n").err().unwrap();
+        assert_eq!(error, "line 3: unknown action `Explode` for key `x`");
+        let error = KeyMap::from_toml("[visual]\n\"w\" = \"MoveUp\"\n").err().unwrap();
+        assert_eq!(error, "line 1: unknown mode `visual`");
+    }
+
+    #[test]
+    fn read_config_file() {
+        let path = std::env::temp_dir().join(format!("trout_keymap_{}.toml", std::process::id()));
+        std::fs::write(&path, "[normal]\n\"Tab\" = \"EnterInsert\"\n").unwrap();
+        let key_map = KeyMap::from_config(&path).unwrap();
+        assert_eq!(key_map.normal["Tab"], ActionType::EnterInsert);
+        std::fs::remove_file(&path).unwrap();
+        assert!(KeyMap::from_config(&path).is_err());
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+use crossterm::event::KeyEvent;
+use crate::terminal::controls::Terminal;
+
+/// Number of macros which can be started by replayed keys, without a key being typed in
+/// between, before replaying is stopped. This stops a macro which plays itself from running
+/// forever.
+const MAX_NESTED_PLAYS: usize = 100;
+
+/// Key presses recorded into registers, to be replayed as if they were typed again
+#[derive(Debug)]
+pub struct Macros {
+    recorded: HashMap<char, Vec<KeyEvent>>,
+    /// The register being recorded into, if recording
+    recording: Option<char>,
+    /// The register last played, which `@@` plays again
+    last_played: Option<char>,
+    /// Macros played by replayed keys since a key was last typed
+    nested_plays: usize,
+}
+
+impl Macros {
+    pub fn new() -> Macros {
+        Macros {
+            recorded: HashMap::new(),
+            recording: None,
+            last_played: None,
+            nested_plays: 0,
+        }
+    }
+
+    /// The register being recorded into, if recording
+    pub fn recording(&self) -> Option<char> {
+        self.recording
+    }
+
+    /// Start recording the keys read into the register, replacing what it held
+    pub fn start(&mut self, register: char) {
+        self.recording = Some(register);
+        Terminal::start_recording();
+    }
+
+    /// Stop recording, keeping every key read but the last, which is the one that stopped
+    /// the recording. Returns the register recorded into, if recording.
+    pub fn stop(&mut self) -> Option<char> {
+        let register = self.recording.take()?;
+        let mut keys = Terminal::stop_recording();
+        keys.pop();
+        self.recorded.insert(register, keys);
+        Some(register)
+    }
+
+    /// The keys recorded into the register
+    #[cfg(test)]
+    pub fn get(&self, register: char) -> Option<&[KeyEvent]> {
+        self.recorded.get(&register).map(Vec::as_slice)
+    }
+
+    /// Replay the keys recorded into the register (or the last one played) count times, by
+    /// having them read before anything else typed
+    pub fn play(&mut self, register: Option<char>, count: usize) -> Result<(), String> {
+        let register = register.or(self.last_played).ok_or("no macro has been played yet")?;
+        let keys = self.recorded.get(&register).ok_or(format!("nothing is recorded in @{register}"))?;
+        self.nested_plays = if Terminal::replaying() { self.nested_plays + 1 } else { 0 };
+        if self.nested_plays >= MAX_NESTED_PLAYS {
+            Terminal::cancel_replay();
+            self.nested_plays = 0;
+            return Err(format!("@{register} keeps playing itself, stopped replaying"));
+        }
+        self.last_played = Some(register);
+        Terminal::replay(&keys.repeat(count));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::{Event, KeyCode};
+
+    fn key(c: char) -> KeyEvent {
+        KeyEvent::from(KeyCode::Char(c))
+    }
+
+    #[test]
+    fn record_and_play() {
+        let mut macros = Macros::new();
+        let typed = ['s', 'd', 'Q'].map(|c| Event::Key(key(c))).to_vec();
+        Terminal::script_input(typed, || {
+            macros.start('a');
+            for _ in 0..3 {
+                Terminal::read_event().unwrap();
+            }
+            assert_eq!(macros.stop(), Some('a'));
+        });
+        assert_eq!(macros.get('a'), Some([key('s'), key('d')].as_slice()));
+
+        Terminal::script_input(Vec::new(), || {
+            macros.play(Some('a'), 2).unwrap();
+            let replayed: Vec<Event> = (0..4).map(|_| Terminal::read_event().unwrap()).collect();
+            assert_eq!(replayed, ['s', 'd', 's', 'd'].map(|c| Event::Key(key(c))));
+            assert!(Terminal::replaying());
+            // `@@` plays the last macro again
+            macros.play(None, 1).unwrap();
+            assert_eq!(Terminal::read_event().unwrap(), Event::Key(key('s')));
+            Terminal::cancel_replay();
+            assert!(Terminal::read_event().is_err());
+            assert!(macros.play(Some('b'), 1).is_err());
+        });
+    }
+
+    #[test]
+    fn stop_macro_playing_itself() {
+        let mut macros = Macros::new();
+        macros.recorded.insert('a', vec![key('@'), key('a')]);
+        // Each time the replayed keys play the macro again, until it is stopped
+        let plays = Terminal::script_input(Vec::new(), || {
+            macros.play(Some('a'), 1).unwrap();
+            let mut plays = 1;
+            while Terminal::read_event().is_ok() && Terminal::read_event().is_ok() {
+                if macros.play(Some('a'), 1).is_err() {
+                    break;
+                }
+                plays += 1;
+            }
+            // The rest of the replay was dropped
+            assert!(Terminal::read_event().is_err());
+            plays
+        });
+        assert_eq!(plays, MAX_NESTED_PLAYS);
+    }
+}
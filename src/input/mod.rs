@@ -1,2 +1,3 @@
 mod keyboard;
-mod keymap;
\ No newline at end of file
+pub mod keymap;
+pub mod macros;
\ No newline at end of file
@@ -1,5 +1,7 @@
-use std::io::Write;
-use regex::Regex;
+use std::io::{stdin, IsTerminal};
+use std::path::PathBuf;
+use crate::editor::Editor;
+use crate::textbuffer::buffer::Buffer;
 
 mod editor;
 mod view;
@@ -7,13 +9,33 @@ mod textbuffer;
 mod commands;
 mod terminal;
 mod input;
+mod settings;
 
 fn main() {
-   //let editor = Editor::default();
-    let word_regex = Regex::new(r"\w|[(){}\-+&=]").unwrap();
-    let test = "fn test_function{println!(\"Hello World\")}";
-    match word_regex.find_iter(test).last(){
-        None => {}
-        Some(m) => {println!("{}", m.start())}
+    let mut arguments = std::env::args_os().skip(1).peekable();
+    // `-R` opens the file read-only
+    let readonly = arguments.next_if(|argument| argument == "-R").is_some();
+    let argument = arguments.next();
+    // `-`, or no path with input piped in, edits the input. It has to be read in full now,
+    // before the editor puts the terminal into raw mode.
+    let piped = match &argument {
+        Some(argument) => argument == "-",
+        None => !stdin().is_terminal(),
+    };
+    let input = if piped {
+        match Buffer::from_reader(stdin().lock()) {
+            Ok(buffer) => Some(buffer),
+            Err(error) => {
+                eprintln!("trout: couldn't read stdin: {error}");
+                return;
+            }
+        }
+    } else {
+        None
+    };
+    let path = argument.filter(|_| !piped).map(PathBuf::from);
+    let mut editor = Editor::new(path.as_deref(), input, readonly);
+    if let Err(error) = editor.run() {
+        eprintln!("trout: {error}");
     }
 }
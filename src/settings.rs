@@ -0,0 +1,350 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+use crate::terminal::controls::CursorStyle;
+use crate::textbuffer::search::SearchOptions;
+use crate::view::screen::Mode;
+
+/// How files of a particular type are opened
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileTypeSettings {
+    /// Mode the screen starts in, rather than normal mode
+    pub initial_mode: Option<Mode>,
+    /// Whether the buffer is opened read-only
+    pub readonly: bool,
+}
+
+/// Glyphs drawn in place of whitespace in list mode, where `None` leaves it blank
+#[derive(Clone, Debug, PartialEq)]
+pub struct ListChars {
+    /// Drawn at the start of each tab, which is padded out to the tab width
+    pub tab: Option<char>,
+    /// Drawn for spaces after the last non-whitespace grapheme of a line
+    pub trail: Option<char>,
+    /// Drawn for the other spaces
+    pub space: Option<char>,
+    /// Drawn just after the end of a line
+    pub eol: Option<char>,
+}
+
+impl ListChars {
+    /// Create the default glyphs, showing tabs, trailing spaces and line ends
+    pub fn default() -> ListChars {
+        Self { tab: Some('→'), trail: Some('·'), space: None, eol: Some('¶') }
+    }
+
+    /// Parse the glyphs from a comma separated list of `name:glyph` entries, as given to
+    /// `:set listchars=`. Whitespace without an entry is left blank.
+    pub fn parse(value: &str) -> Result<ListChars, String> {
+        let mut chars = Self { tab: None, trail: None, space: None, eol: None };
+        for entry in value.split(',').filter(|entry| !entry.is_empty()) {
+            let invalid = || format!("Invalid listchars entry: {entry}");
+            let (name, glyph) = entry.split_once(':').ok_or_else(invalid)?;
+            let mut glyphs = glyph.chars();
+            let glyph = match (glyphs.next(), glyphs.next()) {
+                (Some(glyph), None) => glyph,
+                _ => return Err(invalid()),
+            };
+            let slot = match name {
+                "tab" => &mut chars.tab,
+                "trail" => &mut chars.trail,
+                "space" => &mut chars.space,
+                "eol" => &mut chars.eol,
+                _ => return Err(invalid()),
+            };
+            *slot = Some(glyph);
+        }
+        Ok(chars)
+    }
+}
+
+/// User configurable options controlling how a screen behaves and is displayed
+#[derive(Clone, Debug)]
+pub struct Settings {
+    /// Whether long lines are wrapped across multiple visual rows
+    pub wrap: bool,
+    /// Lines with more graphemes than this skip highlighting and wrapping
+    pub overlong_threshold: usize,
+    /// Column (counting from 1) at which to draw a vertical ruler
+    pub ruler: Option<usize>,
+    /// Whether whitespace and line ends are drawn with visible glyphs
+    pub list: bool,
+    /// The glyphs drawn for whitespace and line ends in list mode
+    pub list_chars: ListChars,
+    /// Whether the bracket matching the one under the cursor is highlighted
+    pub match_brackets: bool,
+    /// Whether a bracket under the cursor without a match is shown in a warning color
+    pub warn_unmatched_brackets: bool,
+    /// Whether prose files (`.md`, `.txt`) are spell checked
+    pub spell: bool,
+    /// Word list used for spell checking
+    pub spell_dictionary: PathBuf,
+    /// Word list which words added to the dictionary are saved to
+    pub spell_personal: Option<PathBuf>,
+    /// Minimum number of columns used for line numbers in the gutter
+    pub gutter_min_width: usize,
+    /// Whether line numbers are shown relative to the cursor's line
+    pub relative_numbers: bool,
+    /// Whether a modified buffer is written to its file when the terminal loses focus
+    pub autosave_on_focus_lost: bool,
+    /// Whether the file is checked for outside changes when the terminal gains focus
+    pub check_disk_on_focus: bool,
+    /// Character drawn on rows past the end of the buffer
+    pub empty_line_char: char,
+    /// Character drawn in the first column of lines with text scrolled off to the left
+    pub overflow_left_char: char,
+    /// Character drawn in the last column of lines with text past the right edge
+    pub overflow_right_char: char,
+    /// Whether an `[EOF]` marker is drawn on the row after the last line
+    pub eof_marker: bool,
+    /// Cursor shown in normal mode, and the other modes which move around the text
+    pub normal_cursor: CursorStyle,
+    /// Cursor shown in insert and command modes, where text is typed
+    pub insert_cursor: CursorStyle,
+    /// Number of columns a tab character spans
+    pub tab_width: usize,
+    /// Whether typing an opening bracket or quote in insert mode also inserts its closing
+    /// partner. Off by default, as pasting into a terminal without bracketed paste would
+    /// double the closing characters.
+    pub auto_pairs: bool,
+    /// The opening and closing characters inserted together by `auto_pairs`
+    pub pairs: Vec<(char, char)>,
+    /// Whether new lines started with Enter, `o` or `O` get the indentation of the line they
+    /// were started from
+    pub auto_indent: bool,
+    /// Whether indentation is made of spaces rather than tabs
+    pub expand_tab: bool,
+    /// Number of columns lines are indented or dedented by with `>` and `<`
+    pub shift_width: usize,
+    /// Whether lines holding only whitespace separate paragraphs for `{` and `}`, rather
+    /// than only empty lines
+    pub whitespace_is_blank: bool,
+    /// Width paragraphs are wrapped to by `gq`
+    pub text_width: usize,
+    /// Whether whitespace at the end of lines is highlighted
+    pub highlight_trailing_whitespace: bool,
+    /// Whether whitespace at the end of lines is removed when the buffer is written
+    pub trim_trailing_whitespace: bool,
+    /// Whether the cursor can move past the end of lines, with typing there padding the
+    /// line with spaces
+    pub virtual_edit: bool,
+    /// Whether the gutter marks lines changed since the buffer was last saved
+    pub sign_column: bool,
+    /// Whether undo history is saved alongside files, so it survives restarting the editor
+    pub undo_file: bool,
+    /// Directory undo history is saved to
+    pub undo_directory: PathBuf,
+    /// How files are opened, by their extension
+    pub file_types: HashMap<String, FileTypeSettings>,
+    /// Mode the screen starts in when opening a file which doesn't exist yet
+    pub new_file_mode: Mode,
+    /// Whether unsaved edits are kept in a swap file, so they can be recovered after a crash
+    pub swap_file: bool,
+    /// Directory swap files are kept in, rather than alongside the file
+    pub swap_directory: Option<PathBuf>,
+    /// Least time between updates of the swap file
+    pub swap_interval: Duration,
+    /// Whether searches match letters regardless of case
+    pub ignore_case: bool,
+    /// Whether searches without upper case letters match regardless of case
+    pub smart_case: bool,
+    /// Whether searches only match whole words
+    pub whole_word: bool,
+    /// Number of lines kept visible above and below the cursor, where the buffer allows
+    pub scroll_off: usize,
+    /// Size in bytes from which files are paged in as they are viewed, rather than read whole
+    pub large_file_size: usize,
+}
+
+impl Settings {
+    /// Create the default settings
+    pub fn default() -> Settings {
+        Self {
+            wrap: false,
+            overlong_threshold: 10_000,
+            ruler: None,
+            list: false,
+            list_chars: ListChars::default(),
+            match_brackets: true,
+            warn_unmatched_brackets: false,
+            spell: false,
+            spell_dictionary: PathBuf::from("/usr/share/dict/words"),
+            spell_personal: None,
+            gutter_min_width: 3,
+            relative_numbers: false,
+            autosave_on_focus_lost: false,
+            check_disk_on_focus: true,
+            empty_line_char: '~',
+            overflow_left_char: '<',
+            overflow_right_char: '>',
+            eof_marker: false,
+            normal_cursor: CursorStyle::BlinkingBlock,
+            insert_cursor: CursorStyle::SteadyBar,
+            tab_width: 4,
+            auto_pairs: false,
+            pairs: vec![('(', ')'), ('[', ']'), ('{', '}'), ('"', '"'), ('\'', '\'')],
+            auto_indent: true,
+            expand_tab: true,
+            shift_width: 4,
+            whitespace_is_blank: true,
+            text_width: 79,
+            highlight_trailing_whitespace: false,
+            trim_trailing_whitespace: false,
+            virtual_edit: false,
+            sign_column: false,
+            undo_file: false,
+            undo_directory: std::env::var_os("HOME")
+                .map(PathBuf::from)
+                .unwrap_or_default()
+                .join(".local/state/trout/undo"),
+            file_types: HashMap::from([
+                ("log".to_string(), FileTypeSettings { initial_mode: None, readonly: true }),
+            ]),
+            new_file_mode: Mode::Normal,
+            swap_file: true,
+            swap_directory: None,
+            swap_interval: Duration::from_secs(4),
+            ignore_case: false,
+            smart_case: false,
+            whole_word: false,
+            scroll_off: 0,
+            large_file_size: 256 << 20,
+        }
+    }
+
+    /// How search patterns are matched
+    pub fn search_options(&self) -> SearchOptions {
+        SearchOptions { ignore_case: self.ignore_case, smart_case: self.smart_case, whole_word: self.whole_word }
+    }
+
+    /// How a file with the extension should be opened, if it is configured
+    pub fn file_type(&self, extension: Option<&str>) -> Option<&FileTypeSettings> {
+        self.file_types.get(extension?)
+    }
+
+    /// Change a setting, as given to `:set`: `name` turns a switch on and `noname` turns it
+    /// off, while `name=value` sets a number, or a cursor style like `insert_cursor=blinking_bar`
+    pub fn set(&mut self, assignment: &str) -> Result<(), String> {
+        if let Some((name, value)) = assignment.split_once('=') {
+            if matches!(name.trim(), "list_chars" | "listchars") {
+                self.list_chars = ListChars::parse(value.trim())?;
+                return Ok(());
+            }
+            if let Some(cursor) = self.cursor(name.trim()) {
+                *cursor = CursorStyle::parse(value.trim())?;
+                return Ok(());
+            }
+            let value = value.trim().parse::<usize>().map_err(|_| format!("Invalid number: {value}"))?;
+            let option = self.number(name.trim()).ok_or(format!("Unknown option: {name}"))?;
+            *option = value;
+            return Ok(());
+        }
+        let (name, value) = match assignment.strip_prefix("no") {
+            Some(name) if self.switch(name).is_some() => (name, false),
+            _ => (assignment, true),
+        };
+        let option = self.switch(name).ok_or(format!("Unknown option: {name}"))?;
+        *option = value;
+        Ok(())
+    }
+
+    /// The switch (on or off) setting with the name used by `:set`
+    fn switch(&mut self, name: &str) -> Option<&mut bool> {
+        Some(match name {
+            "wrap" => &mut self.wrap,
+            "list" => &mut self.list,
+            "match_brackets" => &mut self.match_brackets,
+            "warn_unmatched_brackets" => &mut self.warn_unmatched_brackets,
+            "spell" => &mut self.spell,
+            "relative_numbers" => &mut self.relative_numbers,
+            "eof_marker" => &mut self.eof_marker,
+            "auto_indent" | "autoindent" => &mut self.auto_indent,
+            "expand_tab" | "expandtab" => &mut self.expand_tab,
+            "highlight_trailing_whitespace" => &mut self.highlight_trailing_whitespace,
+            "trim_trailing_whitespace" => &mut self.trim_trailing_whitespace,
+            "virtual_edit" => &mut self.virtual_edit,
+            "sign_column" => &mut self.sign_column,
+            "ignore_case" | "ignorecase" => &mut self.ignore_case,
+            "smart_case" | "smartcase" => &mut self.smart_case,
+            "whole_word" => &mut self.whole_word,
+            "auto_pairs" | "autopairs" => &mut self.auto_pairs,
+            "whitespace_is_blank" => &mut self.whitespace_is_blank,
+            _ => return None,
+        })
+    }
+
+    /// The number setting with the name used by `:set`
+    fn number(&mut self, name: &str) -> Option<&mut usize> {
+        Some(match name {
+            "tab_width" => &mut self.tab_width,
+            "text_width" => &mut self.text_width,
+            "gutter_min_width" => &mut self.gutter_min_width,
+            "scroll_off" | "scrolloff" => &mut self.scroll_off,
+            "shift_width" | "shiftwidth" => &mut self.shift_width,
+            "large_file_size" => &mut self.large_file_size,
+            _ => return None,
+        })
+    }
+
+    /// The cursor style setting with the name used by `:set`
+    fn cursor(&mut self, name: &str) -> Option<&mut CursorStyle> {
+        Some(match name {
+            "normal_cursor" => &mut self.normal_cursor,
+            "insert_cursor" => &mut self.insert_cursor,
+            _ => return None,
+        })
+    }
+
+    /// The whitespace one level of indentation adds: `shift_width` spaces, or with
+    /// `expand_tab` off, as many tabs as fit followed by spaces for the rest
+    pub fn indent_unit(&self) -> String {
+        if self.expand_tab {
+            return " ".repeat(self.shift_width);
+        }
+        let tab_width = self.tab_width.max(1);
+        format!("{}{}", "\t".repeat(self.shift_width / tab_width), " ".repeat(self.shift_width % tab_width))
+    }
+
+    /// The cursor style to show in a mode
+    pub fn cursor_style(&self, mode: &Mode) -> CursorStyle {
+        match mode {
+            Mode::Insert | Mode::Command | Mode::Search => self.insert_cursor,
+            _ => self.normal_cursor,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_options() {
+        let mut settings = Settings::default();
+        settings.set("trim_trailing_whitespace").unwrap();
+        assert!(settings.trim_trailing_whitespace);
+        settings.set("notrim_trailing_whitespace").unwrap();
+        assert!(!settings.trim_trailing_whitespace);
+        settings.set("tab_width=8").unwrap();
+        assert_eq!(settings.tab_width, 8);
+        assert!(settings.set("tab_width=wide").is_err());
+        assert!(settings.set("nothing").is_err());
+        settings.set("shiftwidth=2").unwrap();
+        assert_eq!(settings.indent_unit(), "  ");
+        settings.set("noexpandtab").unwrap();
+        settings.set("shift_width=10").unwrap();
+        assert_eq!(settings.indent_unit(), "\t  ");
+        settings.set("listchars=tab:>,space:_").unwrap();
+        assert_eq!(settings.list_chars, ListChars { tab: Some('>'), trail: None, space: Some('_'), eol: None });
+        assert!(settings.set("listchars=tab:>>").is_err());
+        assert!(settings.set("listchars=nbsp:+").is_err());
+        settings.set("ignorecase").unwrap();
+        settings.set("smartcase").unwrap();
+        assert_eq!(settings.search_options(), SearchOptions { ignore_case: true, smart_case: true, whole_word: false });
+        settings.set("insert_cursor=blinking_underline").unwrap();
+        settings.set("normal_cursor=steady_block").unwrap();
+        assert_eq!(settings.cursor_style(&Mode::Insert), CursorStyle::BlinkingUnderline);
+        assert_eq!(settings.cursor_style(&Mode::Normal), CursorStyle::SteadyBlock);
+        assert!(settings.set("insert_cursor=beam").is_err());
+    }
+}
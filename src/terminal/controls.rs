@@ -1,9 +1,12 @@
 use crossterm::cursor::{Hide, MoveTo, SetCursorStyle, Show};
+use crossterm::event::{DisableFocusChange, EnableFocusChange, Event, KeyCode, KeyEvent, KeyEventKind, read};
 use crossterm::style::Print;
 use crossterm::terminal::{Clear, ClearType, disable_raw_mode, enable_raw_mode, EnterAlternateScreen,
                           LeaveAlternateScreen, size};
 use crossterm::{Command, queue};
-use std::io::{Error, stdout, Write};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind, stdout, Write};
 use crate::terminal::screen_location::ScreenLocation;
 
 /// Struct representing the current size of the visible screen
@@ -13,6 +16,57 @@ pub struct Size {
     pub width: usize,
 }
 
+/// Shape of the cursor, and whether it blinks
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CursorStyle {
+    SteadyBlock,
+    BlinkingBlock,
+    SteadyBar,
+    BlinkingBar,
+    SteadyUnderline,
+    BlinkingUnderline,
+}
+
+impl CursorStyle {
+    /// The style with the name used by `:set`, like `blinking_bar` or `steady_underline`
+    pub fn parse(name: &str) -> Result<CursorStyle, String> {
+        Ok(match name {
+            "steady_block" => CursorStyle::SteadyBlock,
+            "blinking_block" => CursorStyle::BlinkingBlock,
+            "steady_bar" => CursorStyle::SteadyBar,
+            "blinking_bar" => CursorStyle::BlinkingBar,
+            "steady_underline" => CursorStyle::SteadyUnderline,
+            "blinking_underline" => CursorStyle::BlinkingUnderline,
+            _ => return Err(format!("Invalid cursor style: {name}")),
+        })
+    }
+
+    /// The crossterm command which sets the cursor to this style
+    pub fn command(self) -> SetCursorStyle {
+        match self {
+            CursorStyle::SteadyBlock => SetCursorStyle::SteadyBlock,
+            CursorStyle::BlinkingBlock => SetCursorStyle::BlinkingBlock,
+            CursorStyle::SteadyBar => SetCursorStyle::SteadyBar,
+            CursorStyle::BlinkingBar => SetCursorStyle::BlinkingBar,
+            CursorStyle::SteadyUnderline => SetCursorStyle::SteadyUnderScore,
+            CursorStyle::BlinkingUnderline => SetCursorStyle::BlinkingUnderScore,
+        }
+    }
+}
+
+thread_local! {
+    /// Where output is collected instead of stdout while capturing, for headless rendering
+    static CAPTURED_OUTPUT: RefCell<Option<Vec<u8>>> = const { RefCell::new(None) };
+    /// Events read instead of the terminal's while input is scripted
+    static SCRIPTED_INPUT: RefCell<Option<VecDeque<Event>>> = const { RefCell::new(None) };
+    /// Replayed key presses, read before any other input
+    static REPLAYED_INPUT: RefCell<VecDeque<Event>> = const { RefCell::new(VecDeque::new()) };
+    /// Whether the last event read was replayed
+    static LAST_REPLAYED: Cell<bool> = const { Cell::new(false) };
+    /// Key presses read while recording, not counting replayed ones
+    static RECORDED_INPUT: RefCell<Option<Vec<KeyEvent>>> = const { RefCell::new(None) };
+}
+
 /// Represents the Terminal, and implements methods for interacting
 /// with the terminal more easily
 pub struct Terminal;
@@ -20,22 +74,44 @@ pub struct Terminal;
 impl Terminal {
     /// End the current terminal session, leaving alternate screen, and ensuring caret isn't hidden
     pub fn terminate() -> Result<(), Error> {
+        Self::queue_command(DisableFocusChange)?;
         Self::leave_alternate_screen()?;
         Self::show_caret()?;
         Self::execute()?;
-        disable_raw_mode()?;
+        if !Self::capturing() {
+            disable_raw_mode()?;
+        }
         Ok(())
     }
 
     /// Begin terminal session, entering alternate screen and clearing it
     pub fn initialize() -> Result<(), Error> {
-        enable_raw_mode()?;
+        if !Self::capturing() {
+            enable_raw_mode()?;
+        }
         Self::enter_alternate_screen()?;
+        // Report focus changes, so buffers can be autosaved when focus is lost
+        Self::queue_command(EnableFocusChange)?;
         Self::clear_screen()?;
         Self::execute()?;
         Ok(())
     }
 
+    /// Hand the terminal back to the shell and stop the editor, as with Ctrl-Z. The terminal
+    /// is set up again once the shell resumes the editor, after which it should be redrawn.
+    #[cfg(unix)]
+    pub fn suspend() -> Result<(), Error> {
+        Self::terminate()?;
+        // Raw mode stops Ctrl-Z from sending SIGTSTP itself, so send it here. This blocks
+        // until the process is continued with SIGCONT.
+        if !Self::capturing() && unsafe { libc::raise(libc::SIGTSTP) } != 0 {
+            let error = Error::last_os_error();
+            Self::initialize()?;
+            return Err(error);
+        }
+        Self::initialize()
+    }
+
     /// Clear the current screen
     pub fn clear_screen() -> Result<(), Error> {
         Self::queue_command(Clear(ClearType::All))?;
@@ -91,6 +167,7 @@ impl Terminal {
     }
 
     /// Print a string to a particular row
+    #[cfg(test)]
     pub fn print_row(row:usize, line_text: &str)->Result<(), Error> {
         Self::move_caret_to(ScreenLocation{row, col:0})?;
         Self::clear_line()?;
@@ -106,26 +183,200 @@ impl Terminal {
         Ok(Size {height, width})
     }
 
-    /// Set the Cursor to be a steady bar
-    pub fn bar_cursor()->Result<(), Error>{
-        Self::queue_command(SetCursorStyle::SteadyBar)?;
-        Ok(())
+    /// Ask the terminal where its cursor actually is, for recovering after something else
+    /// has written to the screen. Fails if the terminal doesn't answer in time, and while
+    /// output is being captured, as there is no terminal to ask.
+    #[cfg(test)]
+    pub fn cursor_position() -> Result<ScreenLocation, Error> {
+        if Self::capturing() {
+            return Err(Error::new(ErrorKind::Unsupported, "Output is being captured"));
+        }
+        let (col, row) = crossterm::cursor::position().map_err(|error| {
+            Error::new(error.kind(), format!("Terminal did not report the cursor position: {error}"))
+        })?;
+        Ok(ScreenLocation::from_terminal(col, row))
     }
 
-    pub fn blinking_block_cursor()-> Result<(), Error>{
-        Self::queue_command(SetCursorStyle::BlinkingBlock)?;
+    /// Set the shape and blinking of the Cursor
+    pub fn set_cursor_style(style: CursorStyle)->Result<(), Error>{
+        Self::queue_command(style.command())?;
         Ok(())
     }
 
     /// Execute the queued commands
     pub fn execute() -> Result<(), Error> {
+        if Self::capturing() {
+            return Ok(());
+        }
         stdout().flush()?;
         Ok(())
     }
 
+    /// Run f with everything written to the terminal (on this thread) collected in memory
+    /// instead of sent to stdout, returning what was written
+    #[cfg(test)]
+    pub fn capture<F: FnOnce()>(f: F) -> String {
+        let previous = CAPTURED_OUTPUT.with(|output| output.replace(Some(Vec::new())));
+        f();
+        let captured = CAPTURED_OUTPUT.with(|output| output.replace(previous)).unwrap_or_default();
+        String::from_utf8_lossy(&captured).to_string()
+    }
+
+    /// Wait for a key to be pressed in answer to a prompt, returning it as a character.
+    /// Escape gives None, like cancelling.
+    pub fn read_answer() -> Option<char> {
+        loop {
+            match Terminal::read_event() {
+                Ok(Event::Key(KeyEvent { code: KeyCode::Char(c), kind: KeyEventKind::Press, .. })) => return Some(c),
+                Ok(Event::Key(KeyEvent { code: KeyCode::Esc, kind: KeyEventKind::Press, .. })) | Err(_) => return None,
+                _ => {}
+            }
+        }
+    }
+
+    /// Wait for the next input event (key press, resize, etc.)
+    pub fn read_event() -> Result<Event, Error> {
+        let replayed = REPLAYED_INPUT.with(|input| input.borrow_mut().pop_front());
+        LAST_REPLAYED.with(|last| last.set(replayed.is_some()));
+        if let Some(event) = replayed {
+            return Ok(event);
+        }
+        let scripted = SCRIPTED_INPUT.with(|input| input.borrow_mut().as_mut().map(VecDeque::pop_front));
+        let event = match scripted {
+            Some(Some(event)) => event,
+            Some(None) => return Err(Error::new(ErrorKind::UnexpectedEof, "Scripted input ran out")),
+            None => read()?,
+        };
+        if let Event::Key(key @ KeyEvent { kind: KeyEventKind::Press, .. }) = &event {
+            RECORDED_INPUT.with(|recorded| {
+                if let Some(recorded) = recorded.borrow_mut().as_mut() {
+                    recorded.push(*key);
+                }
+            });
+        }
+        Ok(event)
+    }
+
+    /// Start keeping the key presses read, until `stop_recording`
+    pub fn start_recording() {
+        RECORDED_INPUT.with(|recorded| recorded.replace(Some(Vec::new())));
+    }
+
+    /// Stop recording, returning the key presses read since recording started
+    pub fn stop_recording() -> Vec<KeyEvent> {
+        RECORDED_INPUT.with(|recorded| recorded.take()).unwrap_or_default()
+    }
+
+    /// Have the key presses read next, ahead of any which are already waiting to be replayed
+    pub fn replay(keys: &[KeyEvent]) {
+        REPLAYED_INPUT.with(|input| {
+            let mut input = input.borrow_mut();
+            for key in keys.iter().rev() {
+                input.push_front(Event::Key(*key));
+            }
+        });
+    }
+
+    /// Whether the last event read was replayed rather than typed
+    pub fn replaying() -> bool {
+        LAST_REPLAYED.with(Cell::get)
+    }
+
+    /// Drop any key presses still waiting to be replayed
+    pub fn cancel_replay() {
+        REPLAYED_INPUT.with(|input| input.borrow_mut().clear());
+    }
+
+    /// Run f with `read_event` (on this thread) returning the given events in turn instead of
+    /// reading the terminal, returning f's result
+    #[cfg(test)]
+    pub fn script_input<T, F: FnOnce() -> T>(events: Vec<Event>, f: F) -> T {
+        let previous = SCRIPTED_INPUT.with(|input| input.replace(Some(events.into())));
+        let result = f();
+        SCRIPTED_INPUT.with(|input| input.replace(previous));
+        result
+    }
+
+    /// Whether output is being captured rather than sent to the terminal
+    fn capturing() -> bool {
+        CAPTURED_OUTPUT.with(|output| output.borrow().is_some())
+    }
+
     /// Add a command to the Command Queue
     fn queue_command<T:Command>(command:T) -> Result<(), Error> {
-        queue!(stdout(), command)?;
+        CAPTURED_OUTPUT.with(|output| match output.borrow_mut().as_mut() {
+            Some(buffer) => queue!(buffer, command),
+            None => queue!(stdout(), command),
+        })?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::{KeyCode, KeyEvent};
+
+    #[test]
+    fn scripted_input() {
+        let key = Event::Key(KeyEvent::from(KeyCode::Char('x')));
+        let events = Terminal::script_input(vec![key.clone(), Event::FocusLost], || {
+            (0..3).map(|_| Terminal::read_event().ok()).collect::<Vec<_>>()
+        });
+        assert_eq!(events, [Some(key), Some(Event::FocusLost), None]);
+    }
+
+    #[test]
+    fn cursor_style_commands() {
+        let expected = [
+            (CursorStyle::BlinkingBlock, "\x1b[1 q"),
+            (CursorStyle::SteadyBlock, "\x1b[2 q"),
+            (CursorStyle::BlinkingUnderline, "\x1b[3 q"),
+            (CursorStyle::SteadyUnderline, "\x1b[4 q"),
+            (CursorStyle::BlinkingBar, "\x1b[5 q"),
+            (CursorStyle::SteadyBar, "\x1b[6 q"),
+        ];
+        for (style, code) in expected {
+            let mut ansi = String::new();
+            style.command().write_ansi(&mut ansi).unwrap();
+            assert_eq!(ansi, code, "{style:?}");
+        }
+    }
+
+    #[test]
+    fn cursor_position() {
+        // Crossterm reports (column, row)
+        let location = ScreenLocation::from_terminal(7, 3);
+        assert_eq!((location.row, location.col), (3, 7));
+        let result = Terminal::capture(|| {
+            assert_eq!(Terminal::cursor_position().unwrap_err().kind(), ErrorKind::Unsupported);
+        });
+        assert_eq!(result, "");
+    }
+
+    #[test]
+    fn print_row_replaces_row() {
+        let output = Terminal::capture(|| Terminal::print_row(2, "text").unwrap());
+        assert!(output.starts_with("\x1b[3;1H"));
+        assert!(output.ends_with("text"));
+    }
+
+    #[test]
+    fn terminate_undoes_initialize() {
+        let output = Terminal::capture(|| {
+            Terminal::initialize().unwrap();
+            Terminal::terminate().unwrap();
+        });
+        let (initialize, terminate) = output.split_once("\x1b[?1004l").unwrap();
+        assert!(initialize.starts_with("\x1b[?1049h\x1b[?1004h"));
+        assert_eq!(terminate, "\x1b[?1049l\x1b[?25h");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn suspend_restores_terminal() {
+        let output = Terminal::capture(|| Terminal::suspend().unwrap());
+        assert!(output.starts_with("\x1b[?1004l\x1b[?1049l\x1b[?25h"));
+        assert!(output.ends_with("\x1b[?1049h\x1b[?1004h\x1b[2J"));
+    }
+}
@@ -33,4 +33,9 @@ impl ScreenLocation {
     pub fn default()->Self {
         Self {row:0, col:0}
     }
+
+    /// Create a location from a position reported by the terminal, which puts the column first
+    pub fn from_terminal(col: u16, row: u16)->Self {
+        Self {row: row as usize, col: col as usize}
+    }
 }
\ No newline at end of file
@@ -1,20 +1,92 @@
-use std::fs::{File, read_to_string};
-use std::io::{Error, Write};
-use std::path::PathBuf;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fs::{canonicalize, create_dir_all, File, metadata, read_to_string, remove_file};
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use once_cell::sync::Lazy;
 use regex::{Match, Regex};
+use unicode_segmentation::UnicodeSegmentation;
+use crate::textbuffer::changes::{ChangeKind, diff_lines};
 use crate::textbuffer::lines::Line;
+use crate::textbuffer::rope::LineRope;
 use crate::textbuffer::text_location::TextPosition;
+use crate::textbuffer::undo::{hash_lines, LineChange, UndoTree};
+
+/// Regions of text which can be selected around a position, each without its surroundings
+/// (the inner objects of vim's `iw`, `ip` and `i(`)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextObject {
+    /// A run of word characters, whitespace, or punctuation
+    Word,
+    /// A line, without its leading and trailing whitespace
+    Line,
+    /// A run of non-blank (or blank) lines
+    Paragraph,
+    /// The text inside the enclosing pair of brackets, given by its opening bracket
+    Bracket(char),
+}
+
+/// Swap files written by buffers and not yet removed, so they can be cleaned up even if the
+/// editor panics
+static SWAP_FILES: Lazy<Mutex<HashSet<PathBuf>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+/// Counts of what a buffer's text is made of
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BufferStats {
+    pub lines: usize,
+    pub words: usize,
+    pub graphemes: usize,
+    /// Bytes of text, not counting line breaks
+    pub bytes: usize,
+}
 
 /// A text buffer, representing a collection of lines of text
 pub struct Buffer {
-    /// A vector of lines representing the text
-    pub text: Vec<Line>,
+    /// The lines of the text, in a rope so edits to large files stay fast
+    pub text: LineRope,
     /// The file extension (used for syntax highlighting)
     pub extension: Option<String>,
     /// Path to where to output the buffer
     pub path: PathBuf,
     /// Number of lines within the buffer
     pub num_lines: usize,
+    /// Whether the buffer has been changed since it was last written
+    pub modified: bool,
+    /// Whether the buffer is only for viewing, so edits to it are refused
+    pub readonly: bool,
+    /// Whether an edit was refused because the buffer is read-only, since this was last cleared
+    pub edit_refused: bool,
+    /// Why the file couldn't be read as it is on disk, to be reported once. Such a buffer is
+    /// made read-only, so writing it back can't lose what wasn't read.
+    pub load_problem: Option<String>,
+    /// Incremented on every edit, so cached information about the text can tell it is stale
+    pub version: u64,
+    /// Modification time of the file when it was last read or written
+    disk_modified: Option<SystemTime>,
+    /// The state of the undo tree when the buffer was last read or written
+    saved_state: usize,
+    /// Change status of the lines which differ from the saved text, from the first of them,
+    /// along with the version they were found for
+    change_cache: RefCell<Option<(u64, usize, Vec<ChangeKind>)>>,
+    /// History of edits, for undo and redo
+    pub undo_tree: UndoTree,
+    /// Directory the undo history is saved to alongside the file, when it is persisted
+    pub undo_directory: Option<PathBuf>,
+    /// Where the text is kept while it has unsaved edits, so it can be recovered after a crash
+    pub swap_path: Option<PathBuf>,
+    /// Version of the text last written to the swap file, and when it was written
+    swap_written: Option<(u64, Instant)>,
+    /// The write to the swap file still running in the background, if there is one
+    swap_writer: Option<thread::JoinHandle<Result<(), Error>>>,
+    /// Receives the remaining lines of a file still being loaded in the background, and why
+    /// reading them went wrong if it did
+    loading: Option<Receiver<(Vec<Line>, Option<String>)>>,
     /// Current line for iterator
     cur_line: usize
 }
@@ -36,122 +108,1251 @@ impl Buffer {
     /// Create an empty buffer
     pub fn empty()->Buffer{
         Self{
-            text: Vec::new(),
+            text: LineRope::new(),
             extension: None,
             path: PathBuf::new(),
             num_lines: 0,
+            modified: false,
+            readonly: false,
+            edit_refused: false,
+            load_problem: None,
+            version: 0,
+            disk_modified: None,
+            saved_state: 0,
+            change_cache: RefCell::new(None),
+            undo_tree: UndoTree::new(),
+            undo_directory: None,
+            swap_path: None,
+            swap_written: None,
+            swap_writer: None,
+            loading: None,
             cur_line:0,
         }
     }
 
-    /// Create a buffer from a file
+    /// Create a buffer from a file. A file which can't be read, or isn't valid UTF-8, is
+    /// opened read-only with the problem noted in `load_problem`.
     pub fn from_file(file_path: PathBuf)->Buffer{
-        let mut file_str = "".to_string();
-        if file_path.exists() {
-            file_str = match read_to_string(&file_path) {
-                Ok(result) => { result }
-                Err(_)=> "".to_string() // If it doesn't exist, just set this to an empty string
-            };
-        }
-        let mut text: Vec<Line> = Vec::new();
-        for line in file_str.lines(){
-            text.push(Line::from_string(line))
-        }
+        let (file_str, problem) = match std::fs::read(&file_path) {
+            Ok(bytes) => match String::from_utf8(bytes) {
+                Ok(text) => (text, None),
+                Err(error) => (String::from_utf8_lossy(error.as_bytes()).into_owned(), Some(NOT_UTF8.to_string())),
+            },
+            // A file which doesn't exist yet is simply empty
+            Err(error) if error.kind() == ErrorKind::NotFound => (String::new(), None),
+            Err(error) => (String::new(), Some(read_failure(&error))),
+        };
         let extension = match file_path.extension(){
             None => {None}
             Some(ext) => {Some(ext.to_str().unwrap_or("").to_string())}
         };
+        let mut buffer = Self::from_string(&file_str);
+        buffer.extension = extension;
+        buffer.disk_modified = modified_time(&file_path);
+        buffer.path = file_path;
+        buffer.note_load_problem(problem);
+        buffer
+    }
+
+    /// Create an unnamed buffer from everything the reader gives, such as input piped to
+    /// the editor. Invalid UTF-8 is replaced rather than rejected.
+    pub fn from_reader(mut reader: impl Read)->Result<Buffer, Error>{
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        Ok(Self::from_string(&String::from_utf8_lossy(&bytes)))
+    }
+
+    /// Create a buffer from a file, reading only the first `initial_lines` lines immediately.
+    /// The rest of the file is read on a background thread, and added to the buffer by
+    /// `receive_lines` or `finish_loading`.
+    pub fn from_file_streaming(file_path: PathBuf, initial_lines: usize)->Buffer{
+        let mut buffer = Self::empty();
+        buffer.extension = file_path.extension().map(|ext| ext.to_str().unwrap_or("").to_string());
+        buffer.path = file_path.clone();
+        buffer.disk_modified = modified_time(&file_path);
+        let file = match File::open(&file_path) {
+            Ok(file) => file,
+            // A file which doesn't exist yet is simply empty
+            Err(error) if error.kind() == ErrorKind::NotFound => return buffer,
+            Err(error) => {
+                buffer.note_load_problem(Some(read_failure(&error)));
+                return buffer;
+            }
+        };
+        let mut lines = BufReader::new(file).split(b'\n');
+        let mut problem = None;
+        let mut failed = false;
+        for line in lines.by_ref().take(initial_lines) {
+            match line {
+                Ok(line) => {
+                    let (line, lossy) = decode_line(line);
+                    if lossy {
+                        problem = Some(NOT_UTF8.to_string());
+                    }
+                    // Loaded lines aren't edits, so they are added without being tracked
+                    buffer.text.extend([Line::from_string(&line)]);
+                }
+                Err(error) => {
+                    problem = Some(read_failure(&error));
+                    failed = true;
+                    break;
+                }
+            }
+        }
+        buffer.num_lines = buffer.text.len();
+        buffer.note_load_problem(problem);
+        if failed {
+            return buffer; // Nothing more can be read
+        }
+        let (sender, receiver) = channel();
+        thread::spawn(move || {
+            const CHUNK_SIZE: usize = 1000;
+            let mut chunk: Vec<Line> = Vec::with_capacity(CHUNK_SIZE);
+            let mut problem = None;
+            for line in lines {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(error) => {
+                        problem = Some(read_failure(&error));
+                        break;
+                    }
+                };
+                let (line, lossy) = decode_line(line);
+                if lossy && problem.is_none() {
+                    problem = Some(NOT_UTF8.to_string());
+                }
+                chunk.push(Line::from_string(&line));
+                if chunk.len() == CHUNK_SIZE {
+                    if sender.send((chunk, problem.take())).is_err() {
+                        return; // The buffer was dropped, so stop reading
+                    }
+                    chunk = Vec::with_capacity(CHUNK_SIZE);
+                }
+            }
+            if !chunk.is_empty() || problem.is_some() {
+                let _ = sender.send((chunk, problem));
+            }
+        });
+        buffer.loading = Some(receiver);
+        buffer
+    }
+
+    /// Create a buffer from a file too large to comfortably hold in memory. The file is read
+    /// through once to find where its lines are, and the lines themselves are read as they
+    /// are needed, with `LineRope::release_pages` letting go of them again.
+    pub fn from_file_paged(file_path: PathBuf)->Buffer{
+        let mut buffer = Self::empty();
+        buffer.extension = file_path.extension().map(|ext| ext.to_str().unwrap_or("").to_string());
+        buffer.path = file_path.clone();
+        buffer.disk_modified = modified_time(&file_path);
+        match File::open(&file_path).and_then(LineRope::paged) {
+            Ok((text, lossy)) => {
+                buffer.text = text;
+                buffer.num_lines = buffer.text.len();
+                buffer.note_load_problem(lossy.then(|| NOT_UTF8.to_string()));
+            }
+            // A file which doesn't exist yet is simply empty
+            Err(error) if error.kind() == ErrorKind::NotFound => {}
+            Err(error) => buffer.note_load_problem(Some(read_failure(&error))),
+        }
+        buffer
+    }
+
+    /// Add any lines which have arrived from the background load, without blocking.
+    /// Returns true once the whole file has been loaded.
+    pub fn receive_lines(&mut self)->bool{
+        loop {
+            let received = match &self.loading {
+                None => return true,
+                Some(receiver) => receiver.try_recv(),
+            };
+            match received {
+                Ok(chunk) => self.add_loaded_lines(chunk),
+                Err(TryRecvError::Empty) => return false,
+                Err(TryRecvError::Disconnected) => {
+                    self.loading = None;
+                    return true;
+                }
+            }
+        }
+    }
+
+    /// Block until the background load has finished, adding all remaining lines
+    pub fn finish_loading(&mut self){
+        if let Some(receiver) = self.loading.take() {
+            for chunk in receiver {
+                self.add_loaded_lines(chunk);
+            }
+        }
+    }
+
+    /// Add lines from the background load to the end of the buffer, along with any problem
+    /// met reading them
+    fn add_loaded_lines(&mut self, (lines, problem): (Vec<Line>, Option<String>)){
+        self.num_lines += lines.len();
+        self.text.extend(lines);
+        self.note_load_problem(problem);
+    }
+
+    /// Make the buffer read-only if its file couldn't be read as it is, keeping the first
+    /// problem to report
+    fn note_load_problem(&mut self, problem: Option<String>){
+        if let Some(problem) = problem {
+            self.readonly = true;
+            self.load_problem.get_or_insert(problem);
+        }
+    }
+
+    /// Create a buffer (not associated with any file) from a string
+    pub fn from_string(text_str: &str)->Buffer{
+        let text: LineRope = text_str.lines().map(Line::from_string).collect();
         let num_lines = text.len();
         Self {
             text,
-            extension,
-            path: file_path,
+            extension: None,
+            path: PathBuf::new(),
             num_lines,
+            modified: false,
+            readonly: false,
+            edit_refused: false,
+            load_problem: None,
+            version: 0,
+            disk_modified: None,
+            saved_state: 0,
+            change_cache: RefCell::new(None),
+            undo_tree: UndoTree::new(),
+            undo_directory: None,
+            swap_path: None,
+            swap_written: None,
+            swap_writer: None,
+            loading: None,
             cur_line:0,
         }
     }
 
+    /// Count the lines, words, graphemes and bytes of the text. A word is a run of characters
+    /// matching the word pattern.
+    pub fn stats(&mut self, word: &Regex)->BufferStats{
+        self.finish_loading();
+        let mut stats = BufferStats { lines: self.num_lines, words: 0, graphemes: 0, bytes: 0 };
+        self.cur_line = 0;
+        for line in self.by_ref() {
+            stats.words += count_words(&line.text, word);
+            stats.graphemes += line.grapheme_count;
+            stats.bytes += line.text.len();
+        }
+        self.cur_line = 0;
+        stats
+    }
+
+    /// Remove the spaces and tabs at the end of every line, returning whether any were removed
+    pub fn trim_trailing_whitespace(&mut self)->bool{
+        // Forcing a read-only buffer to be written shouldn't change what is written
+        if self.readonly {
+            return false;
+        }
+        self.finish_loading();
+        // Only the lines which change are touched, so they alone are copied for undo
+        let rows: Vec<usize> = self.text.iter().enumerate()
+            .filter(|(_, line)| line.text.ends_with([' ', '\t']))
+            .map(|(row, _)| row)
+            .collect();
+        for row in &rows {
+            self.text[*row].trim_trailing_whitespace();
+        }
+        let trimmed = !rows.is_empty();
+        if trimmed {
+            self.mark_modified();
+        }
+        trimmed
+    }
+
     /// Write the current buffer to the file it is targeting
-    pub fn write_file(&self)->Result<(), Error>{
+    pub fn write_file(&mut self)->Result<(), Error>{
+        if self.path.as_os_str().is_empty() {
+            return Err(Error::new(ErrorKind::NotFound, "No file name (write it with :w path)"));
+        }
+        // Don't truncate the file by writing before it has finished loading
+        self.finish_loading();
+        // Nor before every line paged from it has been read
+        let paged = self.text.is_paged();
+        self.text.detach_pages();
         let mut file = File::create(&self.path)?;
         file.write_all(self.lines_to_str().as_bytes())?;
+        if paged {
+            // Page the lines from what was written, so those read to write it can be let go.
+            // If that fails they are simply kept.
+            let _ = File::open(&self.path).and_then(|file| self.text.repage(file));
+        }
+        self.modified = false;
+        self.disk_modified = modified_time(&self.path);
+        self.commit_undo();
+        self.saved_state = self.undo_tree.current();
+        // The statuses depend on the saved text as well as the current one
+        self.change_cache.replace(None);
+        // The edits are safely on disk, so the swap file is no longer needed
+        self.remove_swap();
+        if let Some(directory) = self.undo_directory.clone() {
+            // The file is saved even if its history can't be
+            let _ = self.write_undo_file(&directory);
+        }
+        Ok(())
+    }
+
+    /// Write the text to a different file, leaving the buffer's own file and saved state alone
+    pub fn write_copy(&mut self, path: &Path)->Result<(), Error>{
+        self.finish_loading();
+        let mut file = File::create(path)?;
+        file.write_all(self.lines_to_str().as_bytes())
+    }
+
+    /// Give an unnamed buffer the file it is written to
+    pub fn set_path(&mut self, path: PathBuf){
+        self.extension = path.extension().map(|ext| ext.to_str().unwrap_or("").to_string());
+        self.disk_modified = None;
+        self.path = path;
+    }
+
+    /// Where the undo history of the buffer's file is kept within the directory, named by a
+    /// hash of the file's path
+    pub fn undo_file_path(&self, directory: &Path)->PathBuf{
+        let path = canonicalize(&self.path).unwrap_or_else(|_| self.path.clone());
+        let name = hash_lines(path.to_string_lossy().lines());
+        directory.join(format!("{name:016x}"))
+    }
+
+    /// Save the undo history, along with a hash of the text it leads to
+    fn write_undo_file(&mut self, directory: &Path)->Result<(), Error>{
+        self.commit_undo();
+        create_dir_all(directory)?;
+        let hash = hash_lines(self.text.range(0..self.num_lines).map(|line| line.text.as_str()));
+        let contents = format!("text {hash:016x}\n{}", self.undo_tree.serialize());
+        File::create(self.undo_file_path(directory))?.write_all(contents.as_bytes())
+    }
+
+    /// Restore the undo history saved for the file by a previous session. The history is
+    /// ignored if the file has been changed since, as its edits would no longer line up.
+    /// Returns whether the history was restored.
+    pub fn read_undo_file(&mut self)->Result<bool, Error>{
+        let undo_file = match &self.undo_directory {
+            Some(directory) => self.undo_file_path(directory),
+            None => return Ok(false),
+        };
+        if !undo_file.exists() {
+            return Ok(false);
+        }
+        let contents = read_to_string(undo_file)?;
+        let (header, history) = contents.split_once('\n').unwrap_or((&contents, ""));
+        // Undoing needs the whole file, so that the hash can be checked and edits applied
+        self.finish_loading();
+        let hash = format!("text {:016x}", hash_lines(self.text.range(0..self.num_lines).map(|line| line.text.as_str())));
+        if header != hash || self.text.edited().is_some() {
+            return Ok(false);
+        }
+        match UndoTree::deserialize(history) {
+            Ok(tree) => {
+                // The history was saved along with the file, so its current state is the text
+                self.saved_state = tree.current();
+                self.undo_tree = tree;
+                Ok(true)
+            }
+            Err(_) => Ok(false),
+        }
+    }
+
+    /// How the line differs from when the buffer was last read or written
+    pub fn line_change_status(&self, row: usize)->ChangeKind{
+        let mut cache = self.change_cache.borrow_mut();
+        let stale = !matches!(&*cache, Some((version, _, _)) if *version == self.version);
+        if stale {
+            *cache = Some(match self.unsaved_region() {
+                Some((start, saved, len)) => {
+                    let current: Vec<&Line> = self.text.range(start..start + len).collect();
+                    (self.version, start, diff_lines(&saved, &current))
+                }
+                None => (self.version, 0, Vec::new()),
+            });
+        }
+        cache.as_ref()
+            .and_then(|(_, start, statuses)| statuses.get(row.checked_sub(*start)?).copied())
+            .unwrap_or(ChangeKind::Unchanged)
+    }
+
+    /// The run of lines holding every difference from the saved text: its first row, the
+    /// saved lines in its place, and how many lines it now covers. It is found by undoing
+    /// the edits since the text was saved on a copy of just the lines they touch.
+    fn unsaved_region(&self)->Option<(usize, Vec<String>, usize)>{
+        let mut region = self.text.edited().map(|edited| (edited.start, edited.old.clone(), edited.len));
+        for change in self.undo_tree.path(self.undo_tree.current(), self.saved_state) {
+            let (start, saved, len) = region.get_or_insert((change.start, Vec::new(), 0));
+            // Outside the region, the text being undone is still the current text
+            if change.start < *start {
+                let before: Vec<String> = self.text.range(change.start..*start).map(|line| line.text.clone()).collect();
+                saved.splice(0..0, before);
+                *len += *start - change.start;
+                *start = change.start;
+            }
+            let change_end = change.start + change.old.len();
+            let saved_end = *start + saved.len();
+            if change_end > saved_end {
+                let after = *start + *len;
+                saved.extend(self.text.range(after..after + change_end - saved_end).map(|line| line.text.clone()));
+                *len += change_end - saved_end;
+            }
+            saved.splice(change.start - *start..change_end - *start, change.new.iter().cloned());
+        }
+        // Lines removed from the end of the region are shown on the line after it
+        let (start, mut saved, mut len) = region?;
+        if let Some(next) = self.text.get(start + len).filter(|_| start + len < self.num_lines) {
+            saved.push(next.text.clone());
+            len += 1;
+        }
+        Some((start, saved, len))
+    }
+
+    /// Whether the file has been changed by something else since it was last read or written
+    pub fn changed_on_disk(&self)->bool{
+        if self.path.as_os_str().is_empty() {
+            return false;
+        }
+        modified_time(&self.path) != self.disk_modified
+    }
+
+    /// Replace the text with the current contents of the file, discarding any edits
+    pub fn reload(&mut self){
+        self.remove_swap();
+        let mut reloaded = if self.text.is_paged() {
+            Self::from_file_paged(self.path.clone())
+        } else {
+            Self::from_file(self.path.clone())
+        };
+        reloaded.version = self.version + 1;
+        reloaded.undo_directory = self.undo_directory.take();
+        reloaded.swap_path = self.swap_path.take();
+        reloaded.readonly |= self.readonly;
+        *self = reloaded;
+    }
+
+    /// Write the text to the swap file if it has unsaved edits the swap file doesn't have yet.
+    /// The first edit is written straight away, and later ones at most once per interval.
+    /// The file is written in the background, so a large buffer doesn't hold up typing, and
+    /// nothing new is written while the last write is still running. Returns whether a write
+    /// was started, or the error from the last write once it has finished.
+    pub fn update_swap(&mut self, interval: Duration)->Result<bool, Error>{
+        if self.swap_writer.as_ref().is_some_and(|writer| !writer.is_finished()) {
+            return Ok(false);
+        }
+        self.finish_swap_write()?;
+        let swap_path = match &self.swap_path {
+            // A partly loaded file would leave a truncated swap file
+            Some(swap_path) if self.modified && self.loading.is_none() => swap_path.clone(),
+            _ => return Ok(false),
+        };
+        if let Some((version, written)) = self.swap_written {
+            if version == self.version || written.elapsed() < interval {
+                return Ok(false);
+            }
+        }
+        let text = self.lines_to_str();
+        if let Ok(mut swap_files) = SWAP_FILES.lock() {
+            swap_files.insert(swap_path.clone());
+        }
+        self.swap_writer = Some(thread::spawn(move || File::create(&swap_path)?.write_all(text.as_bytes())));
+        self.swap_written = Some((self.version, Instant::now()));
+        Ok(true)
+    }
+
+    /// Wait for the write to the swap file running in the background, if there is one,
+    /// returning how it went
+    fn finish_swap_write(&mut self)->Result<(), Error>{
+        match self.swap_writer.take() {
+            Some(writer) => writer.join().unwrap_or_else(|_| Err(Error::other("Swap file writer panicked"))),
+            None => Ok(()),
+        }
+    }
+
+    /// Delete the swap file this buffer wrote, once the edits it holds are saved or discarded.
+    /// A swap file left by an earlier session is kept, so it can still be recovered.
+    pub fn remove_swap(&mut self){
+        // A write still running would put the file back after it was removed
+        let _ = self.finish_swap_write();
+        if self.swap_written.take().is_none() {
+            return;
+        }
+        if let Some(swap_path) = &self.swap_path {
+            let _ = remove_file(swap_path);
+            if let Ok(mut swap_files) = SWAP_FILES.lock() {
+                swap_files.remove(swap_path);
+            }
+        }
+    }
+
+    /// Whether a swap file left by an earlier session is newer than the file, so that its
+    /// text can be recovered
+    pub fn recovery_available(&self)->bool{
+        if self.swap_written.is_some() {
+            return false;
+        }
+        let swap_time = match self.swap_path.as_ref().and_then(modified_time) {
+            Some(swap_time) => swap_time,
+            None => return false,
+        };
+        self.disk_modified.is_none_or(|file_time| swap_time > file_time)
+    }
+
+    /// Replace the text with that held in the swap file. The recovered text is an unsaved
+    /// edit, which can be undone to get back to the file's text.
+    pub fn recover_swap(&mut self)->Result<(), Error>{
+        let swap_path = self.swap_path.clone()
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "No swap file for this buffer"))?;
+        let text = read_to_string(swap_path)?;
+        self.finish_loading();
+        self.commit_undo();
+        self.replace_lines(0..self.num_lines, &text);
+        self.commit_undo();
         Ok(())
     }
 
     /// Insert a (utf8) character into a line of the text, at grapheme_index
     pub fn insert_char(&mut self, line:usize, grapheme_index: usize, character:char){
+        if self.refuse_edit() {
+            return;
+        }
         self.text[line].insert_char(grapheme_index, character);
+        self.mark_modified();
+    }
+
+    /// Replace the grapheme at grapheme_index with a (utf8) character. Nothing is replaced if
+    /// there is no grapheme there, including on an empty line.
+    pub fn replace_char(&mut self, line: usize, grapheme_index: usize, character: char){
+        if grapheme_index >= self.text[line].grapheme_count || self.refuse_edit() {
+            return;
+        }
+        self.text[line].replace_grapheme(grapheme_index, character);
+        self.mark_modified();
     }
 
-    /// Delete a (utf-8) character at the grapheme_index, if the line is already empty,
-    /// then this will instead delete that line
+    /// Delete a (utf-8) character at the grapheme_index. Nothing is deleted if there is no
+    /// grapheme there, including on an empty line; lines are removed with `delete_line`.
     pub fn delete_char(&mut self, line:usize, grapheme_index: usize){
-        if self.text[line].text == ""{
-            _=self.text.remove(line);
-            self.num_lines-=1;
-        } else {
-            self.text[line].delete_grapheme(grapheme_index)
+        if self.refuse_edit() {
+            return;
+        }
+        if grapheme_index >= self.text[line].grapheme_count {
+            return;
+        }
+        self.text[line].delete_grapheme(grapheme_index);
+        self.mark_modified();
+    }
+
+    /// Remove the text from the grapheme to the end of the line, returning it. Nothing is
+    /// removed if the grapheme is past the end of the line.
+    pub fn delete_to_line_end(&mut self, row: usize, grapheme: usize)->String{
+        if self.refuse_edit() {
+            return String::new();
+        }
+        let line = &self.text[row];
+        if grapheme >= line.grapheme_count {
+            return String::new();
+        }
+        let start = line.grapheme_start(grapheme);
+        let removed = line.text[start..].to_string();
+        self.text[row] = Line::from_string(&line.text[..start]);
+        self.mark_modified();
+        removed
+    }
+
+    /// Remove a whole line from the buffer, returning it. Removing the only line leaves an
+    /// empty one in its place, and a row past the end removes nothing.
+    pub fn delete_line(&mut self, line: usize)->Line{
+        if line >= self.num_lines || self.refuse_edit() {
+            return Line::from_string("");
+        }
+        let removed = self.text.remove(line);
+        self.num_lines -= 1;
+        if self.num_lines == 0 {
+            self.text.insert(0, Line::from_string(""));
+            self.num_lines = 1;
         }
+        self.mark_modified();
+        removed
     }
 
-    /// Create a default line, potentially splitting a line into two parts
-    pub fn new_line(&mut self, line:usize, grapheme_index: usize){
-        if line >= self.num_lines{
+    /// Insert a line at the row, moving the lines from there down
+    pub fn insert_line(&mut self, row: usize, line: Line){
+        if self.refuse_edit() {
+            return;
+        }
+        self.text.insert(row.min(self.num_lines), line);
+        self.num_lines += 1;
+        self.mark_modified();
+    }
+
+    /// The indentation at the start of a line, converted to spaces or tabs following
+    /// `to_spaces`. A row past the end has none.
+    pub fn line_indent(&self, row: usize, to_spaces: bool, width: usize)->String{
+        let Some(line) = self.text.get(row) else { return String::new() };
+        let indent_len = line.text.len() - line.text.trim_start_matches([' ', '\t']).len();
+        convert_indent(&line.text[..indent_len], to_spaces, width)
+    }
+
+    /// Insert the lines of text (split at each `\n`, so an empty string is one empty line)
+    /// before the row
+    pub fn insert_lines(&mut self, row: usize, text: &str){
+        if self.refuse_edit() {
+            return;
+        }
+        let row = row.min(self.num_lines);
+        let lines: Vec<Line> = text.split('\n').map(Line::from_string).collect();
+        self.num_lines += lines.len();
+        self.text.splice(row..row, lines);
+        self.mark_modified();
+    }
+
+    /// Join the line following `line` onto its end, removing the line break between them
+    pub fn merge_next_line(&mut self, line:usize){
+        if self.refuse_edit() {
+            return;
+        }
+        if line + 1 >= self.num_lines {
+            return;
+        }
+        let next_line = self.text.remove(line + 1);
+        let merged = format!("{}{}", self.text[line].text, next_line.text);
+        self.text[line] = Line::from_string(&merged);
+        self.num_lines -= 1;
+        self.mark_modified();
+    }
+
+    /// Join the following line onto the end of the row (vim's `J`), dropping its
+    /// indentation and separating the two with a space. Returns the grapheme where the lines
+    /// were joined, or None on the last line.
+    pub fn join_lines(&mut self, row: usize)->Option<usize>{
+        if self.refuse_edit() {
+            return None;
+        }
+        if row + 1 >= self.num_lines {
+            return None;
+        }
+        let next_line = self.text.remove(row + 1);
+        let current = &self.text[row];
+        let joined = next_line.text.trim_start();
+        let separator = if current.text.is_empty() || current.text.ends_with(char::is_whitespace) || joined.is_empty() {
+            ""
+        } else {
+            " "
+        };
+        let join_grapheme = current.grapheme_count;
+        self.text[row] = Line::from_string(&format!("{}{separator}{joined}", current.text));
+        self.num_lines -= 1;
+        self.mark_modified();
+        Some(join_grapheme)
+    }
+
+    /// Create a default line, potentially splitting a line into two parts. The text from the
+    /// grapheme onwards moves to a new line below, and the start of that line is returned as
+    /// where the cursor goes.
+    pub fn new_line(&mut self, line:usize, grapheme_index: usize)->TextPosition{
+        if self.refuse_edit() {
+            return TextPosition { row: line, byte: 0, grapheme: grapheme_index };
+        }
+        let row = if line >= self.num_lines{
             self.text.push(Line::from_string(""));
             self.num_lines+=1;
+            self.num_lines - 1
         } else {
             let new_line = self.text[line].split_line_grapheme(grapheme_index);
-            self.text.insert(line, new_line);
+            self.text.insert(line + 1, new_line);
             self.num_lines+=1;
+            line + 1
+        };
+        self.mark_modified();
+        TextPosition { row, byte: 0, grapheme: 0 }
+    }
+
+    /// Split a line like pressing Enter, starting the new line below with the indentation of
+    /// the line being split (converted to spaces or tabs following `to_spaces`). Whitespace
+    /// just after the split is dropped, so the moved text lines up with the indentation.
+    /// Returns the number of graphemes in the new line's indentation.
+    pub fn new_line_autoindent(&mut self, line: usize, grapheme_index: usize, to_spaces: bool, width: usize)->usize{
+        if self.refuse_edit() {
+            return 0;
+        }
+        if line >= self.num_lines {
+            self.new_line(line, grapheme_index);
+            return 0;
         }
+        let current = &self.text[line];
+        let split = if grapheme_index >= current.grapheme_count {
+            current.text.len()
+        } else {
+            current.grapheme_start(grapheme_index)
+        };
+        let indent_len = current.text.len() - current.text.trim_start_matches([' ', '\t']).len();
+        let indent = convert_indent(&current.text[..indent_len.min(split)], to_spaces, width);
+        let below = format!("{indent}{}", current.text[split..].trim_start_matches([' ', '\t']));
+        self.text[line] = Line::from_string(&current.text[..split]);
+        self.text.insert(line + 1, Line::from_string(&below));
+        self.num_lines += 1;
+        self.mark_modified();
+        indent.len()
+    }
+
+    /// Replace the rows with the lines of text, which may be a different number of lines
+    pub fn replace_lines(&mut self, rows: Range<usize>, text: &str){
+        if self.refuse_edit() {
+            return;
+        }
+        let lines: Vec<Line> = text.lines().map(Line::from_string).collect();
+        let rows = rows.start.min(self.num_lines)..rows.end.min(self.num_lines);
+        self.num_lines = self.num_lines - rows.len() + lines.len();
+        self.text.splice(rows, lines);
+        self.mark_modified();
     }
 
-    /// Copy text form the start position to the end position
+    /// Copy text from the start position to the end position, including the end grapheme
     pub fn copy_text(&self, start_position: TextPosition, end_position: TextPosition)->String{
+        let start_byte = self.text[start_position.row].grapheme_start(start_position.grapheme);
+        // Byte just past the end grapheme, or 0 on an empty line
+        let end_line = &self.text[end_position.row];
+        let end_byte = match end_line.grapheme_count {
+            0 => 0,
+            count => end_line.grapheme_end(end_position.grapheme.min(count - 1)) + 1,
+        };
         if start_position.row == end_position.row {
             // Only on one line, simplest case
-            let start_byte = self.text[start_position.row].grapheme_start(start_position.grapheme);
-            let end_byte = self.text[end_position.row].grapheme_end(end_position.grapheme);
-            let mut copied_string = String::new();
-            self.text[start_position.row].text[start_byte..=end_byte].clone_into(&mut copied_string);
-            return copied_string;
+            return end_line.text[start_byte..end_byte.max(start_byte)].to_string();
         }
         let mut copied_lines:Vec<&str> = Vec::new();
-        let start_byte = self.text[start_position.row].grapheme_start(start_position.grapheme);
-        let end_byte = self.text[end_position.row].grapheme_end(end_position.grapheme);
         copied_lines.push(&self.text[start_position.row].text[start_byte..]);
         for idx in (start_position.row+1)..end_position.row{
             copied_lines.push(&self.text[idx].text[..]);
         }
-        copied_lines.push(&self.text[start_position.row].text[..=end_byte]);
-        copied_lines.join("\n").to_string()
+        copied_lines.push(&end_line.text[..end_byte]);
+        copied_lines.join("\n")
+    }
+
+    /// Remove the text from the start position to the end position, including the end
+    /// grapheme, returning it. This is the inverse of `copy_text`: what is left of the start
+    /// and end lines is joined into one line.
+    pub fn delete_range(&mut self, start_position: TextPosition, end_position: TextPosition)->String{
+        if self.refuse_edit() {
+            return String::new();
+        }
+        let removed = self.copy_text(start_position.clone(), end_position.clone());
+        let start_line = &self.text[start_position.row];
+        let start_byte = if start_position.grapheme < start_line.grapheme_count {
+            start_line.grapheme_start(start_position.grapheme)
+        } else {
+            start_line.text.len()
+        };
+        let end_line = &self.text[end_position.row];
+        let mut end_byte = if end_position.grapheme < end_line.grapheme_count {
+            end_line.grapheme_end(end_position.grapheme) + 1
+        } else {
+            end_line.text.len()
+        };
+        if start_position.row == end_position.row {
+            end_byte = end_byte.max(start_byte);
+        }
+        let joined = format!("{}{}", &start_line.text[..start_byte], &end_line.text[end_byte..]);
+        self.text.splice(start_position.row..end_position.row + 1, [Line::from_string(&joined)]);
+        self.num_lines -= end_position.row - start_position.row;
+        self.mark_modified();
+        removed
     }
 
     /// Paste text at start position
     pub fn paste_text(&mut self, start_position:TextPosition, insert_str: &str){
+        if self.refuse_edit() {
+            return;
+        }
         // This is a really inefficient way of doing this, but its a lot simpler than
         // alternatives
-        self.text[start_position.row].insert_str(start_position.grapheme, insert_str);
+        let insert_str = normalize_line_endings(insert_str);
+        self.text[start_position.row].insert_str(start_position.grapheme, &insert_str);
         self.fix_newlines();
+        self.mark_modified();
+    }
+
+    /// Copy the rectangle of text between two corners, as one string per row. Rows which
+    /// are too short to reach the rectangle give empty strings.
+    pub fn copy_rect(&self, start_position: TextPosition, end_position: TextPosition)->Vec<String>{
+        let first_col = start_position.grapheme.min(end_position.grapheme);
+        let last_col = start_position.grapheme.max(end_position.grapheme);
+        let first_row = start_position.row.min(end_position.row);
+        let last_row = start_position.row.max(end_position.row).min(self.num_lines.saturating_sub(1));
+        let mut copied = Vec::new();
+        for line in self.text.range(first_row..last_row + 1) {
+            if first_col >= line.grapheme_count {
+                copied.push(String::new());
+                continue;
+            }
+            let last_col = last_col.min(line.grapheme_count - 1);
+            copied.push(line.text[line.grapheme_start(first_col)..=line.grapheme_end(last_col)].to_string());
+        }
+        copied
+    }
+
+    /// Paste a block of text (as copied by `copy_rect`), inserting each row of the block
+    /// at the same column on consecutive lines starting from `at`. Lines are added past the
+    /// end of the buffer, and short lines padded with spaces, so every row lands in the column.
+    pub fn paste_block(&mut self, at: TextPosition, block: &[String]){
+        if self.refuse_edit() {
+            return;
+        }
+        for (offset, segment) in block.iter().enumerate() {
+            let row = at.row + offset;
+            while row >= self.num_lines {
+                self.text.push(Line::from_string(""));
+                self.num_lines += 1;
+            }
+            let line = &self.text[row];
+            let mut text = line.text.clone();
+            if at.grapheme < line.grapheme_count {
+                text.insert_str(line.grapheme_start(at.grapheme), segment);
+            } else {
+                text.push_str(&" ".repeat(at.grapheme - line.grapheme_count));
+                text.push_str(segment);
+            }
+            self.text[row] = Line::from_string(&text);
+        }
+        self.mark_modified();
+    }
+
+    /// Add the whitespace to the start of each of the rows, skipping empty ones
+    pub fn indent_lines(&mut self, rows: Range<usize>, indent: &str){
+        if self.refuse_edit() {
+            return;
+        }
+        let mut changed = false;
+        for row in rows.start.min(self.num_lines)..rows.end.min(self.num_lines) {
+            if !self.text[row].text.is_empty() && !indent.is_empty() {
+                self.text[row].add_indent(indent);
+                changed = true;
+            }
+        }
+        if changed {
+            self.mark_modified();
+        }
+    }
+
+    /// Remove up to `columns` columns of indentation from the start of each of the rows,
+    /// with a tab reaching the next multiple of `tab_width` columns
+    pub fn dedent_lines(&mut self, rows: Range<usize>, columns: usize, tab_width: usize){
+        if self.refuse_edit() {
+            return;
+        }
+        let mut changed = false;
+        for row in rows.start.min(self.num_lines)..rows.end.min(self.num_lines) {
+            if self.text[row].remove_indent(columns, tab_width) {
+                changed = true;
+            }
+        }
+        if changed {
+            self.mark_modified();
+        }
+    }
+
+    /// Convert the indentation of every line to spaces, or to tabs (with spaces for any
+    /// remainder), treating a tab as reaching the next multiple of width columns.
+    /// Whitespace after the indentation is left alone.
+    pub fn retab(&mut self, to_spaces: bool, width: usize){
+        if self.refuse_edit() {
+            return;
+        }
+        let width = width.max(1);
+        let mut changed = false;
+        for row in 0..self.num_lines {
+            let line = &self.text[row];
+            let indent_len = line.text.len() - line.text.trim_start_matches([' ', '\t']).len();
+            let indent = &line.text[..indent_len];
+            let new_indent = convert_indent(indent, to_spaces, width);
+            if new_indent != indent {
+                let text = format!("{}{}", new_indent, &line.text[indent_len..]);
+                self.text[row] = Line::from_string(&text);
+                changed = true;
+            }
+        }
+        if changed {
+            self.mark_modified();
+        }
+    }
+
+    /// Replace matches of the pattern on the rows, only the first on each row unless global
+    /// is set. The replacement can refer to groups as `$1` or `${name}`. Returns the number
+    /// of matches replaced.
+    pub fn replace_all(&mut self, rows: Range<usize>, pattern: &Regex, replacement: &str, global: bool)->usize{
+        if self.refuse_edit() {
+            return 0;
+        }
+        let mut replaced = 0;
+        for row in rows.start.min(self.num_lines)..rows.end.min(self.num_lines) {
+            let line = &self.text[row].text;
+            let matches = if global { pattern.find_iter(line).count() } else { pattern.find(line).iter().count() };
+            if matches == 0 {
+                continue;
+            }
+            let limit = if global { 0 } else { 1 };
+            let text = pattern.replacen(line, limit, replacement).into_owned();
+            self.text[row] = Line::from_string(&text);
+            replaced += matches;
+        }
+        if replaced > 0 {
+            self.mark_modified();
+        }
+        replaced
+    }
+
+    /// Replace the byte range of a line with the text
+    pub fn replace_range(&mut self, row: usize, bytes: Range<usize>, text: &str){
+        if self.refuse_edit() {
+            return;
+        }
+        let mut line = self.text[row].text.clone();
+        line.replace_range(bytes, text);
+        self.text[row] = Line::from_string(&line);
+        self.mark_modified();
+    }
+
+    /// How far through the buffer a view starting at top_row is, as the percentage of the
+    /// lines not in view which are above it (100 when nothing is below the view)
+    pub fn scroll_percentage(&self, top_row: usize, view_height: usize)->u8{
+        let above = top_row.min(self.num_lines);
+        let below = self.num_lines.saturating_sub(top_row + view_height);
+        if below == 0 {
+            return 100;
+        }
+        (above * 100 / (above + below)) as u8
+    }
+
+    /// Describe the view's position like vim's ruler: `All` when the whole buffer fits, `Top`
+    /// or `Bot` when at either end, and otherwise the scroll percentage
+    pub fn scroll_label(&self, top_row: usize, view_height: usize)->String{
+        let at_bottom = top_row + view_height >= self.num_lines;
+        match (top_row == 0, at_bottom) {
+            (true, true) => "All".to_string(),
+            (true, false) => "Top".to_string(),
+            (false, true) => "Bot".to_string(),
+            (false, false) => format!("{}%", self.scroll_percentage(top_row, view_height)),
+        }
+    }
+
+    /// Rewrap the paragraph (run of non-blank lines) containing row, so no line is longer
+    /// than width graphemes where possible, breaking lines at spaces. The row's indent and
+    /// comment marker (like `// ` or `# `) is kept at the start of every line, and in a comment
+    /// the paragraph only covers the neighbouring comment lines. Returns the rows of the
+    /// reflowed paragraph, or None if row is blank.
+    pub fn reflow_paragraph(&mut self, row: usize, width: usize)->Option<Range<usize>>{
+        if self.refuse_edit() {
+            return None;
+        }
+        static PREFIX_REGEX: Lazy<Regex> = Lazy::new(||
+            Regex::new(r"^\s*(?:(?://+|#+|--|;+|\*|>)\s?)?").unwrap());
+        let prefix = PREFIX_REGEX.find(&self.text.get(row)?.text).map_or("", |m| m.as_str()).to_string();
+        // In a comment, the paragraph is the run of comment lines with some text after the marker
+        let marker = prefix.trim().to_string();
+        let in_paragraph = |line: &Line| match line.text.trim_start().strip_prefix(marker.as_str()) {
+            Some(rest) => !rest.trim().is_empty(),
+            None => false,
+        };
+        if !in_paragraph(&self.text[row]) {
+            return None;
+        }
+        let mut start = row;
+        while start > 0 && in_paragraph(&self.text[start - 1]) {
+            start -= 1;
+        }
+        let mut end = row + 1;
+        while end < self.num_lines && in_paragraph(&self.text[end]) {
+            end += 1;
+        }
+        let words: Vec<String> = self.text.range(start..end)
+            .flat_map(|line| {
+                let body = &line.text.trim_start()[marker.len()..];
+                body.split_whitespace().map(|word| word.to_string()).collect::<Vec<String>>()
+            })
+            .collect();
+        let prefix_width = prefix.graphemes(true).count();
+        let mut lines: Vec<String> = Vec::new();
+        let mut current = String::new();
+        let mut current_width = prefix_width;
+        for word in words {
+            let word_width = word.graphemes(true).count();
+            if !current.is_empty() && current_width + 1 + word_width > width {
+                lines.push(format!("{prefix}{current}"));
+                current.clear();
+                current_width = prefix_width;
+            }
+            if !current.is_empty() {
+                current.push(' ');
+                current_width += 1;
+            }
+            current.push_str(&word);
+            current_width += word_width;
+        }
+        lines.push(format!("{prefix}{current}"));
+        let new_end = start + lines.len();
+        self.replace_lines(start..end, &lines.join("\n"));
+        Some(start..new_end)
+    }
+
+    /// Find the identifier (run of word characters) containing the grapheme, returning
+    /// the identifier and the grapheme it starts at
+    pub fn word_at(&self, row: usize, grapheme: usize)->Option<(String, usize)>{
+        static IDENTIFIER_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"\w+").unwrap());
+        let line = self.text.get(row)?;
+        if grapheme >= line.grapheme_count {
+            return None;
+        }
+        let byte = line.grapheme_start(grapheme);
+        IDENTIFIER_REGEX.find_iter(&line.text)
+            .find(|m| m.start() <= byte && byte < m.end())
+            .map(|m| (m.as_str().to_string(), line.text_index_to_grapheme(m.start())))
+    }
+
+    /// Find the number (decimal, or hexadecimal with a `0x` prefix) containing the grapheme,
+    /// or the first one after it on the line. Returns its byte range, including any sign.
+    pub fn number_at(&self, row: usize, grapheme: usize)->Option<Range<usize>>{
+        static NUMBER_REGEX: Lazy<Regex> = Lazy::new(||
+            Regex::new(r"0[xX][0-9a-fA-F]+|-?[0-9]+").unwrap());
+        let line = self.text.get(row)?;
+        if grapheme >= line.grapheme_count {
+            return None;
+        }
+        let byte = line.grapheme_start(grapheme);
+        let found = NUMBER_REGEX.find_iter(&line.text).find(|m| byte < m.end())?;
+        let mut range = found.range();
+        // A dash directly after a word character is a minus, not a sign
+        if line.text[range.clone()].starts_with('-')
+            && line.text[..range.start].chars().next_back().is_some_and(|c| c.is_alphanumeric() || c == '_') {
+            range.start += 1;
+        }
+        Some(range)
+    }
+
+    /// Add delta to the number under (or after) the grapheme, like vim's Ctrl-A and Ctrl-X.
+    /// Leading zeros are kept, as is the case of hexadecimal digits.
+    /// Returns false if there is no number, or the result would overflow.
+    pub fn modify_number(&mut self, row: usize, grapheme: usize, delta: i64)->bool{
+        if self.refuse_edit() {
+            return false;
+        }
+        let range = match self.number_at(row, grapheme) {
+            Some(range) => range,
+            None => return false,
+        };
+        let number = &self.text[row].text[range.clone()];
+        let replacement = if let Some(digits) = number.strip_prefix("0x").or(number.strip_prefix("0X")) {
+            let value = match u64::from_str_radix(digits, 16) {
+                Ok(value) => value,
+                Err(_) => return false,
+            };
+            let value = value.wrapping_add(delta as u64);
+            let width = digits.len();
+            let formatted = if digits.chars().any(|c| c.is_ascii_uppercase()) {
+                format!("{value:0width$X}")
+            } else {
+                format!("{value:0width$x}")
+            };
+            format!("{}{}", &number[..2], formatted)
+        } else {
+            let value = match number.parse::<i64>().ok().and_then(|value| value.checked_add(delta)) {
+                Some(value) => value,
+                None => return false,
+            };
+            let digits = number.trim_start_matches('-');
+            // Only keep the width when it was padded with leading zeros
+            let width = if digits.len() > 1 && digits.starts_with('0') { digits.len() } else { 0 };
+            let sign = if value < 0 { "-" } else { "" };
+            format!("{sign}{:0width$}", value.unsigned_abs())
+        };
+        let mut text = self.text[row].text.clone();
+        text.replace_range(range, &replacement);
+        self.text[row] = Line::from_string(&text);
+        self.mark_modified();
+        true
+    }
+
+    /// Find the region which would be folded at `row`: the row itself along with the
+    /// following lines which are indented deeper than it. Blank lines inside the region are
+    /// included, but trailing blank lines are not. Returns None if no lines are deeper.
+    pub fn indent_fold_range(&self, row: usize)->Option<Range<usize>>{
+        let base_indent = self.text.get(row)?.leading_whitespace();
+        let mut end = row + 1;
+        for idx in (row + 1)..self.num_lines {
+            if self.text[idx].is_blank() {
+                continue;
+            }
+            if self.text[idx].leading_whitespace() <= base_indent {
+                break;
+            }
+            end = idx + 1;
+        }
+        if end == row + 1 {
+            return None;
+        }
+        Some(row..end)
+    }
+
+    /// Find the bracket matching the one at the position, scanning across lines and
+    /// accounting for nesting. Returns None if the position isn't on a bracket, or the
+    /// bracket is unmatched.
+    pub fn find_matching_bracket(&self, position: &TextPosition)->Option<TextPosition>{
+        let bracket = self.text.get(position.row)?.grapheme(position.grapheme)?;
+        let (open, close, forward) = bracket_pair(bracket)?;
+        let mut depth = 0usize;
+        let mut row = position.row;
+        let mut grapheme = position.grapheme;
+        loop {
+            match self.text[row].grapheme(grapheme) {
+                Some(g) if g == open => {
+                    if forward { depth += 1 } else { depth -= 1 }
+                }
+                Some(g) if g == close => {
+                    if forward { depth -= 1 } else { depth += 1 }
+                }
+                _ => {}
+            }
+            if depth == 0 {
+                let byte = self.text[row].grapheme_start(grapheme);
+                return Some(TextPosition { row, byte, grapheme });
+            }
+            // Step to the next grapheme in the scan direction, moving across lines
+            (row, grapheme) = if forward {
+                self.next_grapheme_position(row, grapheme)?
+            } else {
+                self.prev_grapheme_position(row, grapheme)?
+            };
+        }
+    }
+
+    /// Whether the grapheme at the position is a bracket, which `find_matching_bracket`
+    /// would look for the partner of
+    pub fn is_bracket(&self, position: &TextPosition)->bool{
+        self.text.get(position.row)
+            .and_then(|line| line.grapheme(position.grapheme))
+            .is_some_and(|grapheme| bracket_pair(grapheme).is_some())
+    }
+
+    /// The position of the grapheme after (row, grapheme), skipping over empty lines
+    fn next_grapheme_position(&self, row: usize, grapheme: usize)->Option<(usize, usize)>{
+        let (mut row, mut grapheme) = (row, grapheme + 1);
+        while grapheme >= self.text.get(row)?.grapheme_count {
+            row += 1;
+            grapheme = 0;
+        }
+        Some((row, grapheme))
+    }
+
+    /// The position of the grapheme before (row, grapheme), skipping over empty lines
+    fn prev_grapheme_position(&self, row: usize, grapheme: usize)->Option<(usize, usize)>{
+        let (mut row, mut grapheme) = (row, grapheme);
+        while grapheme == 0 {
+            row = row.checked_sub(1)?;
+            grapheme = self.text[row].grapheme_count;
+        }
+        Some((row, grapheme - 1))
+    }
+
+    /// Find the start and end (inclusive) of a text object around the position
+    pub fn text_object(&self, kind: TextObject, at: &TextPosition)->Option<(TextPosition, TextPosition)>{
+        let line = self.text.get(at.row)?;
+        match kind {
+            TextObject::Word => {
+                let class = |g: &str| {
+                    if g.chars().all(char::is_whitespace) { 0 }
+                    else if g.chars().all(|c| c.is_alphanumeric() || c == '_') { 1 }
+                    else { 2 }
+                };
+                let at_class = class(line.grapheme(at.grapheme)?);
+                let mut start = at.grapheme;
+                while start > 0 && class(line.grapheme(start - 1)?) == at_class {
+                    start -= 1;
+                }
+                let mut end = at.grapheme;
+                while end + 1 < line.grapheme_count && class(line.grapheme(end + 1)?) == at_class {
+                    end += 1;
+                }
+                Some((self.position(at.row, start), self.position(at.row, end)))
+            }
+            TextObject::Line => {
+                if line.is_blank() {
+                    return None;
+                }
+                let start = line.leading_whitespace();
+                let trailing = line.text.chars().rev().take_while(|c| c.is_whitespace()).count();
+                let end = line.grapheme_count - trailing - 1;
+                Some((self.position(at.row, start), self.position(at.row, end)))
+            }
+            TextObject::Paragraph => {
+                let blank = line.is_blank();
+                let mut start = at.row;
+                while start > 0 && self.text[start - 1].is_blank() == blank {
+                    start -= 1;
+                }
+                let mut end = at.row;
+                while end + 1 < self.num_lines && self.text[end + 1].is_blank() == blank {
+                    end += 1;
+                }
+                let end_grapheme = self.text[end].grapheme_count.saturating_sub(1);
+                Some((self.position(start, 0), self.position(end, end_grapheme)))
+            }
+            TextObject::Bracket(open) => {
+                let close = match open {
+                    '(' => ")",
+                    '[' => "]",
+                    '{' => "}",
+                    _ => return None,
+                };
+                let open = open.to_string();
+                // Find the unmatched opening bracket enclosing the position
+                let (mut row, mut grapheme) = (at.row, at.grapheme);
+                let mut depth = 0usize;
+                loop {
+                    match self.text[row].grapheme(grapheme) {
+                        Some(g) if g == open && depth == 0 => break,
+                        Some(g) if g == open => depth -= 1,
+                        Some(g) if g == close && !(row == at.row && grapheme == at.grapheme) => depth += 1,
+                        _ => {}
+                    }
+                    (row, grapheme) = self.prev_grapheme_position(row, grapheme)?;
+                }
+                let close_position = self.find_matching_bracket(&self.position(row, grapheme))?;
+                // The inside runs from just after the opening bracket to just before the close
+                let (start_row, start_grapheme) = self.next_grapheme_position(row, grapheme)?;
+                let (end_row, end_grapheme) = self.prev_grapheme_position(close_position.row,
+                                                                          close_position.grapheme)?;
+                if (start_row, start_grapheme) > (end_row, end_grapheme) {
+                    return None; // Nothing between the brackets
+                }
+                Some((self.position(start_row, start_grapheme), self.position(end_row, end_grapheme)))
+            }
+        }
+    }
+
+    /// Create a TextPosition for a grapheme, with its byte offset filled in
+    fn position(&self, row: usize, grapheme: usize)->TextPosition{
+        TextPosition { row, byte: self.text[row].grapheme_start(grapheme), grapheme }
     }
 
-    /// Return a &str for printing (optionally highlighted, not yet implemented)
+    /// Return a &str for printing the graphemes from start to end, inclusive (optionally
+    /// highlighted, not yet implemented)
     pub fn print_line(&mut self, line: usize,
                       start_grapheme: usize,
                       end_grapheme: usize,
                       highlighted: bool)->&str{
-        let mut end_g = end_grapheme;
+        let count = self.text[line].grapheme_count;
         // If the start grapheme is beyond the text, just return an empty string
-        if start_grapheme >= self.text[line].grapheme_count-1{
+        if start_grapheme >= count || end_grapheme < start_grapheme {
             return ""
         }
         // If the end grapheme is beyond the text, set the end grapheme to be the last grapheme
         // in the text
-        if end_grapheme >= self.text[line].grapheme_count - 1{
-            end_g = self.text[line].grapheme_count -1;
-        }
+        let end_g = end_grapheme.min(count - 1);
         let start_byte = self.text[line].grapheme_start(start_grapheme);
-        let end_byte = self.text[line].grapheme_start(end_g);
+        // Slice to the end of the last grapheme, so multi-byte graphemes aren't cut in half
+        let end_byte = self.text[line].grapheme_end(end_g);
         &self.text[line].text[start_byte..=end_byte]
     }
 
@@ -185,6 +1386,72 @@ impl Buffer {
         }
     }
 
+    /// Record the edits made since the last call as a single undo step
+    pub fn commit_undo(&mut self){
+        if let Some(change) = self.text.take_change() {
+            self.undo_tree.record(change);
+        }
+    }
+
+    /// Undo the last edit, returning the first row it changed
+    pub fn undo(&mut self)->Option<usize>{
+        self.apply_changes(|tree| tree.undo().into_iter().collect())
+    }
+
+    /// Redo the last undone edit, returning the first row it changed
+    pub fn redo(&mut self)->Option<usize>{
+        self.apply_changes(|tree| tree.redo().into_iter().collect())
+    }
+
+    /// Go back count edits in the order they were made, across branches of the undo tree,
+    /// returning the first row changed by the last step
+    pub fn earlier(&mut self, count: usize)->Option<usize>{
+        self.apply_changes(|tree| tree.earlier(count))
+    }
+
+    /// Go forward count edits in the order they were made, returning the first row changed
+    /// by the last step
+    pub fn later(&mut self, count: usize)->Option<usize>{
+        self.apply_changes(|tree| tree.later(count))
+    }
+
+    /// Move through the undo tree and apply the changes it gives, which are not themselves
+    /// recorded as edits. A read-only buffer is left where it is in the tree.
+    fn apply_changes<F: FnOnce(&mut UndoTree)->Vec<LineChange>>(&mut self, step: F)->Option<usize>{
+        if self.refuse_edit() {
+            return None;
+        }
+        self.commit_undo();
+        let changes = step(&mut self.undo_tree);
+        let row = changes.last()?.start;
+        for change in &changes {
+            let lines = change.new.iter().map(|line| Line::from_string(line));
+            self.text.splice(change.start..change.start + change.old.len(), lines);
+            self.num_lines = self.num_lines - change.old.len() + change.new.len();
+        }
+        // The tree already holds these changes, so they aren't recorded as a new edit
+        self.text.take_change();
+        self.mark_modified();
+        // Undoing back to the saved text leaves nothing to write
+        self.modified = self.undo_tree.current() != self.saved_state;
+        Some(row)
+    }
+
+    /// Check whether edits are refused because the buffer is read-only, noting the attempt
+    /// so it can be reported
+    pub fn refuse_edit(&mut self)->bool{
+        if self.readonly {
+            self.edit_refused = true;
+        }
+        self.readonly
+    }
+
+    /// Record that the text has been edited
+    fn mark_modified(&mut self){
+        self.modified = true;
+        self.version += 1;
+    }
+
     fn lines_to_str(&self)-> String{
         let mut out_str = String::new();
         for idx in 0..self.num_lines {
@@ -194,3 +1461,766 @@ impl Buffer {
         out_str
     }
 }
+
+/// Reported when a file has invalid UTF-8, which is replaced so the rest can still be shown
+const NOT_UTF8: &str = "File isn't valid UTF-8, so it was opened read-only";
+
+/// Describe a failure to read all of a file
+fn read_failure(error: &Error)->String{
+    format!("Couldn't read all of the file ({error}), so it was opened read-only")
+}
+
+/// Count the words in the text, as runs of adjacent matches of the word pattern
+pub fn count_words(text: &str, word: &Regex)->usize{
+    let mut words = 0;
+    let mut previous_end = None;
+    for found in word.find_iter(text) {
+        if previous_end != Some(found.start()) {
+            words += 1;
+        }
+        previous_end = Some(found.end());
+    }
+    words
+}
+
+/// Turn a line read from a file (without its `\n`) into text, dropping the `\r` of a `\r\n`
+/// line ending. Invalid UTF-8 is replaced, and returns true alongside the text.
+pub(crate) fn decode_line(mut bytes: Vec<u8>)->(String, bool){
+    if bytes.last() == Some(&b'\r') {
+        bytes.pop();
+    }
+    match String::from_utf8(bytes) {
+        Ok(line) => (line, false),
+        Err(error) => (String::from_utf8_lossy(error.as_bytes()).into_owned(), true),
+    }
+}
+
+/// Convert `\r\n` and lone `\r` line endings to the `\n` used within buffers
+pub fn normalize_line_endings(text: &str)->Cow<'_, str>{
+    if !text.contains('\r') {
+        return Cow::Borrowed(text);
+    }
+    Cow::Owned(text.replace("\r\n", "\n").replace('\r', "\n"))
+}
+
+/// The opening and closing brackets of the pair a bracket belongs to, and whether its
+/// partner is found by scanning forward
+fn bracket_pair(bracket: &str)->Option<(&'static str, &'static str, bool)>{
+    Some(match bracket {
+        "(" => ("(", ")", true),
+        "[" => ("[", "]", true),
+        "{" => ("{", "}", true),
+        ")" => ("(", ")", false),
+        "]" => ("[", "]", false),
+        "}" => ("{", "}", false),
+        _ => return None,
+    })
+}
+
+/// Convert indentation to spaces, or to tabs (with spaces for any remainder), treating a tab
+/// as reaching the next multiple of width columns
+fn convert_indent(indent: &str, to_spaces: bool, width: usize)->String{
+    let width = width.max(1);
+    let columns = indent.chars().fold(0, |col, c| match c {
+        '\t' => (col / width + 1) * width,
+        _ => col + 1,
+    });
+    if to_spaces {
+        " ".repeat(columns)
+    } else {
+        format!("{}{}", "\t".repeat(columns / width), " ".repeat(columns % width))
+    }
+}
+
+/// Where the swap file for a file is kept: `.name.swp` in the directory if one is given,
+/// otherwise alongside the file
+pub fn swap_path(file_path: &Path, directory: Option<&Path>)->PathBuf{
+    let name = file_path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    let directory = match directory {
+        Some(directory) => directory.to_path_buf(),
+        None => file_path.parent().map(Path::to_path_buf).unwrap_or_default(),
+    };
+    directory.join(format!(".{name}.swp"))
+}
+
+/// Delete every swap file still left by a buffer, for when the editor exits abnormally
+pub fn remove_swap_files(){
+    if let Ok(mut swap_files) = SWAP_FILES.lock() {
+        for swap_file in swap_files.drain() {
+            let _ = remove_file(swap_file);
+        }
+    }
+}
+
+/// Modification time of the file at the path, if it exists
+fn modified_time(path: &PathBuf)->Option<SystemTime>{
+    metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(row: usize, grapheme: usize) -> TextPosition {
+        TextPosition { row, byte: grapheme, grapheme }
+    }
+
+    #[test]
+    fn from_reader_reads_unnamed_buffer() {
+        let mut buffer = Buffer::from_reader(&b"one\ntwo\n"[..]).unwrap();
+        assert_eq!(buffer.num_lines, 2);
+        assert_eq!(buffer.text[0].text, "one");
+        assert_eq!(buffer.text[1].text, "two");
+        assert!(!buffer.modified);
+        assert_eq!(buffer.path, PathBuf::new());
+        assert!(buffer.write_file().unwrap_err().to_string().starts_with("No file name"));
+    }
+
+    #[test]
+    fn readonly_insert_char_leaves_text() {
+        let mut buffer = Buffer::from_string("abc");
+        buffer.readonly = true;
+        buffer.insert_char(0, 1, 'x');
+        assert_eq!(buffer.text[0].text, "abc");
+        assert!(!buffer.modified);
+        assert!(buffer.edit_refused);
+    }
+
+    #[test]
+    fn readonly_undo_leaves_text() {
+        let mut buffer = Buffer::from_string("abc");
+        buffer.insert_char(0, 1, 'x');
+        buffer.commit_undo();
+        buffer.readonly = true;
+        assert!(buffer.undo().is_none());
+        assert!(buffer.earlier(1).is_none());
+        assert_eq!(buffer.text[0].text, "axbc");
+        assert!(buffer.edit_refused);
+        // The undo history didn't move, so undoing works once the buffer is writable
+        buffer.readonly = false;
+        assert_eq!(buffer.undo(), Some(0));
+        assert_eq!(buffer.text[0].text, "abc");
+    }
+
+    #[test]
+    fn streaming_load_completes() {
+        let path = std::env::temp_dir().join(format!("trout_streaming_{}.txt", std::process::id()));
+        let contents: Vec<String> = (0..2500).map(|idx| format!("line {idx}")).collect();
+        std::fs::write(&path, contents.join("\n")).unwrap();
+
+        let mut buffer = Buffer::from_file_streaming(path.clone(), 10);
+        assert_eq!(buffer.extension, Some("txt".to_string()));
+        assert!(buffer.num_lines >= 10);
+        assert_eq!(buffer.text[9].text, "line 9");
+        while !buffer.receive_lines() {
+            thread::yield_now();
+        }
+        assert_eq!(buffer.num_lines, 2500);
+        assert_eq!(buffer.text.len(), 2500);
+        assert_eq!(buffer.text[2499].text, "line 2499");
+        assert!(!buffer.modified);
+
+        let mut buffer = Buffer::from_file_streaming(path.clone(), 0);
+        buffer.finish_loading();
+        assert_eq!(buffer.num_lines, 2500);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn write_paged_file() {
+        let path = std::env::temp_dir().join(format!("trout_write_paged_{}.txt", std::process::id()));
+        let contents: Vec<String> = (0..2000).map(|idx| format!("line {idx}")).collect();
+        std::fs::write(&path, contents.join("\n")).unwrap();
+
+        let mut buffer = Buffer::from_file_paged(path.clone());
+        assert_eq!(buffer.num_lines, 2000);
+        assert!(buffer.text.is_paged());
+        buffer.insert_char(1500, 0, 'x');
+        buffer.commit_undo();
+        buffer.write_file().unwrap();
+        let mut expected = contents.clone();
+        expected[1500].insert(0, 'x');
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), format!("{}\n", expected.join("\n")));
+        // The lines read to write the file are let go, and paged from what was written
+        assert!(buffer.text.is_paged());
+        assert_eq!(buffer.text.loaded_lines(), 0);
+        assert_eq!(buffer.text[1500].text, "xline 1500");
+        assert!(!buffer.modified);
+        buffer.undo();
+        assert_eq!(buffer.text[1500].text, "line 1500");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn invalid_utf8_opens_read_only() {
+        let path = std::env::temp_dir().join(format!("trout_invalid_utf8_{}.txt", std::process::id()));
+        let mut contents: Vec<u8> = (0..1500).flat_map(|idx| format!("line {idx}\r\n").into_bytes()).collect();
+        contents.extend(b"bad \xff byte\nlast\n");
+        std::fs::write(&path, &contents).unwrap();
+
+        // The bad line is past the first lines, so it is found by the background load
+        let mut buffer = Buffer::from_file_streaming(path.clone(), 10);
+        assert!(!buffer.readonly);
+        buffer.finish_loading();
+        assert_eq!(buffer.num_lines, 1502);
+        assert_eq!(buffer.text[0].text, "line 0");
+        assert_eq!(buffer.text[1500].text, "bad \u{FFFD} byte");
+        assert_eq!(buffer.text[1501].text, "last");
+        assert!(buffer.readonly);
+        assert!(buffer.load_problem.as_deref().is_some_and(|problem| problem.contains("UTF-8")));
+
+        let buffer = Buffer::from_file_streaming(path.clone(), 2000);
+        assert_eq!(buffer.num_lines, 1502);
+        assert!(buffer.readonly);
+        let buffer = Buffer::from_file(path.clone());
+        assert_eq!(buffer.num_lines, 1502);
+        assert!(buffer.readonly);
+        let buffer = Buffer::from_file_paged(path.clone());
+        assert_eq!(buffer.num_lines, 1502);
+        assert_eq!(buffer.text[0].text, "line 0");
+        assert_eq!(buffer.text[1500].text, "bad \u{FFFD} byte");
+        assert!(buffer.readonly);
+        std::fs::remove_file(&path).unwrap();
+
+        // A file which doesn't exist yet isn't a problem
+        let buffer = Buffer::from_file_streaming(path.clone(), 10);
+        assert_eq!(buffer.num_lines, 0);
+        assert!(!buffer.readonly && buffer.load_problem.is_none());
+    }
+
+    #[test]
+    fn word_under_cursor() {
+        let buffer = Buffer::from_string("let total = add_one(x);");
+        assert_eq!(buffer.word_at(0, 14), Some(("add_one".to_string(), 12)));
+        assert_eq!(buffer.word_at(0, 12), Some(("add_one".to_string(), 12)));
+        assert_eq!(buffer.word_at(0, 3), None);
+        assert_eq!(buffer.word_at(1, 0), None);
+    }
+
+    fn object_range(buffer: &Buffer, kind: TextObject, row: usize, grapheme: usize)
+        -> Option<((usize, usize), (usize, usize))> {
+        buffer.text_object(kind, &position(row, grapheme))
+            .map(|(start, end)| ((start.row, start.grapheme), (end.row, end.grapheme)))
+    }
+
+    #[test]
+    fn inner_word_object() {
+        let buffer = Buffer::from_string("call(bar_baz, éx)");
+        assert_eq!(object_range(&buffer, TextObject::Word, 0, 6), Some(((0, 5), (0, 11))));
+        assert_eq!(object_range(&buffer, TextObject::Word, 0, 2), Some(((0, 0), (0, 3))));
+        assert_eq!(object_range(&buffer, TextObject::Word, 0, 4), Some(((0, 4), (0, 4))));
+        let found = buffer.text_object(TextObject::Word, &position(0, 15)).unwrap();
+        assert_eq!(found.0.byte, 14);
+    }
+
+    #[test]
+    fn inner_line_and_paragraph_objects() {
+        let buffer = Buffer::from_string("  first line  \nsecond\n\n\nthird");
+        assert_eq!(object_range(&buffer, TextObject::Line, 0, 0), Some(((0, 2), (0, 11))));
+        assert_eq!(object_range(&buffer, TextObject::Paragraph, 1, 2), Some(((0, 0), (1, 5))));
+        assert_eq!(object_range(&buffer, TextObject::Paragraph, 2, 0), Some(((2, 0), (3, 0))));
+        assert_eq!(object_range(&buffer, TextObject::Paragraph, 4, 0), Some(((4, 0), (4, 4))));
+    }
+
+    #[test]
+    fn inner_bracket_object() {
+        let buffer = Buffer::from_string("f(a, (b),\n  c)\ng()");
+        let inner = TextObject::Bracket('(');
+        assert_eq!(object_range(&buffer, inner, 1, 2), Some(((0, 2), (1, 2))));
+        assert_eq!(object_range(&buffer, inner, 0, 6), Some(((0, 6), (0, 6))));
+        assert_eq!(object_range(&buffer, inner, 0, 1), Some(((0, 2), (1, 2))));
+        assert_eq!(object_range(&buffer, inner, 1, 3), Some(((0, 2), (1, 2))));
+        assert_eq!(object_range(&buffer, inner, 2, 1), None);
+        assert_eq!(object_range(&buffer, inner, 2, 0), None);
+    }
+
+    #[test]
+    fn increment_and_decrement_numbers() {
+        let mut buffer = Buffer::from_string("x = 9;\nid 099\nn = -5\nmask 0x0f\nnone");
+        // The cursor may be before the number
+        assert!(buffer.modify_number(0, 0, 1));
+        assert_eq!(buffer.text[0].text, "x = 10;");
+        assert!(buffer.modify_number(1, 4, 1));
+        assert_eq!(buffer.text[1].text, "id 100");
+        assert!(buffer.modify_number(2, 4, 1));
+        assert_eq!(buffer.text[2].text, "n = -4");
+        assert!(buffer.modify_number(2, 4, -10));
+        assert_eq!(buffer.text[2].text, "n = -14");
+        assert!(buffer.modify_number(2, 4, 20));
+        assert_eq!(buffer.text[2].text, "n = 6");
+        assert!(buffer.modify_number(3, 0, 1));
+        assert_eq!(buffer.text[3].text, "mask 0x10");
+        assert!(!buffer.modify_number(4, 0, 1));
+        // A dash joined to a word is subtraction
+        let mut buffer = Buffer::from_string("a-1");
+        assert!(buffer.modify_number(0, 0, 1));
+        assert_eq!(buffer.text[0].text, "a-2");
+    }
+
+    #[test]
+    fn paste_block_across_lines() {
+        let mut buffer = Buffer::from_string("abcdef\nab\nabcdef");
+        let block = vec!["12".to_string(), "34".to_string(), "56".to_string()];
+        buffer.paste_block(position(0, 3), &block);
+        assert_eq!(buffer.text[0].text, "abc12def");
+        // Shorter lines are padded out to the column
+        assert_eq!(buffer.text[1].text, "ab 34");
+        assert_eq!(buffer.text[2].text, "abc56def");
+        // Rows past the end of the buffer are added
+        buffer.paste_block(position(2, 1), &block);
+        assert_eq!(buffer.num_lines, 5);
+        assert_eq!(buffer.text[2].text, "a12bc56def");
+        assert_eq!(buffer.text[3].text, " 34");
+        assert_eq!(buffer.text[4].text, " 56");
+    }
+
+    #[test]
+    fn copy_rect_round_trip() {
+        let mut buffer = Buffer::from_string("abcd\nef\nghij");
+        let block = buffer.copy_rect(position(0, 1), position(2, 2));
+        assert_eq!(block, vec!["bc", "f", "hi"]);
+        buffer.paste_block(position(0, 4), &block);
+        assert_eq!(buffer.text[0].text, "abcdbc");
+        assert_eq!(buffer.text[1].text, "ef  f");
+    }
+
+    #[test]
+    fn delete_char_keeps_empty_lines() {
+        let mut buffer = Buffer::from_string("a\n\nb");
+        buffer.delete_char(1, 0);
+        assert_eq!(buffer.num_lines, 3);
+        assert!(!buffer.modified);
+        buffer.delete_char(0, 0);
+        buffer.delete_char(0, 0);
+        assert_eq!(buffer.num_lines, 3);
+        assert_eq!(buffer.text[0].text, "");
+        let removed = buffer.delete_line(1);
+        assert_eq!(removed.text, "");
+        assert_eq!(buffer.num_lines, 2);
+        assert_eq!(buffer.text[1].text, "b");
+    }
+
+    #[test]
+    fn delete_last_remaining_line() {
+        let mut buffer = Buffer::from_string("only");
+        assert_eq!(buffer.delete_line(0).text, "only");
+        assert_eq!(buffer.num_lines, 1);
+        assert_eq!(buffer.text[0].text, "");
+        assert_eq!(buffer.delete_line(3).text, "");
+        assert_eq!(buffer.num_lines, 1);
+        buffer.insert_lines(1, "a\n");
+        let text: Vec<&str> = buffer.text.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(text, ["", "a", ""]);
+    }
+
+    #[test]
+    fn scroll_position() {
+        let lines: Vec<String> = (0..100).map(|n| n.to_string()).collect();
+        let buffer = Buffer::from_string(&lines.join("\n"));
+        assert_eq!(buffer.scroll_label(0, 10), "Top");
+        assert_eq!(buffer.scroll_percentage(0, 10), 0);
+        assert_eq!(buffer.scroll_label(45, 10), "50%");
+        assert_eq!(buffer.scroll_percentage(45, 10), 50);
+        assert_eq!(buffer.scroll_label(90, 10), "Bot");
+        assert_eq!(buffer.scroll_percentage(90, 10), 100);
+        // Short files fit entirely
+        let buffer = Buffer::from_string("one\ntwo");
+        assert_eq!(buffer.scroll_label(0, 10), "All");
+    }
+
+    #[test]
+    fn replace_matches() {
+        let mut buffer = Buffer::from_string("a cat and a cat\nno match\ncat");
+        let pattern = Regex::new("c(a)t").unwrap();
+        assert_eq!(buffer.replace_all(0..3, &pattern, "d${1}g", false), 2);
+        assert_eq!(buffer.text[0].text, "a dag and a cat");
+        assert_eq!(buffer.text[2].text, "dag");
+        assert_eq!(buffer.replace_all(0..1, &pattern, "x", true), 1);
+        assert_eq!(buffer.text[0].text, "a dag and a x");
+        assert_eq!(buffer.text[0].grapheme_count, 13);
+        assert_eq!(buffer.replace_all(1..2, &pattern, "x", true), 0);
+        buffer.replace_range(1, 3..8, "other");
+        assert_eq!(buffer.text[1].text, "no other");
+    }
+
+    #[test]
+    fn join_lines() {
+        let mut buffer = Buffer::from_string("naïve café\n    crème brûlée\nend \n\nlast");
+        assert_eq!(buffer.join_lines(0), Some(10));
+        assert_eq!(buffer.text[0].text, "naïve café crème brûlée");
+        assert_eq!(buffer.text[0].grapheme_count, 23);
+        assert_eq!(buffer.text[0].grapheme(11), Some("c"));
+        assert_eq!(buffer.text[0].grapheme(22), Some("e"));
+        assert_eq!(buffer.num_lines, 4);
+        // No extra space after trailing whitespace, or when joining an empty line
+        assert_eq!(buffer.join_lines(1), Some(4));
+        assert_eq!(buffer.text[1].text, "end ");
+        assert_eq!(buffer.join_lines(1), Some(4));
+        assert_eq!(buffer.text[1].text, "end last");
+        assert_eq!(buffer.join_lines(1), None);
+        assert_eq!(buffer.num_lines, 2);
+    }
+
+    #[test]
+    fn delete_to_line_end() {
+        let mut buffer = Buffer::from_string("a日本語b🦀c");
+        assert_eq!(buffer.delete_to_line_end(0, 3), "語b🦀c");
+        assert_eq!(buffer.text[0].text, "a日本");
+        assert_eq!(buffer.text[0].grapheme_count, 3);
+        assert_eq!(buffer.text[0].grapheme_end(2), 6);
+        assert!(buffer.modified);
+        buffer.modified = false;
+        assert_eq!(buffer.delete_to_line_end(0, 3), "");
+        assert!(!buffer.modified);
+    }
+
+    #[test]
+    fn retab_indentation() {
+        let mut buffer = Buffer::from_string("fn main() {\n\tlet a = \"\tx\";\n\t\tcall();\n}");
+        buffer.retab(true, 4);
+        assert!(buffer.modified);
+        assert_eq!(buffer.text[1].text, "    let a = \"\tx\";");
+        assert_eq!(buffer.text[2].text, "        call();");
+        assert_eq!(buffer.text[2].grapheme_count, 15);
+        buffer.retab(false, 4);
+        assert_eq!(buffer.text[1].text, "\tlet a = \"\tx\";");
+        assert_eq!(buffer.text[2].text, "\t\tcall();");
+        // Partial tab stops keep their spaces
+        let mut buffer = Buffer::from_string("  \tx\n      y");
+        buffer.retab(false, 4);
+        assert_eq!(buffer.text[0].text, "\tx");
+        assert_eq!(buffer.text[1].text, "\t  y");
+    }
+
+    #[test]
+    fn replace_lines() {
+        let mut buffer = Buffer::from_string("a\nb\nc");
+        buffer.replace_lines(1..2, "x\ny\n");
+        assert_eq!(buffer.num_lines, 4);
+        assert_eq!(buffer.text[2].text, "y");
+        // An empty range inserts
+        buffer.replace_lines(4..4, "end");
+        assert_eq!(buffer.text[4].text, "end");
+        buffer.replace_lines(0..5, "");
+        assert_eq!(buffer.num_lines, 0);
+    }
+
+    #[test]
+    fn reflow_long_paragraph() {
+        let mut buffer = Buffer::from_string(
+            "before\n\nThe quick brown fox jumps over the lazy dog, then the dog wakes up and chases \
+            the fox all the way back\nto its den.\n\nafter");
+        assert_eq!(buffer.reflow_paragraph(3, 40), Some(2..5));
+        let text: Vec<&str> = buffer.text.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(text, [
+            "before",
+            "",
+            "The quick brown fox jumps over the lazy",
+            "dog, then the dog wakes up and chases",
+            "the fox all the way back to its den.",
+            "",
+            "after",
+        ]);
+        assert!(buffer.text.iter().all(|line| line.grapheme_count <= 40));
+        assert_eq!(buffer.reflow_paragraph(1, 40), None);
+    }
+
+    #[test]
+    fn reflow_comment_block() {
+        let mut buffer = Buffer::from_string(
+            "    // Short\n    // lines of a comment which\n    // should be joined together\n    //\n    // Next\nfn main() {}");
+        assert_eq!(buffer.reflow_paragraph(1, 30), Some(0..3));
+        let text: Vec<&str> = buffer.text.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(text, [
+            "    // Short lines of a",
+            "    // comment which should be",
+            "    // joined together",
+            "    //",
+            "    // Next",
+            "fn main() {}",
+        ]);
+    }
+
+    #[test]
+    fn paste_normalizes_line_endings() {
+        assert_eq!(normalize_line_endings("a\r\nb\rc\n"), "a\nb\nc\n");
+        let mut buffer = Buffer::from_string("ab");
+        buffer.paste_text(position(0, 1), "x\r\ny\r\n");
+        let text: Vec<&str> = buffer.text.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(text, ["ax", "y", "b"]);
+        let mut buffer = Buffer::from_string("ab");
+        buffer.paste_text(position(0, 1), "x\ry\rz");
+        let text: Vec<&str> = buffer.text.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(text, ["ax", "y", "zb"]);
+        assert!(buffer.text.iter().all(|line| !line.text.contains('\r')));
+        assert_eq!(buffer.num_lines, 3);
+    }
+
+    #[test]
+    fn change_status_since_save() {
+        let path = std::env::temp_dir().join(format!("trout_change_status_{}", std::process::id()));
+        std::fs::write(&path, "one\ntwo\nthree\n").unwrap();
+        let mut buffer = Buffer::from_file(path.clone());
+        assert_eq!(buffer.line_change_status(1), ChangeKind::Unchanged);
+        buffer.insert_char(1, 0, 'x');
+        assert_eq!(buffer.line_change_status(1), ChangeKind::Modified);
+        assert_eq!(buffer.line_change_status(0), ChangeKind::Unchanged);
+        buffer.new_line(3, 0);
+        assert_eq!(buffer.line_change_status(3), ChangeKind::Added);
+        buffer.write_file().unwrap();
+        assert_eq!(buffer.line_change_status(1), ChangeKind::Unchanged);
+        assert_eq!(buffer.line_change_status(3), ChangeKind::Unchanged);
+        // Moving through the history compares against the saved state, not the loaded one
+        buffer.undo();
+        assert_eq!(buffer.line_change_status(1), ChangeKind::Modified);
+        assert_eq!(buffer.line_change_status(2), ChangeKind::Removed);
+        buffer.redo();
+        assert_eq!(buffer.line_change_status(1), ChangeKind::Unchanged);
+        assert!(!buffer.modified);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn fold_range_for_indented_block() {
+        let buffer = Buffer::from_string("fn main() {\n    let a = 1;\n\n    if a {\n        a;\n    }\n\n}\nfn b() {}");
+        assert_eq!(buffer.indent_fold_range(0), Some(0..6));
+        assert_eq!(buffer.indent_fold_range(3), Some(3..5));
+        assert_eq!(buffer.indent_fold_range(1), None);
+        assert_eq!(buffer.indent_fold_range(8), None);
+    }
+
+    #[test]
+    fn matching_bracket() {
+        let buffer = Buffer::from_string("fn a(b: (u8, u8)) {\n    [b]\n}");
+        let found = buffer.find_matching_bracket(&position(0, 4)).unwrap();
+        assert_eq!((found.row, found.grapheme), (0, 16));
+        let found = buffer.find_matching_bracket(&position(0, 15)).unwrap();
+        assert_eq!((found.row, found.grapheme), (0, 8));
+        let found = buffer.find_matching_bracket(&position(0, 18)).unwrap();
+        assert_eq!((found.row, found.grapheme), (2, 0));
+        let found = buffer.find_matching_bracket(&position(2, 0)).unwrap();
+        assert_eq!((found.row, found.grapheme), (0, 18));
+        assert!(buffer.find_matching_bracket(&position(0, 1)).is_none());
+        assert!(Buffer::from_string("(()").find_matching_bracket(&position(0, 0)).is_none());
+    }
+
+    #[test]
+    fn undo_and_redo_edits() {
+        let mut buffer = Buffer::from_string("one\ntwo");
+        buffer.insert_char(0, 3, '!');
+        buffer.insert_char(0, 4, '!');
+        buffer.commit_undo();
+        buffer.delete_line(1);
+        assert_eq!(buffer.undo(), Some(1));
+        assert_eq!(buffer.num_lines, 2);
+        assert_eq!(buffer.text[0].text, "one!!");
+        // Both inserted characters were one step
+        assert_eq!(buffer.undo(), Some(0));
+        assert_eq!(buffer.text[0].text, "one");
+        assert!(!buffer.modified);
+        assert!(buffer.undo().is_none());
+        assert_eq!(buffer.redo(), Some(0));
+        assert_eq!(buffer.text[0].text, "one!!");
+        assert!(buffer.modified);
+        // A new edit branches off rather than discarding the line deletion
+        buffer.insert_char(1, 0, '>');
+        assert_eq!(buffer.earlier(1), Some(1));
+        assert_eq!(buffer.num_lines, 1);
+        assert_eq!(buffer.later(1), Some(1));
+        assert_eq!(buffer.text[1].text, ">two");
+    }
+
+    #[test]
+    fn undo_history_persists() {
+        let directory = std::env::temp_dir().join(format!("trout_undo_dir_{}", std::process::id()));
+        let file = std::env::temp_dir().join(format!("trout_undo_file_{}", std::process::id()));
+        std::fs::write(&file, "one\n").unwrap();
+        let open = || {
+            let mut buffer = Buffer::from_file(file.clone());
+            buffer.undo_directory = Some(directory.clone());
+            buffer
+        };
+        let mut buffer = open();
+        assert!(!buffer.read_undo_file().unwrap());
+        buffer.insert_char(0, 3, '!');
+        buffer.commit_undo();
+        buffer.replace_lines(1..1, "two");
+        buffer.write_file().unwrap();
+
+        // A later session can undo the edits made by this one
+        let mut buffer = open();
+        assert!(buffer.read_undo_file().unwrap());
+        assert_eq!(buffer.undo(), Some(1));
+        assert_eq!(buffer.num_lines, 1);
+        assert_eq!(buffer.undo(), Some(0));
+        assert_eq!(buffer.text[0].text, "one");
+        assert!(buffer.undo().is_none());
+
+        // Changing the file outside the editor invalidates the history
+        std::fs::write(&file, "changed\n").unwrap();
+        let mut buffer = open();
+        assert!(!buffer.read_undo_file().unwrap());
+        assert!(buffer.undo().is_none());
+        std::fs::remove_file(&file).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn copy_text_across_lines() {
+        let buffer = Buffer::from_string("héllo world\n\nsecond line");
+        assert_eq!(buffer.copy_text(position(0, 1), position(0, 4)), "éllo");
+        assert_eq!(buffer.copy_text(position(0, 6), position(2, 5)), "world\n\nsecond");
+        assert_eq!(buffer.copy_text(position(1, 0), position(1, 0)), "");
+    }
+
+    #[test]
+    fn delete_range_on_one_line() {
+        let mut buffer = Buffer::from_string("héllo wörld\nnext");
+        assert_eq!(buffer.delete_range(position(0, 1), position(0, 6)), "éllo w");
+        assert_eq!(buffer.text[0], Line::from_string("hörld"));
+        assert_eq!(buffer.num_lines, 2);
+        assert!(buffer.modified);
+    }
+
+    #[test]
+    fn delete_range_across_three_lines() {
+        let mut buffer = Buffer::from_string("première ligne\nçà et là\ndernière ligne\nfin");
+        assert_eq!(buffer.delete_range(position(0, 3), position(2, 7)), "mière ligne\nçà et là\ndernière");
+        assert_eq!(buffer.text[0], Line::from_string("pre ligne"));
+        assert_eq!(buffer.text[1].text, "fin");
+        assert_eq!(buffer.num_lines, 2);
+        // Deleting back what was copied leaves the text as it was
+        let mut buffer = Buffer::from_string("héllo world\n\nsecond line");
+        let copied = buffer.copy_text(position(0, 6), position(2, 5));
+        assert_eq!(buffer.delete_range(position(0, 6), position(2, 5)), copied);
+        assert_eq!(buffer.text[0].text, "héllo  line");
+    }
+
+    #[test]
+    fn new_line_splits_below() {
+        let mut buffer = Buffer::from_string("abcdef\nnext");
+        assert_eq!(buffer.new_line(0, 3), position(1, 0));
+        assert_eq!(buffer.text[0].text, "abc");
+        assert_eq!(buffer.text[1].text, "def");
+        assert_eq!(buffer.text[2].text, "next");
+        assert_eq!(buffer.num_lines, 3);
+        // Past the last line an empty line is added
+        assert_eq!(buffer.new_line(3, 0), position(3, 0));
+        assert_eq!(buffer.text[3].text, "");
+    }
+
+    #[test]
+    fn print_line_ranges() {
+        let mut buffer = Buffer::from_string("héllo\nx\n\nend");
+        assert_eq!(buffer.print_line(0, 0, 10, false), "héllo");
+        assert_eq!(buffer.print_line(0, 0, 4, false), "héllo");
+        assert_eq!(buffer.print_line(0, 1, 3, false), "éll");
+        assert_eq!(buffer.print_line(0, 4, 4, false), "o");
+        assert_eq!(buffer.print_line(0, 5, 10, false), "");
+        assert_eq!(buffer.print_line(1, 0, 10, false), "x");
+        assert_eq!(buffer.print_line(2, 0, 10, false), "");
+    }
+
+    #[test]
+    fn count_stats() {
+        let mut buffer = Buffer::from_string("hello wörld\n\n  one two-three");
+        let word = Regex::new(r"\w").unwrap();
+        assert_eq!(buffer.stats(&word), BufferStats { lines: 3, words: 5, graphemes: 26, bytes: 27 });
+        // Counting again gives the same result, and leaves the buffer unmodified
+        assert_eq!(buffer.stats(&word).words, 5);
+        assert!(!buffer.modified);
+    }
+
+    #[test]
+    fn new_line_keeps_indentation() {
+        let mut buffer = Buffer::from_string("    let x = 1;\n\tfoo(bar)");
+        assert_eq!(buffer.new_line_autoindent(0, 8, true, 4), 4);
+        assert_eq!(buffer.text[0].text, "    let ");
+        assert_eq!(buffer.text[1].text, "    x = 1;");
+        // Indentation follows the tab settings
+        assert_eq!(buffer.new_line_autoindent(2, 5, true, 4), 4);
+        assert_eq!(buffer.text[3].text, "    bar)");
+        assert_eq!(buffer.new_line_autoindent(3, 4, false, 4), 1);
+        assert_eq!(buffer.text[4].text, "\tbar)");
+        assert_eq!(buffer.num_lines, 5);
+    }
+
+    #[test]
+    fn swap_file_written_and_removed() {
+        let file = std::env::temp_dir().join(format!("trout_swap_{}.txt", std::process::id()));
+        std::fs::write(&file, "saved\n").unwrap();
+        let swap = swap_path(&file, None);
+        assert_eq!(swap.file_name().unwrap().to_string_lossy(), format!(".trout_swap_{}.txt.swp", std::process::id()));
+
+        let mut buffer = Buffer::from_file(file.clone());
+        buffer.swap_path = Some(swap.clone());
+        // Nothing is written until the buffer is edited
+        assert!(!buffer.update_swap(Duration::from_secs(60)).unwrap());
+        buffer.insert_char(0, 0, 'x');
+        assert!(buffer.update_swap(Duration::from_secs(60)).unwrap());
+        buffer.finish_swap_write().unwrap();
+        assert_eq!(read_to_string(&swap).unwrap(), "xsaved\n");
+        // Later edits wait for the interval
+        buffer.insert_char(0, 0, 'y');
+        assert!(!buffer.update_swap(Duration::from_secs(60)).unwrap());
+        assert!(buffer.update_swap(Duration::ZERO).unwrap());
+        // Nothing more is written until there are new edits
+        buffer.finish_swap_write().unwrap();
+        assert!(!buffer.update_swap(Duration::ZERO).unwrap());
+        assert_eq!(read_to_string(&swap).unwrap(), "yxsaved\n");
+        buffer.write_file().unwrap();
+        assert!(!swap.exists());
+
+        // A swap file left behind by a crash can be recovered
+        std::fs::write(&swap, "recovered\n").unwrap();
+        let an_hour_ago = SystemTime::now() - Duration::from_secs(3600);
+        File::options().write(true).open(&file).unwrap().set_modified(an_hour_ago).unwrap();
+        let mut buffer = Buffer::from_file(file.clone());
+        buffer.swap_path = Some(swap.clone());
+        assert!(buffer.recovery_available());
+        buffer.recover_swap().unwrap();
+        assert_eq!(buffer.text[0].text, "recovered");
+        assert!(buffer.modified);
+        // Removing the swap only removes one this buffer wrote
+        buffer.remove_swap();
+        assert!(swap.exists());
+        std::fs::remove_file(&swap).unwrap();
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    /// Benchmark for large files, run with `cargo test --release -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn edit_million_line_file() {
+        use std::time::Instant;
+        let file = std::env::temp_dir().join(format!("trout_million_lines_{}", std::process::id()));
+        let text: String = (0..1_000_000).map(|idx| format!("line number {idx}\n")).collect();
+        std::fs::write(&file, text).unwrap();
+
+        let start = Instant::now();
+        let mut buffer = Buffer::from_file(file.clone());
+        println!("load 1M lines: {:?}", start.elapsed());
+        assert_eq!(buffer.num_lines, 1_000_000);
+
+        let start = Instant::now();
+        for idx in 0..1000 {
+            buffer.new_line(500_000 + idx, 4);
+            buffer.insert_char(500_000 + idx, 0, 'x');
+            buffer.merge_next_line(500_000 + idx);
+            buffer.commit_undo();
+        }
+        println!("1000 line splits and joins: {:?}", start.elapsed());
+        assert_eq!(buffer.undo_tree.len(), 1000);
+
+        let start = Instant::now();
+        assert_eq!(buffer.line_change_status(500_000), ChangeKind::Modified);
+        assert_eq!(buffer.line_change_status(0), ChangeKind::Unchanged);
+        println!("change status: {:?}", start.elapsed());
+
+        let start = Instant::now();
+        let total: usize = (0..1_000_000).step_by(997).map(|row| buffer.text[row].grapheme_count).sum();
+        println!("random access: {:?}", start.elapsed());
+        assert!(total > 0);
+        std::fs::remove_file(&file).unwrap();
+    }
+}
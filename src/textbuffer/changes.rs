@@ -0,0 +1,112 @@
+use std::borrow::Borrow;
+use crate::textbuffer::lines::Line;
+
+/// How a line differs from the last saved version of the buffer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChangeKind {
+    Unchanged,
+    /// The line is new
+    Added,
+    /// The line replaced a saved line
+    Modified,
+    /// Saved lines were removed just before this line
+    Removed,
+}
+
+/// Above this many line comparisons, a changed region is marked as modified without finding
+/// exactly which lines changed
+const MAX_DIFF_CELLS: usize = 1_000_000;
+
+/// Compare the current lines with the saved ones, finding the change status of each current line
+pub fn diff_lines<L: Borrow<Line>>(saved: &[String], current: &[L]) -> Vec<ChangeKind> {
+    let current: Vec<&Line> = current.iter().map(Borrow::borrow).collect();
+    let mut status = vec![ChangeKind::Unchanged; current.len()];
+    // Edits are usually local, so only the region between the unchanged start and end is diffed
+    let prefix = saved.iter().zip(&current).take_while(|(old, new)| **old == new.text).count();
+    let suffix = saved[prefix..].iter().rev().zip(current[prefix..].iter().rev())
+        .take_while(|(old, new)| **old == new.text)
+        .count();
+    let old = &saved[prefix..saved.len() - suffix];
+    let new = &current[prefix..current.len() - suffix];
+    if old.len().saturating_mul(new.len()) > MAX_DIFF_CELLS {
+        status[prefix..prefix + new.len()].fill(ChangeKind::Modified);
+        return status;
+    }
+    // Longest common subsequence table, where lcs[i][j] covers old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j].text {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    // Walk the table, collecting runs of removed and inserted lines between matching ones
+    let (mut i, mut j) = (0, 0);
+    let (mut removed, mut inserted) = (0, 0);
+    loop {
+        let matched = i < old.len() && j < new.len() && old[i] == new[j].text;
+        if matched || (i == old.len() && j == new.len()) {
+            mark_run(&mut status, prefix + j, removed, inserted);
+            removed = 0;
+            inserted = 0;
+            if !matched {
+                break;
+            }
+            i += 1;
+            j += 1;
+        } else if j < new.len() && (i == old.len() || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            inserted += 1;
+            j += 1;
+        } else {
+            removed += 1;
+            i += 1;
+        }
+    }
+    status
+}
+
+/// Mark a run of inserted lines ending just before `end`, which replaced `removed` saved lines.
+/// Inserted lines pair up with removed ones as modifications, and any removed lines left over
+/// are shown on the line after the run (or before it, at the end of the buffer).
+fn mark_run(status: &mut [ChangeKind], end: usize, removed: usize, inserted: usize) {
+    let start = end - inserted;
+    for (offset, row) in (start..end).enumerate() {
+        status[row] = if offset < removed { ChangeKind::Modified } else { ChangeKind::Added };
+    }
+    if removed > inserted {
+        let row = if end < status.len() { end } else { end.saturating_sub(1) };
+        if let Some(kind @ ChangeKind::Unchanged) = status.get_mut(row) {
+            *kind = ChangeKind::Removed;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &[&str]) -> Vec<Line> {
+        text.iter().map(|line| Line::from_string(line)).collect()
+    }
+
+    fn saved(text: &[&str]) -> Vec<String> {
+        text.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn diff_statuses() {
+        use ChangeKind::*;
+        let old = saved(&["a", "b", "c", "d"]);
+        assert_eq!(diff_lines(&old, &lines(&["a", "b", "c", "d"])), [Unchanged; 4]);
+        assert_eq!(diff_lines(&old, &lines(&["a", "B", "c", "d"])), [Unchanged, Modified, Unchanged, Unchanged]);
+        assert_eq!(diff_lines(&old, &lines(&["a", "b", "new", "c", "d"])),
+                   [Unchanged, Unchanged, Added, Unchanged, Unchanged]);
+        assert_eq!(diff_lines(&old, &lines(&["a", "d"])), [Unchanged, Removed]);
+        assert_eq!(diff_lines(&old, &lines(&["a", "b", "c"])), [Unchanged, Unchanged, Removed]);
+        assert_eq!(diff_lines(&old, &lines(&["x", "y", "a", "b", "c", "D"])),
+                   [Added, Added, Unchanged, Unchanged, Unchanged, Modified]);
+    }
+}
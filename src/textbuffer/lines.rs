@@ -1,5 +1,6 @@
 use std::ops::Range;
 use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 /// Represents a line of utf-8 encoded text
 #[derive(Debug, Clone)]
@@ -12,6 +13,8 @@ pub struct Line {
     grapheme_starts: Vec<usize>,
     /// The end byte for graphemes in the text
     grapheme_ends: Vec<usize>,
+    /// The number of terminal cells each grapheme occupies when displayed
+    grapheme_widths: Vec<usize>,
 }
 
 
@@ -20,9 +23,11 @@ impl Line {
         let mut grapheme_count: usize = 0;
         let mut grapheme_starts: Vec<usize> = Vec::new();
         let mut grapheme_ends: Vec<usize> = Vec::new();
+        let mut grapheme_widths: Vec<usize> = Vec::new();
 
-        for (index, _) in UnicodeSegmentation::grapheme_indices(in_string, true) {
+        for (index, grapheme) in UnicodeSegmentation::grapheme_indices(in_string, true) {
             grapheme_count += 1;
+            grapheme_widths.push(grapheme.width());
 
             // After skipping the first iteration, start adding index-1 to grapheme ends
             if grapheme_starts.len() > 0 {
@@ -40,6 +45,7 @@ impl Line {
             grapheme_count,
             grapheme_starts,
             grapheme_ends,
+            grapheme_widths,
         }
     }
 
@@ -60,6 +66,7 @@ impl Line {
             // Insert the correct grapheme start and end
             self.grapheme_starts.insert(0, 0);
             self.grapheme_ends.insert(0, grapheme_length - 1);
+            self.grapheme_widths.insert(0, character.width().unwrap_or(0));
             // Update grapheme count
             self.grapheme_count += 1;
         } else if grapheme_index == self.grapheme_count {
@@ -69,6 +76,7 @@ impl Line {
             // Since its inserted at the end, only need to update the last ones
             self.grapheme_starts.push(self.grapheme_ends.last().unwrap_or(&0usize) + 1);
             self.grapheme_ends.push(self.grapheme_starts.last().unwrap_or(&0usize) + grapheme_length - 1);
+            self.grapheme_widths.push(character.width().unwrap_or(0));
             // Add one to the grapheme count
             self.grapheme_count += 1;
         } else {
@@ -83,42 +91,23 @@ impl Line {
             // Insert grapheme boundaries of inserted character
             self.grapheme_starts.insert(grapheme_index, text_position);
             self.grapheme_ends.insert(grapheme_index, text_position + grapheme_length - 1);
+            self.grapheme_widths.insert(grapheme_index, character.width().unwrap_or(0));
             // Update grapheme count
             self.grapheme_count += 1;
         }
     }
 
-    /// Insert a str into the line at the specified grapheme index
+    /// Insert a str into the line at the specified grapheme index, or at the end if the
+    /// index is past it
     pub fn insert_str(&mut self, grapheme_index: usize, insert_str: &str) {
-        if self.grapheme_count == 0 {
-            // Essentially just use the from_string method to generate a new line,
-            // then copy all the properties into self
-            let new_text = Line::from_string(insert_str);
-            self.text = new_text.text;
-            self.grapheme_ends = new_text.grapheme_ends;
-            self.grapheme_starts = new_text.grapheme_starts;
-            self.grapheme_count = new_text.grapheme_count;
-        }
-        // Use the from_string method to find the grapheme locations
-        let new_text = Line::from_string(insert_str);
-        let insert_idx = self.grapheme_starts[grapheme_index];
-        self.text.insert_str(insert_idx, insert_str);
-
-        // Update the grapheme indices and count
-        let insert_len = match new_text.grapheme_ends.last(){
-            None => {0usize}
-            Some(v)=> v+1usize
+        let insert_idx = match self.grapheme_starts.get(grapheme_index) {
+            Some(start) => *start,
+            None => self.text.len(),
         };
-        for idx in grapheme_index..self.grapheme_count{
-            self.grapheme_starts[idx]+=insert_len;
-            self.grapheme_ends[idx]+=insert_len;
-        }
-
-        let insert_starts:Vec<usize> = new_text.grapheme_starts.iter().map(|x| {x+insert_idx}).collect();
-        let insert_ends:Vec<usize> = new_text.grapheme_ends.iter().map(|x| {x+insert_idx}).collect();
-        self.grapheme_starts.splice(grapheme_index..grapheme_index, insert_starts);
-        self.grapheme_ends.splice(grapheme_index..grapheme_index, insert_ends);
-        self.grapheme_count += new_text.grapheme_count;
+        // Rebuild the grapheme tables, as the inserted text may combine with its neighbours
+        let mut text = self.text.clone();
+        text.insert_str(insert_idx, insert_str);
+        *self = Line::from_string(&text);
     }
 
     /// Delete the grapheme at the specified index
@@ -129,15 +118,145 @@ impl Line {
         }
         self.text.replace_range(self.grapheme_starts[grapheme_index]..=self.grapheme_ends[grapheme_index], "");
         let grapheme_length = (self.grapheme_ends[grapheme_index] - self.grapheme_starts[grapheme_index]) + 1;
-        for idx in grapheme_index..self.grapheme_count {
+        // Shift the graphemes after the deleted one back
+        for idx in (grapheme_index + 1)..self.grapheme_count {
             self.grapheme_starts[idx] -= grapheme_length;
             self.grapheme_ends[idx] -= grapheme_length;
         }
         self.grapheme_starts.remove(grapheme_index);
         self.grapheme_ends.remove(grapheme_index);
+        self.grapheme_widths.remove(grapheme_index);
         self.grapheme_count -= 1;
     }
 
+    /// Replace the grapheme at the specified index with a character, which may be a different
+    /// number of bytes. Nothing is replaced if there is no grapheme there.
+    pub fn replace_grapheme(&mut self, grapheme_index: usize, character: char) {
+        if grapheme_index >= self.grapheme_count {
+            return;
+        }
+        self.delete_grapheme(grapheme_index);
+        self.insert_char(grapheme_index, character);
+    }
+
+    /// Whether the line has more graphemes than the threshold, in which case expensive
+    /// display features (highlighting, wrapping) should be skipped for it
+    pub fn is_overlong(&self, threshold: usize) -> bool {
+        self.grapheme_count > threshold
+    }
+
+    /// Number of whitespace graphemes at the start of the line
+    pub fn leading_whitespace(&self) -> usize {
+        self.text.chars().take_while(|c| c.is_whitespace()).count()
+    }
+
+    /// Remove the spaces and tabs at the end of the line, returning whether there were any
+    pub fn trim_trailing_whitespace(&mut self) -> bool {
+        let content_end = self.text.trim_end_matches([' ', '\t']).len();
+        if content_end == self.text.len() {
+            return false;
+        }
+        self.text.truncate(content_end);
+        *self = Line::from_string(&self.text);
+        true
+    }
+
+    /// Add the whitespace to the start of the line
+    pub fn add_indent(&mut self, indent: &str) {
+        self.insert_str(0, indent);
+    }
+
+    /// Remove up to `columns` columns of whitespace from the start of the line, with a tab
+    /// reaching the next multiple of `tab_width` columns. A tab reaching past `columns` is
+    /// removed, leaving spaces for the columns it covered beyond them. Returns whether any
+    /// whitespace was removed.
+    pub fn remove_indent(&mut self, columns: usize, tab_width: usize) -> bool {
+        let tab_width = tab_width.max(1);
+        let mut removed_columns = 0;
+        let mut removed = 0;
+        for c in self.text.chars() {
+            if removed_columns >= columns {
+                break;
+            }
+            removed_columns = match c {
+                '\t' => (removed_columns / tab_width + 1) * tab_width,
+                ' ' => removed_columns + 1,
+                _ => break,
+            };
+            removed += 1;
+        }
+        if removed == 0 {
+            return false;
+        }
+        // Spaces and tabs are each a single byte grapheme
+        let remainder = " ".repeat(removed_columns.saturating_sub(columns));
+        *self = Line::from_string(&format!("{remainder}{}", &self.text[removed..]));
+        true
+    }
+
+    /// Whether the line is empty or contains only whitespace
+    pub fn is_blank(&self) -> bool {
+        self.text.trim().is_empty()
+    }
+
+    /// The text of the grapheme at the specified index, if there is one
+    pub fn grapheme(&self, grapheme_index: usize) -> Option<&str> {
+        if grapheme_index >= self.grapheme_count {
+            return None;
+        }
+        Some(&self.text[self.grapheme_starts[grapheme_index]..=self.grapheme_ends[grapheme_index]])
+    }
+
+    /// The number of terminal cells the grapheme at the specified index occupies, with a tab
+    /// reaching the next multiple of `tab_width` columns, zero if there is no grapheme there
+    pub fn grapheme_width(&self, grapheme_index: usize, tab_width: usize) -> usize {
+        if grapheme_index >= self.grapheme_count {
+            return 0;
+        }
+        self.cell_width(grapheme_index, self.display_column(grapheme_index, tab_width), tab_width)
+    }
+
+    /// The column the grapheme at the specified index is displayed from, counting from the
+    /// start of the line with each tab reaching the next multiple of `tab_width` columns
+    pub fn display_column(&self, grapheme_index: usize, tab_width: usize) -> usize {
+        (0..grapheme_index.min(self.grapheme_count))
+            .fold(0, |column, idx| column + self.cell_width(idx, column, tab_width))
+    }
+
+    /// The number of terminal cells the graphemes in the range occupy when displayed, with
+    /// each tab reaching the next multiple of `tab_width` columns
+    pub fn display_width(&self, graphemes: Range<usize>, tab_width: usize) -> usize {
+        let end = graphemes.end.min(self.grapheme_count);
+        let start = graphemes.start.min(end);
+        let start_column = self.display_column(start, tab_width);
+        (start..end).fold(start_column, |column, idx| column + self.cell_width(idx, column, tab_width)) - start_column
+    }
+
+    /// The index just past the last grapheme which fits, starting from `start_grapheme`,
+    /// within `width` terminal cells, with each tab reaching the next multiple of `tab_width`
+    /// columns
+    pub fn graphemes_within_width(&self, start_grapheme: usize, width: usize, tab_width: usize) -> usize {
+        let start_column = self.display_column(start_grapheme, tab_width);
+        let mut column = start_column;
+        for idx in start_grapheme..self.grapheme_count {
+            column += self.cell_width(idx, column, tab_width);
+            if column - start_column > width {
+                return idx;
+            }
+        }
+        self.grapheme_count.max(start_grapheme)
+    }
+
+    /// The number of cells the grapheme at the index takes when displayed from the column
+    fn cell_width(&self, grapheme_index: usize, column: usize, tab_width: usize) -> usize {
+        // Tabs are a single byte grapheme, which unicode-width gives no width
+        if self.text.as_bytes()[self.grapheme_starts[grapheme_index]] == b'\t' {
+            let tab_width = tab_width.max(1);
+            return tab_width - column % tab_width;
+        }
+        self.grapheme_widths[grapheme_index]
+    }
+
     pub fn grapheme_start(&self, grapheme_index: usize) -> usize {
         if self.grapheme_count == 0 {
             return 0;
@@ -237,6 +356,7 @@ impl Line {
         let mut end_str = String::new();
         self.text[index..].clone_into(&mut end_str);
         self.text.truncate(index);
+        *self = Line::from_string(&self.text);
         return Line::from_string(&end_str);
     }
 
@@ -251,7 +371,8 @@ impl PartialEq<Self> for Line {
         (self.text == other.text) &&
             (self.grapheme_count == self.grapheme_count) &&
             (self.grapheme_ends == other.grapheme_ends) &&
-            (self.grapheme_starts == other.grapheme_starts)
+            (self.grapheme_starts == other.grapheme_starts) &&
+            (self.grapheme_widths == other.grapheme_widths)
     }
 }
 
@@ -269,6 +390,7 @@ mod tests {
             grapheme_count: 6usize,
             grapheme_starts: vec![0, 1, 2, 3, 4, 5],
             grapheme_ends: vec![0, 1, 2, 3, 4, 5],
+            grapheme_widths: vec![1, 1, 1, 1, 1, 1],
         })
     }
 
@@ -357,6 +479,23 @@ mod tests {
         assert_eq!(test_line.grapheme_ends, vec![2, 5]);
     }
 
+    #[test]
+    fn replace_grapheme() {
+        let mut test_line = Line::from_string("€£ế");
+        test_line.replace_grapheme(1, 'x');
+        assert_eq!(test_line.text, "€xế");
+        assert_eq!(test_line.grapheme_count, 3);
+        assert_eq!(test_line.grapheme_starts, vec![0, 3, 4]);
+        assert_eq!(test_line.grapheme_ends, vec![2, 3, 6]);
+        let mut test_line = Line::from_string("abc");
+        test_line.replace_grapheme(1, '𐍈');
+        assert_eq!(test_line.text, "a𐍈c");
+        assert_eq!(test_line.grapheme_starts, vec![0, 1, 5]);
+        assert_eq!(test_line.grapheme_ends, vec![0, 4, 5]);
+        test_line.replace_grapheme(3, 'z');
+        assert_eq!(test_line.text, "a𐍈c");
+    }
+
     #[test]
     fn split_line() {
         let mut test_line = Line::from_string("abcdef");
@@ -387,6 +526,21 @@ mod tests {
         assert_eq!(grapheme, 2);
     }
 
+    #[test]
+    fn is_overlong() {
+        let test_line = Line::from_string(&"a".repeat(100_000));
+        assert!(test_line.is_overlong(10_000));
+        assert!(!Line::from_string("abcdef").is_overlong(10_000));
+    }
+
+    #[test]
+    fn grapheme_text() {
+        let test_line = Line::from_string("a€🇦🇨");
+        assert_eq!(test_line.grapheme(1), Some("€"));
+        assert_eq!(test_line.grapheme(2), Some("🇦🇨"));
+        assert_eq!(test_line.grapheme(3), None);
+    }
+
     #[test]
     fn insert_ascii_str(){
         let mut test_line = Line::from_string("abcdef");
@@ -406,4 +560,67 @@ mod tests {
         assert_eq!(test_line.grapheme_starts, vec![0,3, 5, 9]); // lengths are 3 2 4 3
         assert_eq!(test_line.grapheme_ends, vec![2, 4, 8, 11]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn wide_grapheme_widths() {
+        let mut test_line = Line::from_string("a日本b");
+        assert_eq!(test_line.grapheme_width(1, 4), 2);
+        assert_eq!(test_line.display_width(0..3, 4), 5);
+        assert_eq!(test_line.display_width(2..10, 4), 3);
+        test_line.insert_char(0, '語');
+        assert_eq!(test_line.display_width(0..2, 4), 3);
+        test_line.delete_grapheme(2);
+        assert_eq!(test_line.display_width(0..test_line.grapheme_count, 4), 6);
+    }
+
+    #[test]
+    fn graphemes_within_width() {
+        let test_line = Line::from_string("a日本b");
+        assert_eq!(test_line.graphemes_within_width(0, 3, 4), 2);
+        assert_eq!(test_line.graphemes_within_width(0, 4, 4), 2);
+        assert_eq!(test_line.graphemes_within_width(1, 4, 4), 3);
+        assert_eq!(test_line.graphemes_within_width(0, 10, 4), 4);
+        // Tabs reach the next tab stop
+        let test_line = Line::from_string("\ta");
+        assert_eq!(test_line.display_width(0..2, 8), 9);
+        assert_eq!(test_line.graphemes_within_width(0, 4, 4), 1);
+        assert_eq!(test_line.graphemes_within_width(0, 3, 4), 0);
+        let test_line = Line::from_string("ab\tc\t");
+        assert_eq!(test_line.grapheme_width(2, 4), 2);
+        assert_eq!(test_line.display_column(3, 4), 4);
+        assert_eq!(test_line.grapheme_width(4, 4), 3);
+        assert_eq!(test_line.display_width(2..5, 4), 6);
+        assert_eq!(test_line.graphemes_within_width(2, 3, 4), 4);
+    }
+
+    #[test]
+    fn add_and_remove_indent() {
+        let mut test_line = Line::from_string("a€");
+        test_line.add_indent("    ");
+        assert_eq!(test_line, Line::from_string("    a€"));
+        assert!(test_line.remove_indent(2, 4));
+        assert_eq!(test_line, Line::from_string("  a€"));
+        // Only the whitespace there is removed
+        assert!(test_line.remove_indent(4, 4));
+        assert_eq!(test_line, Line::from_string("a€"));
+        assert!(!test_line.remove_indent(4, 4));
+        let mut test_line = Line::from_string("\t\tb");
+        assert!(test_line.remove_indent(4, 4));
+        assert_eq!(test_line, Line::from_string("\tb"));
+        // A tab wider than the columns removed leaves spaces for the rest of it
+        let mut test_line = Line::from_string("\tb");
+        assert!(test_line.remove_indent(2, 8));
+        assert_eq!(test_line, Line::from_string("      b"));
+        let mut test_line = Line::from_string(" \tb");
+        assert!(test_line.remove_indent(2, 4));
+        assert_eq!(test_line, Line::from_string("  b"));
+    }
+
+    #[test]
+    fn trim_trailing_whitespace() {
+        let mut test_line = Line::from_string("a b\t c \t ");
+        assert!(test_line.trim_trailing_whitespace());
+        assert_eq!(test_line, Line::from_string("a b\t c"));
+        assert!(!test_line.trim_trailing_whitespace());
+    }
+}
@@ -1,3 +1,9 @@
 pub(crate) mod buffer;
-mod lines;
-pub mod text_location;
\ No newline at end of file
+pub mod changes;
+pub(crate) mod lines;
+pub mod registers;
+pub mod rope;
+pub mod search;
+pub mod spell_check;
+pub mod text_location;
+pub mod undo;
\ No newline at end of file
@@ -0,0 +1,165 @@
+use std::collections::{HashMap, VecDeque};
+
+/// Whether register text is pasted within a line, or as whole lines
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RegisterKind {
+    /// Pasted at the cursor, like text deleted with `x`
+    Charwise,
+    /// Pasted as new lines below or above the cursor's line, like lines yanked with `yy`
+    Linewise,
+    /// Pasted as a rectangle, each line at the cursor's column on consecutive rows, like a
+    /// block selection
+    Blockwise,
+}
+
+/// Text held for pasting, after being yanked or deleted
+#[derive(Clone, Debug, PartialEq)]
+pub struct Register {
+    /// The text, with lines separated by `\n`
+    pub text: String,
+    pub kind: RegisterKind,
+}
+
+impl Register {
+    /// Text to be pasted within a line
+    pub fn charwise(text: &str) -> Register {
+        Register { text: text.to_string(), kind: RegisterKind::Charwise }
+    }
+
+    /// Whole lines, to be pasted as new lines
+    pub fn linewise(text: &str) -> Register {
+        Register { text: text.to_string(), kind: RegisterKind::Linewise }
+    }
+
+    /// The rows of a rectangle, to be pasted as a block
+    pub fn blockwise(rows: &[String]) -> Register {
+        Register { text: rows.join("\n"), kind: RegisterKind::Blockwise }
+    }
+}
+
+/// Number of recent deletes kept in the numbered registers `1` to `9`
+const NUMBERED_REGISTERS: usize = 9;
+
+/// Every register text can be yanked into and pasted from: the unnamed register used by
+/// default, `0` holding the last yank, the named registers `a` to `z`, and the numbered
+/// registers `1` to `9` holding recent deletes of whole lines, newest first
+#[derive(Clone, Debug)]
+pub struct Registers {
+    unnamed: Option<Register>,
+    last_yank: Option<Register>,
+    named: HashMap<char, Register>,
+    numbered: VecDeque<Register>,
+}
+
+impl Registers {
+    pub fn new() -> Registers {
+        Registers {
+            unnamed: None,
+            last_yank: None,
+            named: HashMap::new(),
+            numbered: VecDeque::with_capacity(NUMBERED_REGISTERS),
+        }
+    }
+
+    /// Whether the name refers to a register, for parsing a `"x` prefix
+    pub fn is_valid_name(name: char) -> bool {
+        name == '"' || name.is_ascii_alphanumeric()
+    }
+
+    /// Store yanked text in the named register (if given), the unnamed one, and `0`
+    pub fn yank(&mut self, name: Option<char>, register: Register) {
+        if name.is_none() || name == Some('"') {
+            self.last_yank = Some(register.clone());
+        }
+        self.store(name, register);
+    }
+
+    /// Store deleted text in the named register (if given) and the unnamed one. Deleted
+    /// lines are also pushed onto the numbered registers, shifting older deletes along.
+    pub fn delete(&mut self, name: Option<char>, register: Register) {
+        if register.kind == RegisterKind::Linewise {
+            if self.numbered.len() == NUMBERED_REGISTERS {
+                self.numbered.pop_back();
+            }
+            self.numbered.push_front(register.clone());
+        }
+        self.store(name, register);
+    }
+
+    /// The register with the name, or the unnamed register if none is given
+    pub fn get(&self, name: Option<char>) -> Option<&Register> {
+        match name {
+            None | Some('"') => self.unnamed.as_ref(),
+            Some('0') => self.last_yank.as_ref(),
+            Some(digit @ '1'..='9') => self.numbered.get(digit as usize - '1' as usize),
+            Some(letter) => self.named.get(&letter.to_ascii_lowercase()),
+        }
+    }
+
+    /// Store text in the unnamed register, and the named one if given. An uppercase name
+    /// appends to the lowercase register rather than replacing it.
+    fn store(&mut self, name: Option<char>, register: Register) {
+        match name {
+            Some(letter) if letter.is_ascii_uppercase() => {
+                let lower = letter.to_ascii_lowercase();
+                let combined = match self.named.remove(&lower) {
+                    Some(existing) => append(existing, &register),
+                    None => register.clone(),
+                };
+                self.named.insert(lower, combined.clone());
+                self.unnamed = Some(combined);
+                return;
+            }
+            Some(letter) if letter.is_ascii_lowercase() => {
+                self.named.insert(letter, register.clone());
+            }
+            _ => {}
+        }
+        self.unnamed = Some(register);
+    }
+}
+
+/// Add text onto the end of a register, which becomes linewise if either part is, and
+/// otherwise blockwise if either part is
+fn append(existing: Register, addition: &Register) -> Register {
+    let kinds = [existing.kind, addition.kind];
+    if kinds.contains(&RegisterKind::Linewise) {
+        Register::linewise(&format!("{}\n{}", existing.text, addition.text))
+    } else if kinds.contains(&RegisterKind::Blockwise) {
+        Register { text: format!("{}\n{}", existing.text, addition.text), kind: RegisterKind::Blockwise }
+    } else {
+        Register::charwise(&format!("{}{}", existing.text, addition.text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn named_registers() {
+        let mut registers = Registers::new();
+        registers.yank(Some('a'), Register::charwise("alpha"));
+        registers.yank(None, Register::charwise("plain"));
+        assert_eq!(registers.get(Some('a')), Some(&Register::charwise("alpha")));
+        assert_eq!(registers.get(None), Some(&Register::charwise("plain")));
+        assert_eq!(registers.get(Some('0')), Some(&Register::charwise("plain")));
+        registers.yank(Some('A'), Register::linewise("line"));
+        assert_eq!(registers.get(Some('a')), Some(&Register::linewise("alpha\nline")));
+        assert!(registers.get(Some('b')).is_none());
+    }
+
+    #[test]
+    fn deletes_shift_numbered_registers() {
+        let mut registers = Registers::new();
+        for n in 0..12 {
+            registers.delete(None, Register::linewise(&n.to_string()));
+        }
+        registers.delete(None, Register::charwise("word"));
+        assert_eq!(registers.get(Some('1')), Some(&Register::linewise("11")));
+        assert_eq!(registers.get(Some('2')), Some(&Register::linewise("10")));
+        assert_eq!(registers.get(Some('9')), Some(&Register::linewise("3")));
+        assert_eq!(registers.get(None), Some(&Register::charwise("word")));
+        assert!(registers.get(Some('0')).is_none());
+    }
+}
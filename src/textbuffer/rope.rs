@@ -0,0 +1,642 @@
+use std::cell::OnceCell;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Seek, SeekFrom};
+use std::ops::{Index, IndexMut, Range};
+use std::sync::{Arc, Mutex};
+use crate::textbuffer::buffer::decode_line;
+use crate::textbuffer::lines::Line;
+use crate::textbuffer::undo::LineChange;
+
+/// Number of lines a chunk is filled to, chunks are split once they grow to twice this
+const CHUNK_SIZE: usize = 512;
+
+/// The run of lines edited since the edits were last taken: the lines it held before, and how
+/// many lines are now in their place
+#[derive(Clone, Debug)]
+pub struct EditedLines {
+    pub start: usize,
+    pub old: Vec<String>,
+    pub len: usize,
+}
+
+/// Lines inserted or removed at a row, which moves the rows after them. When lines are
+/// replaced by a different number of lines, the lines they have in common count as edited in
+/// place, so only one of `removed` and `added` is ever non-zero.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LineShift {
+    pub start: usize,
+    pub removed: usize,
+    pub added: usize,
+}
+
+impl LineShift {
+    /// Where a row moved to. A removed row moves to the first row after the removed lines.
+    pub fn row(&self, row: usize) -> usize {
+        if row < self.start {
+            row
+        } else if row < self.start + self.removed {
+            self.start
+        } else {
+            row - self.removed + self.added
+        }
+    }
+
+    /// Where the (exclusive) end of a range of rows moved to. Lines added right after the
+    /// range don't join it.
+    pub fn end(&self, end: usize) -> usize {
+        if end <= self.start { end } else { self.row(end) }
+    }
+}
+
+/// A run of whole lines in a paged file
+#[derive(Clone, Copy, Debug)]
+struct Page {
+    /// Byte offset of the first line
+    offset: u64,
+    /// Number of bytes, including the line breaks
+    len: usize,
+    /// Number of lines
+    lines: usize,
+}
+
+/// A file too large to comfortably hold in memory, whose lines are read a page at a time as
+/// they are needed
+#[derive(Debug)]
+struct PagedFile {
+    file: Mutex<File>,
+}
+
+impl PagedFile {
+    /// Read the lines of a page. If the file has changed since it was indexed, so the page
+    /// can't be read as it was, the lines missing from it are left empty.
+    fn read(&self, page: &Page) -> Vec<Line> {
+        let mut bytes = vec![0; page.len];
+        let mut file = self.file.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if file.seek(SeekFrom::Start(page.offset)).and_then(|_| file.read_exact(&mut bytes)).is_err() {
+            bytes.clear();
+        }
+        let mut lines: Vec<Line> = Vec::with_capacity(page.lines);
+        if !bytes.is_empty() {
+            // The page's last line break ends its last line rather than starting another
+            let text = bytes.strip_suffix(b"\n").unwrap_or(&bytes);
+            lines.extend(text.split(|byte| *byte == b'\n').map(|line| Line::from_string(&decode_line(line.to_vec()).0)));
+        }
+        lines.resize_with(page.lines, || Line::from_string(""));
+        lines
+    }
+}
+
+/// The lines of a rope chunk. A chunk of a paged file only holds its lines once they are
+/// needed, and can let them go again until they are edited, as the file still has them.
+#[derive(Clone, Debug)]
+struct Chunk {
+    /// The lines, once they have been read if the chunk is paged
+    lines: OnceCell<Vec<Line>>,
+    /// Where the lines are in the paged file, while they are unedited
+    page: Option<Page>,
+}
+
+impl Chunk {
+    fn new(lines: Vec<Line>) -> Chunk {
+        Chunk { lines: OnceCell::from(lines), page: None }
+    }
+
+    fn paged(page: Page) -> Chunk {
+        Chunk { lines: OnceCell::new(), page: Some(page) }
+    }
+
+    fn len(&self) -> usize {
+        match (self.lines.get(), &self.page) {
+            (Some(lines), _) => lines.len(),
+            (None, Some(page)) => page.lines,
+            (None, None) => 0,
+        }
+    }
+
+    /// The lines, read from the file first if they haven't been yet
+    fn lines(&self, file: Option<&PagedFile>) -> &Vec<Line> {
+        self.lines.get_or_init(|| match (&self.page, file) {
+            (Some(page), Some(file)) => file.read(page),
+            _ => Vec::new(),
+        })
+    }
+
+    /// The lines, to be edited. They will no longer match the file, so they are kept.
+    fn lines_mut(&mut self, file: Option<&PagedFile>) -> &mut Vec<Line> {
+        self.lines(file);
+        self.page = None;
+        self.lines.get_mut().unwrap()
+    }
+}
+
+/// A rope of lines: the lines are kept in bounded chunks, so inserting or removing a line only
+/// moves the lines of one chunk, and looking one up is a binary search over the chunk starts.
+/// Each line keeps its grapheme tables, so indexing gives the same `Line` a `Vec` would.
+///
+/// Edits through the rope are tracked as they are made, keeping a copy of only the lines they
+/// replace, so they can be taken as one change for undo without comparing the whole text.
+/// Lines inserted or removed are also logged as shifts, for what points at rows to follow.
+///
+/// A rope can also page its lines from a file: only where each chunk's lines are in the file
+/// is kept, the lines are read when they are first looked at, and `release_pages` lets go of
+/// those no longer in view.
+#[derive(Clone, Debug, Default)]
+pub struct LineRope {
+    /// The lines, in order, split into chunks. There are no empty chunks.
+    chunks: Vec<Chunk>,
+    /// The index of the first line of each chunk
+    starts: Vec<usize>,
+    /// Total number of lines
+    len: usize,
+    /// Lines edited since the edits were last taken
+    edited: Option<EditedLines>,
+    /// Lines inserted or removed since the shifts were last taken, in the order they happened
+    shifts: Vec<LineShift>,
+    /// The file lines are paged from, if they are
+    file: Option<Arc<PagedFile>>,
+}
+
+impl LineRope {
+    /// Create an empty rope
+    pub fn new() -> LineRope {
+        LineRope { chunks: Vec::new(), starts: Vec::new(), len: 0, edited: None, shifts: Vec::new(), file: None }
+    }
+
+    /// Page the lines of a file, reading through it once to find where each chunk of lines
+    /// starts without keeping any of them. Returns the rope, and whether any line isn't valid
+    /// UTF-8.
+    pub fn paged(file: File) -> Result<(LineRope, bool), Error> {
+        let mut chunks = Vec::new();
+        let mut lossy = false;
+        {
+            let mut reader = BufReader::with_capacity(1 << 16, &file);
+            let mut line = Vec::new();
+            let mut page = Page { offset: 0, len: 0, lines: 0 };
+            loop {
+                line.clear();
+                let read = reader.read_until(b'\n', &mut line)?;
+                if read == 0 {
+                    break;
+                }
+                lossy |= std::str::from_utf8(&line).is_err();
+                page.len += read;
+                page.lines += 1;
+                if page.lines == CHUNK_SIZE {
+                    chunks.push(Chunk::paged(page));
+                    page = Page { offset: page.offset + page.len as u64, len: 0, lines: 0 };
+                }
+            }
+            if page.lines > 0 {
+                chunks.push(Chunk::paged(page));
+            }
+        }
+        let mut rope = LineRope::new();
+        rope.chunks = chunks;
+        rope.file = Some(Arc::new(PagedFile { file: Mutex::new(file) }));
+        rope.update_starts(0);
+        Ok((rope, lossy))
+    }
+
+    /// Page the lines from a file holding exactly the same text, such as the one they were just
+    /// written to, letting go of the lines read so far
+    pub fn repage(&mut self, file: File) -> Result<(), Error> {
+        let (paged, _) = LineRope::paged(file)?;
+        if paged.len != self.len {
+            return Err(Error::new(ErrorKind::InvalidData, "file doesn't hold the lines being paged"));
+        }
+        self.chunks = paged.chunks;
+        self.starts = paged.starts;
+        self.file = paged.file;
+        Ok(())
+    }
+
+    /// Whether lines are read from a file as they are needed
+    pub fn is_paged(&self) -> bool {
+        self.file.is_some()
+    }
+
+    /// Read every line still only in the paged file, so the rope no longer needs the file,
+    /// such as before it is overwritten
+    pub fn detach_pages(&mut self) {
+        let file = self.file.take();
+        for chunk in &mut self.chunks {
+            chunk.lines_mut(file.as_deref());
+        }
+    }
+
+    /// Let go of the paged lines outside the rows, to be read from the file again if they are
+    /// needed. Edited lines are kept, as the file doesn't have them.
+    pub fn release_pages(&mut self, keep: Range<usize>) {
+        if self.file.is_none() {
+            return;
+        }
+        for (chunk, start) in self.chunks.iter_mut().zip(&self.starts) {
+            if chunk.page.is_some() && (start + chunk.len() <= keep.start || *start >= keep.end) {
+                chunk.lines.take();
+            }
+        }
+    }
+
+    /// Number of lines held in memory
+    #[cfg(test)]
+    pub fn loaded_lines(&self) -> usize {
+        self.chunks.iter().filter_map(|chunk| chunk.lines.get()).map(Vec::len).sum()
+    }
+
+    /// Number of lines in the rope
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The lines edited since the edits were last taken, if there have been any
+    pub fn edited(&self) -> Option<&EditedLines> {
+        self.edited.as_ref()
+    }
+
+    /// Take the edits made since they were last taken, as a single change replacing a run of
+    /// lines. None if there were none, or they left the lines as they were.
+    pub fn take_change(&mut self) -> Option<LineChange> {
+        let edited = self.edited.take()?;
+        let new: Vec<&Line> = self.range(edited.start..edited.start + edited.len).collect();
+        let mut change = LineChange::between(&edited.old, &new)?;
+        change.start += edited.start;
+        Some(change)
+    }
+
+    /// Take the lines inserted or removed since they were last taken
+    pub fn take_shifts(&mut self) -> Vec<LineShift> {
+        std::mem::take(&mut self.shifts)
+    }
+
+    /// Log lines replaced by a different number of lines, not counting the lines in common
+    fn log_shift(&mut self, start: usize, removed: usize, added: usize) {
+        let common = removed.min(added);
+        if removed != added {
+            self.shifts.push(LineShift { start: start + common, removed: removed - common, added: added - common });
+        }
+    }
+
+    /// Widen the edited run to cover the rows about to be edited, copying the lines it
+    /// didn't already cover, which are still as they were
+    fn touch(&mut self, rows: Range<usize>) {
+        let end = rows.end.min(self.len);
+        let start = rows.start.min(end);
+        let mut edited = self.edited.take().unwrap_or(EditedLines { start, old: Vec::new(), len: 0 });
+        if start < edited.start {
+            let before: Vec<String> = self.range(start..edited.start).map(|line| line.text.clone()).collect();
+            edited.old.splice(0..0, before);
+            edited.len += edited.start - start;
+            edited.start = start;
+        }
+        let edited_end = edited.start + edited.len;
+        if end > edited_end {
+            edited.old.extend(self.range(edited_end..end).map(|line| line.text.clone()));
+            edited.len += end - edited_end;
+        }
+        self.edited = Some(edited);
+    }
+
+    /// Change how many lines the edited run covers, after lines were inserted or removed in it
+    fn resize_edited(&mut self, added: usize, removed: usize) {
+        if let Some(edited) = &mut self.edited {
+            edited.len = edited.len + added - removed;
+        }
+    }
+
+    /// Find the chunk holding a line, and the line's index within it
+    fn locate(&self, index: usize) -> (usize, usize) {
+        let chunk = self.starts.partition_point(|start| *start <= index) - 1;
+        (chunk, index - self.starts[chunk])
+    }
+
+    /// Recompute the chunk starts from the chunk onwards, after chunks were added or removed
+    fn update_starts(&mut self, from_chunk: usize) {
+        self.starts.truncate(from_chunk);
+        let mut start = match from_chunk {
+            0 => 0,
+            chunk => self.starts[chunk - 1] + self.chunks[chunk - 1].len(),
+        };
+        for chunk in &self.chunks[from_chunk..] {
+            self.starts.push(start);
+            start += chunk.len();
+        }
+        self.len = start;
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Line> {
+        if index >= self.len {
+            return None;
+        }
+        let (chunk, offset) = self.locate(index);
+        Some(&self.chunks[chunk].lines(self.file.as_deref())[offset])
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut Line> {
+        if index >= self.len {
+            return None;
+        }
+        self.touch(index..index + 1);
+        let (chunk, offset) = self.locate(index);
+        Some(&mut self.chunks[chunk].lines_mut(self.file.as_deref())[offset])
+    }
+
+    /// Iterate over the lines in order
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Line> {
+        let file = self.file.as_deref();
+        self.chunks.iter().flat_map(move |chunk| chunk.lines(file))
+    }
+
+    /// Iterate over the lines from `start` onwards, without walking the lines before it
+    pub fn iter_from(&self, start: usize) -> impl Iterator<Item = &Line> {
+        let (chunk, offset) = if start < self.len { self.locate(start) } else { (self.chunks.len(), 0) };
+        let file = self.file.as_deref();
+        self.chunks[chunk..].iter().enumerate()
+            .flat_map(move |(idx, chunk)| &chunk.lines(file)[if idx == 0 { offset } else { 0 }..])
+    }
+
+    /// Iterate over the lines in the range
+    pub fn range(&self, rows: Range<usize>) -> impl Iterator<Item = &Line> {
+        self.iter_from(rows.start).take(rows.len())
+    }
+
+    /// Add a line to the end
+    pub fn push(&mut self, line: Line) {
+        self.insert(self.len, line);
+    }
+
+    /// Insert a line before the index, or at the end if the index is the number of lines
+    pub fn insert(&mut self, index: usize, line: Line) {
+        assert!(index <= self.len, "insertion index {index} is past the end of {} lines", self.len);
+        self.touch(index..index);
+        self.resize_edited(1, 0);
+        self.log_shift(index, 0, 1);
+        if self.is_empty() {
+            self.chunks.push(Chunk::new(vec![line]));
+            self.update_starts(0);
+            return;
+        }
+        let (chunk, offset) = if index == self.len {
+            let last = self.chunks.len() - 1;
+            (last, self.chunks[last].len())
+        } else {
+            self.locate(index)
+        };
+        let lines = self.chunks[chunk].lines_mut(self.file.as_deref());
+        lines.insert(offset, line);
+        if lines.len() >= 2 * CHUNK_SIZE {
+            let second_half = lines.split_off(CHUNK_SIZE);
+            self.chunks.insert(chunk + 1, Chunk::new(second_half));
+            self.update_starts(chunk);
+        } else {
+            for start in &mut self.starts[chunk + 1..] {
+                *start += 1;
+            }
+            self.len += 1;
+        }
+    }
+
+    /// Remove the line at the index, returning it
+    pub fn remove(&mut self, index: usize) -> Line {
+        assert!(index < self.len, "removal index {index} is past the end of {} lines", self.len);
+        self.touch(index..index + 1);
+        self.resize_edited(0, 1);
+        self.log_shift(index, 1, 0);
+        let (chunk, offset) = self.locate(index);
+        let lines = self.chunks[chunk].lines_mut(self.file.as_deref());
+        let removed = lines.remove(offset);
+        if lines.is_empty() {
+            self.chunks.remove(chunk);
+            self.update_starts(chunk);
+        } else {
+            for start in &mut self.starts[chunk + 1..] {
+                *start -= 1;
+            }
+            self.len -= 1;
+        }
+        removed
+    }
+
+    /// Add lines to the end, filling the last chunk before starting new ones. These aren't
+    /// tracked as edits, as they are the rest of a file being loaded.
+    pub fn extend<I: IntoIterator<Item = Line>>(&mut self, lines: I) {
+        let first_new = self.chunks.len().saturating_sub(1);
+        let mut lines = lines.into_iter().peekable();
+        while lines.peek().is_some() {
+            if self.chunks.last().is_none_or(|chunk| chunk.len() >= CHUNK_SIZE) {
+                self.chunks.push(Chunk::new(Vec::with_capacity(CHUNK_SIZE)));
+            }
+            let chunk = self.chunks.last_mut().unwrap().lines_mut(self.file.as_deref());
+            let space = CHUNK_SIZE - chunk.len();
+            chunk.extend(lines.by_ref().take(space));
+        }
+        self.update_starts(first_new.min(self.chunks.len()));
+    }
+
+    /// Split the rope in two at the index, returning the lines from the index onwards
+    fn split_off(&mut self, index: usize) -> LineRope {
+        let mut tail = LineRope::new();
+        tail.file = self.file.clone();
+        if index >= self.len {
+            return tail;
+        }
+        let (chunk, offset) = self.locate(index);
+        tail.chunks = self.chunks.split_off(chunk);
+        if offset > 0 {
+            let rest = tail.chunks[0].lines_mut(self.file.as_deref()).split_off(offset);
+            self.chunks.push(std::mem::replace(&mut tail.chunks[0], Chunk::new(rest)));
+        }
+        self.update_starts(chunk);
+        tail.update_starts(0);
+        tail
+    }
+
+    /// Move every line of the other rope onto the end, merging the chunks where they meet if
+    /// they are small
+    fn append(&mut self, mut other: LineRope) {
+        let joined = self.chunks.len().saturating_sub(1);
+        if self.file.is_none() {
+            self.file = other.file.take();
+        }
+        let file = self.file.as_deref();
+        if let (Some(last), Some(first)) = (self.chunks.last_mut(), other.chunks.first_mut()) {
+            if last.len() + first.len() <= CHUNK_SIZE {
+                last.lines_mut(file).append(first.lines_mut(file));
+                other.chunks.remove(0);
+            }
+        }
+        self.chunks.append(&mut other.chunks);
+        self.update_starts(joined.min(self.chunks.len()));
+    }
+
+    /// Replace the lines in the range with new ones, which may be a different number of lines
+    pub fn splice<I: IntoIterator<Item = Line>>(&mut self, range: Range<usize>, lines: I) {
+        self.touch(range.clone());
+        let mut removed = self.split_off(range.start);
+        let tail = removed.split_off(range.end - range.start);
+        let mut inserted = LineRope::new();
+        inserted.extend(lines);
+        self.resize_edited(inserted.len(), removed.len());
+        self.log_shift(range.start, removed.len(), inserted.len());
+        self.append(inserted);
+        self.append(tail);
+    }
+}
+
+impl FromIterator<Line> for LineRope {
+    fn from_iter<I: IntoIterator<Item = Line>>(lines: I) -> LineRope {
+        let mut rope = LineRope::new();
+        rope.extend(lines);
+        rope
+    }
+}
+
+impl Index<usize> for LineRope {
+    type Output = Line;
+
+    fn index(&self, index: usize) -> &Line {
+        match self.get(index) {
+            Some(line) => line,
+            None => panic!("line index {index} is past the end of {} lines", self.len),
+        }
+    }
+}
+
+impl IndexMut<usize> for LineRope {
+    fn index_mut(&mut self, index: usize) -> &mut Line {
+        let len = self.len;
+        match self.get_mut(index) {
+            Some(line) => line,
+            None => panic!("line index {index} is past the end of {len} lines"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbered(count: usize) -> Vec<Line> {
+        (0..count).map(|idx| Line::from_string(&idx.to_string())).collect()
+    }
+
+    fn texts(rope: &LineRope) -> Vec<String> {
+        rope.iter().map(|line| line.text.clone()).collect()
+    }
+
+    #[test]
+    fn matches_vec_through_edits() {
+        let mut rope: LineRope = numbered(3000).into_iter().collect();
+        let mut expected = numbered(3000);
+        for idx in (0..3000).step_by(7) {
+            rope.insert(idx, Line::from_string("new"));
+            expected.insert(idx, Line::from_string("new"));
+        }
+        for idx in (0..2000).step_by(5) {
+            assert_eq!(rope.remove(idx), expected.remove(idx));
+        }
+        rope.splice(100..1500, numbered(20));
+        expected.splice(100..1500, numbered(20));
+        rope.push(Line::from_string("end"));
+        expected.push(Line::from_string("end"));
+        assert_eq!(rope.len(), expected.len());
+        assert_eq!(texts(&rope), expected.iter().map(|line| line.text.clone()).collect::<Vec<_>>());
+        for idx in [0, 99, 100, 119, 120, rope.len() - 1] {
+            assert_eq!(rope[idx], expected[idx]);
+        }
+        assert!(rope.get(rope.len()).is_none());
+    }
+
+    #[test]
+    fn iterate_from_line() {
+        let rope: LineRope = numbered(1200).into_iter().collect();
+        let from: Vec<String> = rope.iter_from(1000).map(|line| line.text.clone()).collect();
+        assert_eq!(from.len(), 200);
+        assert_eq!(from[0], "1000");
+        assert_eq!(rope.iter_from(1200).count(), 0);
+    }
+
+    #[test]
+    fn remove_until_empty() {
+        let mut rope: LineRope = numbered(3).into_iter().collect();
+        rope.remove(1);
+        rope.remove(0);
+        rope.remove(0);
+        assert!(rope.is_empty());
+        rope.insert(0, Line::from_string("a"));
+        assert_eq!(texts(&rope), ["a"]);
+    }
+
+    #[test]
+    fn track_edits_as_one_change() {
+        let mut rope: LineRope = numbered(3000).into_iter().collect();
+        assert!(rope.take_change().is_none());
+        rope[1500] = Line::from_string("changed");
+        rope.insert(1498, Line::from_string("new"));
+        rope.remove(1502);
+        let change = rope.take_change().unwrap();
+        assert_eq!(change, LineChange {
+            start: 1498,
+            old: vec!["1498".to_string(), "1499".to_string(), "1500".to_string(), "1501".to_string()],
+            new: vec!["new".to_string(), "1498".to_string(), "1499".to_string(), "changed".to_string()],
+        });
+        assert!(rope.edited().is_none());
+        // Edits which put the lines back as they were make no change
+        rope.splice(10..12, ["10", "11", "extra"].map(Line::from_string));
+        rope.remove(12);
+        assert_eq!(texts(&rope)[10..12], ["10", "11"]);
+        assert!(rope.take_change().is_none());
+    }
+
+    #[test]
+    fn log_line_shifts() {
+        let mut rope: LineRope = numbered(10).into_iter().collect();
+        rope[3] = Line::from_string("changed");
+        rope.insert(2, Line::from_string("new"));
+        rope.splice(5..7, ["a", "b", "c"].map(Line::from_string));
+        rope.splice(0..3, [Line::from_string("one")]);
+        assert_eq!(rope.take_shifts(), [
+            LineShift { start: 2, removed: 0, added: 1 },
+            LineShift { start: 7, removed: 0, added: 1 },
+            LineShift { start: 1, removed: 2, added: 0 },
+        ]);
+        assert!(rope.take_shifts().is_empty());
+
+        let removed = LineShift { start: 4, removed: 2, added: 0 };
+        assert_eq!([3, 4, 5, 6].map(|row| removed.row(row)), [3, 4, 4, 4]);
+        let added = LineShift { start: 4, removed: 0, added: 2 };
+        assert_eq!([3, 4, 5].map(|row| added.row(row)), [3, 6, 7]);
+        assert_eq!(added.end(4), 4);
+        assert_eq!(added.end(5), 7);
+    }
+
+    #[test]
+    fn page_lines_from_file() {
+        let path = std::env::temp_dir().join(format!("trout_paged_rope_{}", std::process::id()));
+        let text: Vec<String> = (0..1500).map(|idx| idx.to_string()).collect();
+        std::fs::write(&path, text.join("\n")).unwrap();
+        let (mut rope, lossy) = LineRope::paged(File::open(&path).unwrap()).unwrap();
+        assert!(!lossy);
+        assert_eq!(rope.len(), 1500);
+        assert_eq!(rope.loaded_lines(), 0);
+        // Only the chunk looked at is read, including the last line without a line break
+        assert_eq!(rope[1499].text, "1499");
+        assert_eq!(rope.loaded_lines(), 1500 - 2 * CHUNK_SIZE);
+        assert_eq!(rope[5].text, "5");
+        rope.release_pages(0..10);
+        assert_eq!(rope.loaded_lines(), CHUNK_SIZE);
+        // Edited lines are kept, as the file no longer has them
+        rope[1200] = Line::from_string("changed");
+        rope.insert(600, Line::from_string("new"));
+        rope.release_pages(0..0);
+        assert_eq!(rope.loaded_lines(), 1500 - 2 * CHUNK_SIZE + CHUNK_SIZE + 1);
+        let mut expected = text.clone();
+        expected[1200] = "changed".to_string();
+        expected.insert(600, "new".to_string());
+        assert_eq!(texts(&rope), expected);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,328 @@
+use regex::Regex;
+use crate::textbuffer::buffer::Buffer;
+use crate::textbuffer::text_location::TextPosition;
+
+/// A location where the search pattern matched
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchMatch {
+    pub row: usize,
+    /// Byte offset of the start of the match in the line
+    pub start: usize,
+    /// Byte offset just past the end of the match in the line
+    pub end: usize,
+}
+
+/// Where the cursor is left relative to a match, given after the pattern like `/foo/e`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SearchOffset {
+    /// Graphemes after the start of the match (`b`, `s`, or nothing)
+    Start(isize),
+    /// Graphemes after the last grapheme of the match (`e`)
+    End(isize),
+}
+
+/// Options controlling what a search pattern matches, set with `:set`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SearchOptions {
+    /// Whether letters match regardless of case
+    pub ignore_case: bool,
+    /// Whether a pattern with no upper case letters matches regardless of case, while one with
+    /// an upper case letter matches case exactly
+    pub smart_case: bool,
+    /// Whether patterns only match whole words
+    pub whole_word: bool,
+}
+
+impl SearchOptions {
+    /// Match patterns exactly as written
+    pub fn default() -> SearchOptions {
+        SearchOptions { ignore_case: false, smart_case: false, whole_word: false }
+    }
+
+    /// Whether the pattern should match regardless of case
+    pub fn ignores_case(&self, pattern: &str) -> bool {
+        if self.smart_case && has_upper_case(pattern) {
+            return false;
+        }
+        self.ignore_case || self.smart_case
+    }
+
+    /// The pattern to compile, with the flags the options call for. Whole word patterns are
+    /// grouped before adding the word boundaries, so alternatives like `a|b` are all bounded.
+    pub fn apply(&self, pattern: &str) -> String {
+        let flags = if self.ignores_case(pattern) { "(?i)" } else { "" };
+        if self.whole_word {
+            format!(r"{flags}\b(?:{pattern})\b")
+        } else {
+            format!("{flags}{pattern}")
+        }
+    }
+}
+
+/// Whether a pattern has an upper case letter, not counting escapes like `\S` and `\W`
+fn has_upper_case(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            chars.next();
+        } else if c.is_uppercase() {
+            return true;
+        }
+    }
+    false
+}
+
+/// An active search, caching where its pattern matches in a buffer. The matches are
+/// recomputed whenever the buffer has been edited since they were found.
+pub struct Search {
+    pub pattern: Regex,
+    pub offset: SearchOffset,
+    matches: Vec<SearchMatch>,
+    /// Version of the buffer the matches were found in
+    buffer_version: Option<u64>,
+}
+
+impl Search {
+    pub fn new(pattern: &str) -> Result<Search, regex::Error> {
+        Ok(Self {
+            pattern: Regex::new(pattern)?,
+            offset: SearchOffset::Start(0),
+            matches: Vec::new(),
+            buffer_version: None,
+        })
+    }
+
+    /// Parse a search query, which is a pattern optionally followed by `/` and an offset,
+    /// like `foo/e`, `foo/e-1` or `foo/b+2`. A `/` inside the pattern is escaped as `\/`.
+    pub fn parse(query: &str, options: SearchOptions)->Result<Search, String>{
+        let query = query.strip_prefix('/').unwrap_or(query);
+        let mut pattern = String::new();
+        let mut offset = None;
+        let mut chars = query.char_indices();
+        while let Some((idx, c)) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some((_, '/')) => pattern.push('/'),
+                    Some((_, escaped)) => {
+                        pattern.push('\\');
+                        pattern.push(escaped);
+                    }
+                    None => pattern.push('\\'),
+                },
+                '/' => {
+                    offset = Some(&query[idx + 1..]);
+                    break;
+                }
+                _ => pattern.push(c),
+            }
+        }
+        let mut search = Search::new(&options.apply(&pattern)).map_err(|e| e.to_string())?;
+        if let Some(offset) = offset {
+            search.offset = parse_offset(offset)?;
+        }
+        Ok(search)
+    }
+
+    /// Where the cursor should be placed for a match, after applying the offset
+    pub fn cursor_for(&self, buffer: &Buffer, found: &SearchMatch)->TextPosition{
+        let line = &buffer.text[found.row];
+        let (base, offset) = match self.offset {
+            SearchOffset::Start(offset) => (line.text_index_to_grapheme(found.start), offset),
+            SearchOffset::End(offset) => {
+                (line.text_index_to_grapheme(found.end.max(found.start + 1) - 1), offset)
+            }
+        };
+        let last = line.grapheme_count.saturating_sub(1);
+        let grapheme = base.saturating_add_signed(offset).min(last);
+        TextPosition { row: found.row, byte: line.grapheme_start(grapheme), grapheme }
+    }
+
+    /// Undo the offset from a cursor position, giving the start of the match it came from.
+    /// Matches are compared by their starts, so an end offset would otherwise leave the
+    /// cursor after the start of its own match.
+    fn match_position(&mut self, buffer: &Buffer, position: &TextPosition)->TextPosition{
+        self.update(buffer);
+        let placed_by = self.matches.iter().find(|m| self.cursor_for(buffer, m) == *position);
+        if let Some(found) = placed_by {
+            let grapheme = buffer.text[found.row].text_index_to_grapheme(found.start);
+            return TextPosition { row: found.row, byte: found.start, grapheme };
+        }
+        let offset = match self.offset {
+            SearchOffset::Start(offset) | SearchOffset::End(offset) => offset,
+        };
+        let line = match buffer.text.get(position.row) {
+            Some(line) if offset != 0 => line,
+            _ => return position.clone(),
+        };
+        let grapheme = position.grapheme.saturating_add_signed(-offset)
+            .min(line.grapheme_count.saturating_sub(1));
+        TextPosition { row: position.row, byte: line.grapheme_start(grapheme), grapheme }
+    }
+
+    /// Find the matches in the buffer again, if it has changed since they were found
+    pub fn update(&mut self, buffer: &Buffer) {
+        if self.buffer_version == Some(buffer.version) {
+            return;
+        }
+        self.matches.clear();
+        for (row, line) in buffer.text.iter().enumerate().take(buffer.num_lines) {
+            for m in self.pattern.find_iter(&line.text) {
+                self.matches.push(SearchMatch { row, start: m.start(), end: m.end() });
+            }
+        }
+        self.buffer_version = Some(buffer.version);
+    }
+
+    /// All matches in the buffer
+    pub fn matches(&mut self, buffer: &Buffer) -> &[SearchMatch] {
+        self.update(buffer);
+        &self.matches
+    }
+
+    /// The first match after the position, wrapping around to the start of the buffer
+    pub fn next_match(&mut self, buffer: &Buffer, position: &TextPosition) -> Option<SearchMatch> {
+        let position = self.match_position(buffer, position);
+        let matches = self.matches(buffer);
+        matches.iter()
+            .find(|m| (m.row, m.start) > (position.row, position.byte))
+            .or(matches.first())
+            .cloned()
+    }
+
+    /// The last match before the position, wrapping around to the end of the buffer
+    pub fn prev_match(&mut self, buffer: &Buffer, position: &TextPosition) -> Option<SearchMatch> {
+        let position = self.match_position(buffer, position);
+        let matches = self.matches(buffer);
+        matches.iter()
+            .rev()
+            .find(|m| (m.row, m.start) < (position.row, position.byte))
+            .or(matches.last())
+            .cloned()
+    }
+
+    /// Describe which match the position is at, like `match 2 of 5`
+    pub fn indicator(&mut self, buffer: &Buffer, position: &TextPosition) -> String {
+        let position = self.match_position(buffer, position);
+        let matches = self.matches(buffer);
+        if matches.is_empty() {
+            return "no matches".to_string();
+        }
+        let current = matches.iter()
+            .filter(|m| (m.row, m.start) <= (position.row, position.byte))
+            .count()
+            .max(1);
+        format!("match {} of {}", current, matches.len())
+    }
+}
+
+/// Parse the offset following a search pattern: `e` or `b`/`s` optionally followed by a
+/// signed count, or just a signed count from the start
+fn parse_offset(offset: &str)->Result<SearchOffset, String>{
+    let (anchor, count) = match offset.chars().next() {
+        Some('e') => ("e", &offset[1..]),
+        Some('b') | Some('s') => ("b", &offset[1..]),
+        _ => ("b", offset),
+    };
+    let count = match count {
+        "" => 0,
+        "+" => 1,
+        "-" => -1,
+        count => count.parse::<isize>().map_err(|_| format!("Invalid search offset: {offset}"))?,
+    };
+    Ok(match anchor {
+        "e" => SearchOffset::End(count),
+        _ => SearchOffset::Start(count),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_update_after_edit() {
+        let mut buffer = Buffer::from_string("cat dog\ncat\nbird");
+        let mut search = Search::new("cat").unwrap();
+        let mut position = TextPosition::default();
+        assert_eq!(search.indicator(&buffer, &position), "match 1 of 2");
+        position.row = 1;
+        assert_eq!(search.indicator(&buffer, &position), "match 2 of 2");
+
+        // Typing "cat" after the first match adds another
+        for (idx, c) in "cat".chars().enumerate() {
+            buffer.insert_char(0, 3 + idx, c);
+        }
+        assert_eq!(search.indicator(&buffer, &position), "match 3 of 3");
+        assert_eq!(search.matches(&buffer)[1], SearchMatch { row: 0, start: 3, end: 6 });
+
+        // Breaking up a match removes it
+        buffer.delete_char(1, 1);
+        assert_eq!(search.indicator(&buffer, &position), "match 2 of 2");
+    }
+
+    #[test]
+    fn next_and_prev_wrap() {
+        let buffer = Buffer::from_string("a x\nb x\nc");
+        let mut search = Search::new("x").unwrap();
+        let start = TextPosition { row: 1, byte: 2, grapheme: 2 };
+        assert_eq!(search.next_match(&buffer, &start), Some(SearchMatch { row: 0, start: 2, end: 3 }));
+        assert_eq!(search.prev_match(&buffer, &start), Some(SearchMatch { row: 0, start: 2, end: 3 }));
+        let start = TextPosition::default();
+        assert_eq!(search.prev_match(&buffer, &start), Some(SearchMatch { row: 1, start: 2, end: 3 }));
+    }
+
+    #[test]
+    fn prev_match_from_end_offset() {
+        let buffer = Buffer::from_string("foo bar foo\nfoo");
+        let mut search = Search::parse("foo/e", SearchOptions::default()).unwrap();
+        let last = search.cursor_for(&buffer, &SearchMatch { row: 1, start: 0, end: 3 });
+        assert_eq!(last.grapheme, 2);
+        let found = search.prev_match(&buffer, &last).unwrap();
+        assert_eq!(found, SearchMatch { row: 0, start: 8, end: 11 });
+        let cursor = search.cursor_for(&buffer, &found);
+        assert_eq!(search.prev_match(&buffer, &cursor), Some(SearchMatch { row: 0, start: 0, end: 3 }));
+        assert_eq!(search.indicator(&buffer, &cursor), "match 2 of 3");
+        assert_eq!(search.next_match(&buffer, &cursor), Some(SearchMatch { row: 1, start: 0, end: 3 }));
+    }
+
+    #[test]
+    fn parse_offsets() {
+        assert_eq!(Search::parse("foo", SearchOptions::default()).unwrap().offset, SearchOffset::Start(0));
+        assert_eq!(Search::parse("/foo/e", SearchOptions::default()).unwrap().offset, SearchOffset::End(0));
+        assert_eq!(Search::parse("foo/e-1", SearchOptions::default()).unwrap().offset, SearchOffset::End(-1));
+        assert_eq!(Search::parse("foo/b+2", SearchOptions::default()).unwrap().offset, SearchOffset::Start(2));
+        assert_eq!(Search::parse("foo/s-", SearchOptions::default()).unwrap().offset, SearchOffset::Start(-1));
+        assert!(Search::parse("foo/x", SearchOptions::default()).is_err());
+        let search = Search::parse(r"a\/b/e", SearchOptions::default()).unwrap();
+        assert_eq!(search.pattern.as_str(), "a/b");
+    }
+
+    #[test]
+    fn smart_case() {
+        let buffer = Buffer::from_string("Foo\nfoo");
+        let options = SearchOptions { ignore_case: false, smart_case: true, whole_word: false };
+        let mut search = Search::parse("foo", options).unwrap();
+        assert_eq!(search.matches(&buffer).len(), 2);
+        let mut search = Search::parse("Foo", options).unwrap();
+        assert_eq!(search.matches(&buffer), [SearchMatch { row: 0, start: 0, end: 3 }]);
+        // Escapes aren't upper case letters
+        assert!(options.ignores_case(r"fo\S"));
+
+        let options = SearchOptions { ignore_case: true, smart_case: false, whole_word: false };
+        assert_eq!(Search::parse("Foo", options).unwrap().matches(&buffer).len(), 2);
+        assert_eq!(Search::parse("Foo", SearchOptions::default()).unwrap().matches(&buffer).len(), 1);
+    }
+
+    #[test]
+    fn whole_word() {
+        let buffer = Buffer::from_string("a cat\ncategory\nCat|dog");
+        let options = SearchOptions { ignore_case: false, smart_case: true, whole_word: true };
+        let mut search = Search::parse("cat", options).unwrap();
+        assert_eq!(search.matches(&buffer), [SearchMatch { row: 0, start: 2, end: 5 },
+                                             SearchMatch { row: 2, start: 0, end: 3 }]);
+        // Patterns with their own boundaries and alternatives still work
+        let mut search = Search::parse(r"\bcat|dog", options).unwrap();
+        assert_eq!(search.pattern.as_str(), r"(?i)\b(?:\bcat|dog)\b");
+        assert_eq!(search.matches(&buffer).len(), 3);
+    }
+}
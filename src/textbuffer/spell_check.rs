@@ -0,0 +1,110 @@
+use std::collections::HashSet;
+use std::fs::{OpenOptions, read_to_string};
+use std::io::{Error, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// Regex for recognizing a word to spell check
+static SPELL_WORD_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"\w+(?:'\w+)*").unwrap());
+
+/// Checks words against a dictionary loaded from a word list
+pub struct SpellChecker {
+    /// Known words, stored in lowercase
+    words: HashSet<String>,
+    /// Word list which words added by the user are appended to
+    personal_path: Option<PathBuf>,
+}
+
+impl SpellChecker {
+    /// Create a spell checker from a word list file, with one word per line
+    pub fn new(dict_path: &Path) -> Result<SpellChecker, Error> {
+        let mut checker = Self {
+            words: HashSet::new(),
+            personal_path: None,
+        };
+        checker.load_words(dict_path)?;
+        Ok(checker)
+    }
+
+    /// Load a personal word list (if it exists), which words added with `add_word` are
+    /// saved to
+    pub fn load_personal(&mut self, personal_path: &Path) -> Result<(), Error> {
+        if personal_path.exists() {
+            self.load_words(personal_path)?;
+        }
+        self.personal_path = Some(personal_path.to_path_buf());
+        Ok(())
+    }
+
+    /// Whether the word is in the dictionary. Words containing digits or underscores
+    /// (identifiers, numbers) are always accepted.
+    pub fn is_word(&self, word: &str) -> bool {
+        if word.chars().any(|c| c.is_ascii_digit() || c == '_') {
+            return true;
+        }
+        self.words.contains(&word.to_lowercase())
+    }
+
+    /// Add a word to the dictionary, saving it to the personal word list if one is loaded
+    pub fn add_word(&mut self, word: &str) -> Result<(), Error> {
+        self.words.insert(word.to_lowercase());
+        if let Some(path) = &self.personal_path {
+            let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+            writeln!(file, "{word}")?;
+        }
+        Ok(())
+    }
+
+    /// Find the byte ranges of words in the text which aren't in the dictionary
+    pub fn misspelled(&self, text: &str) -> Vec<Range<usize>> {
+        SPELL_WORD_REGEX.find_iter(text)
+            .filter(|m| !self.is_word(m.as_str()))
+            .map(|m| m.range())
+            .collect()
+    }
+
+    fn load_words(&mut self, path: &Path) -> Result<(), Error> {
+        for word in read_to_string(path)?.lines() {
+            let word = word.trim();
+            if !word.is_empty() {
+                self.words.insert(word.to_lowercase());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, remove_dir_all};
+
+    #[test]
+    fn check_words() {
+        let dir = std::env::temp_dir().join(format!("trout_spell_check_{}", std::process::id()));
+        create_dir_all(&dir).unwrap();
+        let dict_path = dir.join("words");
+        let personal_path = dir.join("personal");
+        std::fs::write(&dict_path, "hello\nworld\ndon't\n").unwrap();
+
+        let mut checker = SpellChecker::new(&dict_path).unwrap();
+        assert!(checker.is_word("hello"));
+        assert!(checker.is_word("World"));
+        assert!(checker.is_word("don't"));
+        assert!(checker.is_word("x86"));
+        assert!(!checker.is_word("wrold"));
+        assert_eq!(checker.misspelled("Hello wrold, don't helo"), vec![6..11, 19..23]);
+
+        checker.load_personal(&personal_path).unwrap();
+        checker.add_word("trout").unwrap();
+        assert!(checker.is_word("trout"));
+        // Added words persist in the personal word list
+        let mut reloaded = SpellChecker::new(&dict_path).unwrap();
+        reloaded.load_personal(&personal_path).unwrap();
+        assert!(reloaded.is_word("trout"));
+        remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,6 +1,6 @@
 
 /// Represents the location of the cursor within text
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct TextPosition {
     pub row: usize,
     pub byte: usize,
@@ -0,0 +1,362 @@
+use std::borrow::Borrow;
+use std::collections::HashSet;
+use crate::textbuffer::lines::Line;
+
+/// A replacement of a run of lines, which undo and redo apply in one direction or the other
+#[derive(Clone, Debug, PartialEq)]
+pub struct LineChange {
+    /// Row of the first replaced line
+    pub start: usize,
+    /// Lines before the change
+    pub old: Vec<String>,
+    /// Lines after the change
+    pub new: Vec<String>,
+}
+
+impl LineChange {
+    /// Find the change turning the old lines into the new ones, as the region between the
+    /// lines they start and end with in common. None if they are the same.
+    pub fn between<L: Borrow<Line>>(old: &[String], new: &[L]) -> Option<LineChange> {
+        let new: Vec<&Line> = new.iter().map(Borrow::borrow).collect();
+        let prefix = old.iter().zip(&new).take_while(|(old, new)| **old == new.text).count();
+        if prefix == old.len() && prefix == new.len() {
+            return None;
+        }
+        let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev())
+            .take_while(|(old, new)| **old == new.text)
+            .count();
+        Some(LineChange {
+            start: prefix,
+            old: old[prefix..old.len() - suffix].to_vec(),
+            new: new[prefix..new.len() - suffix].iter().map(|line| line.text.clone()).collect(),
+        })
+    }
+
+    /// The change which undoes this one
+    pub fn inverse(&self) -> LineChange {
+        LineChange { start: self.start, old: self.new.clone(), new: self.old.clone() }
+    }
+
+    /// Apply the change to a list of lines
+    #[cfg(test)]
+    pub fn apply(&self, lines: &mut Vec<String>) {
+        lines.splice(self.start..self.start + self.old.len(), self.new.iter().cloned());
+    }
+}
+
+/// A state of the buffer in the undo tree
+#[derive(Clone, Debug)]
+struct UndoNode {
+    parent: Option<usize>,
+    /// Change from the parent's state to this one
+    change: Option<LineChange>,
+    children: Vec<usize>,
+    /// The child redo moves to, which is the most recently made or visited one
+    redo_child: Option<usize>,
+}
+
+/// Edit history kept as a tree, so undoing and then making a different edit starts a new
+/// branch rather than discarding the edits which were undone. Nodes are numbered in the
+/// order the edits were made, with the unedited buffer as node 0.
+#[derive(Clone, Debug)]
+pub struct UndoTree {
+    nodes: Vec<UndoNode>,
+    current: usize,
+}
+
+impl UndoTree {
+    pub fn new() -> UndoTree {
+        UndoTree {
+            nodes: vec![UndoNode { parent: None, change: None, children: Vec::new(), redo_child: None }],
+            current: 0,
+        }
+    }
+
+    /// Number of the current state, counting edits in the order they were made
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// Number of edits recorded, across all branches
+    pub fn len(&self) -> usize {
+        self.nodes.len() - 1
+    }
+
+    /// Record an edit made from the current state, which becomes the new current state
+    pub fn record(&mut self, change: LineChange) {
+        let node = self.nodes.len();
+        self.nodes.push(UndoNode { parent: Some(self.current), change: Some(change), children: Vec::new(), redo_child: None });
+        let parent = &mut self.nodes[self.current];
+        parent.children.push(node);
+        parent.redo_child = Some(node);
+        self.current = node;
+    }
+
+    /// Step back to the previous state, returning the change which gets there
+    pub fn undo(&mut self) -> Option<LineChange> {
+        let node = &self.nodes[self.current];
+        let parent = node.parent?;
+        let change = node.change.as_ref().map(LineChange::inverse);
+        self.nodes[parent].redo_child = Some(self.current);
+        self.current = parent;
+        change
+    }
+
+    /// Step forward along the branch last made or visited, returning the change to apply
+    pub fn redo(&mut self) -> Option<LineChange> {
+        let child = self.nodes[self.current].redo_child?;
+        self.current = child;
+        self.nodes[child].change.clone()
+    }
+
+    /// Make redo follow the next branch from the current state, returning whether there was
+    /// more than one branch to choose from
+    pub fn switch_branch(&mut self) -> bool {
+        let node = &mut self.nodes[self.current];
+        if node.children.len() < 2 {
+            return false;
+        }
+        let position = node.children.iter().position(|child| Some(*child) == node.redo_child).unwrap_or(0);
+        node.redo_child = Some(node.children[(position + 1) % node.children.len()]);
+        true
+    }
+
+    /// Move to the state after the given edit (0 for the unedited buffer), which may be on
+    /// another branch, returning the changes to apply in order
+    pub fn goto(&mut self, target: usize) -> Vec<LineChange> {
+        let target = target.min(self.len());
+        let to = self.ancestors(target);
+        let common = self.common_ancestor(self.current, &to);
+        let mut changes = Vec::new();
+        while self.current != common {
+            changes.extend(self.undo());
+        }
+        for node in to.iter().take_while(|node| **node != common).collect::<Vec<_>>().into_iter().rev() {
+            self.nodes[self.current].redo_child = Some(*node);
+            changes.extend(self.redo());
+        }
+        changes
+    }
+
+    /// The changes leading from one state to another, through the last state they have in
+    /// common, without moving to it
+    pub fn path(&self, from: usize, to: usize) -> Vec<LineChange> {
+        if from == to {
+            return Vec::new();
+        }
+        let to_ancestors = self.ancestors(to);
+        let common = self.common_ancestor(from, &to_ancestors);
+        let mut changes: Vec<LineChange> = self.ancestors(from).into_iter()
+            .take_while(|node| *node != common)
+            .filter_map(|node| self.nodes[node].change.as_ref().map(LineChange::inverse))
+            .collect();
+        let down: Vec<usize> = to_ancestors.into_iter().take_while(|node| *node != common).collect();
+        changes.extend(down.into_iter().rev().filter_map(|node| self.nodes[node].change.clone()));
+        changes
+    }
+
+    /// The node and each of its ancestors, from the node up to the unedited buffer
+    fn ancestors(&self, mut node: usize) -> Vec<usize> {
+        let mut path = vec![node];
+        while let Some(parent) = self.nodes[node].parent {
+            path.push(parent);
+            node = parent;
+        }
+        path
+    }
+
+    /// The closest ancestor of the node which is also among the given ancestors of another
+    fn common_ancestor(&self, node: usize, other_ancestors: &[usize]) -> usize {
+        let others: HashSet<&usize> = other_ancestors.iter().collect();
+        *self.ancestors(node).iter().find(|node| others.contains(node)).unwrap_or(&0)
+    }
+
+    /// Go back count edits in the order they were made (`:earlier`)
+    pub fn earlier(&mut self, count: usize) -> Vec<LineChange> {
+        self.goto(self.current.saturating_sub(count))
+    }
+
+    /// Go forward count edits in the order they were made (`:later`)
+    pub fn later(&mut self, count: usize) -> Vec<LineChange> {
+        self.goto(self.current.saturating_add(count))
+    }
+
+    /// Write the tree as text, one header line per node followed by its change's lines
+    pub fn serialize(&self) -> String {
+        let optional = |value: Option<usize>| value.map_or("-".to_string(), |value| value.to_string());
+        let mut out = format!("current {}\n", self.current);
+        for node in &self.nodes {
+            let empty = LineChange { start: 0, old: Vec::new(), new: Vec::new() };
+            let change = node.change.as_ref().unwrap_or(&empty);
+            out.push_str(&format!("node {} {} {} {} {}\n", optional(node.parent), optional(node.redo_child),
+                                  change.start, change.old.len(), change.new.len()));
+            for line in change.old.iter().chain(&change.new) {
+                out.push_str(line);
+                out.push('\n');
+            }
+        }
+        out
+    }
+
+    /// Read a tree written by `serialize`
+    pub fn deserialize(data: &str) -> Result<UndoTree, String> {
+        let invalid = || "Invalid undo history".to_string();
+        let number = |field: Option<&str>| field.and_then(|field| field.parse::<usize>().ok()).ok_or_else(invalid);
+        let optional = |field: Option<&str>| match field {
+            Some("-") => Ok(None),
+            field => number(field).map(Some),
+        };
+        // Split on newlines alone, as `lines` would strip the `\r` from lines which end in one
+        let mut lines = data.split_terminator('\n');
+        let current = number(lines.next().and_then(|line| line.strip_prefix("current ")))?;
+        let mut nodes: Vec<UndoNode> = Vec::new();
+        while let Some(header) = lines.next() {
+            let mut fields = header.strip_prefix("node ").ok_or_else(invalid)?.split(' ');
+            let parent = optional(fields.next())?;
+            let redo_child = optional(fields.next())?;
+            let start = number(fields.next())?;
+            let old_count = number(fields.next())?;
+            let new_count = number(fields.next())?;
+            let mut take = |count: usize| -> Result<Vec<String>, String> {
+                (0..count).map(|_| lines.next().map(str::to_string).ok_or_else(invalid)).collect()
+            };
+            let old = take(old_count)?;
+            let new = take(new_count)?;
+            // Nodes only ever follow their parents, and only the root has none
+            let node = nodes.len();
+            match parent {
+                None if node == 0 => {}
+                Some(parent) if parent < node => nodes[parent].children.push(node),
+                _ => return Err(invalid()),
+            }
+            let change = parent.map(|_| LineChange { start, old, new });
+            nodes.push(UndoNode { parent, change, children: Vec::new(), redo_child });
+        }
+        let valid_redo = nodes.iter().all(|node| node.redo_child.is_none_or(|child| node.children.contains(&child)));
+        if nodes.is_empty() || current >= nodes.len() || !valid_redo {
+            return Err(invalid());
+        }
+        Ok(UndoTree { nodes, current })
+    }
+}
+
+/// Hash of some lines of text (FNV-1a), stable between runs so it can be kept in files
+pub fn hash_lines<'a>(lines: impl Iterator<Item = &'a str>) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for line in lines {
+        for byte in line.bytes().chain(Some(b'\n')) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply_all(lines: &mut Vec<String>, changes: Vec<LineChange>) {
+        for change in changes {
+            change.apply(lines);
+        }
+    }
+
+    /// Make an edit replacing the whole text, recording it in the tree
+    fn edit(tree: &mut UndoTree, lines: &mut Vec<String>, text: &[&str]) {
+        let new: Vec<Line> = text.iter().map(|line| Line::from_string(line)).collect();
+        tree.record(LineChange::between(lines, &new).unwrap());
+        *lines = text.iter().map(|line| line.to_string()).collect();
+    }
+
+    #[test]
+    fn change_between_lines() {
+        let old = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let new = vec![Line::from_string("a"), Line::from_string("x"), Line::from_string("y"), Line::from_string("c")];
+        let change = LineChange::between(&old, &new).unwrap();
+        assert_eq!(change, LineChange { start: 1, old: vec!["b".to_string()], new: vec!["x".to_string(), "y".to_string()] });
+        let mut lines = old.clone();
+        change.apply(&mut lines);
+        assert_eq!(lines, ["a", "x", "y", "c"]);
+        change.inverse().apply(&mut lines);
+        assert_eq!(lines, old);
+        assert_eq!(LineChange::between(&old, &new[..1]).unwrap().old, ["b", "c"]);
+        assert!(LineChange::between(&old, &[Line::from_string("a"), Line::from_string("b"), Line::from_string("c")]).is_none());
+    }
+
+    #[test]
+    fn serialize_round_trip() {
+        let mut tree = UndoTree::new();
+        let mut lines = vec!["start".to_string()];
+        edit(&mut tree, &mut lines, &["one"]);
+        edit(&mut tree, &mut lines, &["one", "", "two"]);
+        apply_all(&mut lines, tree.undo().into_iter().collect());
+        edit(&mut tree, &mut lines, &["branch"]);
+        apply_all(&mut lines, tree.undo().into_iter().collect());
+
+        let mut restored = UndoTree::deserialize(&tree.serialize()).unwrap();
+        assert_eq!(restored.serialize(), tree.serialize());
+        apply_all(&mut lines, restored.redo().into_iter().collect());
+        assert_eq!(lines, ["branch"]);
+        apply_all(&mut lines, restored.earlier(1));
+        assert_eq!(lines, ["one", "", "two"]);
+        // Carriage returns kept in lines survive the round trip
+        edit(&mut tree, &mut lines, &["crlf\r", "\r"]);
+        let mut restored = UndoTree::deserialize(&tree.serialize()).unwrap();
+        assert_eq!(restored.undo().unwrap().old, ["crlf\r", "\r"]);
+        assert!(UndoTree::deserialize("current 3\nnode - - 0 0 0\n").is_err());
+        assert!(UndoTree::deserialize("current 0\nnode - - 0 0 0\nnode 0 - 0 2 0\nonly one\n").is_err());
+    }
+
+    #[test]
+    fn branches_stay_reachable() {
+        let mut tree = UndoTree::new();
+        let mut lines = vec!["start".to_string()];
+        edit(&mut tree, &mut lines, &["one"]);
+        edit(&mut tree, &mut lines, &["one", "two"]);
+        // Undo the second edit, then branch off with a different one
+        apply_all(&mut lines, tree.undo().into_iter().collect());
+        assert_eq!(lines, ["one"]);
+        edit(&mut tree, &mut lines, &["one", "branch"]);
+        assert_eq!(tree.len(), 3);
+
+        apply_all(&mut lines, tree.undo().into_iter().collect());
+        assert_eq!(lines, ["one"]);
+        // Redo follows the newest branch, until switching to the other
+        apply_all(&mut lines, tree.redo().into_iter().collect());
+        assert_eq!(lines, ["one", "branch"]);
+        apply_all(&mut lines, tree.undo().into_iter().collect());
+        assert!(tree.switch_branch());
+        apply_all(&mut lines, tree.redo().into_iter().collect());
+        assert_eq!(lines, ["one", "two"]);
+        assert!(tree.redo().is_none());
+
+        // Moving through time crosses between branches
+        apply_all(&mut lines, tree.later(1));
+        assert_eq!(lines, ["one", "branch"]);
+        apply_all(&mut lines, tree.earlier(3));
+        assert_eq!(lines, ["start"]);
+        apply_all(&mut lines, tree.later(2));
+        assert_eq!(lines, ["one", "two"]);
+    }
+
+    #[test]
+    fn path_between_branches() {
+        let mut tree = UndoTree::new();
+        let mut lines = vec!["start".to_string()];
+        edit(&mut tree, &mut lines, &["one"]);
+        edit(&mut tree, &mut lines, &["one", "two"]);
+        apply_all(&mut lines, tree.undo().into_iter().collect());
+        edit(&mut tree, &mut lines, &["one", "branch"]);
+        assert!(tree.path(3, 3).is_empty());
+        // Up from the branch to the first edit, then down the other branch
+        apply_all(&mut lines, tree.path(3, 2));
+        assert_eq!(lines, ["one", "two"]);
+        apply_all(&mut lines, tree.path(2, 0));
+        assert_eq!(lines, ["start"]);
+        apply_all(&mut lines, tree.path(0, 3));
+        assert_eq!(lines, ["one", "branch"]);
+        // Finding the path does not move through the history
+        assert_eq!(tree.current(), 3);
+    }
+}
@@ -0,0 +1,99 @@
+use std::ops::Range;
+use crate::textbuffer::rope::LineShift;
+
+/// Tracks the folded (collapsed) regions of a screen. Each fold is a range of rows whose
+/// first row is shown as a summary, with the remaining rows hidden.
+#[derive(Clone, Debug)]
+pub struct FoldState {
+    folds: Vec<Range<usize>>,
+}
+
+impl FoldState {
+    pub fn new() -> FoldState {
+        Self { folds: Vec::new() }
+    }
+
+    /// Fold a range of rows, replacing any folds it overlaps
+    pub fn fold(&mut self, range: Range<usize>) {
+        self.folds.retain(|fold| fold.end <= range.start || fold.start >= range.end);
+        self.folds.push(range);
+        self.folds.sort_by_key(|fold| fold.start);
+    }
+
+    /// Remove the fold containing the row, returning whether there was one
+    pub fn unfold_at(&mut self, row: usize) -> bool {
+        let count = self.folds.len();
+        self.folds.retain(|fold| !fold.contains(&row));
+        self.folds.len() != count
+    }
+
+    /// Move the folds along with lines inserted or removed above or in them. A fold left
+    /// without a row to hide is removed.
+    pub fn shift(&mut self, shift: &LineShift) {
+        for fold in &mut self.folds {
+            *fold = shift.row(fold.start)..shift.end(fold.end);
+        }
+        self.folds.retain(|fold| fold.end > fold.start + 1);
+    }
+
+    /// Remove every fold
+    pub fn clear(&mut self) {
+        self.folds.clear();
+    }
+
+    /// The fold containing the row, if it is folded
+    pub fn fold_containing(&self, row: usize) -> Option<&Range<usize>> {
+        self.folds.iter().find(|fold| fold.contains(&row))
+    }
+
+    /// Whether the row is hidden inside a fold (the first row of a fold is not hidden,
+    /// as it shows the fold's summary)
+    pub fn is_hidden(&self, row: usize) -> bool {
+        self.folds.iter().any(|fold| fold.start < row && row < fold.end)
+    }
+
+    /// Number of hidden rows in the range of rows
+    pub fn hidden_between(&self, rows: Range<usize>) -> usize {
+        rows.filter(|row| self.is_hidden(*row)).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fold_and_unfold() {
+        let mut folds = FoldState::new();
+        folds.fold(2..5);
+        assert!(!folds.is_hidden(2));
+        assert!(folds.is_hidden(3));
+        assert!(folds.is_hidden(4));
+        assert!(!folds.is_hidden(5));
+        assert_eq!(folds.hidden_between(0..10), 2);
+        // A larger fold replaces the folds it covers
+        folds.fold(1..8);
+        assert_eq!(folds.fold_containing(3), Some(&(1..8)));
+        assert!(folds.unfold_at(4));
+        assert!(folds.fold_containing(3).is_none());
+        assert!(!folds.unfold_at(4));
+    }
+
+    #[test]
+    fn folds_follow_shifted_lines() {
+        let mut folds = FoldState::new();
+        folds.fold(2..5);
+        folds.fold(8..10);
+        folds.shift(&LineShift { start: 0, removed: 0, added: 2 });
+        assert_eq!(folds.fold_containing(4), Some(&(4..7)));
+        // Lines added inside a fold grow it, but not lines added right after it
+        folds.shift(&LineShift { start: 5, removed: 0, added: 1 });
+        folds.shift(&LineShift { start: 8, removed: 0, added: 1 });
+        assert_eq!(folds.fold_containing(4), Some(&(4..8)));
+        assert_eq!(folds.fold_containing(12), Some(&(12..14)));
+        // Removing all but one of a fold's rows leaves nothing to fold
+        folds.shift(&LineShift { start: 13, removed: 2, added: 0 });
+        assert!(folds.fold_containing(12).is_none());
+        assert_eq!(folds.fold_containing(4), Some(&(4..8)));
+    }
+}
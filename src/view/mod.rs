@@ -1,3 +1,5 @@
 pub(crate) mod screen;
+pub mod folds;
 mod modes;
+pub mod render;
 pub mod splash_art;
\ No newline at end of file
@@ -1,3 +1,209 @@
-pub struct CommandViewer {
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind};
+use crate::commands::command::Command;
+use crate::commands::completion::{complete_command, complete_path};
+use crate::terminal::controls::Terminal;
+use crate::terminal::screen_location::ScreenLocation;
+use crate::view::screen::{Mode, Screen, ScreenAction};
 
-}
\ No newline at end of file
+pub struct CommandViewer<'a> {
+    screen: &'a mut Screen,
+    command_line: CommandLine,
+}
+
+impl<'a> CommandViewer<'a> {
+    pub fn enter(screen: &'a mut Screen) -> ScreenAction {
+        let mut s = Self {
+            screen,
+            command_line: CommandLine::new(),
+        };
+        s.run()
+    }
+
+    pub fn run(&mut self) -> ScreenAction {
+        Terminal::set_cursor_style(self.screen.settings.cursor_style(&Mode::Command)).unwrap();
+        self.draw();
+        loop {
+            match Terminal::read_event().unwrap() {
+                Event::Key(KeyEvent { code, kind, .. }) => {
+                    if kind == KeyEventKind::Press {
+                        match code {
+                            KeyCode::Esc => { return ScreenAction::EnterMode(Mode::Normal) }
+                            KeyCode::Enter => {
+                                return match Command::parse(&self.command_line.input) {
+                                    Ok(command) => ScreenAction::for_command(command),
+                                    Err(_) => ScreenAction::EnterMode(Mode::Normal),
+                                }
+                            }
+                            KeyCode::Tab => { self.command_line.complete() }
+                            KeyCode::Backspace => { self.command_line.pop() }
+                            KeyCode::Char(c) => { self.command_line.push(c) }
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+            self.draw();
+        }
+    }
+
+    /// Draw the command line on the bottom row of the screen
+    pub fn draw(&mut self) {
+        let row = self.screen.size.height.saturating_sub(1);
+        let _ = Terminal::move_caret_to(ScreenLocation { row, col: 0 });
+        let _ = Terminal::clear_line();
+        let _ = Terminal::print(&format!(":{}", self.command_line.input));
+        let _ = Terminal::execute();
+    }
+}
+
+/// The text typed in command mode, along with any in-progress tab completion
+pub struct CommandLine {
+    pub input: String,
+    completion: Option<Completion>,
+}
+
+/// Candidates for completing the word being typed, cycled through with repeated Tab presses
+struct Completion {
+    candidates: Vec<String>,
+    index: usize,
+    /// Byte offset in the input where the completed word starts
+    word_start: usize,
+}
+
+impl CommandLine {
+    pub fn new() -> CommandLine {
+        Self {
+            input: String::new(),
+            completion: None,
+        }
+    }
+
+    /// Add a character to the end of the input
+    pub fn push(&mut self, c: char) {
+        self.completion = None;
+        self.input.push(c);
+    }
+
+    /// Remove the last character of the input
+    pub fn pop(&mut self) {
+        self.completion = None;
+        self.input.pop();
+    }
+
+    /// Complete the word being typed. A single match is completed fully, while repeated
+    /// calls cycle through multiple matches.
+    pub fn complete(&mut self) {
+        if let Some(completion) = &mut self.completion {
+            completion.index = (completion.index + 1) % completion.candidates.len();
+            self.input.truncate(completion.word_start);
+            self.input.push_str(&completion.candidates[completion.index]);
+            return;
+        }
+        let (word_start, candidates) = match self.candidates() {
+            Some(found) => found,
+            None => return,
+        };
+        if candidates.is_empty() {
+            return;
+        }
+        self.input.truncate(word_start);
+        self.input.push_str(&candidates[0]);
+        if candidates.len() > 1 {
+            self.completion = Some(Completion { candidates, index: 0, word_start });
+        }
+    }
+
+    /// Find the start of the word being completed and the candidates for completing it
+    fn candidates(&self) -> Option<(usize, Vec<String>)> {
+        let (name, argument) = match self.input.split_once(' ') {
+            Some(split) => split,
+            None => {
+                let names = complete_command(&self.input);
+                return Some((0, names.iter().map(|name| name.to_string()).collect()));
+            }
+        };
+        match name {
+            "e" => {
+                let candidates = complete_path(argument).iter().map(|path| {
+                    let mut candidate = path.to_string_lossy().to_string();
+                    if path.is_dir() {
+                        candidate.push('/');
+                    }
+                    candidate
+                }).collect();
+                Some((name.len() + 1, candidates))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, remove_dir_all, File};
+
+    fn type_str(command_line: &mut CommandLine, input: &str) {
+        for c in input.chars() {
+            command_line.push(c);
+        }
+    }
+
+    #[test]
+    fn complete_command_name() {
+        let mut command_line = CommandLine::new();
+        type_str(&mut command_line, "q");
+        let mut offered = Vec::new();
+        for _ in 0..4 {
+            command_line.complete();
+            offered.push(command_line.input.clone());
+        }
+        assert_eq!(offered, vec!["q", "q!", "qa", "qa!"]);
+
+        // Completing a full (unique) command name changes nothing
+        let mut command_line = CommandLine::new();
+        type_str(&mut command_line, "wq");
+        command_line.complete();
+        assert_eq!(command_line.input, "wq");
+    }
+
+    #[test]
+    fn complete_edit_path() {
+        let dir = std::env::temp_dir().join(format!("trout_command_line_{}", std::process::id()));
+        create_dir_all(dir.join("subdir")).unwrap();
+        File::create(dir.join("alpha.txt")).unwrap();
+        File::create(dir.join("beta.txt")).unwrap();
+        File::create(dir.join("beta.rs")).unwrap();
+        let dir_str = format!("{}/", dir.display());
+
+        // A single matching file is completed fully
+        let mut command_line = CommandLine::new();
+        type_str(&mut command_line, &format!("e {dir_str}al"));
+        command_line.complete();
+        assert_eq!(command_line.input, format!("e {dir_str}alpha.txt"));
+
+        // A single matching directory gets a trailing slash
+        let mut command_line = CommandLine::new();
+        type_str(&mut command_line, &format!("e {dir_str}su"));
+        command_line.complete();
+        assert_eq!(command_line.input, format!("e {dir_str}subdir/"));
+
+        // Multiple matches are cycled through
+        let mut command_line = CommandLine::new();
+        type_str(&mut command_line, &format!("e {dir_str}be"));
+        command_line.complete();
+        assert_eq!(command_line.input, format!("e {dir_str}beta.rs"));
+        command_line.complete();
+        assert_eq!(command_line.input, format!("e {dir_str}beta.txt"));
+        command_line.complete();
+        assert_eq!(command_line.input, format!("e {dir_str}beta.rs"));
+
+        // No matches leaves the input alone
+        let mut command_line = CommandLine::new();
+        type_str(&mut command_line, &format!("e {dir_str}zzz"));
+        command_line.complete();
+        assert_eq!(command_line.input, format!("e {dir_str}zzz"));
+        remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,5 +1,30 @@
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind};
+use crate::terminal::controls::Terminal;
+use crate::view::screen::{CharFind, Mode, Screen, ScreenAction};
 
+/// Reads the character for a find on the cursor's line, started with `f`, `t`, `F` or `T`
+pub struct FindViewer<'a> {
+    screen: &'a mut Screen,
+}
 
-pub struct FindViewer {
+impl<'a> FindViewer<'a> {
+    pub fn enter(screen: &'a mut Screen) -> ScreenAction {
+        let mut s = Self { screen };
+        s.run()
+    }
 
-}
\ No newline at end of file
+    /// Wait for a character and find it, going back to normal mode either way. Esc, or any
+    /// other key which isn't a character, cancels the find.
+    pub fn run(&mut self) -> ScreenAction {
+        Terminal::set_cursor_style(self.screen.settings.cursor_style(&Mode::Find)).unwrap();
+        loop {
+            if let Event::Key(KeyEvent { code, kind: KeyEventKind::Press, .. }) = Terminal::read_event().unwrap() {
+                if let KeyCode::Char(c) = code {
+                    let find = CharFind { target: c.to_string(), motion: self.screen.find_motion };
+                    self.screen.find_char(find).unwrap();
+                }
+                return ScreenAction::EnterMode(Mode::Normal);
+            }
+        }
+    }
+}
@@ -1,20 +1,58 @@
-use crate::view::screen::Screen;
-
-pub struct InsertViewer{
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crate::terminal::controls::Terminal;
+use crate::view::screen::{Mode, Screen, ScreenAction};
 
+pub struct InsertViewer<'a> {
+    screen: &'a mut Screen,
 }
 
-impl InsertViewer {
-    pub fn enter(screen: &mut Screen){
-        loop{
+impl<'a> InsertViewer<'a> {
+    pub fn enter(screen: &'a mut Screen) -> ScreenAction {
+        screen.set_mode(Mode::Insert);
+        let mut s = Self {
+            screen,
+        };
+        s.run()
+    }
 
+    pub fn run(&mut self) -> ScreenAction {
+        Terminal::set_cursor_style(self.screen.settings.cursor_style(&Mode::Insert)).unwrap();
+        loop {
+            let event = Terminal::read_event().unwrap();
+            if let Event::Key(key_event @ KeyEvent{kind: KeyEventKind::Press, ..}) = event {
+                self.screen.key_pressed();
+                self.screen.record_insert_key(key_event);
+            }
+            match event {
+                Event::Key(KeyEvent { code, modifiers, kind, .. }) => {
+                    if kind == KeyEventKind::Press {
+                        match (modifiers, code) {
+                            (KeyModifiers::CONTROL, KeyCode::Char('u')) => { self.screen.delete_to_line_start().unwrap() }
+                            (_, KeyCode::Esc) => {
+                                self.screen.set_mode(Mode::Normal);
+                                return ScreenAction::EnterMode(Mode::Normal)
+                            }
+                            (_, KeyCode::Enter) => { self.screen.insert_new_line().unwrap() }
+                            (_, KeyCode::Backspace) => { self.screen.backspace().unwrap() }
+                            (_, KeyCode::Delete) => { self.screen.delete_forward().unwrap() }
+                            (_, KeyCode::Left) => { self.screen.move_left().unwrap() }
+                            (_, KeyCode::Right) => { self.screen.move_right().unwrap() }
+                            (_, KeyCode::Up) => { self.screen.move_up().unwrap() }
+                            (_, KeyCode::Down) => { self.screen.move_down().unwrap() }
+                            (KeyModifiers::NONE | KeyModifiers::SHIFT, KeyCode::Char(c)) => {
+                                self.screen.type_char(c).unwrap()
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+            self.draw();
         }
     }
 
-    pub fn draw(){
-        todo!()
+    pub fn draw(&mut self) {
+        self.screen.draw();
     }
-    pub fn exit(){
-        todo!()
-    }
-}
\ No newline at end of file
+}
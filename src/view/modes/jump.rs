@@ -1,4 +1,135 @@
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind};
+use crossterm::style::Stylize;
+use crate::terminal::controls::Terminal;
+use crate::textbuffer::text_location::TextPosition;
+use crate::view::screen::{Mode, Screen, ScreenAction};
 
-pub struct JumpViewer {
+/// Letters used for labels, in the order they are handed out
+const LABEL_LETTERS: &str = "abcdefghijklmnopqrstuvwxyz";
 
-}
\ No newline at end of file
+pub struct JumpViewer<'a> {
+    screen: &'a mut Screen,
+    labels: JumpLabels,
+    /// Keys typed so far, the start of a two letter label
+    typed: String,
+}
+
+impl<'a> JumpViewer<'a> {
+    pub fn enter(screen: &'a mut Screen) -> ScreenAction {
+        let labels = JumpLabels::new(screen.visible_word_starts());
+        let mut s = Self {
+            screen,
+            labels,
+            typed: String::new(),
+        };
+        s.run()
+    }
+
+    /// Read label keys until one picks a word to jump to. Anything which isn't part of a label
+    /// cancels, and going back to normal mode redraws the screen without the labels.
+    pub fn run(&mut self) -> ScreenAction {
+        Terminal::set_cursor_style(self.screen.settings.cursor_style(&Mode::Jump)).unwrap();
+        if self.labels.targets.is_empty() {
+            return ScreenAction::EnterMode(Mode::Normal);
+        }
+        self.draw();
+        loop {
+            if let Event::Key(KeyEvent { code, kind: KeyEventKind::Press, .. }) = Terminal::read_event().unwrap() {
+                let KeyCode::Char(c) = code else { return ScreenAction::EnterMode(Mode::Normal) };
+                self.typed.push(c);
+                match self.labels.select(&self.typed) {
+                    LabelMatch::Target(position) => {
+                        self.screen.jump_to(position).unwrap();
+                        return ScreenAction::EnterMode(Mode::Normal);
+                    }
+                    LabelMatch::Prefix => {}
+                    LabelMatch::None => return ScreenAction::EnterMode(Mode::Normal),
+                }
+            }
+            self.draw();
+        }
+    }
+
+    /// Draw the labels still matching what has been typed over the starts of their words
+    pub fn draw(&mut self) {
+        let _ = Terminal::hide_caret();
+        for (label, position) in &self.labels.targets {
+            let Some(rest) = label.strip_prefix(self.typed.as_str()) else { continue };
+            let _ = Terminal::move_caret_to(self.screen.location_of(position));
+            let _ = Terminal::print(&format!("{}", rest.black().on_yellow()));
+        }
+        let _ = Terminal::execute();
+    }
+}
+
+/// Labels for jump targets: single letters while there are few enough targets, otherwise
+/// two letters for every target
+pub struct JumpLabels {
+    pub targets: Vec<(String, TextPosition)>,
+}
+
+/// What a sequence of typed keys picks out of the labels
+#[derive(Debug, PartialEq)]
+pub enum LabelMatch {
+    Target(TextPosition),
+    /// The keys start at least one label, so more are needed
+    Prefix,
+    None,
+}
+
+impl JumpLabels {
+    /// Label targets in order. Targets beyond what two letter labels can cover are dropped.
+    pub fn new(targets: Vec<TextPosition>) -> JumpLabels {
+        let letters: Vec<char> = LABEL_LETTERS.chars().collect();
+        let count = letters.len();
+        let labels: Vec<String> = if targets.len() <= count {
+            letters.iter().map(|letter| letter.to_string()).collect()
+        } else {
+            (0..count * count).map(|idx| format!("{}{}", letters[idx / count], letters[idx % count])).collect()
+        };
+        JumpLabels { targets: labels.into_iter().zip(targets).collect() }
+    }
+
+    pub fn select(&self, typed: &str) -> LabelMatch {
+        let mut prefix = false;
+        for (label, position) in &self.targets {
+            if label == typed {
+                return LabelMatch::Target(position.clone());
+            }
+            prefix |= label.starts_with(typed);
+        }
+        if prefix { LabelMatch::Prefix } else { LabelMatch::None }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::textbuffer::buffer::Buffer;
+
+    fn position(row: usize, grapheme: usize) -> TextPosition {
+        TextPosition { row, byte: grapheme, grapheme }
+    }
+
+    #[test]
+    fn label_visible_words() {
+        let mut screen = Screen::default();
+        screen.buffer = Buffer::from_string("one two\n  three four\n");
+        let labels = JumpLabels::new(screen.visible_word_starts());
+        let names: Vec<&str> = labels.targets.iter().map(|(label, _)| label.as_str()).collect();
+        assert_eq!(names, ["a", "b", "c", "d"]);
+        // The third word is the first on the second line
+        assert_eq!(labels.select("c"), LabelMatch::Target(position(1, 2)));
+        assert_eq!(labels.select("z"), LabelMatch::None);
+    }
+
+    #[test]
+    fn two_letter_labels() {
+        let labels = JumpLabels::new((0..30).map(|idx| position(idx, 0)).collect());
+        assert_eq!(labels.targets[0].0, "aa");
+        assert_eq!(labels.targets[27].0, "bb");
+        assert_eq!(labels.select("b"), LabelMatch::Prefix);
+        assert_eq!(labels.select("bb"), LabelMatch::Target(position(27, 0)));
+        assert_eq!(labels.select("zz"), LabelMatch::None);
+    }
+}
@@ -10,11 +10,11 @@ use crate::view::modes::select::SelectViewer;
 use crate::view::modes::welcome_screen::WelcomeViewer;
 use crate::view::screen::Boundary;
 pub mod normal;
-mod jump;
-mod search;
-mod insert;
-mod command;
-mod find;
-mod open;
-mod select;
+pub mod jump;
+pub mod search;
+pub mod insert;
+pub mod command;
+pub mod find;
+pub mod open;
+pub mod select;
 mod welcome_screen;
\ No newline at end of file
@@ -1,15 +1,22 @@
 use std::cmp::min;
 use std::iter::Enumerate;
-use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, read};
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crate::commands::actions::ActionType;
+use crate::input::keymap::KeyMap;
 use crate::terminal::controls::Terminal;
 use crate::terminal::screen_location::ScreenLocation;
-use crate::view::screen::{Mode, Screen, ScreenAction};
+use crate::textbuffer::registers::Registers;
+use crate::view::screen::{FindKind, FindMotion, Mode, Screen, ScreenAction, SelectionKind};
 
 pub struct NormalViewer<'a> {
     quit_view: bool,
     screen: &'a mut Screen,
     screen_action: ScreenAction,
     needs_redraw: bool,
+    /// First key of a two key command, waiting for the second
+    pending: Option<char>,
+    /// Count typed before a command, for the commands which repeat
+    count: Option<usize>,
 }
 
 impl<'a> NormalViewer<'a> {
@@ -18,31 +25,59 @@ impl<'a> NormalViewer<'a> {
             quit_view:false,
             screen,
             screen_action: ScreenAction::QuitScreen,
-            needs_redraw:false
+            needs_redraw:false,
+            pending: None,
+            count: None,
         };
         s.run()
     }
     pub fn run(&mut self) -> ScreenAction{
-        // Set Cursor to blinking block
-        Terminal::blinking_block_cursor().unwrap();
+        Terminal::set_cursor_style(self.screen.settings.cursor_style(&Mode::Normal)).unwrap();
         loop {
             if self.quit_view{
                 break;
             }
-            match read().unwrap() {
-                Event::FocusGained => {} // Nothing for now
-                Event::FocusLost => {} // Nothing for now
+            let event = Terminal::read_event().unwrap();
+            if matches!(event, Event::Key(KeyEvent{kind: KeyEventKind::Press, ..})) {
+                self.screen.key_pressed();
+            }
+            match event {
+                Event::FocusGained => {self.screen.focus_gained().unwrap();}
+                Event::FocusLost => {self.screen.focus_lost().unwrap();}
+                Event::Key(KeyEvent{code, modifiers, kind: KeyEventKind::Press, ..}) if self.pending.is_some() => {
+                    let prefix = self.pending.take().unwrap();
+                    if let Some(action) = self.complete_pending(prefix, code, modifiers) {
+                        return action;
+                    }
+                }
+                Event::Key(KeyEvent{code, modifiers, kind: KeyEventKind::Press, ..}) if self.bound_action(code, modifiers).is_some() => {
+                    self.count = None;
+                    let action_type = self.bound_action(code, modifiers).unwrap();
+                    if let Some(action) = self.run_bound_action(action_type) {
+                        return action;
+                    }
+                }
                 Event::Key(KeyEvent{code, modifiers, kind,.. }) => {
                     if kind == KeyEventKind::Press{
+                        let count = self.count.take();
                         match modifiers{
                             KeyModifiers::CONTROL => {
                                 match code {
                                     KeyCode::Char(c)=>{
                                         match c {
-                                            'w'=>{}
-                                            'a'=>{}
-                                            's'=>{}
-                                            'd'=>{}
+                                            ']'=>{let _ = self.screen.goto_definition();}
+                                            'r'=>{self.screen.redo().unwrap()}
+                                            'o'=>{self.screen.jump_back().unwrap();}
+                                            'w'=>{self.screen.scroll_page_up().unwrap()}
+                                            'a'=>{self.screen.increment_number(1).unwrap()}
+                                            's'=>{self.screen.scroll_page_down().unwrap()}
+                                            'd'=>{self.screen.scroll_half_page_down().unwrap()}
+                                            'u'=>{self.screen.scroll_half_page_up().unwrap()}
+                                            'x'=>{self.screen.increment_number(-1).unwrap()}
+                                            'g'=>{self.screen.show_stats()}
+                                            'v'=>{return self.enter_select(SelectionKind::Block)}
+                                            #[cfg(unix)]
+                                            'z'=>{Terminal::suspend().unwrap()}
                                             _=>{}
                                         }
                                     }
@@ -63,6 +98,30 @@ impl<'a> NormalViewer<'a> {
                                             'a'=>{self.screen.move_prev_word().unwrap()}
                                             's' => {self.screen.move_last_line().unwrap()}
                                             'd'=>{self.screen.move_next_word().unwrap()}
+                                            '{'=>{self.screen.move_prev_paragraph().unwrap()}
+                                            '}'=>{self.screen.move_next_paragraph().unwrap()}
+                                            '>'=>{self.shift_lines(count, false)}
+                                            '<'=>{self.shift_lines(count, true)}
+                                            '^'=>{self.screen.move_first_non_blank().unwrap()}
+                                            'N'=>{self.screen.search_prev().unwrap()}
+                                            'F'=>{return self.enter_find(false, FindKind::To)}
+                                            'T'=>{return self.enter_find(false, FindKind::Till)}
+                                            'J'=>{self.screen.join_lines().unwrap()}
+                                            '"'=>{self.pending = Some('"')}
+                                            'D'=>{self.screen.delete_to_line_end().unwrap()}
+                                            'P'=>{self.screen.paste(true).unwrap()}
+                                            '%'=>{self.screen.move_matching_bracket().unwrap();}
+                                            '@'=>{self.start_play(count)}
+                                            // `q` quits, so macros are recorded with `Q`
+                                            'Q'=>{self.toggle_recording()}
+                                            'C'=>{self.pending = Some('C')}
+                                            'I'=>{return self.screen.insert_at_first_non_blank().unwrap()}
+                                            'L'=>{return self.screen.insert_at_line_end().unwrap()}
+                                            'O'=>{
+                                                let action = self.screen.open_line(true).unwrap();
+                                                self.record_change("O");
+                                                return action;
+                                            }
                                             _ => {}
                                         }
                                     }
@@ -73,15 +132,15 @@ impl<'a> NormalViewer<'a> {
                             KeyModifiers::META => {}
                             KeyModifiers::NONE => {
                                 match code {
-                                    KeyCode::Delete =>{self.screen.delete_grapheme(
-                                        self.screen.text_position.clone()
-                                    )}
+                                    KeyCode::Delete =>{self.screen.delete_forward().unwrap()}
                                     KeyCode::Left => {self.screen.move_left().unwrap()}
                                     KeyCode::Right => {self.screen.move_right().unwrap()}
                                     KeyCode::Up => {self.screen.move_up().unwrap()}
                                     KeyCode::Down => {self.screen.move_down().unwrap()}
-                                    KeyCode::Home => {self.screen.move_start_line().unwrap()}
-                                    KeyCode::End => {self.screen.move_end_line().unwrap()}
+                                    KeyCode::Home => {self.screen.move_start_visual_row().unwrap()}
+                                    KeyCode::End => {self.screen.move_end_visual_row().unwrap()}
+                                    KeyCode::PageUp => {self.screen.scroll_page_up().unwrap()}
+                                    KeyCode::PageDown => {self.screen.scroll_page_down().unwrap()}
                                     KeyCode::Char(c) => {
                                         match c{
                                             'q'=>{return ScreenAction::QuitScreen}
@@ -90,12 +149,45 @@ impl<'a> NormalViewer<'a> {
                                             's'=>{self.screen.move_down().unwrap()}
                                             'd'=>{self.screen.move_right().unwrap()}
                                             'i'=>{return ScreenAction::EnterMode(Mode::Insert)}
+                                            // `a` moves left, so inserting after the cursor is `l` (and `L` at the line end)
+                                            'l'=>{return self.screen.insert_after().unwrap()}
                                             ' '=>{return ScreenAction::EnterMode(Mode::Jump)}
                                             'e'=>{return ScreenAction::EnterMode(Mode::Open)}
-                                            'f'=>{return ScreenAction::EnterMode(Mode::Find)}
+                                            'o'=>{
+                                                let action = self.screen.open_line(false).unwrap();
+                                                self.record_change("o");
+                                                return action;
+                                            }
+                                            'f'=>{return self.enter_find(true, FindKind::To)}
+                                            't'=>{return self.enter_find(true, FindKind::Till)}
+                                            ';'=>{self.screen.repeat_find(false).unwrap()}
+                                            ','=>{self.screen.repeat_find(true).unwrap()}
                                             'c'=>{return ScreenAction::EnterMode(Mode::Command)}
-                                            'h'=>{return ScreenAction::EnterMode(Mode::Select)}
+                                            'h'=>{return self.enter_select(SelectionKind::Charwise)}
                                             'x'=>{self.screen.delete_grapheme(self.screen.text_position.clone())}
+                                            'g'=>{self.pending = Some('g')}
+                                            'm'=>{self.pending = Some('m')}
+                                            '"'=>{self.pending = Some('"')}
+                                            'k'=>{self.pending = Some('k')}
+                                            'y'=>{self.pending = Some('y')}
+                                            'z'=>{self.pending = Some('z')}
+                                            'r'=>{self.pending = Some('r')}
+                                            'p'=>{self.screen.paste(false).unwrap()}
+                                            '`'=>{self.pending = Some('`')}
+                                            '{'=>{self.screen.move_prev_paragraph().unwrap()}
+                                            '}'=>{self.screen.move_next_paragraph().unwrap()}
+                                            '>'=>{self.shift_lines(count, false)}
+                                            '<'=>{self.shift_lines(count, true)}
+                                            '^'=>{self.screen.move_first_non_blank().unwrap()}
+                                            '/'=>{return ScreenAction::EnterMode(Mode::Search)}
+                                            'n'=>{self.screen.search_next().unwrap()}
+                                            '%'=>{self.screen.move_matching_bracket().unwrap();}
+                                            'u'=>{self.screen.undo().unwrap()}
+                                            '.'=>{self.screen.repeat_change()}
+                                            '@'=>{self.start_play(count)}
+                                            '1'..='9' => {self.add_count_digit(count, c)}
+                                            '0' if count.is_some() => {self.add_count_digit(count, c)}
+                                            '0'=>{self.screen.move_start_line().unwrap()}
                                             _=>{}
                                         }
                                     }
@@ -111,51 +203,132 @@ impl<'a> NormalViewer<'a> {
                 Event::Paste(_) => {}
                 Event::Resize(row, col) => {} // Nothing yet, but should resize the screen bounds
             }
+            // Every normal mode command is undone as one step
+            self.screen.buffer.commit_undo();
             self.draw();
         }
         return self.screen_action.clone();
     }
 
-    pub fn draw(&mut self) {
-        let _ = Terminal::hide_caret(); // Hide the caret so it doesn't flicker across the screen
-        self.draw_text(); // Draw the text to the screen
-        let _ = Terminal::execute(); // Execute the queued commands, drawing the current view
+    /// Run a two key command, given its first key and the key which followed, returning the
+    /// action if it leaves normal mode. Unknown sequences are ignored.
+    fn complete_pending(&mut self, prefix: char, code: KeyCode, modifiers: KeyModifiers) -> Option<ScreenAction> {
+        match (prefix, code) {
+            ('g', KeyCode::Char('g')) if modifiers == KeyModifiers::CONTROL => {self.screen.show_selection_counts()}
+            ('g', KeyCode::Char('q')) => {self.screen.reflow_paragraph().unwrap()}
+            ('g', KeyCode::Char('-')) => {self.screen.earlier(1).unwrap()}
+            ('g', KeyCode::Char('+')) => {self.screen.later(1).unwrap()}
+            ('g', KeyCode::Char('b')) => {self.screen.buffer.undo_tree.switch_branch();}
+            ('m', KeyCode::Char(c)) if c.is_ascii_alphabetic() => {self.screen.set_mark(c)}
+            // `d` moves right, so lines and words are deleted ("killed") with `kk` and `kw`
+            ('k', KeyCode::Char('k')) => {self.screen.delete_current_line().unwrap()}
+            ('k', KeyCode::Char('w')) => {self.screen.delete_word().unwrap()}
+            ('y', KeyCode::Char('y')) => {self.screen.yank_current_line()}
+            ('"', KeyCode::Char(c)) if Registers::is_valid_name(c) => {
+                self.screen.selected_register = Some(c);
+            }
+            ('`', KeyCode::Char(c)) => {self.screen.jump_to_mark(c).unwrap();}
+            ('@', KeyCode::Char('@')) => {self.screen.play_macro(None, self.count.take().unwrap_or(1))}
+            ('@', KeyCode::Char(c)) if c.is_ascii_alphanumeric() => {
+                self.screen.play_macro(Some(c), self.count.take().unwrap_or(1))
+            }
+            ('Q', KeyCode::Char(c)) if c.is_ascii_alphanumeric() => {self.screen.start_recording(c)}
+            ('z', KeyCode::Char('z')) => {self.screen.center_cursor().unwrap()}
+            ('z', KeyCode::Char('t')) => {self.screen.cursor_to_top().unwrap()}
+            ('z', KeyCode::Char('b')) => {self.screen.cursor_to_bottom().unwrap()}
+            ('r', KeyCode::Char(c)) => {
+                self.screen.replace_char(c).unwrap();
+                self.record_change(&format!("r{c}"));
+            }
+            // `c` opens command mode, so words are changed with `Cw`
+            ('C', KeyCode::Char('w')) => {
+                let action = self.screen.change_word().unwrap();
+                self.record_change("Cw");
+                return Some(action);
+            }
+            _ => {}
+        }
+        None
     }
 
-    /// Draw the text portion of the screen
-    fn draw_text(&mut self){
-        for (idx,line) in (self.screen.scroll_offset.row..(
-            self.screen.view_height()+self.screen.scroll_offset.row)).enumerate(){
-            if line < self.screen.buffer.num_lines {
-                self.draw_line(idx, line);
-            } else {
-                self.draw_empty_line(idx);
-            }
+    /// The action the user's keymap binds the key to in normal mode, which takes the place of
+    /// the built in command for the key
+    fn bound_action(&self, code: KeyCode, modifiers: KeyModifiers) -> Option<ActionType> {
+        let name = KeyMap::key_name(code, modifiers)?;
+        self.screen.key_map.normal.get(&name).copied()
+    }
+
+    /// Carry out a bound action, returning the action if it leaves normal mode. Actions which
+    /// mean nothing in normal mode do nothing.
+    fn run_bound_action(&mut self, action_type: ActionType) -> Option<ScreenAction> {
+        match action_type {
+            ActionType::MoveRight => {self.screen.move_right().unwrap()}
+            ActionType::MoveLeft => {self.screen.move_left().unwrap()}
+            ActionType::MoveUp => {self.screen.move_up().unwrap()}
+            ActionType::MoveDown => {self.screen.move_down().unwrap()}
+            ActionType::EnterInsert => {return Some(ScreenAction::EnterMode(Mode::Insert))}
+            ActionType::EnterJump => {return Some(ScreenAction::EnterMode(Mode::Jump))}
+            ActionType::EnterCommand => {return Some(ScreenAction::EnterMode(Mode::Command))}
+            ActionType::EnterFind => {return Some(self.enter_find(true, FindKind::To))}
+            ActionType::EnterOpen => {return Some(ScreenAction::EnterMode(Mode::Open))}
+            ActionType::EnterNormal | ActionType::InsertChar | ActionType::Cancel => {}
         }
+        None
     }
 
-    /// draw a line of text to the screen
-    fn draw_line(&mut self, screen_row: usize, text_line: usize){
-        // Move caret to start of view on current line
-        let _=Terminal::move_caret_to(ScreenLocation{
-            row:screen_row, col: self.screen.inner_boundary.left});
-        // Clear to the end of the line
-        let _ = Terminal::clear_to_line_end();
-        // Print the row of text
-        let _ = Terminal::print(&self.screen.buffer.print_line(
-            text_line,
-            self.screen.scroll_offset.col,
-            self.screen.scroll_offset.col+self.screen.view_width(),
-            false
-        ));
+    /// Remember the keys typed for a change, so `.` can repeat it
+    fn record_change(&mut self, keys: &str) {
+        let keys = keys.chars().map(|c| {
+            let modifiers = if c.is_uppercase() { KeyModifiers::SHIFT } else { KeyModifiers::NONE };
+            KeyEvent::new(KeyCode::Char(c), modifiers)
+        }).collect();
+        self.screen.record_change(keys);
     }
 
-    /// draw an empty line to the screen
-    fn draw_empty_line(&self, screen_row: usize){
-        let _ = Terminal::move_caret_to(ScreenLocation{
-            row: screen_row, col: self.screen.inner_boundary.left
-        });
-        let _ = Terminal::clear_to_line_end();
-        let _ = Terminal::print("~");
+    /// Add a digit typed onto the end of the count for the next command
+    fn add_count_digit(&mut self, count: Option<usize>, digit: char) {
+        let digit = digit.to_digit(10).unwrap() as usize;
+        self.count = Some(count.unwrap_or(0).saturating_mul(10).saturating_add(digit));
+    }
+
+    /// Wait for the register of a macro to play, keeping the count to play it with
+    fn start_play(&mut self, count: Option<usize>) {
+        self.count = count;
+        self.pending = Some('@');
+    }
+
+    /// Stop recording a macro, or wait for the register to record one into
+    fn toggle_recording(&mut self) {
+        if self.screen.macros.borrow().recording().is_some() {
+            self.screen.stop_recording();
+        } else {
+            self.pending = Some('Q');
+        }
+    }
+
+    /// Indent (or dedent) the cursor's line, and the lines below it with a count
+    fn shift_lines(&mut self, count: Option<usize>, dedent: bool) {
+        let row = self.screen.text_position.row;
+        let rows = row..row + count.unwrap_or(1);
+        if dedent {
+            self.screen.dedent_lines(rows).unwrap()
+        } else {
+            self.screen.indent_lines(rows).unwrap()
+        }
+    }
+
+    fn enter_select(&mut self, kind: SelectionKind) -> ScreenAction {
+        self.screen.selection_kind = kind;
+        ScreenAction::EnterMode(Mode::Select)
+    }
+
+    /// Go to find mode to read the character for a find on the cursor's line
+    fn enter_find(&mut self, forward: bool, kind: FindKind) -> ScreenAction {
+        self.screen.find_motion = FindMotion { forward, kind };
+        ScreenAction::EnterMode(Mode::Find)
+    }
+
+    pub fn draw(&mut self) {
+        self.screen.draw();
     }
 }
\ No newline at end of file
@@ -1,3 +1,263 @@
-pub struct OpenViewer{
+use std::fs::read_dir;
+use std::path::{Path, PathBuf};
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind};
+use crossterm::style::Stylize;
+use crate::terminal::controls::Terminal;
+use crate::terminal::screen_location::ScreenLocation;
+use crate::view::screen::{Mode, Screen, ScreenAction};
 
-}
\ No newline at end of file
+pub struct OpenViewer<'a> {
+    screen: &'a mut Screen,
+    listing: DirectoryListing,
+}
+
+impl<'a> OpenViewer<'a> {
+    pub fn enter(screen: &'a mut Screen) -> ScreenAction {
+        // Browse the directory the screen was opened on, otherwise the one holding its file
+        let directory = match &screen.browse_directory {
+            Some(directory) => directory.clone(),
+            None => match screen.buffer.path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+                _ => PathBuf::from("."),
+            },
+        };
+        let mut s = Self {
+            screen,
+            listing: DirectoryListing::read(&directory),
+        };
+        s.run()
+    }
+
+    pub fn run(&mut self) -> ScreenAction {
+        Terminal::set_cursor_style(self.screen.settings.cursor_style(&Mode::Open)).unwrap();
+        self.draw();
+        loop {
+            match Terminal::read_event().unwrap() {
+                Event::Key(KeyEvent { code, kind: KeyEventKind::Press, .. }) => {
+                    match code {
+                        KeyCode::Esc => { return ScreenAction::EnterMode(Mode::Normal) }
+                        KeyCode::Up | KeyCode::Char('w') => { self.listing.move_up() }
+                        KeyCode::Down | KeyCode::Char('s') => { self.listing.move_down() }
+                        KeyCode::Enter => {
+                            if let Some(path) = self.listing.activate() {
+                                return ScreenAction::OpenScreen(path);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+            self.draw();
+        }
+    }
+
+    /// Draw the listing over the whole screen, with the caret on the selected entry. Rows below
+    /// the listing are cleared, so no text from the buffer shows through a short listing.
+    pub fn draw(&mut self) {
+        let _ = Terminal::hide_caret();
+        let rows = self.listing.render_rows(self.screen.size.height);
+        for idx in 0..self.screen.size.height {
+            let _ = Terminal::move_caret_to(ScreenLocation { row: idx, col: 0 });
+            let _ = Terminal::clear_line();
+            if let Some(row) = rows.get(idx) {
+                let _ = Terminal::print(row);
+            }
+        }
+        let selected_row = 1 + self.listing.selected - self.listing.first_visible(self.screen.size.height);
+        let _ = Terminal::move_caret_to(ScreenLocation { row: selected_row, col: 0 });
+        let _ = Terminal::show_caret();
+        let _ = Terminal::execute();
+    }
+}
+
+/// What an entry in a directory listing is
+#[derive(Clone, Debug, PartialEq)]
+pub enum EntryKind {
+    /// The directory containing the one being listed, `..`
+    Parent,
+    Directory,
+    File,
+    /// An entry which couldn't be read, with the reason
+    Unreadable(String),
+}
+
+/// An entry in a directory listing
+#[derive(Clone, Debug, PartialEq)]
+pub struct DirectoryEntry {
+    pub name: String,
+    pub path: PathBuf,
+    pub kind: EntryKind,
+}
+
+/// The entries of a directory, one of which is selected
+pub struct DirectoryListing {
+    pub directory: PathBuf,
+    pub entries: Vec<DirectoryEntry>,
+    pub selected: usize,
+}
+
+impl DirectoryListing {
+    /// List a directory: its parent first, then subdirectories, then files, each sorted by name.
+    /// Entries which can't be read are listed with the error rather than stopping the listing.
+    pub fn read(directory: &Path) -> DirectoryListing {
+        let directory = directory.canonicalize().unwrap_or_else(|_| directory.to_path_buf());
+        let mut entries = Vec::new();
+        if let Some(parent) = directory.parent() {
+            entries.push(DirectoryEntry { name: "..".to_string(), path: parent.to_path_buf(), kind: EntryKind::Parent });
+        }
+        let mut listed = Vec::new();
+        match read_dir(&directory) {
+            Ok(read) => {
+                for entry in read {
+                    listed.push(match entry {
+                        Ok(entry) => {
+                            let name = entry.file_name().to_string_lossy().to_string();
+                            let kind = match entry.path().metadata() {
+                                Ok(metadata) if metadata.is_dir() => EntryKind::Directory,
+                                Ok(_) => EntryKind::File,
+                                Err(error) => EntryKind::Unreadable(error.to_string()),
+                            };
+                            DirectoryEntry { name, path: entry.path(), kind }
+                        }
+                        Err(error) => DirectoryEntry {
+                            name: "?".to_string(),
+                            path: directory.clone(),
+                            kind: EntryKind::Unreadable(error.to_string()),
+                        },
+                    });
+                }
+            }
+            Err(error) => listed.push(DirectoryEntry {
+                name: directory.display().to_string(),
+                path: directory.clone(),
+                kind: EntryKind::Unreadable(error.to_string()),
+            }),
+        }
+        listed.sort_by(|a, b| {
+            let is_file = |entry: &DirectoryEntry| entry.kind != EntryKind::Directory;
+            (is_file(a), &a.name).cmp(&(is_file(b), &b.name))
+        });
+        entries.extend(listed);
+        DirectoryListing { directory, entries, selected: 0 }
+    }
+
+    pub fn move_up(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    pub fn move_down(&mut self) {
+        if self.selected + 1 < self.entries.len() {
+            self.selected += 1;
+        }
+    }
+
+    /// Act on the selected entry: directories are descended into, while a file's path is
+    /// returned so that it can be opened
+    pub fn activate(&mut self) -> Option<PathBuf> {
+        let entry = self.entries.get(self.selected)?.clone();
+        match entry.kind {
+            EntryKind::Parent | EntryKind::Directory => {
+                *self = DirectoryListing::read(&entry.path);
+                None
+            }
+            EntryKind::File => Some(entry.path),
+            EntryKind::Unreadable(_) => None,
+        }
+    }
+
+    /// Index of the first entry shown, scrolled so that the selected entry is visible below
+    /// the header row
+    fn first_visible(&self, height: usize) -> usize {
+        let shown = height.saturating_sub(1).max(1);
+        (self.selected + 1).saturating_sub(shown)
+    }
+
+    /// Render the rows of the listing: the directory as a header, then as many entries as
+    /// fit. Directories are blue with a trailing `/`, and the selected entry is reversed.
+    pub fn render_rows(&self, height: usize) -> Vec<String> {
+        let mut rows = vec![format!("{}", self.directory.display().to_string().bold())];
+        let first = self.first_visible(height);
+        for (idx, entry) in self.entries.iter().enumerate().skip(first).take(height.saturating_sub(1)) {
+            let text = match &entry.kind {
+                EntryKind::Parent | EntryKind::Directory => format!("{}/", entry.name).blue(),
+                EntryKind::File => entry.name.clone().stylize(),
+                EntryKind::Unreadable(error) => format!("{} ({error})", entry.name).red(),
+            };
+            rows.push(if idx == self.selected { format!("{}", text.reverse()) } else { format!("{text}") });
+        }
+        rows
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+
+    fn names(listing: &DirectoryListing) -> Vec<&str> {
+        listing.entries.iter().map(|entry| entry.name.as_str()).collect()
+    }
+
+    #[test]
+    fn list_and_navigate_directory() {
+        let dir = std::env::temp_dir().join(format!("trout_open_viewer_{}", std::process::id()));
+        create_dir_all(dir.join("sub")).unwrap();
+        write(dir.join("b.txt"), "").unwrap();
+        write(dir.join("a.txt"), "").unwrap();
+        write(dir.join("sub").join("inner.rs"), "").unwrap();
+
+        let mut listing = DirectoryListing::read(&dir);
+        assert_eq!(names(&listing), ["..", "sub", "a.txt", "b.txt"]);
+        assert_eq!(listing.entries[1].kind, EntryKind::Directory);
+        assert!(listing.render_rows(10)[2].contains("sub/"));
+
+        // Descend into the subdirectory, then open the file in it
+        listing.move_down();
+        assert_eq!(listing.activate(), None);
+        assert_eq!(names(&listing), ["..", "inner.rs"]);
+        listing.move_down();
+        listing.move_down();
+        assert_eq!(listing.activate(), Some(dir.canonicalize().unwrap().join("sub").join("inner.rs")));
+
+        // The parent entry goes back up
+        listing.move_up();
+        listing.move_up();
+        assert_eq!(listing.activate(), None);
+        assert_eq!(listing.directory, dir.canonicalize().unwrap());
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn scroll_to_selected_entry() {
+        let listing = DirectoryListing {
+            directory: PathBuf::from("/"),
+            entries: (0..10).map(|idx| DirectoryEntry {
+                name: format!("file{idx}"),
+                path: PathBuf::from(format!("/file{idx}")),
+                kind: EntryKind::File,
+            }).collect(),
+            selected: 7,
+        };
+        let rows = listing.render_rows(4);
+        assert_eq!(rows.len(), 4);
+        assert!(rows[1].contains("file5"));
+        assert!(rows[3].contains("file7"));
+    }
+
+    #[test]
+    fn draw_clears_rows_below_listing() {
+        let mut screen = Screen::default();
+        screen.size.height = 8;
+        let listing = DirectoryListing {
+            directory: PathBuf::from("/"),
+            entries: vec![DirectoryEntry { name: "only".to_string(), path: PathBuf::from("/only"), kind: EntryKind::File }],
+            selected: 0,
+        };
+        let rows = listing.render_rows(8).len();
+        let mut viewer = OpenViewer { screen: &mut screen, listing };
+        let output = Terminal::capture(|| viewer.draw());
+        assert!(rows < 8);
+        assert_eq!(output.matches("\x1b[2K").count(), 8);
+    }
+}
@@ -1,3 +1,57 @@
-pub struct SearchViewer{
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind};
+use crate::terminal::controls::Terminal;
+use crate::terminal::screen_location::ScreenLocation;
+use crate::view::screen::{MessageKind, Mode, Screen, ScreenAction};
 
-}
\ No newline at end of file
+/// Reads a search query typed after `/`, like `foo`, `foo/e` or `foo/s+2`, and moves the
+/// cursor to its first match when Enter is pressed
+pub struct SearchViewer<'a> {
+    screen: &'a mut Screen,
+    query: String,
+}
+
+impl<'a> SearchViewer<'a> {
+    pub fn enter(screen: &'a mut Screen) -> ScreenAction {
+        let mut s = Self {
+            screen,
+            query: String::new(),
+        };
+        s.run()
+    }
+
+    pub fn run(&mut self) -> ScreenAction {
+        Terminal::set_cursor_style(self.screen.settings.cursor_style(&Mode::Search)).unwrap();
+        self.draw();
+        loop {
+            match Terminal::read_event().unwrap() {
+                Event::Key(KeyEvent { code, kind, .. }) => {
+                    if kind == KeyEventKind::Press {
+                        match code {
+                            KeyCode::Esc => { return ScreenAction::EnterMode(Mode::Normal) }
+                            KeyCode::Enter => {
+                                if let Err(error) = self.screen.start_search(&self.query) {
+                                    self.screen.set_message(&error, MessageKind::Error);
+                                }
+                                return ScreenAction::EnterMode(Mode::Normal);
+                            }
+                            KeyCode::Backspace => { self.query.pop(); }
+                            KeyCode::Char(c) => { self.query.push(c) }
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+            self.draw();
+        }
+    }
+
+    /// Draw the query being typed on the bottom row of the screen
+    pub fn draw(&mut self) {
+        let row = self.screen.size.height.saturating_sub(1);
+        let _ = Terminal::move_caret_to(ScreenLocation { row, col: 0 });
+        let _ = Terminal::clear_line();
+        let _ = Terminal::print(&format!("/{}", self.query));
+        let _ = Terminal::execute();
+    }
+}
@@ -1,3 +1,89 @@
-pub struct SelectViewer {
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crate::terminal::controls::Terminal;
+use crate::textbuffer::buffer::TextObject;
+use crate::view::screen::{Mode, Screen, ScreenAction};
 
-}
\ No newline at end of file
+/// Selects text from where select mode was entered to the cursor, for yanking or deleting,
+/// either as a run of text or as a block of columns
+pub struct SelectViewer<'a> {
+    screen: &'a mut Screen,
+    /// Whether `i` was typed, so the next key names the text object to select
+    pending_object: bool,
+}
+
+impl<'a> SelectViewer<'a> {
+    pub fn enter(screen: &'a mut Screen) -> ScreenAction {
+        if screen.selection_anchor.is_none() {
+            screen.selection_anchor = Some(screen.text_position.clone());
+        }
+        let mut s = Self { screen, pending_object: false };
+        s.run()
+    }
+
+    pub fn run(&mut self) -> ScreenAction {
+        Terminal::set_cursor_style(self.screen.settings.cursor_style(&Mode::Select)).unwrap();
+        self.draw();
+        loop {
+            if let Event::Key(KeyEvent { code, modifiers, kind: KeyEventKind::Press, .. }) = Terminal::read_event().unwrap() {
+                self.screen.key_pressed();
+                if self.pending_object {
+                    self.pending_object = false;
+                    if let Some(kind) = Self::text_object(code) {
+                        self.screen.select_text_object(kind).unwrap();
+                    }
+                    self.draw();
+                    continue;
+                }
+                match code {
+                    KeyCode::Esc => {
+                        self.screen.selection_anchor = None;
+                        return ScreenAction::EnterMode(Mode::Normal);
+                    }
+                    KeyCode::Char('g') if modifiers == KeyModifiers::CONTROL => { self.screen.show_selection_counts() }
+                    KeyCode::Up | KeyCode::Char('w') => { self.screen.move_up().unwrap() }
+                    KeyCode::Left | KeyCode::Char('a') => { self.screen.move_left().unwrap() }
+                    KeyCode::Down | KeyCode::Char('s') => { self.screen.move_down().unwrap() }
+                    KeyCode::Right | KeyCode::Char('d') => { self.screen.move_right().unwrap() }
+                    KeyCode::Char('y') => {
+                        self.screen.yank_selection().unwrap();
+                        return ScreenAction::EnterMode(Mode::Normal);
+                    }
+                    KeyCode::Char('>') | KeyCode::Char('<') => {
+                        self.screen.shift_selection(code == KeyCode::Char('<')).unwrap();
+                        return ScreenAction::EnterMode(Mode::Normal);
+                    }
+                    KeyCode::Char('i') if self.screen.selected_block().is_some() => {
+                        self.screen.insert_on_block().unwrap();
+                        return ScreenAction::EnterMode(Mode::Insert);
+                    }
+                    KeyCode::Char('i') => { self.pending_object = true }
+                    // `d` moves right, so the selection is deleted ("killed") with `k`
+                    KeyCode::Delete | KeyCode::Char('k') => {
+                        self.screen.delete_selection().unwrap();
+                        return ScreenAction::EnterMode(Mode::Normal);
+                    }
+                    _ => {}
+                }
+            }
+            self.draw();
+        }
+    }
+
+    /// The text object named by the key typed after `i`: `w` for a word, `l` a line, `p` a
+    /// paragraph, or either bracket of a pair for the inside of the brackets
+    fn text_object(code: KeyCode) -> Option<TextObject> {
+        match code {
+            KeyCode::Char('w') => Some(TextObject::Word),
+            KeyCode::Char('l') => Some(TextObject::Line),
+            KeyCode::Char('p') => Some(TextObject::Paragraph),
+            KeyCode::Char('(') | KeyCode::Char(')') => Some(TextObject::Bracket('(')),
+            KeyCode::Char('[') | KeyCode::Char(']') => Some(TextObject::Bracket('[')),
+            KeyCode::Char('{') | KeyCode::Char('}') => Some(TextObject::Bracket('{')),
+            _ => None,
+        }
+    }
+
+    pub fn draw(&mut self) {
+        self.screen.draw();
+    }
+}
@@ -0,0 +1,154 @@
+use crossterm::style::{Attribute, ContentStyle, StyledContent};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+use crate::settings::ListChars;
+
+/// A single screen cell, holding the grapheme displayed in it and its style
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cell {
+    pub content: String,
+    pub style: ContentStyle,
+}
+
+impl Cell {
+    /// Create an unstyled cell
+    pub fn plain(content: &str) -> Cell {
+        Self {
+            content: content.to_string(),
+            style: ContentStyle::new(),
+        }
+    }
+}
+
+/// A row of cells which will be printed to the screen
+#[derive(Clone, Debug)]
+pub struct RenderedRow {
+    pub cells: Vec<Cell>,
+}
+
+impl RenderedRow {
+    /// Create a row from plain text, with one cell per grapheme
+    pub fn from_text(text: &str) -> RenderedRow {
+        Self {
+            cells: text.graphemes(true).map(Cell::plain).collect(),
+        }
+    }
+
+    /// Replace space cells with the visible glyphs of list mode, drawn dimmed. Spaces from
+    /// the cell `trailing_from` onwards are trailing ones. Tabs are drawn by `expand_tabs`.
+    pub fn show_whitespace(&mut self, chars: &ListChars, trailing_from: usize) {
+        for (idx, cell) in self.cells.iter_mut().enumerate() {
+            let glyph = match cell.content.as_str() {
+                " " if idx >= trailing_from => chars.trail.or(chars.space),
+                " " => chars.space,
+                _ => None,
+            };
+            if let Some(glyph) = glyph {
+                cell.content = glyph.to_string();
+                cell.style.attributes.set(Attribute::Dim);
+            }
+        }
+    }
+
+    /// Expand each tab cell to reach the next multiple of `tab_width` columns, counting from
+    /// `start_column` for the first cell. A tab starts with the marker (drawn dimmed) when
+    /// list mode gives one, and is padded out with spaces.
+    pub fn expand_tabs(&mut self, tab_width: usize, marker: Option<char>, start_column: usize) {
+        let tab_width = tab_width.max(1);
+        let mut column = start_column;
+        for cell in self.cells.iter_mut() {
+            if cell.content != "\t" {
+                column += cell.content.width();
+                continue;
+            }
+            let width = tab_width - column % tab_width;
+            let padding = " ".repeat(width - 1);
+            match marker {
+                Some(marker) => {
+                    cell.content = format!("{marker}{padding}");
+                    cell.style.attributes.set(Attribute::Dim);
+                }
+                None => cell.content = format!(" {padding}"),
+            }
+            column += width;
+        }
+    }
+
+    /// Pad the row with blank cells so that it is at least `width` cells long
+    pub fn pad_to(&mut self, width: usize) {
+        while self.cells.len() < width {
+            self.cells.push(Cell::plain(" "));
+        }
+    }
+
+    /// Set the style of the cell at `col`, if the row reaches that far
+    pub fn style_cell(&mut self, col: usize, style: ContentStyle) {
+        if let Some(cell) = self.cells.get_mut(col) {
+            cell.style = style;
+        }
+    }
+
+    /// Add an attribute (underline, bold, etc.) to the cell at `col`, keeping its existing style
+    pub fn add_attribute(&mut self, col: usize, attribute: Attribute) {
+        if let Some(cell) = self.cells.get_mut(col) {
+            cell.style.attributes.set(attribute);
+        }
+    }
+
+    /// Convert the row into a string (including escape sequences for styling) for printing.
+    /// Runs of cells sharing a style are printed together.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let mut run = String::new();
+        let mut run_style = ContentStyle::new();
+        for cell in &self.cells {
+            if cell.style != run_style {
+                out.push_str(&StyledContent::new(run_style, &run).to_string());
+                run.clear();
+                run_style = cell.style;
+            }
+            run.push_str(&cell.content);
+        }
+        out.push_str(&StyledContent::new(run_style, &run).to_string());
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::style::{Color, Stylize};
+
+    #[test]
+    fn render_plain_row() {
+        let row = RenderedRow::from_text("a€c");
+        assert_eq!(row.cells.len(), 3);
+        assert_eq!(row.render(), "a€c");
+    }
+
+    #[test]
+    fn expand_tab_cells() {
+        let mut row = RenderedRow::from_text("a\tb");
+        row.expand_tabs(4, None, 0);
+        assert_eq!(row.render(), "a   b");
+        // Each tab reaches the next tab stop
+        let mut row = RenderedRow::from_text("\tab\tc");
+        row.expand_tabs(3, None, 0);
+        assert_eq!(row.render(), "   ab c");
+        let mut row = RenderedRow::from_text("a\tb");
+        row.expand_tabs(4, None, 5);
+        assert_eq!(row.render(), "a  b");
+        let mut row = RenderedRow::from_text("\t");
+        row.expand_tabs(2, Some('→'), 0);
+        assert_eq!(row.render(), format!("{}", "→ ".attribute(Attribute::Dim)));
+    }
+
+    #[test]
+    fn render_styled_cell() {
+        let mut row = RenderedRow::from_text("ab");
+        row.pad_to(4);
+        let style = ContentStyle::new().on(Color::DarkGrey);
+        row.style_cell(3, style);
+        assert_eq!(row.render(), format!("ab {}", StyledContent::new(style, " ")));
+    }
+}
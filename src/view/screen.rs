@@ -1,13 +1,48 @@
-use std::io::Error;
+use std::cell::RefCell;
+use std::cmp::min;
+use std::collections::HashMap;
+use std::fs::metadata;
+use std::io::{Error, ErrorKind};
+use std::mem::take;
+use std::ops::{Range, RangeInclusive};
 use std::path::PathBuf;
+use std::rc::Rc;
 
+use crossterm::event::KeyEvent;
+use crossterm::style::{Attribute, Color, ContentStyle, Stylize};
 use once_cell::sync::Lazy;
 use regex::Regex;
+use unicode_segmentation::UnicodeSegmentation;
+use crate::commands::command::Command;
 use crate::editor::EditorAction;
+use crate::settings::Settings;
 use crate::terminal::controls::{Size, Terminal};
 use crate::terminal::screen_location::ScreenLocation;
-use crate::textbuffer::buffer::Buffer;
+use crate::textbuffer::buffer::{Buffer, TextObject, count_words, swap_path};
+use crate::textbuffer::changes::ChangeKind;
+use crate::textbuffer::lines::Line;
+use crate::input::keymap::KeyMap;
+use crate::input::macros::Macros;
+use crate::textbuffer::registers::{Register, RegisterKind, Registers};
+use crate::textbuffer::search::{Search, SearchMatch};
+use crate::textbuffer::spell_check::SpellChecker;
 use crate::textbuffer::text_location::TextPosition;
+use crate::view::folds::FoldState;
+use crate::view::modes::command::CommandViewer;
+use crate::view::modes::insert::InsertViewer;
+use crate::view::modes::normal::NormalViewer;
+use crate::view::modes::find::FindViewer;
+use crate::view::modes::jump::JumpViewer;
+use crate::view::modes::open::OpenViewer;
+use crate::view::modes::search::SearchViewer;
+use crate::view::modes::select::SelectViewer;
+use crate::view::render::{Cell, RenderedRow};
+
+/// Most positions kept in the jump list, after which the oldest are forgotten
+const MAX_JUMPS: usize = 100;
+
+/// Regex for recognizing the characters words are made of
+static WORD_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"\w|[(){}\-+&=]").unwrap());
 
 /// Struct representing the currently viewed screen
 pub struct Screen {
@@ -17,6 +52,12 @@ pub struct Screen {
     pub screen_location: ScreenLocation,
     /// Location of the cursor within the text
     pub text_position: TextPosition,
+    /// Column the cursor returns to when moving vertically, which is kept when passing over
+    /// shorter lines
+    pub desired_grapheme: usize,
+    /// Whether vertical moves keep the cursor at the end of lines, after moving to the end of
+    /// one, while it stays there
+    pub stick_to_line_end: bool,
     /// Offset of current view from 0,0
     pub scroll_offset: ScreenLocation,
     /// Edges of the buffer area
@@ -29,6 +70,50 @@ pub struct Screen {
     pub welcome_screen: bool,
     /// Flag for whether the current screen should close
     pub quit_screen: bool,
+    /// Options controlling behavior and display
+    pub settings: Settings,
+    /// Position of the bracket matching the one under the cursor, if any
+    pub matching_bracket: Option<TextPosition>,
+    /// Whether the cursor is on a bracket which has no match
+    pub unmatched_bracket: bool,
+    /// Spell checker for prose buffers, when spell checking is enabled
+    pub spell_checker: Option<SpellChecker>,
+    /// Folded regions of the buffer
+    pub folds: FoldState,
+    /// Positions the cursor jumped away from, most recent last
+    pub jump_list: Vec<TextPosition>,
+    /// Positions marked with `m` and a letter, to jump back to with `` ` `` and the letter
+    pub marks: HashMap<char, TextPosition>,
+    /// The active search, if any
+    pub search: Option<Search>,
+    /// Directory listed by open mode, when the screen is a file browser
+    pub browse_directory: Option<PathBuf>,
+    /// Block insert in progress, repeating the text typed on its first row on the others
+    pub block_insert: Option<BlockInsert>,
+    /// Message shown on the command line until the next key press
+    pub message: Option<(String, MessageKind)>,
+    /// Where the selection started, while selecting. It runs from here to the cursor.
+    pub selection_anchor: Option<TextPosition>,
+    /// Registers text is yanked and deleted into, shared with the other screens
+    pub registers: Rc<RefCell<Registers>>,
+    /// Register chosen with a `"x` prefix for the next yank, delete or paste
+    pub selected_register: Option<char>,
+    /// Text highlighted for attention, like a match waiting for a substitution to be confirmed
+    pub highlight: Option<SearchMatch>,
+    /// How the find started by `f`, `t`, `F` or `T` moves, once find mode reads its character
+    pub find_motion: FindMotion,
+    /// The last find on a line, repeated by `;` and `,`
+    pub last_find: Option<CharFind>,
+    /// Whether select mode selects a run of text or a rectangle
+    pub selection_kind: SelectionKind,
+    /// Macros recorded and played with `Q` and `@`, shared with the other screens
+    pub macros: Rc<RefCell<Macros>>,
+    /// Keybindings from the user's keymap config, shared with the other screens
+    pub key_map: Rc<KeyMap>,
+    /// Keys which make the last change, replayed by `.` to repeat it
+    pub last_change: Vec<KeyEvent>,
+    /// Keys of a change which went on to insert mode, collected until insert mode is left
+    pub change_keys: Option<Vec<KeyEvent>>,
 }
 
 impl Screen {
@@ -39,12 +124,36 @@ impl Screen {
             buffer:Buffer::empty(),
             screen_location:ScreenLocation::default(),
             text_position: TextPosition::default(),
+            desired_grapheme: 0,
+            stick_to_line_end: false,
             scroll_offset: ScreenLocation::default(),
             inner_boundary: Boundary::default(),
             mode: Mode::Normal,
             size,
             welcome_screen: false,
             quit_screen: false,
+            settings: Settings::default(),
+            matching_bracket: None,
+            unmatched_bracket: false,
+            spell_checker: None,
+            folds: FoldState::new(),
+            jump_list: Vec::new(),
+            marks: HashMap::new(),
+            search: None,
+            browse_directory: None,
+            block_insert: None,
+            message: None,
+            selection_anchor: None,
+            registers: Rc::new(RefCell::new(Registers::new())),
+            selected_register: None,
+            highlight: None,
+            find_motion: FindMotion { forward: true, kind: FindKind::To },
+            last_find: None,
+            selection_kind: SelectionKind::Charwise,
+            macros: Rc::new(RefCell::new(Macros::new())),
+            key_map: Rc::new(KeyMap::default()),
+            last_change: Vec::new(),
+            change_keys: None,
         }
     }
 
@@ -55,27 +164,249 @@ impl Screen {
         welcome_screen
     }
 
-    /// Reads a file
+    /// Create a instance of Screen browsing the files in a directory in open mode
+    pub fn browser(directory: PathBuf)->Screen{
+        let mut browser = Self::default();
+        browser.mode = Mode::Open;
+        browser.browse_directory = Some(directory);
+        browser
+    }
+
+    /// Reads a file, starting in the mode configured for its type
     pub fn load_file(&mut self, file_path:PathBuf){
-        self.buffer = Buffer::from_file(file_path);
+        let new_file = !file_path.exists();
+        let size = metadata(&file_path).map(|metadata| metadata.len()).unwrap_or(0);
+        self.buffer = if size >= self.settings.large_file_size as u64 {
+            Buffer::from_file_paged(file_path)
+        } else {
+            // Load enough to fill the screen now, with the rest arriving in the background
+            Buffer::from_file_streaming(file_path, self.size.height.max(1))
+        };
+        // Checking saved history against the file would read all of a paged one
+        if self.settings.undo_file && !self.buffer.text.is_paged() {
+            self.buffer.undo_directory = Some(self.settings.undo_directory.clone());
+            // Without readable history, undo just starts afresh
+            let _ = self.buffer.read_undo_file();
+        }
+        let file_type = self.settings.file_type(self.buffer.extension.as_deref()).cloned();
+        let mode = if new_file {
+            Some(self.settings.new_file_mode.clone())
+        } else {
+            file_type.as_ref().and_then(|file_type| file_type.initial_mode.clone())
+        };
+        if let Some(mode) = mode {
+            self.set_mode(mode);
+        }
+        // A file which couldn't be read properly is already read-only
+        self.buffer.readonly |= file_type.is_some_and(|file_type| file_type.readonly);
+        if self.settings.swap_file {
+            let swap = swap_path(&self.buffer.path, self.settings.swap_directory.as_deref());
+            self.buffer.swap_path = Some(swap.clone());
+            if self.buffer.recovery_available() {
+                self.set_message(&format!("Found swap file {}, use :recover to restore it", swap.display()),
+                                 MessageKind::Warning);
+            }
+        }
+        if self.settings.spell && self.is_prose() {
+            // Without a readable dictionary, the file just isn't spell checked
+            let _ = self.enable_spell_check();
+        }
+    }
+
+    /// Whether the buffer holds prose (markdown or plain text) rather than code
+    pub fn is_prose(&self)->bool{
+        matches!(self.buffer.extension.as_deref(), Some("md") | Some("txt"))
+    }
+
+    /// Load the spell checker's dictionaries, so misspelled words are underlined
+    pub fn enable_spell_check(&mut self)->Result<(), Error>{
+        let mut checker = SpellChecker::new(&self.settings.spell_dictionary)?;
+        if let Some(personal) = &self.settings.spell_personal {
+            checker.load_personal(personal)?;
+        }
+        self.spell_checker = Some(checker);
+        Ok(())
+    }
+
+    /// Write the buffer when the terminal loses focus, if autosaving is enabled and the
+    /// buffer is a modified file. Returns whether the buffer was written.
+    pub fn focus_lost(&mut self)->Result<bool, Error>{
+        if !self.settings.autosave_on_focus_lost
+            || !self.buffer.modified
+            || self.buffer.readonly
+            || self.buffer.path.as_os_str().is_empty() {
+            return Ok(false);
+        }
+        self.write_file(false)?;
+        Ok(true)
+    }
+
+    /// Write the buffer to the file at the path (`:w path`). An unnamed buffer takes the path
+    /// as its file, while any other buffer is copied there, keeping its own file.
+    pub fn write_file_as(&mut self, path: PathBuf, force: bool)->Result<(), Error>{
+        if self.buffer.path.as_os_str().is_empty() {
+            self.buffer.set_path(path);
+            return self.write_file(force);
+        }
+        if self.buffer.readonly && !force {
+            return Err(Error::new(ErrorKind::PermissionDenied, "Buffer is read-only (add ! to override)"));
+        }
+        self.buffer.write_copy(&path)
+    }
+
+    /// Change a setting given to `:set`. Whether the buffer is read-only belongs to the buffer
+    /// rather than the settings, so it is handled here.
+    pub fn set_option(&mut self, assignment: &str)->Result<(), String>{
+        match assignment {
+            "readonly" | "ro" => self.buffer.readonly = true,
+            "noreadonly" | "noro" => self.buffer.readonly = false,
+            _ => return self.settings.set(assignment),
+        }
+        Ok(())
+    }
+
+    /// Write the buffer to its file, first removing trailing whitespace if that is enabled.
+    /// A read-only buffer is only written when forced.
+    pub fn write_file(&mut self, force: bool)->Result<(), Error>{
+        if self.buffer.readonly && !force {
+            return Err(Error::new(ErrorKind::PermissionDenied, "Buffer is read-only (add ! to override)"));
+        }
+        if self.settings.trim_trailing_whitespace && self.buffer.trim_trailing_whitespace() {
+            self.buffer.commit_undo();
+            // The cursor may have been in the whitespace which was removed
+            self.sync_text_position_byte_to_grapheme();
+        }
+        self.buffer.write_file()
+    }
+
+    /// Check whether the file changed on disk while the terminal was unfocused. An unmodified
+    /// buffer is reloaded, while a modified one is left alone so no edits are lost.
+    /// Returns whether the file had changed.
+    pub fn focus_gained(&mut self)->Result<bool, Error>{
+        if !self.settings.check_disk_on_focus || !self.buffer.changed_on_disk() {
+            return Ok(false);
+        }
+        if !self.buffer.modified {
+            self.buffer.reload();
+            self.clamp_cursor_to_buffer()?;
+        }
+        Ok(true)
+    }
+
+    /// Replace the buffer with its file as it is on disk, throwing away any unsaved changes
+    pub fn reload_file(&mut self)->Result<(), Error>{
+        if self.buffer.path.as_os_str().is_empty() {
+            return Err(Error::new(ErrorKind::NotFound, "No file name"));
+        }
+        self.buffer.reload();
+        self.folds.clear();
+        self.clamp_cursor_to_buffer()
+    }
+
+    /// Open a different file in this screen, in place of the current buffer. Marks and jumps
+    /// point into the old file, so they are dropped with it.
+    pub fn edit_file(&mut self, path: PathBuf)->Result<(), Error>{
+        self.buffer.remove_swap();
+        self.set_mode(Mode::Normal);
+        self.load_file(path);
+        self.folds.clear();
+        self.marks.clear();
+        self.jump_list.clear();
+        self.selection_anchor = None;
+        self.text_position = TextPosition::default();
+        self.desired_grapheme = 0;
+        self.stick_to_line_end = false;
+        self.scroll_offset = ScreenLocation::default();
+        self.scroll_into_view()
+    }
+
+    /// Pull the cursor back into the buffer after the text has been replaced, as the file may
+    /// have shrunk out from under it
+    fn clamp_cursor_to_buffer(&mut self)->Result<(), Error>{
+        let last_row = self.buffer.num_lines.saturating_sub(1);
+        if self.text_position.row > last_row {
+            self.text_position = TextPosition::default();
+            self.text_position.row = last_row;
+        }
+        if let Some(line) = self.buffer.text.get(self.text_position.row) {
+            self.text_position.grapheme = min(self.text_position.grapheme, line.grapheme_count.saturating_sub(1));
+            self.text_position.byte = line.grapheme_start(self.text_position.grapheme);
+        }
+        self.scroll_into_view()
     }
 
-    /// Runs the current screen
+    /// Runs the current screen, handing input to the viewer for its mode until something
+    /// needs the editor to act
     pub fn run(&mut self)->EditorAction{
         loop {
             if self.quit_screen{
                 return EditorAction::QuitScreen;
             }
+            self.draw();
+            let action = match self.mode {
+                Mode::Normal => NormalViewer::enter(self),
+                Mode::Insert => InsertViewer::enter(self),
+                Mode::Command => CommandViewer::enter(self),
+                Mode::Open => OpenViewer::enter(self),
+                Mode::Jump => JumpViewer::enter(self),
+                Mode::Find => FindViewer::enter(self),
+                Mode::Search => SearchViewer::enter(self),
+                Mode::Select => SelectViewer::enter(self),
+            };
+            match action {
+                ScreenAction::EnterMode(mode) => self.set_mode(mode),
+                ScreenAction::OpenScreen(path) => return EditorAction::NewScreen(path),
+                ScreenAction::QuitScreen => {
+                    let action = self.confirm_quit(|screen| {
+                        screen.draw();
+                        Terminal::read_answer()
+                    });
+                    self.quit_screen = matches!(action, ScreenAction::QuitScreen);
+                }
+                ScreenAction::QuitEditor => {
+                    // Back in normal mode if the editor doesn't quit after all
+                    self.set_mode(Mode::Normal);
+                    return EditorAction::QuitEditor;
+                }
+                ScreenAction::ChangeScreen(index) => {
+                    self.set_mode(Mode::Normal);
+                    return EditorAction::ChangeScreen(index);
+                }
+                ScreenAction::NextScreen => {
+                    self.set_mode(Mode::Normal);
+                    return EditorAction::NextScreen;
+                }
+                ScreenAction::PreviousScreen => {
+                    self.set_mode(Mode::Normal);
+                    return EditorAction::PreviousScreen;
+                }
+                ScreenAction::NewWelcomeScreen => {
+                    self.set_mode(Mode::Normal);
+                    return EditorAction::NewWelcomeScreen;
+                }
+                ScreenAction::ExecuteCommand(command) => {
+                    self.set_mode(Mode::Normal);
+                    return EditorAction::ExecuteCommand(command);
+                }
+            }
         }
     }
 
     /// Move the caret cursor one line up
     pub fn move_up(&mut self)-> Result<(), Error>{
+        if self.settings.wrap {
+            return self.visual_up();
+        }
         // Move the text position up a line, unless already at 0
-        if self.text_position.row > 0{
-            self.text_position.row -= 1;
+        let desired = self.vertical_target();
+        let at_end = self.sticking_to_line_end();
+        self.stick_to_line_end = at_end;
+        if let Some(prev) = self.prev_visible_row(self.text_position.row) {
+            self.text_position.row = prev;
         }
+        self.text_position.grapheme = if at_end { self.max_cursor_grapheme() } else { desired };
         self.sync_text_position_byte_to_grapheme();
+        self.desired_grapheme = if at_end { self.text_position.grapheme } else { desired };
         // Move the cursor location onto screen
         self.scroll_into_view()?;
         // Move the caret to the correct position
@@ -85,11 +416,19 @@ impl Screen {
 
     /// Move the caret and cursor down one line
     pub fn move_down(&mut self)->Result<(), Error>{
+        if self.settings.wrap {
+            return self.visual_down();
+        }
         // Move the text position down a line, if there are more lines in the buffer
-        if self.text_position.row < self.buffer.num_lines.saturating_sub(1){
-            self.text_position.row +=1;
+        let desired = self.vertical_target();
+        let at_end = self.sticking_to_line_end();
+        self.stick_to_line_end = at_end;
+        if let Some(next) = self.next_visible_row(self.text_position.row) {
+            self.text_position.row = next;
         }
+        self.text_position.grapheme = if at_end { self.max_cursor_grapheme() } else { desired };
         self.sync_text_position_byte_to_grapheme();
+        self.desired_grapheme = if at_end { self.text_position.grapheme } else { desired };
         // Move the cursor location onto screen
         self.scroll_into_view()?;
         // Move the caret to the correct position
@@ -97,13 +436,246 @@ impl Screen {
         Ok(())
     }
 
+    /// Whether a vertical move should go to the end of the next line, as the cursor was moved
+    /// to the end of its line and hasn't left it
+    fn sticking_to_line_end(&self)->bool{
+        self.stick_to_line_end && self.text_position.grapheme == self.max_cursor_grapheme()
+    }
+
+    /// The column a vertical move should aim for. This is the remembered column, unless the
+    /// cursor has since been put somewhere else by something other than a motion.
+    fn vertical_target(&self)->usize{
+        if min(self.desired_grapheme, self.max_cursor_grapheme()) == self.text_position.grapheme {
+            self.desired_grapheme
+        } else {
+            self.text_position.grapheme
+        }
+    }
+
+    /// Move the caret and cursor down one visual row, which may be within the same line
+    /// when the line is wrapped
+    pub fn visual_down(&mut self)->Result<(), Error>{
+        let rows = self.visual_rows(self.text_position.row);
+        let (visual_row, col) = self.visual_position(&rows, self.text_position.grapheme);
+        if visual_row + 1 < rows.len() {
+            let next = &rows[visual_row + 1];
+            self.text_position.grapheme = min(next.start + col, next.end.saturating_sub(1));
+        } else if let Some(next) = self.next_visible_row(self.text_position.row) {
+            self.text_position.row = next;
+            let first = &self.visual_rows(self.text_position.row)[0];
+            self.text_position.grapheme = min(col, first.end.saturating_sub(1));
+        }
+        self.sync_text_position_byte_to_grapheme();
+        self.scroll_into_view()?;
+        Terminal::move_caret_to(self.screen_location.clone())?;
+        Ok(())
+    }
+
+    /// Move the caret and cursor up one visual row, which may be within the same line
+    /// when the line is wrapped
+    pub fn visual_up(&mut self)->Result<(), Error>{
+        let rows = self.visual_rows(self.text_position.row);
+        let (visual_row, col) = self.visual_position(&rows, self.text_position.grapheme);
+        if visual_row > 0 {
+            let prev = &rows[visual_row - 1];
+            self.text_position.grapheme = min(prev.start + col, prev.end.saturating_sub(1));
+        } else if let Some(prev) = self.prev_visible_row(self.text_position.row) {
+            self.text_position.row = prev;
+            let rows = self.visual_rows(self.text_position.row);
+            let last = &rows[rows.len() - 1];
+            self.text_position.grapheme = min(last.start + col, last.end.saturating_sub(1));
+        }
+        self.sync_text_position_byte_to_grapheme();
+        self.scroll_into_view()?;
+        Terminal::move_caret_to(self.screen_location.clone())?;
+        Ok(())
+    }
+
+    /// The next row below `row` which isn't hidden in a fold, if there is one
+    fn next_visible_row(&self, row: usize)->Option<usize>{
+        let next = match self.folds.fold_containing(row) {
+            Some(fold) => fold.end,
+            None => row + 1,
+        };
+        if next < self.buffer.num_lines { Some(next) } else { None }
+    }
+
+    /// The previous row above `row` which isn't hidden in a fold, if there is one
+    fn prev_visible_row(&self, row: usize)->Option<usize>{
+        if row == 0 {
+            return None;
+        }
+        match self.folds.fold_containing(row - 1) {
+            Some(fold) => Some(fold.start),
+            None => Some(row - 1),
+        }
+    }
+
+    /// Fold the lines indented deeper than `row` which follow it, returning whether
+    /// there was anything to fold
+    pub fn fold_at(&mut self, row: usize)->Result<bool, Error>{
+        let range = match self.buffer.indent_fold_range(row) {
+            Some(range) => range,
+            None => return Ok(false),
+        };
+        // Keep the cursor out of the hidden lines
+        if range.contains(&self.text_position.row) {
+            self.text_position.row = range.start;
+            self.sync_text_position_byte_to_grapheme();
+        }
+        self.folds.fold(range);
+        self.scroll_into_view()?;
+        Ok(true)
+    }
+
+    /// Expand the fold containing `row`, returning whether there was one
+    pub fn unfold_at(&mut self, row: usize)->Result<bool, Error>{
+        let unfolded = self.folds.unfold_at(row);
+        self.scroll_into_view()?;
+        Ok(unfolded)
+    }
+
+    /// Move the caret/cursor to the first grapheme of the current visual row
+    pub fn move_start_visual_row(&mut self)->Result<(), Error>{
+        let rows = self.visual_rows(self.text_position.row);
+        let (visual_row, _) = self.visual_position(&rows, self.text_position.grapheme);
+        self.text_position.grapheme = rows[visual_row].start;
+        self.sync_text_position_byte_to_grapheme();
+        self.scroll_into_view()?;
+        Terminal::move_caret_to(self.screen_location.clone())?;
+        Ok(())
+    }
+
+    /// Move the caret/cursor to the last grapheme of the current visual row
+    pub fn move_end_visual_row(&mut self)->Result<(), Error>{
+        let rows = self.visual_rows(self.text_position.row);
+        let (visual_row, _) = self.visual_position(&rows, self.text_position.grapheme);
+        self.text_position.grapheme = rows[visual_row].end.saturating_sub(1);
+        self.sync_text_position_byte_to_grapheme();
+        self.scroll_into_view()?;
+        Terminal::move_caret_to(self.screen_location.clone())?;
+        Ok(())
+    }
+
+    /// Split a line into the ranges of graphemes displayed on each visual row. Without
+    /// wrapping, this is always a single range covering the whole line. Rows break after
+    /// whitespace where they can, so words aren't split unless one is wider than the view.
+    pub fn visual_rows(&self, row: usize)->Vec<Range<usize>>{
+        let Some(line) = self.buffer.text.get(row) else { return vec![0..0] };
+        let count = line.grapheme_count;
+        let width = self.view_width();
+        if !self.settings.wrap || width == 0 || self.is_overlong(row) || line.display_width(0..count, self.settings.tab_width) <= width {
+            return vec![0..count];
+        }
+        let is_space = |idx: usize| line.grapheme(idx).is_some_and(|g| g.chars().all(char::is_whitespace));
+        let mut rows = Vec::new();
+        let mut start = 0;
+        while start < count {
+            // Always take at least one grapheme, even one wider than the view
+            let mut end = line.graphemes_within_width(start, width, self.settings.tab_width).max(start + 1);
+            if end < count {
+                if let Some(after_space) = (start + 1..=end).rev().find(|idx| is_space(idx - 1)) {
+                    end = after_space;
+                }
+            }
+            rows.push(start..end);
+            start = end;
+        }
+        rows
+    }
+
+    /// Number of screen rows a line takes up: one for a fold, or each of its visual rows
+    fn display_rows(&self, row: usize)->usize{
+        if self.folds.fold_containing(row).is_some() { 1 } else { self.visual_rows(row).len() }
+    }
+
+    /// Whether a line is too long to be worth highlighting or wrapping
+    pub fn is_overlong(&self, row: usize)->bool{
+        self.buffer.text.get(row)
+            .is_some_and(|line| line.is_overlong(self.settings.overlong_threshold))
+    }
+
+    /// Find which of the visual rows a grapheme is on, and its column within that row
+    fn visual_position(&self, rows: &[Range<usize>], grapheme: usize)->(usize, usize){
+        for (idx, range) in rows.iter().enumerate() {
+            if range.contains(&grapheme) {
+                return (idx, grapheme - range.start);
+            }
+        }
+        let last = rows.len() - 1;
+        (last, grapheme.saturating_sub(rows[last].start))
+    }
+
     fn sync_text_position_byte_to_grapheme(&mut self){
-        // Make sure the cursor isn't past the last character
-        if self.text_position.grapheme >= self.buffer.text[self.text_position.row].grapheme_count{
-            self.text_position.grapheme = self.buffer.text[self.text_position.row].grapheme_count-1
+        // Make sure the cursor isn't past the last character, unless it can move into the
+        // virtual space past the end of lines
+        if !self.settings.virtual_edit {
+            self.text_position.grapheme = min(self.text_position.grapheme, self.max_cursor_grapheme());
+        }
+        self.text_position.byte = self.grapheme_to_byte(self.text_position.row,
+                                                        self.text_position.grapheme);
+    }
+
+    /// The furthest right the cursor can go on its line. In insert mode this is one past the
+    /// last grapheme, so text can be appended, otherwise it is the last grapheme.
+    pub fn max_cursor_grapheme(&self)->usize{
+        let count = self.buffer.text.get(self.text_position.row)
+            .map_or(0, |line| line.grapheme_count);
+        match self.mode {
+            Mode::Insert => count,
+            _ => count.saturating_sub(1),
+        }
+    }
+
+    /// Switch the screen's mode, pulling the cursor back onto the line if it was past the
+    /// end for appending. Leaving insert mode finishes any block insert.
+    pub fn set_mode(&mut self, mode: Mode){
+        if mode != Mode::Insert {
+            if let Some(block) = self.block_insert.take() {
+                self.finish_block_insert(block);
+            }
+        }
+        if mode != Mode::Insert {
+            if let Some(keys) = self.change_keys.take() {
+                self.last_change = keys;
+            }
+        }
+        self.mode = mode;
+        // Each visit to insert mode is undone as one step
+        self.buffer.commit_undo();
+        if self.buffer.num_lines > 0 {
+            self.sync_text_position_byte_to_grapheme();
+        }
+    }
+
+    /// Start inserting at the column on every row of the block (vim's block `I`). Text is
+    /// typed on the first row as normal, and copied to the other rows on leaving insert mode.
+    pub fn start_block_insert(&mut self, rows: Range<usize>, column: usize)->Result<(), Error>{
+        self.text_position.row = rows.start;
+        self.text_position.grapheme = column;
+        self.block_insert = Some(BlockInsert { rows, column });
+        self.set_mode(Mode::Insert);
+        self.scroll_into_view()?;
+        Terminal::move_caret_to(self.screen_location.clone())?;
+        Ok(())
+    }
+
+    /// Copy the text typed on the first row of a block insert to the rest of its rows. Rows
+    /// too short to reach the column are skipped, as is everything if the cursor left the row.
+    fn finish_block_insert(&mut self, block: BlockInsert){
+        let first = block.rows.start;
+        if self.text_position.row != first || self.text_position.grapheme <= block.column {
+            return;
+        }
+        let line = &self.buffer.text[first];
+        let typed = line.text[line.grapheme_start(block.column)..self.grapheme_to_byte(first, self.text_position.grapheme)].to_string();
+        for row in (first + 1)..block.rows.end.min(self.buffer.num_lines) {
+            if self.buffer.text[row].grapheme_count < block.column {
+                continue;
+            }
+            let at = TextPosition { row, byte: 0, grapheme: block.column };
+            self.buffer.paste_block(at, &[typed.clone()]);
         }
-        self.text_position.byte = self.buffer.text[self.text_position.row]
-            .grapheme_start(self.text_position.grapheme);
     }
 
     /// Move the caret cursor one column left
@@ -115,6 +687,8 @@ impl Screen {
                 self.buffer.text[self.text_position.row]
                     .grapheme_start(self.text_position.grapheme);
         }
+        self.desired_grapheme = self.text_position.grapheme;
+        self.stick_to_line_end = false;
         // Move cursor location onto screen
         self.scroll_into_view()?;
         // Move the caret to the correct position
@@ -125,15 +699,13 @@ impl Screen {
     /// Move the caret cursor one column right
     pub fn move_right(&mut self)-> Result<(), Error>{
         // Move the text position right a column, unless at the end of a line
-        if self.text_position.grapheme < self.buffer
-            .text[self.text_position.row]
-            .grapheme_count
-            .saturating_sub(1){
+        if self.settings.virtual_edit || self.text_position.grapheme < self.max_cursor_grapheme(){
             self.text_position.grapheme = self.text_position.grapheme.saturating_add(1);
-            self.text_position.byte =
-                self.buffer.text[self.text_position.row]
-                    .grapheme_start(self.text_position.grapheme);
+            self.text_position.byte = self.grapheme_to_byte(self.text_position.row,
+                                                            self.text_position.grapheme);
         }
+        self.desired_grapheme = self.text_position.grapheme;
+        self.stick_to_line_end = false;
         // Move cursor location onto screen
         self.scroll_into_view()?;
         // Move the caret to the correct position
@@ -144,10 +716,12 @@ impl Screen {
     /// Move the caret/cursor to the last grapheme of a line
     pub fn move_end_line(&mut self)->Result<(), Error>{
         // Move the text position to the end of the current line
-        let line_length = self.buffer.text[self.text_position.row].grapheme_count;
-        if line_length > 0 {
-            self.text_position.grapheme = line_length-1;
-        }
+        self.text_position.grapheme = self.max_cursor_grapheme();
+        self.text_position.byte = self.grapheme_to_byte(self.text_position.row,
+                                                        self.text_position.grapheme);
+        // Stay at the end of lines when moving vertically
+        self.desired_grapheme = self.text_position.grapheme;
+        self.stick_to_line_end = true;
         self.scroll_into_view()?;
         Terminal::move_caret_to(self.screen_location.clone())?;
         Ok(())
@@ -156,13 +730,29 @@ impl Screen {
     /// Move the caret/cursor to the first grapheme of a line
     pub fn move_start_line(&mut self)->Result<(), Error>{
         self.text_position.grapheme=0;
+        self.text_position.byte=0;
+        self.desired_grapheme = 0;
+        self.stick_to_line_end = false;
         self.scroll_into_view()?;
         Terminal::move_caret_to(self.screen_location.clone())?;
         Ok(())
     }
 
+    /// Move the caret/cursor to the first grapheme of the line which isn't whitespace, or to
+    /// the start of the line when already there, so pressing it again toggles between them.
+    /// On a blank line this is its last grapheme.
+    pub fn move_first_non_blank(&mut self)->Result<(), Error>{
+        let row = self.text_position.row;
+        let indent = self.buffer.text.get(row).map_or(0, |line| line.leading_whitespace());
+        if self.text_position.grapheme == indent.min(self.max_cursor_grapheme()) {
+            return self.move_start_line();
+        }
+        self.move_to_first_non_blank(row)
+    }
+
     /// Move the cursor to the first line of a buffer
     pub fn move_first_line(&mut self)->Result<(), Error>{
+        self.push_jump();
         self.text_position.row=0;
         self.sync_text_position_byte_to_grapheme();
         self.scroll_into_view()?;
@@ -172,6 +762,7 @@ impl Screen {
 
     /// Move the caret/cursor to the last line of a buffer
     pub fn move_last_line(&mut self)->Result<(), Error>{
+        self.push_jump();
         self.text_position.row = self.buffer.num_lines.saturating_sub(1);
         self.sync_text_position_byte_to_grapheme();
         self.scroll_into_view()?;
@@ -179,10 +770,168 @@ impl Screen {
         Ok(())
     }
 
+    /// Scroll down half a screen, moving the cursor with the text
+    pub fn scroll_half_page_down(&mut self)->Result<(), Error>{
+        self.scroll_down_by((self.view_height() / 2).max(1))
+    }
+
+    /// Scroll up half a screen, moving the cursor with the text
+    pub fn scroll_half_page_up(&mut self)->Result<(), Error>{
+        self.scroll_up_by((self.view_height() / 2).max(1))
+    }
+
+    /// Scroll down a full screen, moving the cursor with the text
+    pub fn scroll_page_down(&mut self)->Result<(), Error>{
+        self.scroll_down_by(self.view_height().max(1))
+    }
+
+    /// Scroll up a full screen, moving the cursor with the text
+    pub fn scroll_page_up(&mut self)->Result<(), Error>{
+        self.scroll_up_by(self.view_height().max(1))
+    }
+
+    /// Scroll so the cursor's line is in the middle of the screen
+    pub fn center_cursor(&mut self)->Result<(), Error>{
+        self.scroll_cursor_to(self.view_height() / 2)
+    }
+
+    /// Scroll so the cursor's line is at the top of the screen
+    pub fn cursor_to_top(&mut self)->Result<(), Error>{
+        self.scroll_cursor_to(0)
+    }
+
+    /// Scroll so the cursor's line is at the bottom of the screen
+    pub fn cursor_to_bottom(&mut self)->Result<(), Error>{
+        self.scroll_cursor_to(self.view_height().saturating_sub(1))
+    }
+
+    /// Scroll so the cursor's line is the given number of rows from the top of the screen, or
+    /// as near as it can be without scrolling before the start of the buffer
+    fn scroll_cursor_to(&mut self, screen_row: usize)->Result<(), Error>{
+        self.scroll_offset.row = self.text_position.row.saturating_sub(screen_row);
+        self.sync_screen_position();
+        Terminal::move_caret_to(self.screen_location.clone())?;
+        Ok(())
+    }
+
+    /// Move the scroll offset and cursor down together, so the cursor keeps its place on the
+    /// screen. The view stops once the last line is at the bottom, and the cursor at the last line.
+    fn scroll_down_by(&mut self, rows: usize)->Result<(), Error>{
+        let last_scroll = self.buffer.num_lines.saturating_sub(self.view_height());
+        self.scroll_offset.row = min(self.scroll_offset.row + rows, last_scroll.max(self.scroll_offset.row));
+        self.text_position.row = min(self.text_position.row + rows, self.buffer.num_lines.saturating_sub(1));
+        self.finish_scroll()
+    }
+
+    /// Move the scroll offset and cursor up together, stopping at the start of the buffer
+    fn scroll_up_by(&mut self, rows: usize)->Result<(), Error>{
+        self.scroll_offset.row = self.scroll_offset.row.saturating_sub(rows);
+        self.text_position.row = self.text_position.row.saturating_sub(rows);
+        self.finish_scroll()
+    }
+
+    /// Put the cursor back in its remembered column after a scroll moved it to another line
+    fn finish_scroll(&mut self)->Result<(), Error>{
+        self.text_position.grapheme = self.vertical_target();
+        self.sync_text_position_byte_to_grapheme();
+        self.scroll_into_view()?;
+        Terminal::move_caret_to(self.screen_location.clone())?;
+        Ok(())
+    }
+
+    /// Move the cursor to (or next to) a grapheme on its line, remembering the find so it can
+    /// be repeated. The cursor stays put if the grapheme isn't found.
+    pub fn find_char(&mut self, find: CharFind)->Result<(), Error>{
+        self.last_find = Some(find.clone());
+        self.run_find(&find, false)
+    }
+
+    /// Repeat the last find on a line, the opposite way if `reverse`
+    pub fn repeat_find(&mut self, reverse: bool)->Result<(), Error>{
+        let Some(mut find) = self.last_find.clone() else { return Ok(()) };
+        find.motion.forward ^= reverse;
+        self.run_find(&find, true)
+    }
+
+    fn run_find(&mut self, find: &CharFind, repeating: bool)->Result<(), Error>{
+        let Some(line) = self.buffer.text.get(self.text_position.row) else { return Ok(()) };
+        let cursor = self.text_position.grapheme;
+        // Repeating a till find starts past the character it stopped next to, so it moves on
+        let skip = if repeating && find.motion.kind == FindKind::Till { 2 } else { 1 };
+        let is_target = |idx: &usize| line.grapheme(*idx) == Some(find.target.as_str());
+        let found = if find.motion.forward {
+            (cursor + skip..line.grapheme_count).find(is_target)
+                .map(|idx| if find.motion.kind == FindKind::Till { idx - 1 } else { idx })
+        } else {
+            (0..cursor.saturating_sub(skip - 1)).rev().find(is_target)
+                .map(|idx| if find.motion.kind == FindKind::Till { idx + 1 } else { idx })
+        };
+        let Some(grapheme) = found else { return Ok(()) };
+        self.text_position.grapheme = grapheme;
+        self.desired_grapheme = grapheme;
+        self.stick_to_line_end = false;
+        self.sync_text_position_byte_to_grapheme();
+        self.scroll_into_view()?;
+        Terminal::move_caret_to(self.screen_location.clone())?;
+        Ok(())
+    }
+
+    /// Move the caret/cursor forward to the next blank line after the current paragraph,
+    /// or the last line if there isn't one
+    pub fn move_next_paragraph(&mut self)->Result<(), Error>{
+        if self.buffer.num_lines == 0 {
+            return Ok(());
+        }
+        let last = self.buffer.num_lines - 1;
+        let mut row = self.text_position.row;
+        // Skip any blank lines at the cursor, then the paragraph itself
+        while row < last && self.is_paragraph_break(row) {
+            row += 1;
+        }
+        while row < last && !self.is_paragraph_break(row) {
+            row += 1;
+        }
+        self.move_to_row_start(row)
+    }
+
+    /// Move the caret/cursor back to the previous blank line before the current paragraph,
+    /// or the first line if there isn't one
+    pub fn move_prev_paragraph(&mut self)->Result<(), Error>{
+        if self.buffer.num_lines == 0 {
+            return Ok(());
+        }
+        let mut row = self.text_position.row;
+        while row > 0 && self.is_paragraph_break(row) {
+            row -= 1;
+        }
+        while row > 0 && !self.is_paragraph_break(row) {
+            row -= 1;
+        }
+        self.move_to_row_start(row)
+    }
+
+    /// Whether a row separates paragraphs: an empty line, or one of only whitespace when
+    /// `whitespace_is_blank` is set
+    fn is_paragraph_break(&self, row: usize)->bool{
+        let line = &self.buffer.text[row];
+        if self.settings.whitespace_is_blank { line.is_blank() } else { line.text.is_empty() }
+    }
+
+    /// Move the caret/cursor to the first grapheme of a row
+    fn move_to_row_start(&mut self, row: usize)->Result<(), Error>{
+        self.text_position.row = row;
+        self.text_position.grapheme = 0;
+        self.text_position.byte = 0;
+        self.scroll_into_view()?;
+        Terminal::move_caret_to(self.screen_location.clone())?;
+        Ok(())
+    }
+
     /// Move the caret/cursor to the next word of a buffer
     pub fn move_next_word(&mut self)->Result<(), Error>{
-        // Regex for recognizing a word
-        static WORD_REGEX:Lazy<Regex> = Lazy::new(|| Regex::new(r"\w|[(){}\-+&=]").unwrap());
+        if self.buffer.num_lines == 0 {
+            return Ok(());
+        }
         match WORD_REGEX.find(&self.buffer
             .text[self.text_position.row]
             .text[self.text_position.byte..]){
@@ -214,7 +963,9 @@ impl Screen {
 
     /// Move the caret/cursor to the previous word of a buffer
     pub fn move_prev_word(&mut self)->Result<(), Error>{
-        static WORD_REGEX:Lazy<Regex> = Lazy::new(|| Regex::new(r"\w|[(){}\-+&=]").unwrap());
+        if self.buffer.num_lines == 0 {
+            return Ok(());
+        }
         match WORD_REGEX.find_iter(&self.buffer
             .text[self.text_position.row]
             .text[..self.text_position.byte]).last(){
@@ -245,78 +996,1529 @@ impl Screen {
         Ok(())
     }
 
-
-    /// Updates the scroll offset and caret_position so the text_position is on screen
-    pub fn scroll_into_view(&mut self)->Result<(), Error>{
-        self.scroll_vertical();
-        self.scroll_horizontal();
-        self.sync_screen_position();
-        Ok(())
-    }
-
-    /// Delete the grapheme at the text position
-    pub fn delete_grapheme(&mut self, location: TextPosition){
-        self.buffer.delete_char(location.row, location.grapheme)
-    }
-
-    fn scroll_horizontal(&mut self){
-        // If the text position is too far right, move the scroll offset right
-        if self.text_position.grapheme.saturating_sub(self.scroll_offset.col) > self.view_width(){
-            self.scroll_offset.col = self.text_position.grapheme-self.scroll_offset.col - self.view_width();
-        } else if self.text_position.grapheme < self.scroll_offset.col{
-            // The cursor is too far left, move the scroll offset to the left
-            self.scroll_offset.col = self.text_position.grapheme;
+    /// Find where the run of graphemes like the one at the grapheme (word characters,
+    /// whitespace, or other punctuation) ends on its line, as the index just past it
+    fn word_run_end(&self, row: usize, grapheme: usize)->usize{
+        let line = &self.buffer.text[row];
+        let class = match line.grapheme(grapheme) {
+            Some(text) => WordClass::of(text),
+            None => return grapheme,
+        };
+        let mut end = grapheme;
+        while line.grapheme(end).is_some_and(|text| WordClass::of(text) == class) {
+            end += 1;
         }
+        end
     }
 
-    fn scroll_vertical(&mut self){
-        // if the text position is too far down, move the scroll offset up
-        if self.text_position.row.saturating_sub(self.scroll_offset.row) > self.view_height(){
-            self.scroll_offset.row = self.text_position.row-self.scroll_offset.row - self.view_height();
-        } else if self.text_position.row < self.scroll_offset.row {
-            self.scroll_offset.row = self.text_position.row;
+    /// Jump to the definition of the identifier under the cursor, found by searching the
+    /// buffer for a definition pattern for the file's language. The current position is
+    /// pushed onto the jump list. Returns whether a definition was found.
+    pub fn goto_definition(&mut self)->Result<bool, Error>{
+        let (word, _) = match self.buffer.word_at(self.text_position.row, self.text_position.grapheme) {
+            Some(found) => found,
+            None => return Ok(false),
+        };
+        let keywords = match self.buffer.extension.as_deref() {
+            Some("rs") => "fn|struct|enum|trait|type|const|static|mod|macro_rules!",
+            Some("py") => "def|class",
+            Some("js") | Some("ts") => "function|class|const|let|var",
+            Some("c") | Some("h") | Some("cpp") | Some("hpp") => "struct|class|enum|#define",
+            _ => "fn|def|function|class|struct|enum|trait|type",
+        };
+        let definition_regex = match Regex::new(&format!(r"(?:^|\W)(?:{keywords})\s+({word})\b")) {
+            Ok(regex) => regex,
+            Err(_) => return Ok(false),
+        };
+        for row in 0..self.buffer.num_lines {
+            let start = match definition_regex.captures(&self.buffer.text[row].text) {
+                Some(captures) => captures.get(1).unwrap().start(),
+                None => continue,
+            };
+            self.push_jump();
+            self.text_position.row = row;
+            self.text_position.byte = start;
+            self.text_position.grapheme = self.buffer.text[row].text_index_to_grapheme(start);
+            self.scroll_into_view()?;
+            Terminal::move_caret_to(self.screen_location.clone())?;
+            return Ok(true);
         }
+        Ok(false)
     }
 
-    /// Syncs the positions of the caret and the cursor
-    fn sync_screen_position(&mut self) {
-        self.screen_location.col = self.text_position.grapheme - self.scroll_offset.col + self.inner_boundary.left;
-        self.screen_location.row = self.text_position.row - self.scroll_offset.row + self.inner_boundary.top;
+    /// Start searching for the query's pattern, moving the cursor to the first match after
+    /// it, then by the query's offset
+    pub fn start_search(&mut self, query: &str)->Result<(), String>{
+        let search = Search::parse(query, self.settings.search_options())?;
+        self.search = Some(search);
+        self.search_next().map_err(|e| e.to_string())
     }
 
-    pub fn view_width(&self)->usize{
-        self.size.width - self.inner_boundary.left -self.inner_boundary.right
+    /// Move the cursor to the next match of the active search
+    pub fn search_next(&mut self)->Result<(), Error>{
+        let found = match self.search.as_mut() {
+            Some(search) => search.next_match(&self.buffer, &self.text_position),
+            None => return Ok(()),
+        };
+        self.move_to_match(found)
     }
 
-    pub fn view_height(&self)->usize{
-        self.size.height - self.inner_boundary.top - self.inner_boundary.bottom
+    /// Move the cursor to the previous match of the active search
+    pub fn search_prev(&mut self)->Result<(), Error>{
+        let found = match self.search.as_mut() {
+            Some(search) => search.prev_match(&self.buffer, &self.text_position),
+            None => return Ok(()),
+        };
+        self.move_to_match(found)
     }
 
-}
+    /// Describe the cursor's place among the active search's matches, like `match 2 of 5`
+    pub fn search_indicator(&mut self)->Option<String>{
+        let search = self.search.as_mut()?;
+        Some(search.indicator(&self.buffer, &self.text_position))
+    }
 
+    fn move_to_match(&mut self, found: Option<SearchMatch>)->Result<(), Error>{
+        let position = match (found, self.search.as_ref()) {
+            (Some(found), Some(search)) => search.cursor_for(&self.buffer, &found),
+            (None, Some(_)) => {
+                self.set_message("Pattern not found", MessageKind::Warning);
+                return Ok(());
+            }
+            _ => return Ok(()),
+        };
+        self.push_jump();
+        self.text_position = position;
+        if let Some(indicator) = self.search_indicator() {
+            self.set_message(&indicator, MessageKind::Info);
+        }
+        self.scroll_into_view()?;
+        Terminal::move_caret_to(self.screen_location.clone())?;
+        Ok(())
+    }
 
-/// Represents rows/columns of padding on each of the edges
-pub struct Boundary {
-    top: usize,
-    right: usize,
-    pub(crate) left: usize,
-    bottom: usize,
-}
+    /// Delete the cursor's line, keeping it in the register to be pasted as a line
+    pub fn delete_current_line(&mut self)->Result<(), Error>{
+        if self.buffer.num_lines == 0 {
+            return Ok(());
+        }
+        let removed = self.buffer.delete_line(self.text_position.row);
+        let name = self.selected_register.take();
+        self.registers.borrow_mut().delete(name, Register::linewise(&removed.text));
+        self.text_position.row = self.text_position.row.min(self.buffer.num_lines - 1);
+        self.sync_text_position_byte_to_grapheme();
+        self.scroll_into_view()?;
+        Terminal::move_caret_to(self.screen_location.clone())?;
+        Ok(())
+    }
 
-impl Boundary {
-    pub fn default()-> Boundary{
-        Self {
-            top: 0,
-            right:0,
-            left:4, // To account for line numbers
-            bottom:2, // For status line and command entry line
+    /// Replace the grapheme under the cursor with a character (`r`), leaving the cursor on it
+    pub fn replace_char(&mut self, c: char)->Result<(), Error>{
+        if self.buffer.num_lines == 0 {
+            return Ok(());
         }
+        self.buffer.replace_char(self.text_position.row, self.text_position.grapheme, c);
+        // The cursor's grapheme starts at the same byte, but may now be a different length
+        self.sync_text_position_byte_to_grapheme();
+        self.scroll_into_view()?;
+        Terminal::move_caret_to(self.screen_location.clone())?;
+        Ok(())
     }
-}
 
-/// Enum Representing the current mode of the editor
-#[derive(Clone, Debug)]
-pub enum Mode {
+    /// Delete from the cursor to the start of the next word on its line (`kw`), or to the end
+    /// of the line if there isn't one. At the end of a line, the next line is pulled up onto it.
+    pub fn delete_word(&mut self)->Result<(), Error>{
+        if self.buffer.num_lines == 0 {
+            return Ok(());
+        }
+        let row = self.text_position.row;
+        let start = self.text_position.grapheme;
+        let removed = if start >= self.buffer.text[row].grapheme_count {
+            if row + 1 >= self.buffer.num_lines {
+                return Ok(());
+            }
+            self.buffer.merge_next_line(row);
+            "\n".to_string()
+        } else {
+            let mut end = self.word_run_end(row, start);
+            // Whitespace following a word is deleted along with it
+            let line = &self.buffer.text[row];
+            if line.grapheme(start).is_some_and(|text| WordClass::of(text) != WordClass::Blank)
+                && line.grapheme(end).is_some_and(|text| WordClass::of(text) == WordClass::Blank) {
+                end = self.word_run_end(row, end);
+            }
+            self.buffer.delete_range(TextPosition { row, byte: 0, grapheme: start },
+                                     TextPosition { row, byte: 0, grapheme: end - 1 })
+        };
+        let name = self.selected_register.take();
+        if !removed.is_empty() {
+            self.registers.borrow_mut().delete(name, Register::charwise(&removed));
+        }
+        self.sync_text_position_byte_to_grapheme();
+        self.scroll_into_view()?;
+        Terminal::move_caret_to(self.screen_location.clone())?;
+        Ok(())
+    }
+
+    /// Delete from the cursor to the end of its word (`Cw`), or the run of whitespace or
+    /// punctuation it is on, and start inserting in its place
+    pub fn change_word(&mut self)->Result<ScreenAction, Error>{
+        if self.buffer.refuse_edit() {
+            return Ok(ScreenAction::EnterMode(Mode::Normal));
+        }
+        if self.buffer.num_lines > 0 {
+            let row = self.text_position.row;
+            let start = self.text_position.grapheme;
+            let end = self.word_run_end(row, start);
+            if end > start {
+                let removed = self.buffer.delete_range(TextPosition { row, byte: 0, grapheme: start },
+                                                       TextPosition { row, byte: 0, grapheme: end - 1 });
+                let name = self.selected_register.take();
+                self.registers.borrow_mut().delete(name, Register::charwise(&removed));
+            }
+        }
+        // Insert mode lets the cursor stay past the end of the line, where the word was
+        self.set_mode(Mode::Insert);
+        self.scroll_into_view()?;
+        Terminal::move_caret_to(self.screen_location.clone())?;
+        Ok(ScreenAction::EnterMode(Mode::Insert))
+    }
+
+    /// Copy the cursor's line into the register, to be pasted as a line
+    pub fn yank_current_line(&mut self){
+        let name = self.selected_register.take();
+        if let Some(line) = self.buffer.text.get(self.text_position.row) {
+            self.registers.borrow_mut().yank(name, Register::linewise(&line.text));
+        }
+    }
+
+    /// Paste the register after the cursor, or before it if before is set. Lines are pasted
+    /// below (or above) the cursor's line, with the cursor moving to the first of them.
+    pub fn paste(&mut self, before: bool)->Result<(), Error>{
+        if self.buffer.refuse_edit() {
+            return Ok(());
+        }
+        let name = self.selected_register.take();
+        let register = match self.registers.borrow().get(name) {
+            Some(register) => register.clone(),
+            None => return Ok(()),
+        };
+        let row = self.text_position.row;
+        if register.kind == RegisterKind::Linewise || self.buffer.num_lines == 0 {
+            let at = if before { row } else { (row + 1).min(self.buffer.num_lines) };
+            self.buffer.insert_lines(at, &register.text);
+            return self.move_to_row_start(at);
+        }
+        let line = &self.buffer.text[row];
+        let at = if before || line.grapheme_count == 0 { self.text_position.grapheme } else { self.text_position.grapheme + 1 };
+        let at = at.min(line.grapheme_count);
+        let mut position = self.text_position.clone();
+        position.grapheme = at;
+        if register.kind == RegisterKind::Blockwise {
+            let rows: Vec<String> = register.text.split('\n').map(str::to_string).collect();
+            self.buffer.paste_block(position, &rows);
+            self.text_position.grapheme = at;
+            self.sync_text_position_byte_to_grapheme();
+            self.scroll_into_view()?;
+            Terminal::move_caret_to(self.screen_location.clone())?;
+            return Ok(());
+        }
+        self.buffer.paste_text(position, &register.text);
+        // Leave the cursor on the last pasted grapheme, when it all went on one line
+        if !register.text.contains('\n') {
+            self.text_position.grapheme = at + register.text.graphemes(true).count().saturating_sub(1);
+        }
+        self.sync_text_position_byte_to_grapheme();
+        self.scroll_into_view()?;
+        Terminal::move_caret_to(self.screen_location.clone())?;
+        Ok(())
+    }
+
+    /// Delete from the cursor to the end of its line (`D`)
+    pub fn delete_to_line_end(&mut self)->Result<(), Error>{
+        if self.buffer.num_lines == 0 {
+            return Ok(());
+        }
+        let removed = self.buffer.delete_to_line_end(self.text_position.row, self.text_position.grapheme);
+        let name = self.selected_register.take();
+        if !removed.is_empty() {
+            self.registers.borrow_mut().delete(name, Register::charwise(&removed));
+        }
+        // The cursor is now past the end of the line
+        self.sync_text_position_byte_to_grapheme();
+        self.scroll_into_view()?;
+        Terminal::move_caret_to(self.screen_location.clone())?;
+        Ok(())
+    }
+
+    /// Join the next line onto the cursor's line (`J`), leaving the cursor where they meet
+    pub fn join_lines(&mut self)->Result<(), Error>{
+        let grapheme = match self.buffer.join_lines(self.text_position.row) {
+            Some(grapheme) => grapheme,
+            None => return Ok(()),
+        };
+        self.text_position.grapheme = grapheme;
+        self.sync_text_position_byte_to_grapheme();
+        self.scroll_into_view()?;
+        Terminal::move_caret_to(self.screen_location.clone())?;
+        Ok(())
+    }
+
+    /// Move to the bracket matching the one under the cursor (`%`), or matching the first
+    /// bracket after the cursor on its line. Returns whether a match was found.
+    pub fn move_matching_bracket(&mut self)->Result<bool, Error>{
+        let row = self.text_position.row;
+        let line = match self.buffer.text.get(row) {
+            Some(line) => line,
+            None => return Ok(false),
+        };
+        let bracket = (self.text_position.grapheme..line.grapheme_count)
+            .find(|&grapheme| matches!(line.grapheme(grapheme), Some("(" | ")" | "[" | "]" | "{" | "}")));
+        let grapheme = match bracket {
+            Some(grapheme) => grapheme,
+            None => return Ok(false),
+        };
+        let start = TextPosition { row, byte: line.grapheme_start(grapheme), grapheme };
+        let found = match self.buffer.find_matching_bracket(&start) {
+            Some(found) => found,
+            None => return Ok(false),
+        };
+        self.push_jump();
+        self.text_position = found;
+        self.scroll_into_view()?;
+        Terminal::move_caret_to(self.screen_location.clone())?;
+        Ok(true)
+    }
+
+    /// Remember the cursor's position before a large motion, so `jump_back` can return to it
+    pub fn push_jump(&mut self){
+        if self.jump_list.last() == Some(&self.text_position) {
+            return;
+        }
+        if self.jump_list.len() == MAX_JUMPS {
+            self.jump_list.remove(0);
+        }
+        self.jump_list.push(self.text_position.clone());
+    }
+
+    /// Return to the position before the last large motion (`Ctrl-o`). Returns whether
+    /// there was one.
+    pub fn jump_back(&mut self)->Result<bool, Error>{
+        match self.jump_list.pop() {
+            Some(position) => self.move_to_position(position).map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Mark the cursor's position with a letter
+    pub fn set_mark(&mut self, letter: char){
+        self.marks.insert(letter, self.text_position.clone());
+    }
+
+    /// Jump to the position marked with the letter. Returns whether the mark was set.
+    pub fn jump_to_mark(&mut self, letter: char)->Result<bool, Error>{
+        let position = match self.marks.get(&letter) {
+            Some(position) => position.clone(),
+            None => return Ok(false),
+        };
+        self.push_jump();
+        self.move_to_position(position).map(|_| true)
+    }
+
+    /// Move the folds, marks and jumps along with the lines inserted or removed since this was
+    /// last done, so they stay on the same text
+    fn follow_line_shifts(&mut self){
+        for shift in self.buffer.text.take_shifts() {
+            self.folds.shift(&shift);
+            for position in self.marks.values_mut().chain(self.jump_list.iter_mut()) {
+                position.row = shift.row(position.row);
+            }
+        }
+    }
+
+    /// Move the cursor to a remembered position, keeping it within the buffer in case the
+    /// text has been edited since
+    fn move_to_position(&mut self, position: TextPosition)->Result<(), Error>{
+        if self.buffer.num_lines == 0 {
+            return Ok(());
+        }
+        self.text_position.row = position.row.min(self.buffer.num_lines - 1);
+        self.text_position.grapheme = position.grapheme.min(self.max_cursor_grapheme());
+        self.text_position.byte = self.grapheme_to_byte(self.text_position.row, self.text_position.grapheme);
+        self.scroll_into_view()?;
+        Terminal::move_caret_to(self.screen_location.clone())?;
+        Ok(())
+    }
+
+    /// Ask whether to save the buffer's changes before the screen is closed. `answer` is given
+    /// the screen to draw, and returns the key pressed: `y` to save or `n` to discard the
+    /// changes, either way giving QuitScreen, or Esc (None) to cancel and keep editing. An
+    /// unmodified buffer closes without asking.
+    pub fn confirm_quit(&mut self, mut answer: impl FnMut(&mut Screen)->Option<char>)->ScreenAction{
+        if !self.buffer.modified {
+            return ScreenAction::QuitScreen;
+        }
+        let name = match self.buffer.path.to_str() {
+            Some("") | None => "[No Name]".to_string(),
+            Some(path) => path.to_string(),
+        };
+        loop {
+            self.set_message(&format!("Save changes to {name} (y/n/Esc)?"), MessageKind::Warning);
+            match answer(self) {
+                Some('y') => {
+                    if let Err(error) = self.write_file(false) {
+                        self.set_message(&format!("Failed to write {name}: {error}"), MessageKind::Error);
+                        return ScreenAction::EnterMode(Mode::Normal);
+                    }
+                    self.message = None;
+                    return ScreenAction::QuitScreen;
+                }
+                Some('n') => {
+                    self.message = None;
+                    return ScreenAction::QuitScreen;
+                }
+                None => {
+                    self.message = None;
+                    return ScreenAction::EnterMode(Mode::Normal);
+                }
+                _ => {} // Ask again
+            }
+        }
+    }
+
+    /// Step through the matches of a substitution, highlighting each and asking whether to
+    /// replace it. `answer` is given the screen to draw, and returns the key pressed: `y` to
+    /// replace, `n` to skip, `a` to replace this and every later match, or `q` (or None) to
+    /// stop. Returns the number of matches replaced.
+    pub fn confirm_substitute(&mut self, rows: Range<usize>, pattern: &Regex, replacement: &str, global: bool,
+                              mut answer: impl FnMut(&mut Screen)->Option<char>)->usize{
+        let mut replaced = 0;
+        let mut replace_rest = false;
+        'rows: for row in rows.start..rows.end.min(self.buffer.num_lines) {
+            // Searching resumes after each replacement, so it never matches replaced text
+            let mut from = 0;
+            while let Some(captures) = pattern.captures_at(&self.buffer.text[row].text, from) {
+                let found = captures.get(0).unwrap();
+                let mut text = String::new();
+                captures.expand(replacement, &mut text);
+                let (start, end) = (found.start(), found.end());
+                let choice = if replace_rest {
+                    'y'
+                } else {
+                    let line = &self.buffer.text[row];
+                    self.text_position = TextPosition { row, byte: start, grapheme: line.text_index_to_grapheme(start) };
+                    let _ = self.scroll_into_view();
+                    self.highlight = Some(SearchMatch { row, start, end });
+                    self.set_message(&format!("replace with {text} (y/n/a/q)?"), MessageKind::Info);
+                    answer(self).unwrap_or('q')
+                };
+                match choice {
+                    'y' | 'a' => {
+                        self.buffer.replace_range(row, start..end, &text);
+                        replaced += 1;
+                        if choice == 'a' {
+                            replace_rest = true;
+                        }
+                        from = start + text.len();
+                    }
+                    'n' => from = end,
+                    'q' => break 'rows,
+                    _ => continue, // Ask again
+                }
+                // Step past an empty match, so it isn't found again
+                if start == end {
+                    match self.buffer.text[row].text[from..].chars().next() {
+                        Some(c) => from += c.len_utf8(),
+                        None => break,
+                    }
+                }
+                if !global {
+                    break;
+                }
+            }
+        }
+        self.highlight = None;
+        self.message = None;
+        self.sync_text_position_byte_to_grapheme();
+        replaced
+    }
+
+    /// Add delta to the number under or after the cursor, leaving the cursor on its last digit
+    pub fn increment_number(&mut self, delta: i64)->Result<(), Error>{
+        let row = self.text_position.row;
+        if !self.buffer.modify_number(row, self.text_position.grapheme, delta) {
+            return Ok(());
+        }
+        if let Some(range) = self.buffer.number_at(row, self.text_position.grapheme) {
+            self.text_position.grapheme = self.buffer.text[row].text_index_to_grapheme(range.end - 1);
+            self.text_position.byte = self.buffer.text[row].grapheme_start(self.text_position.grapheme);
+        }
+        self.scroll_into_view()?;
+        Terminal::move_caret_to(self.screen_location.clone())?;
+        Ok(())
+    }
+
+    /// Rewrap the paragraph containing the cursor to the text width, leaving the cursor at the
+    /// start of the paragraph's last line
+    pub fn reflow_paragraph(&mut self)->Result<(), Error>{
+        let rows = match self.buffer.reflow_paragraph(self.text_position.row, self.settings.text_width) {
+            Some(rows) => rows,
+            None => return Ok(()),
+        };
+        self.move_to_row_start(rows.end - 1)
+    }
+
+    /// The start and end of the selection, in the order they appear in the text
+    pub fn selection(&self)->Option<(TextPosition, TextPosition)>{
+        let anchor = self.selection_anchor.clone()?;
+        let cursor = self.text_position.clone();
+        if (anchor.row, anchor.grapheme) <= (cursor.row, cursor.grapheme) {
+            Some((anchor, cursor))
+        } else {
+            Some((cursor, anchor))
+        }
+    }
+
+    /// Count the lines, words and characters selected, or in the whole buffer when there
+    /// is no selection
+    pub fn selection_counts(&self)->(usize, usize, usize){
+        if self.buffer.num_lines == 0 {
+            return (0, 0, 0);
+        }
+        let (start, end) = self.selection().unwrap_or_else(|| {
+            let last_row = self.buffer.num_lines - 1;
+            let mut end = TextPosition::default();
+            end.row = last_row;
+            end.grapheme = self.buffer.text[last_row].grapheme_count.saturating_sub(1);
+            (TextPosition::default(), end)
+        });
+        let text = self.buffer.copy_text(start.clone(), end.clone());
+        (end.row - start.row + 1, count_words(&text, &WORD_REGEX), text.chars().count())
+    }
+
+    /// Select the text object around the cursor in place of the selection, leaving the cursor
+    /// at its end, and return whether there was one
+    pub fn select_text_object(&mut self, kind: TextObject)->Result<bool, Error>{
+        let (start, end) = match self.buffer.text_object(kind, &self.text_position) {
+            Some(found) => found,
+            None => return Ok(false),
+        };
+        self.selection_anchor = Some(start);
+        self.desired_grapheme = end.grapheme;
+        self.stick_to_line_end = false;
+        self.text_position = end;
+        self.scroll_into_view()?;
+        Terminal::move_caret_to(self.screen_location.clone())?;
+        Ok(true)
+    }
+
+    /// Report the counts of the selection on the message line (`Ctrl-g` in select mode, like
+    /// vim's `g Ctrl-G`)
+    pub fn show_selection_counts(&mut self){
+        let (lines, words, characters) = self.selection_counts();
+        let scope = if self.selection_anchor.is_some() { "Selected" } else { "Buffer has" };
+        self.set_message(&format!("{scope} {}, {}, {}", counted(lines, "line"), counted(words, "word"),
+                                  counted(characters, "character")), MessageKind::Info);
+    }
+
+    /// The rows and grapheme columns of a block selection
+    pub fn selected_block(&self)->Option<(Range<usize>, RangeInclusive<usize>)>{
+        if self.selection_kind != SelectionKind::Block {
+            return None;
+        }
+        let anchor = self.selection_anchor.as_ref()?;
+        let cursor = &self.text_position;
+        Some((anchor.row.min(cursor.row)..anchor.row.max(cursor.row) + 1,
+              anchor.grapheme.min(cursor.grapheme)..=anchor.grapheme.max(cursor.grapheme)))
+    }
+
+    /// Copy the selection into the register, ending the selection with the cursor at its start.
+    /// A block is copied as the rows of the rectangle.
+    pub fn yank_selection(&mut self)->Result<(), Error>{
+        let Some((start, end)) = self.selection() else { return Ok(()) };
+        let block = self.selected_block();
+        self.selection_anchor = None;
+        if self.buffer.num_lines == 0 {
+            return Ok(());
+        }
+        let name = self.selected_register.take();
+        if let Some((rows, columns)) = block {
+            let corner = TextPosition { row: rows.start, byte: 0, grapheme: *columns.start() };
+            let rect = self.buffer.copy_rect(corner.clone(), TextPosition { row: rows.end - 1, byte: 0, grapheme: *columns.end() });
+            self.registers.borrow_mut().yank(name, Register::blockwise(&rect));
+            return self.move_to(corner);
+        }
+        let text = self.buffer.copy_text(start.clone(), end);
+        self.registers.borrow_mut().yank(name, Register::charwise(&text));
+        self.move_to(start)
+    }
+
+    /// Insert on every row of a block selection, at its left edge
+    pub fn insert_on_block(&mut self)->Result<(), Error>{
+        let Some((rows, columns)) = self.selected_block() else { return Ok(()) };
+        self.selection_anchor = None;
+        self.start_block_insert(rows, *columns.start())
+    }
+
+    /// Delete the selection into the register, leaving the cursor where it started
+    pub fn delete_selection(&mut self)->Result<(), Error>{
+        let Some((start, end)) = self.selection() else { return Ok(()) };
+        let block = self.selected_block();
+        self.selection_anchor = None;
+        if self.buffer.num_lines == 0 {
+            return Ok(());
+        }
+        let name = self.selected_register.take();
+        if let Some((rows, columns)) = block {
+            let corner = TextPosition { row: rows.start, byte: 0, grapheme: *columns.start() };
+            let rect = self.buffer.copy_rect(corner.clone(), TextPosition { row: rows.end - 1, byte: 0, grapheme: *columns.end() });
+            self.registers.borrow_mut().delete(name, Register::blockwise(&rect));
+            for row in rows {
+                for _ in columns.clone() {
+                    self.buffer.delete_char(row, *columns.start());
+                }
+            }
+            return self.move_to(corner);
+        }
+        let text = self.buffer.delete_range(start.clone(), end);
+        self.registers.borrow_mut().delete(name, Register::charwise(&text));
+        self.move_to(start)
+    }
+
+    /// Indent the rows by `shift_width` columns, moving the cursor to the first non-blank
+    /// grapheme of the first row
+    pub fn indent_lines(&mut self, rows: Range<usize>)->Result<(), Error>{
+        let indent = self.settings.indent_unit();
+        self.buffer.indent_lines(rows.clone(), &indent);
+        self.move_to_first_non_blank(rows.start)
+    }
+
+    /// Remove up to `shift_width` columns of indentation from the rows, moving the cursor to
+    /// the first non-blank grapheme of the first row
+    pub fn dedent_lines(&mut self, rows: Range<usize>)->Result<(), Error>{
+        self.buffer.dedent_lines(rows.clone(), self.settings.shift_width, self.settings.tab_width);
+        self.move_to_first_non_blank(rows.start)
+    }
+
+    /// Indent (or dedent) every row the selection touches, ending the selection
+    pub fn shift_selection(&mut self, dedent: bool)->Result<(), Error>{
+        let Some((start, end)) = self.selection() else { return Ok(()) };
+        self.selection_anchor = None;
+        let rows = start.row..end.row + 1;
+        if dedent { self.dedent_lines(rows) } else { self.indent_lines(rows) }
+    }
+
+    /// Put the cursor on the first grapheme of a row which isn't whitespace
+    fn move_to_first_non_blank(&mut self, row: usize)->Result<(), Error>{
+        // Whitespace characters are each a grapheme, so this is also the indent in graphemes
+        let indent = self.buffer.text.get(row).map_or(0, |line| line.leading_whitespace());
+        self.move_to(TextPosition { row, byte: indent, grapheme: indent })
+    }
+
+    /// Put the cursor at a position, bringing it on screen
+    fn move_to(&mut self, position: TextPosition)->Result<(), Error>{
+        self.text_position = position;
+        self.desired_grapheme = self.text_position.grapheme;
+        self.stick_to_line_end = false;
+        self.sync_text_position_byte_to_grapheme();
+        self.scroll_into_view()?;
+        Terminal::move_caret_to(self.screen_location.clone())?;
+        Ok(())
+    }
+
+    /// Start recording the keys typed into the register, to be played back as a macro
+    pub fn start_recording(&mut self, register: char){
+        self.macros.borrow_mut().start(register);
+        self.set_message(&format!("recording @{register}"), MessageKind::Info);
+    }
+
+    pub fn stop_recording(&mut self){
+        let stopped = self.macros.borrow_mut().stop();
+        if let Some(register) = stopped {
+            self.set_message(&format!("recorded @{register}"), MessageKind::Info);
+        }
+    }
+
+    /// Remember the keys of a change for `.` to repeat. When the change has started insert
+    /// mode, the keys typed there are added until it is left.
+    pub fn record_change(&mut self, keys: Vec<KeyEvent>){
+        if self.mode == Mode::Insert {
+            self.change_keys = Some(keys);
+        } else {
+            self.last_change = keys;
+        }
+    }
+
+    /// Add a key typed in insert mode to the change being recorded, if there is one
+    pub fn record_insert_key(&mut self, key: KeyEvent){
+        if let Some(keys) = self.change_keys.as_mut() {
+            keys.push(key);
+        }
+    }
+
+    /// Repeat the last change (`.`) by replaying its keys
+    pub fn repeat_change(&mut self){
+        Terminal::replay(&self.last_change);
+    }
+
+    /// Play the macro in the register, or the last one played, count times
+    pub fn play_macro(&mut self, register: Option<char>, count: usize){
+        let played = self.macros.borrow_mut().play(register, count);
+        if let Err(error) = played {
+            self.set_message(&error, MessageKind::Error);
+        }
+    }
+
+    /// Report the size of the buffer on the message line (`:stats`)
+    pub fn show_stats(&mut self){
+        let stats = self.buffer.stats(&WORD_REGEX);
+        self.set_message(&format!("{} lines, {} words, {} graphemes, {} bytes",
+                                  stats.lines, stats.words, stats.graphemes, stats.bytes), MessageKind::Info);
+    }
+
+    /// Undo the last edit, moving the cursor to where it was
+    pub fn undo(&mut self)->Result<(), Error>{
+        let row = self.buffer.undo();
+        self.move_to_changed_row(row)
+    }
+
+    /// Redo the last undone edit, moving the cursor to where it was
+    pub fn redo(&mut self)->Result<(), Error>{
+        let row = self.buffer.redo();
+        self.move_to_changed_row(row)
+    }
+
+    /// Go back count edits in the order they were made (`:earlier`), even onto another
+    /// branch of the undo tree
+    pub fn earlier(&mut self, count: usize)->Result<(), Error>{
+        let row = self.buffer.earlier(count);
+        self.move_to_changed_row(row)
+    }
+
+    /// Go forward count edits in the order they were made (`:later`)
+    pub fn later(&mut self, count: usize)->Result<(), Error>{
+        let row = self.buffer.later(count);
+        self.move_to_changed_row(row)
+    }
+
+    /// Move to the start of the row changed by undo or redo, if anything changed
+    fn move_to_changed_row(&mut self, row: Option<usize>)->Result<(), Error>{
+        match row {
+            Some(row) => self.move_to_row_start(row.min(self.buffer.num_lines.saturating_sub(1))),
+            None => Ok(()),
+        }
+    }
+
+    /// Updates the scroll offset and caret_position so the text_position is on screen
+    pub fn scroll_into_view(&mut self)->Result<(), Error>{
+        self.update_gutter_width();
+        self.clamp_scroll_offset();
+        self.scroll_vertical();
+        self.scroll_horizontal();
+        self.sync_screen_position();
+        Ok(())
+    }
+
+    /// Insert a character at the cursor, advancing the cursor past it
+    pub fn insert_char(&mut self, c: char)->Result<(), Error>{
+        if self.buffer.refuse_edit() {
+            return Ok(());
+        }
+        // An empty buffer has no line to insert into yet
+        if self.buffer.num_lines == 0 {
+            self.buffer.new_line(0, 0);
+        }
+        // Typing in the virtual space past the end of the line fills the gap with spaces
+        let row = self.text_position.row;
+        while self.buffer.text[row].grapheme_count < self.text_position.grapheme {
+            let end = self.buffer.text[row].grapheme_count;
+            self.buffer.insert_char(row, end, ' ');
+        }
+        self.buffer.insert_char(self.text_position.row, self.text_position.grapheme, c);
+        self.text_position.grapheme += 1;
+        self.text_position.byte = self.grapheme_to_byte(row, self.text_position.grapheme);
+        self.scroll_into_view()?;
+        Terminal::move_caret_to(self.screen_location.clone())?;
+        Ok(())
+    }
+
+    /// Insert a character typed in insert mode. With `auto_pairs` on, an opening bracket or
+    /// quote is inserted along with its closing partner, leaving the cursor between them, and
+    /// typing a closing one just before the same character steps over it instead.
+    pub fn type_char(&mut self, c: char)->Result<(), Error>{
+        if !self.settings.auto_pairs {
+            return self.insert_char(c);
+        }
+        let row = self.text_position.row;
+        let grapheme = self.text_position.grapheme;
+        let line = self.buffer.text.get(row);
+        let next = line.and_then(|line| line.grapheme(grapheme));
+        if self.settings.pairs.iter().any(|(_, close)| *close == c) && next == Some(c.to_string().as_str()) {
+            self.text_position.grapheme += 1;
+            self.text_position.byte = self.grapheme_to_byte(row, self.text_position.grapheme);
+            self.scroll_into_view()?;
+            Terminal::move_caret_to(self.screen_location.clone())?;
+            return Ok(());
+        }
+        let Some(&(open, close)) = self.settings.pairs.iter().find(|(open, _)| *open == c) else {
+            return self.insert_char(c);
+        };
+        // Quotes just after a word are more likely apostrophes or closing an existing string
+        let after_word = grapheme > 0 && line.and_then(|line| line.grapheme(grapheme - 1))
+            .is_some_and(|previous| previous.chars().all(char::is_alphanumeric));
+        self.insert_char(c)?;
+        if open != close || !after_word {
+            self.buffer.insert_char(row, self.text_position.grapheme, close);
+        }
+        Ok(())
+    }
+
+    /// Break the line at the cursor, moving the cursor onto the new line just after the
+    /// indentation it is given
+    pub fn insert_new_line(&mut self)->Result<(), Error>{
+        if self.buffer.refuse_edit() {
+            return Ok(());
+        }
+        if self.buffer.num_lines == 0 {
+            self.buffer.new_line(0, 0);
+        }
+        let indent = if self.settings.auto_indent {
+            self.buffer.new_line_autoindent(self.text_position.row, self.text_position.grapheme,
+                                            self.settings.expand_tab, self.settings.tab_width)
+        } else {
+            self.buffer.new_line(self.text_position.row, self.text_position.grapheme);
+            0
+        };
+        self.text_position.row += 1;
+        self.text_position.grapheme = indent;
+        self.text_position.byte = indent;
+        self.desired_grapheme = indent;
+        self.stick_to_line_end = false;
+        self.scroll_into_view()?;
+        Terminal::move_caret_to(self.screen_location.clone())?;
+        Ok(())
+    }
+
+    /// Start inserting just after the grapheme under the cursor (vim's `a`). On an empty line
+    /// this is the start of the line.
+    pub fn insert_after(&mut self)->Result<ScreenAction, Error>{
+        self.set_mode(Mode::Insert);
+        let grapheme = min(self.text_position.grapheme + 1, self.max_cursor_grapheme());
+        self.move_to(TextPosition { row: self.text_position.row, byte: 0, grapheme })?;
+        Ok(ScreenAction::EnterMode(Mode::Insert))
+    }
+
+    /// Start inserting at the end of the cursor's line (vim's `A`)
+    pub fn insert_at_line_end(&mut self)->Result<ScreenAction, Error>{
+        // In insert mode the end of the line is just past its last grapheme
+        self.set_mode(Mode::Insert);
+        self.move_end_line()?;
+        Ok(ScreenAction::EnterMode(Mode::Insert))
+    }
+
+    /// Start inserting before the first grapheme of the cursor's line which isn't whitespace
+    /// (`I`)
+    pub fn insert_at_first_non_blank(&mut self)->Result<ScreenAction, Error>{
+        self.set_mode(Mode::Insert);
+        self.move_to_first_non_blank(self.text_position.row)?;
+        Ok(ScreenAction::EnterMode(Mode::Insert))
+    }
+
+    /// Start a new line below the cursor's line (`o`), or above it (`O`), and insert there.
+    /// With `auto_indent` on, the new line gets the indentation of the cursor's line.
+    pub fn open_line(&mut self, above: bool)->Result<ScreenAction, Error>{
+        if self.buffer.refuse_edit() {
+            return Ok(ScreenAction::EnterMode(Mode::Normal));
+        }
+        let current = self.text_position.row;
+        let indent = if self.settings.auto_indent {
+            self.buffer.line_indent(current, self.settings.expand_tab, self.settings.tab_width)
+        } else {
+            String::new()
+        };
+        let row = if above { current } else { (current + 1).min(self.buffer.num_lines) };
+        self.buffer.insert_line(row, Line::from_string(&indent));
+        // Indentation is spaces and tabs, each a single grapheme
+        self.text_position = TextPosition { row, byte: indent.len(), grapheme: indent.len() };
+        self.desired_grapheme = indent.len();
+        self.stick_to_line_end = false;
+        self.set_mode(Mode::Insert);
+        self.scroll_into_view()?;
+        Terminal::move_caret_to(self.screen_location.clone())?;
+        Ok(ScreenAction::EnterMode(Mode::Insert))
+    }
+
+    /// Delete the grapheme before the cursor, joining the line onto the previous line
+    /// if the cursor is at the start of a line
+    pub fn backspace(&mut self)->Result<(), Error>{
+        if self.buffer.refuse_edit() {
+            return Ok(());
+        }
+        if self.buffer.num_lines == 0 {
+            return Ok(());
+        }
+        if self.text_position.grapheme > 0 {
+            self.text_position.grapheme -= 1;
+            self.buffer.delete_char(self.text_position.row, self.text_position.grapheme);
+        } else if self.text_position.row > 0 {
+            let join_point = self.buffer.text[self.text_position.row - 1].grapheme_count;
+            self.buffer.merge_next_line(self.text_position.row - 1);
+            self.text_position.row -= 1;
+            self.text_position.grapheme = join_point;
+        }
+        self.text_position.byte = self.grapheme_to_byte(self.text_position.row,
+                                                        self.text_position.grapheme);
+        self.scroll_into_view()?;
+        Terminal::move_caret_to(self.screen_location.clone())?;
+        Ok(())
+    }
+
+    /// Delete the grapheme under the cursor, pulling the next line up onto the current one
+    /// if the cursor is at the end of a line
+    pub fn delete_forward(&mut self)->Result<(), Error>{
+        if self.buffer.num_lines == 0 {
+            return Ok(());
+        }
+        let row = self.text_position.row;
+        if self.text_position.grapheme < self.buffer.text[row].grapheme_count {
+            self.buffer.delete_char(row, self.text_position.grapheme);
+        } else {
+            self.buffer.merge_next_line(row);
+        }
+        self.text_position.byte = self.grapheme_to_byte(row, self.text_position.grapheme);
+        self.scroll_into_view()?;
+        Terminal::move_caret_to(self.screen_location.clone())?;
+        Ok(())
+    }
+
+    /// Delete from the cursor back to the first non-whitespace grapheme of the line, or to
+    /// the start of the line if the cursor is already within the indentation
+    pub fn delete_to_line_start(&mut self)->Result<(), Error>{
+        if self.buffer.refuse_edit() {
+            return Ok(());
+        }
+        let row = self.text_position.row;
+        let line = match self.buffer.text.get(row) {
+            Some(line) => line,
+            None => return Ok(()),
+        };
+        // Whitespace characters are each a grapheme, so this is also the indent in graphemes
+        let indent = line.leading_whitespace();
+        let start = if self.text_position.grapheme > indent { indent } else { 0 };
+        for _ in start..self.text_position.grapheme {
+            self.buffer.delete_char(row, start);
+        }
+        self.text_position.grapheme = start;
+        self.text_position.byte = self.grapheme_to_byte(row, start);
+        self.scroll_into_view()?;
+        Terminal::move_caret_to(self.screen_location.clone())?;
+        Ok(())
+    }
+
+    /// Find the byte offset of a grapheme, which may be one past the end of the line
+    fn grapheme_to_byte(&self, row: usize, grapheme: usize)->usize{
+        // An empty buffer is treated as a single empty line
+        let line = match self.buffer.text.get(row) {
+            Some(line) => line,
+            None => return 0,
+        };
+        if grapheme >= line.grapheme_count {
+            return line.text.len();
+        }
+        line.grapheme_start(grapheme)
+    }
+
+    /// Delete the grapheme at the text position
+    pub fn delete_grapheme(&mut self, location: TextPosition){
+        if location.row >= self.buffer.num_lines {
+            return;
+        }
+        self.buffer.delete_char(location.row, location.grapheme)
+    }
+
+    /// Pull the view back if the buffer has shrunk so it starts past the last line, showing
+    /// the end of the buffer rather than only empty rows
+    fn clamp_scroll_offset(&mut self){
+        if self.scroll_offset.row >= self.buffer.num_lines {
+            self.scroll_offset.row = self.buffer.num_lines.saturating_sub(self.view_height());
+        }
+    }
+
+    fn scroll_horizontal(&mut self){
+        // Wrapped lines are always shown from their start
+        if self.settings.wrap {
+            self.scroll_offset.col = 0;
+            return;
+        }
+        let width = self.view_width().max(1);
+        let grapheme = self.text_position.grapheme;
+        if grapheme >= self.scroll_offset.col + width {
+            // The cursor is too far right, make its column the rightmost one on screen
+            self.scroll_offset.col = grapheme + 1 - width;
+        } else if grapheme < self.scroll_offset.col {
+            // The cursor is too far left, move the scroll offset to the left
+            self.scroll_offset.col = grapheme;
+        }
+        // Wide graphemes take two cells, so scroll further until the cursor's one fits
+        if let Some(line) = self.buffer.text.get(self.text_position.row) {
+            while self.scroll_offset.col < grapheme && line.display_width(self.scroll_offset.col..grapheme + 1, self.settings.tab_width) > width {
+                self.scroll_offset.col += 1;
+            }
+        }
+    }
+
+    fn scroll_vertical(&mut self){
+        let height = self.view_height().max(1);
+        // A margin of half the view or more would leave nowhere for the cursor to go
+        let margin = self.settings.scroll_off.min((height - 1) / 2);
+        let row = self.text_position.row;
+        // Keep the margin below the cursor on screen, as far as the buffer goes
+        let below = (row + margin).min(self.buffer.num_lines.saturating_sub(1)).max(row);
+        if below >= self.scroll_offset.row + height {
+            self.scroll_offset.row = below + 1 - height;
+        }
+        // And the margin above it, as far as the start of the buffer
+        let above = row.saturating_sub(margin);
+        if above < self.scroll_offset.row {
+            self.scroll_offset.row = above;
+        }
+        // Wrapped lines above the cursor can push it off the bottom of the view
+        if self.settings.wrap {
+            let (visual_row, _) = self.visual_position(&self.visual_rows(row), self.text_position.grapheme);
+            while self.scroll_offset.row < row && self.rows_before(row) + visual_row >= height {
+                match self.next_visible_row(self.scroll_offset.row) {
+                    Some(next) => self.scroll_offset.row = next,
+                    None => break,
+                }
+            }
+        }
+    }
+
+    /// Number of screen rows taken by the lines from the top of the view down to `row`
+    fn rows_before(&self, row: usize)->usize{
+        let mut rows = 0;
+        let mut line = Some(self.scroll_offset.row);
+        while let Some(current) = line.filter(|current| *current < row) {
+            rows += self.display_rows(current);
+            line = self.next_visible_row(current);
+        }
+        rows
+    }
+
+    /// Syncs the positions of the caret and the cursor. The column counts terminal cells, so
+    /// wide graphemes before the cursor push it further right.
+    fn sync_screen_position(&mut self) {
+        self.screen_location = self.location_of(&self.text_position);
+    }
+
+    /// Where a position in the text is drawn on the terminal, given the current scroll offset
+    pub fn location_of(&self, position: &TextPosition)->ScreenLocation{
+        if self.settings.wrap {
+            let rows = self.visual_rows(position.row);
+            let (visual_row, _) = self.visual_position(&rows, position.grapheme);
+            let start = rows[visual_row].start;
+            let cells = self.buffer.text.get(position.row).map_or(
+                position.grapheme.saturating_sub(start),
+                |line| line.display_width(start..position.grapheme, self.settings.tab_width));
+            return ScreenLocation {
+                row: self.rows_before(position.row) + visual_row + self.inner_boundary.top,
+                col: cells + self.inner_boundary.left,
+            };
+        }
+        let cells = self.buffer.text.get(position.row).map_or(
+            position.grapheme.saturating_sub(self.scroll_offset.col),
+            |line| line.display_width(self.scroll_offset.col..position.grapheme, self.settings.tab_width));
+        let hidden = self.folds.hidden_between(self.scroll_offset.row..position.row);
+        ScreenLocation {
+            row: position.row - self.scroll_offset.row - hidden + self.inner_boundary.top,
+            col: cells + self.inner_boundary.left,
+        }
+    }
+
+    /// The start of every word on screen, in order down the screen and then along each line
+    pub fn visible_word_starts(&self)->Vec<TextPosition>{
+        let mut starts = Vec::new();
+        let mut row = Some(self.scroll_offset.row);
+        for _ in 0..self.view_height() {
+            let Some(current) = row else { break };
+            let Some(line) = self.buffer.text.get(current) else { break };
+            let mut previous_end = None;
+            for found in WORD_REGEX.find_iter(&line.text) {
+                // Consecutive matches are part of the same word
+                if previous_end != Some(found.start()) {
+                    let grapheme = line.text_index_to_grapheme(found.start());
+                    let position = TextPosition { row: current, byte: found.start(), grapheme };
+                    let on_screen = if self.settings.wrap {
+                        self.location_of(&position).row < self.inner_boundary.top + self.view_height()
+                    } else {
+                        grapheme >= self.scroll_offset.col
+                            && line.display_width(self.scroll_offset.col..grapheme, self.settings.tab_width) < self.view_width()
+                    };
+                    if on_screen {
+                        starts.push(position);
+                    }
+                }
+                previous_end = Some(found.end());
+            }
+            row = self.next_visible_row(current);
+        }
+        starts
+    }
+
+    /// Move the cursor to a position picked from the screen, remembering where it came from
+    pub fn jump_to(&mut self, position: TextPosition)->Result<(), Error>{
+        self.push_jump();
+        self.desired_grapheme = position.grapheme;
+        self.stick_to_line_end = false;
+        self.text_position = position;
+        self.scroll_into_view()?;
+        Terminal::move_caret_to(self.screen_location.clone())?;
+        Ok(())
+    }
+
+    /// Draw the screen to the terminal
+    pub fn draw(&mut self) {
+        let _ = Terminal::hide_caret(); // Hide the caret so it doesn't flicker across the screen
+        self.buffer.receive_lines();
+        self.follow_line_shifts();
+        if let Some(problem) = self.buffer.load_problem.take() {
+            self.set_message(&problem, MessageKind::Warning);
+        }
+        if take(&mut self.buffer.edit_refused) {
+            self.set_message("Buffer is read-only", MessageKind::Warning);
+        }
+        self.update_gutter_width();
+        self.clamp_scroll_offset();
+        self.release_pages();
+        self.update_matching_bracket();
+        self.draw_text(); // Draw the text to the screen
+        self.draw_status_line();
+        self.draw_message_line();
+        let _ = Terminal::move_caret_to(self.screen_location.clone()); // Return caret to cursor
+        let _ = Terminal::show_caret();
+        let _ = Terminal::execute(); // Execute the queued commands, drawing the current view
+    }
+
+    /// Let go of the lines of a paged file which are a screen or more out of view
+    fn release_pages(&mut self){
+        let height = self.view_height();
+        let top = self.scroll_offset.row;
+        self.buffer.text.release_pages(top.saturating_sub(height)..top + 2 * height);
+    }
+
+    /// Find the bracket matching the one under the cursor, clearing it if the cursor isn't
+    /// on a bracket. The buffer is only scanned when the cursor is on one.
+    pub fn update_matching_bracket(&mut self){
+        self.matching_bracket = None;
+        self.unmatched_bracket = false;
+        if !self.settings.match_brackets || !self.buffer.is_bracket(&self.text_position) {
+            return;
+        }
+        self.matching_bracket = self.buffer.find_matching_bracket(&self.text_position);
+        self.unmatched_bracket = self.matching_bracket.is_none() && self.settings.warn_unmatched_brackets;
+    }
+
+    /// Draw the text portion of the screen
+    fn draw_text(&mut self){
+        for (idx, row) in self.render_rows().iter().enumerate() {
+            let _ = Terminal::move_caret_to(ScreenLocation{row: idx, col: 0});
+            let _ = Terminal::clear_to_line_end();
+            let _ = Terminal::print(row);
+        }
+    }
+
+    /// Render every row of the view, including the gutter
+    pub fn render_rows(&mut self)->Vec<String>{
+        let height = self.view_height();
+        let mut rows = Vec::with_capacity(height);
+        let mut line = self.scroll_offset.row;
+        while rows.len() < height {
+            if line < self.buffer.num_lines {
+                // Folded regions are drawn as a single summary row
+                match self.folds.fold_containing(line).cloned() {
+                    Some(fold) => {
+                        let summary = self.render_fold_summary(fold.clone());
+                        rows.push(format!("{}{}", self.render_gutter(line), summary));
+                        line = fold.end;
+                    }
+                    None if self.settings.wrap => {
+                        // Only the first row of a wrapped line is numbered
+                        let blank_gutter = " ".repeat(self.inner_boundary.left);
+                        for (idx, graphemes) in self.visual_rows(line).into_iter().enumerate().take(height - rows.len()) {
+                            let gutter = if idx == 0 { self.render_gutter(line) } else { blank_gutter.clone() };
+                            rows.push(format!("{gutter}{}", self.render_segment(line, graphemes).render()));
+                        }
+                        line += 1;
+                    }
+                    None => {
+                        rows.push(format!("{}{}", self.render_gutter(line), self.render_line(line)));
+                        line += 1;
+                    }
+                }
+            } else {
+                rows.push(self.render_empty_line(line));
+                line += 1;
+            }
+        }
+        rows
+    }
+
+    /// Draw the status line, below the text
+    fn draw_status_line(&self){
+        let status = self.render_status_line();
+        let _ = Terminal::move_caret_to(ScreenLocation{row: self.size.height.saturating_sub(2), col: 0});
+        let _ = Terminal::clear_to_line_end();
+        let _ = Terminal::print(&format!("{}", status.reverse()));
+    }
+
+    /// Render the status line: the file name and whether it is modified on the left, and the
+    /// cursor position and how far through the buffer the view is on the right
+    pub fn render_status_line(&self)->String{
+        let mut name = match self.buffer.path.to_str() {
+            Some("") | None => "[No Name]".to_string(),
+            Some(path) => path.to_string(),
+        };
+        if self.buffer.modified {
+            name.push_str(" [+]");
+        }
+        let position = format!("{},{}   {}", self.text_position.row + 1, self.text_position.grapheme + 1,
+                               self.buffer.scroll_label(self.scroll_offset.row, self.view_height()));
+        let padding = self.size.width.saturating_sub(name.chars().count() + position.len()).max(1);
+        let status = format!("{name}{}{position}", " ".repeat(padding));
+        status.chars().take(self.size.width).collect()
+    }
+
+    /// Show a message on the command line, replacing any already shown
+    pub fn set_message(&mut self, text: &str, kind: MessageKind){
+        self.message = Some((text.to_string(), kind));
+    }
+
+    /// Called for every key pressed, before it is handled. Messages only last until the
+    /// next key press, and the swap file is brought up to date with the edits so far.
+    pub fn key_pressed(&mut self){
+        self.message = None;
+        if let Err(error) = self.buffer.update_swap(self.settings.swap_interval) {
+            self.set_message(&format!("Failed to write swap file: {error}"), MessageKind::Error);
+        }
+    }
+
+    /// Draw the message on the bottom row, or clear the row if there isn't one
+    fn draw_message_line(&self){
+        let _ = Terminal::move_caret_to(ScreenLocation{row: self.size.height.saturating_sub(1), col: 0});
+        let _ = Terminal::clear_to_line_end();
+        if let Some(message) = self.render_message() {
+            let _ = Terminal::print(&message);
+        }
+    }
+
+    /// Render the message, cut to the width of the screen and colored by its kind
+    pub fn render_message(&self)->Option<String>{
+        let (text, kind) = self.message.as_ref()?;
+        let text: String = text.chars().take(self.size.width).collect();
+        Some(match kind {
+            MessageKind::Info => text,
+            MessageKind::Warning => format!("{}", text.yellow()),
+            MessageKind::Error => format!("{}", text.red()),
+        })
+    }
+
+    /// Render the summary row shown in place of a folded region
+    pub fn render_fold_summary(&self, fold: Range<usize>)->String{
+        let header = self.buffer.text[fold.start].text.trim();
+        let summary = format!("+-- {} lines: {}", fold.len(), header);
+        let summary: String = summary.chars().take(self.view_width()).collect();
+        format!("{}", summary.dark_grey())
+    }
+
+    /// Size the gutter to fit the largest line number, so the text starts just after it
+    pub fn update_gutter_width(&mut self){
+        let digits = self.buffer.num_lines.max(1).to_string().len();
+        let sign_width = if self.settings.sign_column { 1 } else { 0 };
+        self.inner_boundary.left = sign_width + digits.max(self.settings.gutter_min_width) + 1;
+    }
+
+    /// Render the line number shown in the gutter for a line of text
+    pub fn render_gutter(&self, text_line: usize)->String{
+        let number = if self.settings.relative_numbers && text_line != self.text_position.row {
+            text_line.abs_diff(self.text_position.row)
+        } else {
+            text_line + 1
+        };
+        if !self.settings.sign_column {
+            let width = self.inner_boundary.left.saturating_sub(1);
+            return format!("{}", format!("{number:>width$} ").dark_grey());
+        }
+        let sign = match self.buffer.line_change_status(text_line) {
+            ChangeKind::Unchanged => " ".stylize(),
+            ChangeKind::Added => "+".green(),
+            ChangeKind::Modified => "~".yellow(),
+            ChangeKind::Removed => "-".red(),
+        };
+        let width = self.inner_boundary.left.saturating_sub(2);
+        format!("{}{}", sign, format!("{number:>width$} ").dark_grey())
+    }
+
+    /// Render the visible part of a line of text into the string printed for it
+    pub fn render_line(&mut self, text_line: usize)->String{
+        // Wide graphemes take up more than one cell, so stop before any which would overflow
+        // the view
+        let start = self.scroll_offset.col;
+        let width = self.view_width();
+        let tab_width = self.settings.tab_width;
+        let end = self.buffer.text[text_line].graphemes_within_width(start, width, tab_width);
+        let mut row = self.render_segment(text_line, start..end);
+        // Mark text hidden off either side of the view. The markers cover the cells at the
+        // edges rather than moving any text, filling the whole width of a wide grapheme.
+        let line = &self.buffer.text[text_line];
+        let marker_style = ContentStyle::new().attribute(Attribute::Dim);
+        if start > 0 && line.grapheme_count > 0 {
+            let padding = " ".repeat(line.grapheme_width(start, tab_width).saturating_sub(1));
+            let content = format!("{}{padding}", self.settings.overflow_left_char);
+            match row.cells.first_mut() {
+                Some(cell) => *cell = Cell { content, style: marker_style },
+                None => row.cells.push(Cell { content, style: marker_style }),
+            }
+        }
+        if end < line.grapheme_count && width > 0 {
+            let used = line.display_width(start..end, tab_width);
+            let marker = self.settings.overflow_right_char;
+            if used < width {
+                // A wide grapheme didn't fit, leaving a gap before the last column
+                row.pad_to(row.cells.len() + width - 1 - used);
+                row.cells.push(Cell { content: marker.to_string(), style: marker_style });
+            } else if let Some(cell) = row.cells.last_mut() {
+                let padding = " ".repeat(line.grapheme_width(end - 1, tab_width).saturating_sub(1));
+                *cell = Cell { content: format!("{padding}{marker}"), style: marker_style };
+            }
+        }
+        row.render()
+    }
+
+    /// Render a range of graphemes from a line of text, either the part of it scrolled into
+    /// view or one of its visual rows when wrapped
+    pub fn render_segment(&mut self, text_line: usize, graphemes: Range<usize>)->RenderedRow{
+        let overlong = self.is_overlong(text_line);
+        let width = self.view_width();
+        let offset = graphemes.start;
+        let count = self.buffer.text[text_line].grapheme_count;
+        // Padding past the segment only belongs after the end of the line
+        let line_end_visible = graphemes.end >= count;
+        let mut row = RenderedRow::from_text(self.buffer.print_line(
+            text_line,
+            offset,
+            graphemes.end.saturating_sub(1),
+            !overlong
+        ));
+        row.cells.truncate(graphemes.len());
+        let start_column = self.buffer.text[text_line].display_column(offset, self.settings.tab_width);
+        // Overlong lines are printed as a plain slice, as styling them would mean scanning
+        // the whole line on every draw
+        if overlong {
+            row.expand_tabs(self.settings.tab_width, None, start_column);
+            return row;
+        }
+        // Substitute glyphs for whitespace, marking the end of the line if it is visible
+        if self.settings.list {
+            let line = &self.buffer.text[text_line];
+            let content_end = line.text.trim_end_matches([' ', '\t']).len();
+            let trailing = if content_end == line.text.len() { count } else { line.text_index_to_grapheme(content_end) };
+            row.show_whitespace(&self.settings.list_chars, trailing.saturating_sub(offset));
+            if let Some(eol) = self.settings.list_chars.eol.filter(|_| line_end_visible && row.cells.len() < width) {
+                row.cells.push(Cell { content: eol.to_string(), style: ContentStyle::new().attribute(Attribute::Dim) });
+            }
+        }
+        let tab_marker = self.settings.list_chars.tab.filter(|_| self.settings.list);
+        row.expand_tabs(self.settings.tab_width, tab_marker, start_column);
+        // Highlight trailing whitespace, except on the line being edited so it doesn't
+        // flash while typing
+        if self.settings.highlight_trailing_whitespace && text_line != self.text_position.row {
+            let line = &self.buffer.text[text_line];
+            let content_end = line.text.trim_end_matches([' ', '\t']).len();
+            if content_end < line.text.len() {
+                let first = line.text_index_to_grapheme(content_end);
+                for grapheme in first.max(offset)..line.grapheme_count {
+                    row.style_cell(grapheme - offset, ContentStyle::new().on(Color::Red));
+                }
+            }
+        }
+        // Underline misspelled words
+        if let Some(checker) = &self.spell_checker {
+            let line = &self.buffer.text[text_line];
+            for range in checker.misspelled(&line.text) {
+                let first = line.text_index_to_grapheme(range.start);
+                let last = line.text_index_to_grapheme(range.end - 1);
+                for grapheme in first.max(offset)..=last {
+                    row.add_attribute(grapheme - offset, Attribute::Underlined);
+                }
+            }
+        }
+        // Invert the selected graphemes. Lines wholly inside the selection show at least one
+        // cell, so that empty ones can be seen to be selected. A block's columns are padded
+        // out on lines too short to reach across it.
+        if let Some((rows, columns)) = self.selected_block() {
+            if rows.contains(&text_line) {
+                if line_end_visible {
+                    row.pad_to((columns.end() + 1).saturating_sub(offset).min(width));
+                }
+                for grapheme in *columns.start().max(&offset)..=*columns.end() {
+                    row.add_attribute(grapheme - offset, Attribute::Reverse);
+                }
+            }
+        } else if let Some((start, end)) = self.selection() {
+            if (start.row..=end.row).contains(&text_line) {
+                let count = self.buffer.text[text_line].grapheme_count;
+                let first = if text_line == start.row { start.grapheme } else { 0 };
+                let last = if text_line == end.row { end.grapheme } else { count };
+                if text_line != end.row && line_end_visible {
+                    row.pad_to((count + 1).saturating_sub(offset).min(width));
+                }
+                for grapheme in first.max(offset)..=last {
+                    row.add_attribute(grapheme - offset, Attribute::Reverse);
+                }
+            }
+        }
+        // Highlight text drawing attention, in reverse video
+        if let Some(found) = &self.highlight {
+            if found.row == text_line && found.end > found.start {
+                let line = &self.buffer.text[text_line];
+                let first = line.text_index_to_grapheme(found.start);
+                let last = line.text_index_to_grapheme(found.end - 1);
+                for grapheme in first.max(offset)..=last {
+                    row.add_attribute(grapheme - offset, Attribute::Reverse);
+                }
+            }
+        }
+        // Highlight the bracket under the cursor along with its match, or in a warning color
+        // when it has none
+        let cursor = &self.text_position;
+        let on_cursor_line = cursor.row == text_line && cursor.grapheme >= offset;
+        if let Some(bracket) = &self.matching_bracket {
+            let style = ContentStyle::new().black().on(Color::Cyan);
+            if bracket.row == text_line && bracket.grapheme >= offset {
+                row.style_cell(bracket.grapheme - offset, style);
+            }
+            if on_cursor_line {
+                row.style_cell(cursor.grapheme - offset, style);
+            }
+        } else if self.unmatched_bracket && on_cursor_line {
+            row.style_cell(cursor.grapheme - offset, ContentStyle::new().black().on(Color::Yellow));
+        }
+        // Draw the ruler on lines which don't reach it
+        if let Some(ruler) = self.settings.ruler {
+            let ruler_col = ruler.saturating_sub(1);
+            if self.buffer.text[text_line].grapheme_count <= ruler_col
+                && ruler_col >= offset
+                && ruler_col - offset < width {
+                let screen_col = ruler_col - offset;
+                row.pad_to(screen_col + 1);
+                row.style_cell(screen_col, ContentStyle::new().on(Color::DarkGrey));
+            }
+        }
+        row
+    }
+
+    /// Render a row past the end of the buffer, as the empty line indicator, or the end of
+    /// file marker on the row just after the last line
+    fn render_empty_line(&self, text_line: usize)->String{
+        let padding = " ".repeat(self.inner_boundary.left);
+        if self.settings.eof_marker && text_line == self.buffer.num_lines {
+            return format!("{padding}{}", "[EOF]".dark_blue());
+        }
+        format!("{padding}{}", self.settings.empty_line_char)
+    }
+
+    pub fn view_width(&self)->usize{
+        self.size.width - self.inner_boundary.left -self.inner_boundary.right
+    }
+
+    pub fn view_height(&self)->usize{
+        self.size.height - self.inner_boundary.top - self.inner_boundary.bottom
+    }
+
+}
+
+
+/// The shape of a selection between the anchor and the cursor
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SelectionKind {
+    /// Everything from one to the other, running across lines
+    Charwise,
+    /// The rectangle of columns with the anchor and cursor at opposite corners
+    Block,
+}
+
+/// The kinds of grapheme which make up separate runs when deleting by words
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum WordClass {
+    Blank,
+    Word,
+    Punctuation,
+}
+
+impl WordClass {
+    fn of(grapheme: &str)->WordClass{
+        if grapheme.chars().all(char::is_whitespace) {
+            WordClass::Blank
+        } else if WORD_REGEX.is_match(grapheme) {
+            WordClass::Word
+        } else {
+            WordClass::Punctuation
+        }
+    }
+}
+
+/// Where a find on a line stops relative to the character found
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FindKind {
+    /// On the character, as with `f`
+    To,
+    /// Next to the character, on the side the cursor came from, as with `t`
+    Till,
+}
+
+/// Direction and kind of a find on a line
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FindMotion {
+    pub forward: bool,
+    pub kind: FindKind,
+}
+
+/// A find for a grapheme on the cursor's line
+#[derive(Clone, Debug, PartialEq)]
+pub struct CharFind {
+    pub target: String,
+    pub motion: FindMotion,
+}
+
+/// Rows and column of a block insert
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockInsert {
+    pub rows: Range<usize>,
+    pub column: usize,
+}
+
+/// Represents rows/columns of padding on each of the edges
+pub struct Boundary {
+    top: usize,
+    right: usize,
+    pub(crate) left: usize,
+    bottom: usize,
+}
+
+impl Boundary {
+    pub fn default()-> Boundary{
+        Self {
+            top: 0,
+            right:0,
+            left:4, // To account for line numbers
+            bottom:2, // For status line and command entry line
+        }
+    }
+}
+
+/// Enum Representing the current mode of the editor
+#[derive(Clone, Debug, PartialEq)]
+pub enum Mode {
     Normal,
     Insert,
     Jump,
@@ -324,6 +2526,15 @@ pub enum Mode {
     Find,
     Open,
     Select,
+    Search,
+}
+
+/// How a message on the command line should be read, which sets its color
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MessageKind {
+    Info,
+    Warning,
+    Error,
 }
 
 /// Represents next action for screen to take
@@ -338,4 +2549,1715 @@ pub enum ScreenAction {
     QuitScreen,
     /// Close the editor
     QuitEditor,
-}
\ No newline at end of file
+    /// Switch to the screen at the index
+    ChangeScreen(usize),
+    /// Switch to the next screen
+    NextScreen,
+    /// Switch to the previous screen
+    PreviousScreen,
+    /// Open a new welcome screen
+    NewWelcomeScreen,
+    /// Have the editor run a command entered in command mode
+    ExecuteCommand(Command),
+}
+
+impl ScreenAction {
+    /// The action for a command entered in command mode. Switching, opening and closing
+    /// screens are actions of their own, while anything else is run by the editor.
+    pub fn for_command(command: Command)->ScreenAction{
+        match command {
+            Command::Buffer(index) => ScreenAction::ChangeScreen(index),
+            Command::BufferNext => ScreenAction::NextScreen,
+            Command::BufferPrevious => ScreenAction::PreviousScreen,
+            Command::New => ScreenAction::NewWelcomeScreen,
+            Command::QuitAll { force: false } => ScreenAction::QuitEditor,
+            command => ScreenAction::ExecuteCommand(command),
+        }
+    }
+}
+
+/// A count followed by its noun, made plural unless the count is one
+fn counted(count: usize, noun: &str)->String{
+    if count == 1 { format!("{count} {noun}") } else { format!("{count} {noun}s") }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+
+    /// Create a screen showing text, with a view 10 columns wide
+    fn test_screen(text: &str) -> Screen {
+        let mut screen = Screen::default();
+        screen.size = Size { height: 12, width: 14 };
+        screen.buffer = Buffer::from_string(text);
+        screen
+    }
+
+    #[test]
+    fn load_file_by_type() {
+        let log = std::env::temp_dir().join(format!("trout_load_file_{}.log", std::process::id()));
+        std::fs::write(&log, "started\n").unwrap();
+        let mut screen = test_screen("");
+        screen.load_file(log.clone());
+        assert!(screen.buffer.readonly);
+        assert_eq!(screen.mode, Mode::Normal);
+        std::fs::remove_file(&log).unwrap();
+
+        let mut screen = test_screen("");
+        screen.settings.new_file_mode = Mode::Insert;
+        screen.load_file(std::env::temp_dir().join(format!("trout_new_file_{}.rs", std::process::id())));
+        assert!(!screen.buffer.readonly);
+        assert_eq!(screen.mode, Mode::Insert);
+    }
+
+    #[test]
+    fn large_files_are_paged() {
+        let path = std::env::temp_dir().join(format!("trout_large_file_{}", std::process::id()));
+        let text: String = (0..5000).map(|idx| format!("line {idx}\n")).collect();
+        std::fs::write(&path, &text).unwrap();
+        let mut screen = test_screen("");
+        screen.settings.large_file_size = text.len();
+        screen.load_file(path.clone());
+        assert!(screen.buffer.text.is_paged());
+        assert_eq!(screen.buffer.num_lines, 5000);
+        Terminal::capture(|| screen.draw());
+        let near_start = screen.buffer.text.loaded_lines();
+        assert!(near_start > 0 && near_start < 5000);
+        // The start of the file is let go once the view has moved away from it
+        screen.move_last_line().unwrap();
+        Terminal::capture(|| screen.draw());
+        assert!(screen.buffer.text.loaded_lines() <= near_start);
+        assert!(screen.render_rows()[screen.view_height() - 1].ends_with("line 4999"));
+
+        // Smaller files are read whole, as before
+        screen.settings.large_file_size = text.len() + 1;
+        screen.load_file(path.clone());
+        screen.buffer.finish_loading();
+        assert!(!screen.buffer.text.is_paged());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// Benchmark for paged files, run with `cargo test --release -- --ignored --nocapture`.
+    /// Opens a 2 GiB file and scrolls through it, checking the memory used stays bounded.
+    #[test]
+    #[ignore]
+    fn scroll_multi_gigabyte_file() {
+        use std::io::{BufWriter, Write};
+        use std::time::Instant;
+        /// Resident memory of the process in bytes, where the platform reports it
+        fn resident_memory() -> Option<usize> {
+            let status = std::fs::read_to_string("/proc/self/status").ok()?;
+            let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+            let kilobytes: usize = line.split_whitespace().nth(1)?.parse().ok()?;
+            Some(kilobytes * 1024)
+        }
+
+        let path = std::env::temp_dir().join(format!("trout_gigabytes_{}", std::process::id()));
+        let mut writer = BufWriter::new(std::fs::File::create(&path).unwrap());
+        let mut lines = 0;
+        let mut size = 0;
+        while size < 2 << 30 {
+            let line = format!("line number {lines:>12} of a file far too large to read whole\n");
+            writer.write_all(line.as_bytes()).unwrap();
+            size += line.len();
+            lines += 1;
+        }
+        drop(writer);
+
+        let before = resident_memory();
+        let start = Instant::now();
+        let mut screen = test_screen("");
+        screen.size = Size { height: 50, width: 100 };
+        screen.load_file(path.clone());
+        println!("open {} GiB, {lines} lines: {:?}", size >> 30, start.elapsed());
+        assert!(screen.buffer.text.is_paged());
+        assert_eq!(screen.buffer.num_lines, lines);
+
+        let start = Instant::now();
+        for tenth in 0..10 {
+            Terminal::capture(|| screen.move_to_row_start(lines / 10 * tenth).unwrap());
+            for _ in 0..200 {
+                Terminal::capture(|| {
+                    screen.scroll_page_down().unwrap();
+                    screen.draw();
+                });
+            }
+        }
+        Terminal::capture(|| {
+            screen.move_last_line().unwrap();
+            screen.draw();
+        });
+        println!("scroll 2000 pages in 10 places: {:?}", start.elapsed());
+        assert_eq!(screen.text_position.row, lines - 1);
+        let loaded = screen.buffer.text.loaded_lines();
+        println!("lines held: {loaded}");
+        // The three screens around the view are kept, along with the rest of the chunks of
+        // 512 lines at either end of them
+        assert!(loaded <= 3 * screen.size.height + 2 * 512);
+        if let (Some(before), Some(after)) = (before, resident_memory()) {
+            let grown = after.saturating_sub(before);
+            println!("memory grown: {} MiB", grown >> 20);
+            assert!(grown < 64 << 20, "memory grew by {grown} bytes");
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn readonly_refuses_edits_and_writes() {
+        let file = std::env::temp_dir().join(format!("trout_readonly_{}", std::process::id()));
+        let mut screen = test_screen("abc");
+        screen.buffer.path = file.clone();
+        screen.set_option("ro").unwrap();
+        screen.insert_char('x').unwrap();
+        screen.insert_new_line().unwrap();
+        assert_eq!(screen.buffer.text[0].text, "abc");
+        assert_eq!(screen.buffer.num_lines, 1);
+        assert_eq!(screen.text_position, TextPosition::default());
+        assert!(screen.buffer.edit_refused);
+        assert!(screen.write_file(false).is_err());
+        assert!(!file.exists());
+        screen.write_file(true).unwrap();
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "abc\n");
+        screen.set_option("noreadonly").unwrap();
+        screen.insert_char('x').unwrap();
+        assert_eq!(screen.buffer.text[0].text, "xabc");
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn write_unnamed_buffer_to_path() {
+        let dir = std::env::temp_dir();
+        let named = dir.join(format!("trout_write_as_{}.txt", std::process::id()));
+        let copy = dir.join(format!("trout_write_copy_{}", std::process::id()));
+        let mut screen = test_screen("piped");
+        screen.insert_char('x').unwrap();
+        assert!(screen.write_file(false).is_err());
+        screen.write_file_as(named.clone(), false).unwrap();
+        assert_eq!(screen.buffer.path, named);
+        assert_eq!(screen.buffer.extension.as_deref(), Some("txt"));
+        assert!(!screen.buffer.modified);
+        assert_eq!(std::fs::read_to_string(&named).unwrap(), "xpiped\n");
+        // A named buffer is copied, keeping its own file
+        screen.insert_char('y').unwrap();
+        screen.write_file_as(copy.clone(), false).unwrap();
+        assert_eq!(screen.buffer.path, named);
+        assert!(screen.buffer.modified);
+        assert_eq!(std::fs::read_to_string(&copy).unwrap(), "xypiped\n");
+        std::fs::remove_file(&named).unwrap();
+        std::fs::remove_file(&copy).unwrap();
+    }
+
+    #[test]
+    fn trim_trailing_whitespace_on_write() {
+        let file = std::env::temp_dir().join(format!("trout_trim_{}", std::process::id()));
+        let mut screen = test_screen("abc   \n a b\t");
+        screen.buffer.path = file.clone();
+        screen.text_position = TextPosition { row: 0, byte: 5, grapheme: 5 };
+        screen.write_file(false).unwrap();
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "abc   \n a b\t\n");
+        screen.settings.trim_trailing_whitespace = true;
+        screen.write_file(false).unwrap();
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "abc\n a b\n");
+        assert_eq!(screen.buffer.text[0].text, "abc");
+        assert_eq!(screen.text_position.grapheme, 2);
+        assert!(!screen.buffer.modified);
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn visual_down_within_wrapped_line() {
+        let mut screen = test_screen("abcdefghijklmnopqrstuvwxy\nshort");
+        screen.settings.wrap = true;
+        screen.text_position.grapheme = 3;
+        screen.move_down().unwrap();
+        assert_eq!(screen.text_position.row, 0);
+        assert_eq!(screen.text_position.grapheme, 13);
+        screen.move_down().unwrap();
+        assert_eq!(screen.text_position.row, 0);
+        assert_eq!(screen.text_position.grapheme, 23);
+        screen.move_down().unwrap();
+        assert_eq!(screen.text_position.row, 1);
+        assert_eq!(screen.text_position.grapheme, 3);
+        screen.move_up().unwrap();
+        assert_eq!(screen.text_position.row, 0);
+        assert_eq!(screen.text_position.grapheme, 23);
+    }
+
+    #[test]
+    fn visual_row_start_and_end() {
+        let mut screen = test_screen("abcdefghijklmnopqrstuvwxy");
+        screen.settings.wrap = true;
+        screen.text_position.grapheme = 13;
+        screen.move_start_visual_row().unwrap();
+        assert_eq!(screen.text_position.grapheme, 10);
+        screen.move_end_visual_row().unwrap();
+        assert_eq!(screen.text_position.grapheme, 19);
+    }
+
+    #[test]
+    fn overlong_line_is_not_wrapped() {
+        let mut screen = test_screen(&"a".repeat(100_000));
+        screen.settings.wrap = true;
+        assert!(screen.is_overlong(0));
+        assert_eq!(screen.visual_rows(0), vec![0..100_000]);
+        let width = screen.view_width();
+        let printed = screen.buffer.print_line(0, 0, width, false);
+        assert!(printed.len() <= width + 1);
+    }
+
+    #[test]
+    fn overlong_line_is_not_styled() {
+        let mut screen = test_screen(&format!("{}  \nshort  ", "a".repeat(100_000)));
+        screen.text_position.row = 1;
+        screen.settings.list = true;
+        screen.settings.highlight_trailing_whitespace = true;
+        let row = screen.render_segment(0, 99_995..100_002);
+        assert_eq!(row.render(), "aaaaa  ");
+        assert!(row.cells.iter().all(|cell| cell.style == ContentStyle::new()));
+        // Lines under the threshold are still styled
+        screen.text_position.row = 0;
+        let row = screen.render_segment(1, 0..7);
+        assert!(row.cells.iter().any(|cell| cell.style != ContentStyle::new()));
+    }
+
+    #[test]
+    fn wrap_breaks_at_words() {
+        let mut screen = test_screen("the quick brown fox\nabcdefghijklmno pq");
+        screen.settings.wrap = true;
+        assert_eq!(screen.visual_rows(0), vec![0..10, 10..19]);
+        // A word wider than the view is broken wherever it reaches the edge
+        assert_eq!(screen.visual_rows(1), vec![0..10, 10..18]);
+        screen.update_gutter_width();
+        let rows = screen.render_rows();
+        assert!(rows[0].ends_with("the quick "));
+        assert_eq!(rows[1], "    brown fox");
+        assert!(rows[2].ends_with("abcdefghij"));
+        assert_eq!(rows[3], "    klmno pq");
+        // The stored text is untouched
+        assert_eq!(screen.buffer.text[0].text, "the quick brown fox");
+
+        screen.text_position = TextPosition { row: 1, byte: 12, grapheme: 12 };
+        screen.scroll_into_view().unwrap();
+        assert_eq!((screen.screen_location.row, screen.screen_location.col), (3, 6));
+    }
+
+    #[test]
+    fn wrap_scrolls_by_visual_rows() {
+        let lines = vec!["aaaa bbbb cccc"; 8];
+        let mut screen = test_screen(&lines.join("\n"));
+        screen.settings.wrap = true;
+        screen.text_position.row = 5;
+        screen.scroll_into_view().unwrap();
+        // Every line takes two rows, so the sixth line's rows only fit starting from the second
+        assert_eq!(screen.scroll_offset.row, 1);
+        assert_eq!(screen.screen_location.row, 8);
+        screen.text_position.grapheme = 12;
+        screen.scroll_into_view().unwrap();
+        assert_eq!(screen.scroll_offset.row, 1);
+        assert_eq!(screen.screen_location.row, 9);
+    }
+
+    #[test]
+    fn typed_brackets_are_paired() {
+        let mut screen = test_screen("");
+        screen.settings.set("auto_pairs").unwrap();
+        screen.set_mode(Mode::Insert);
+        for c in "f(a, [b".chars() {
+            screen.type_char(c).unwrap();
+        }
+        assert_eq!(screen.buffer.text[0].text, "f(a, [b])");
+        assert_eq!(screen.text_position.grapheme, 7);
+        // Typing the closing characters steps over the ones already there
+        for c in "]);".chars() {
+            screen.type_char(c).unwrap();
+        }
+        assert_eq!(screen.buffer.text[0].text, "f(a, [b]);");
+        assert_eq!(screen.text_position.grapheme, 10);
+        // Quotes pair, except after a word where they are probably apostrophes
+        for c in " \"it's".chars() {
+            screen.type_char(c).unwrap();
+        }
+        screen.type_char('"').unwrap();
+        assert_eq!(screen.buffer.text[0].text, "f(a, [b]); \"it's\"");
+        assert_eq!(screen.text_position.grapheme, 17);
+
+        // Pairing is off by default
+        let mut screen = test_screen("");
+        screen.set_mode(Mode::Insert);
+        for c in "(x))".chars() {
+            screen.type_char(c).unwrap();
+        }
+        assert_eq!(screen.buffer.text[0].text, "(x))");
+    }
+
+    #[test]
+    fn insert_char_advances_cursor() {
+        let mut screen = test_screen("xyz");
+        for c in "a€c".chars() {
+            screen.insert_char(c).unwrap();
+        }
+        assert_eq!(screen.buffer.text[0].text, "a€cxyz");
+        assert_eq!(screen.text_position.grapheme, 3);
+        assert_eq!(screen.text_position.byte, 5);
+        assert!(screen.buffer.modified);
+
+        let mut screen = test_screen("");
+        screen.insert_char('a').unwrap();
+        screen.insert_char('b').unwrap();
+        assert_eq!(screen.buffer.num_lines, 1);
+        assert_eq!(screen.buffer.text[0].text, "ab");
+        assert_eq!(screen.text_position.grapheme, 2);
+    }
+
+    #[test]
+    fn backspace_mid_line() {
+        let mut screen = test_screen("abcdef\nghi");
+        screen.text_position.grapheme = 3;
+        screen.backspace().unwrap();
+        assert_eq!(screen.buffer.text[0].text, "abdef");
+        assert_eq!(screen.text_position.grapheme, 2);
+        assert_eq!(screen.text_position.byte, 2);
+        assert_eq!(screen.buffer.num_lines, 2);
+    }
+
+    #[test]
+    fn backspace_joins_lines() {
+        let mut screen = test_screen("ab€\nghi");
+        screen.text_position.row = 1;
+        screen.backspace().unwrap();
+        assert_eq!(screen.buffer.num_lines, 1);
+        assert_eq!(screen.buffer.text[0].text, "ab€ghi");
+        assert_eq!(screen.text_position.row, 0);
+        assert_eq!(screen.text_position.grapheme, 3);
+        assert_eq!(screen.text_position.byte, 5);
+        // Backspace at the very start of the buffer does nothing
+        screen.text_position.grapheme = 0;
+        screen.text_position.byte = 0;
+        screen.backspace().unwrap();
+        assert_eq!(screen.buffer.text[0].text, "ab€ghi");
+    }
+
+    #[test]
+    fn delete_forward_joins_next_line() {
+        let mut screen = test_screen("abc\ndef");
+        screen.text_position.grapheme = 3;
+        screen.text_position.byte = 3;
+        screen.delete_forward().unwrap();
+        assert_eq!(screen.buffer.num_lines, 1);
+        assert_eq!(screen.buffer.text[0].text, "abcdef");
+        assert_eq!(screen.text_position.grapheme, 3);
+        screen.delete_forward().unwrap();
+        assert_eq!(screen.buffer.text[0].text, "abcef");
+    }
+
+    #[test]
+    fn delete_forward_on_last_line() {
+        let mut screen = test_screen("abc\ndef");
+        screen.text_position.row = 1;
+        screen.text_position.grapheme = 3;
+        screen.text_position.byte = 3;
+        screen.delete_forward().unwrap();
+        assert_eq!(screen.buffer.num_lines, 2);
+        assert_eq!(screen.buffer.text[1].text, "def");
+    }
+
+    #[test]
+    fn ruler_drawn_at_column() {
+        let mut screen = test_screen("abc\nabcdefghijkl");
+        screen.settings.ruler = Some(8);
+        let ruler_cell = format!("{}", " ".on(Color::DarkGrey));
+        assert_eq!(screen.render_line(0), format!("abc    {ruler_cell}"));
+        // Lines reaching the ruler don't show it
+        assert!(!screen.render_line(1).contains(&ruler_cell));
+        // The ruler moves with the horizontal scroll
+        screen.scroll_offset.col = 1;
+        assert_eq!(screen.render_line(0), format!("{}c    {ruler_cell}", "<".dim()));
+    }
+
+    #[test]
+    fn overflow_markers_on_clipped_lines() {
+        let mut screen = test_screen("abcdefghijklmnopqrst\nshort");
+        screen.update_gutter_width();
+        assert_eq!(screen.render_line(0), format!("abcdefghi{}", ">".dim()));
+        assert_eq!(screen.render_line(1), "short");
+        screen.scroll_offset.col = 2;
+        assert_eq!(screen.render_line(0), format!("{}defghijk{}", "<".dim(), ">".dim()));
+        assert_eq!(screen.render_line(1), format!("{}rt", "<".dim()));
+        screen.scroll_offset.col = 10;
+        assert_eq!(screen.render_line(0), format!("{}lmnopqrst", "<".dim()));
+        // The markers cover text without moving the cursor
+        screen.text_position = TextPosition { row: 0, byte: 12, grapheme: 12 };
+        screen.scroll_into_view().unwrap();
+        assert_eq!(screen.screen_location.col - screen.inner_boundary.left, 2);
+        screen.settings.overflow_left_char = '«';
+        assert!(screen.render_line(0).contains(&format!("{}", "«".dim())));
+    }
+
+    #[test]
+    fn wide_graphemes_take_two_cells() {
+        let mut screen = test_screen("日本語のテキストです\nabc");
+        screen.move_end_line().unwrap();
+        screen.move_start_line().unwrap();
+        screen.move_right().unwrap();
+        screen.move_right().unwrap();
+        assert_eq!(screen.screen_location.col - screen.inner_boundary.left, 4);
+        // Only as many wide graphemes as fit in the view are drawn
+        let width = screen.view_width();
+        let rendered = screen.render_line(0);
+        let shown: String = "日本語のテキストです".chars().take(width / 2 - 1).collect();
+        assert_eq!(rendered, format!("{shown}{}", " >".dim()));
+    }
+
+    #[test]
+    fn list_mode_shows_whitespace() {
+        let mut screen = test_screen("  a\t\n a b  ");
+        assert_eq!(screen.render_line(0), "  a ");
+        screen.settings.list = true;
+        assert_eq!(screen.render_line(0), format!("  a{}", "→¶".dim()));
+        // Only the spaces at the end of the line are marked, unless spaces are given a glyph
+        assert_eq!(screen.render_line(1), format!(" a b{}", "··¶".dim()));
+        screen.settings.set("listchars=space:_,trail:~").unwrap();
+        assert_eq!(screen.render_line(1), format!("{}a{}b{}", "_".dim(), "_".dim(), "~~".dim()));
+        assert_eq!(screen.render_line(0), format!("{}a ", "__".dim()));
+        // The stored text is left alone
+        assert_eq!(screen.buffer.text[1].text, " a b  ");
+    }
+
+    #[test]
+    fn tabs_expand_to_tab_width() {
+        let mut screen = test_screen("\tab\tc");
+        screen.settings.tab_width = 3;
+        assert_eq!(screen.render_line(0), "   ab c");
+        screen.settings.list = true;
+        screen.settings.set("listchars=tab:>").unwrap();
+        assert_eq!(screen.render_line(0), format!("{}ab{}c", ">  ".dim(), ">".dim()));
+        // The cursor is placed after the expanded tabs, the second only reaching the next tab stop
+        screen.text_position = TextPosition { row: 0, grapheme: 4, byte: 4 };
+        assert_eq!(screen.location_of(&screen.text_position).col, 6 + screen.inner_boundary.left);
+    }
+
+    #[test]
+    fn matching_bracket_highlighted() {
+        let mut screen = test_screen("f(a, b)");
+        screen.text_position.grapheme = 1;
+        screen.update_matching_bracket();
+        let found = screen.matching_bracket.clone().unwrap();
+        assert_eq!((found.row, found.grapheme), (0, 6));
+        // Both the bracket under the cursor and its match are highlighted
+        let open = format!("{}", "(".black().on(Color::Cyan));
+        let close = format!("{}", ")".black().on(Color::Cyan));
+        assert_eq!(screen.render_line(0), format!("f{open}a, b{close}"));
+        // Moving off the bracket clears the highlight
+        screen.text_position.grapheme = 2;
+        screen.update_matching_bracket();
+        assert!(screen.matching_bracket.is_none());
+        assert_eq!(screen.render_line(0), "f(a, b)");
+    }
+
+    #[test]
+    fn unmatched_bracket_warned() {
+        let mut screen = test_screen("f(a, b");
+        screen.text_position.grapheme = 1;
+        screen.update_matching_bracket();
+        assert!(!screen.unmatched_bracket);
+        assert_eq!(screen.render_line(0), "f(a, b");
+        screen.settings.warn_unmatched_brackets = true;
+        screen.update_matching_bracket();
+        assert!(screen.unmatched_bracket);
+        assert_eq!(screen.render_line(0), format!("f{}a, b", "(".black().on(Color::Yellow)));
+        screen.text_position.grapheme = 2;
+        screen.update_matching_bracket();
+        assert!(!screen.unmatched_bracket);
+    }
+
+    #[test]
+    fn misspelled_words_underlined() {
+        let dir = std::env::temp_dir().join(format!("trout_screen_spell_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dict_path = dir.join("words");
+        std::fs::write(&dict_path, "the\nquick\nfox\n").unwrap();
+        let mut screen = test_screen("the quikc fox");
+        screen.size.width = 40;
+        screen.settings.spell_dictionary = dict_path;
+        screen.enable_spell_check().unwrap();
+        let underlined = format!("{}", "quikc".underlined());
+        assert_eq!(screen.render_line(0), format!("the {underlined} fox"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn movement_skips_folded_lines() {
+        let mut screen = test_screen("fn a() {\n    b;\n    c;\n}\nd");
+        screen.size.width = 40;
+        screen.text_position.row = 1;
+        assert!(screen.fold_at(0).unwrap());
+        assert_eq!(screen.text_position.row, 0);
+        assert!(!screen.fold_at(3).unwrap());
+        screen.move_down().unwrap();
+        assert_eq!(screen.text_position.row, 3);
+        assert_eq!(screen.screen_location.row, 1);
+        screen.move_up().unwrap();
+        assert_eq!(screen.text_position.row, 0);
+        assert!(screen.render_fold_summary(0..3).contains("+-- 3 lines: fn a() {"));
+        assert!(screen.unfold_at(0).unwrap());
+        screen.move_down().unwrap();
+        assert_eq!(screen.text_position.row, 1);
+    }
+
+    #[test]
+    fn folds_and_marks_follow_edits_above() {
+        let mut screen = test_screen("top\nfn a() {\n    b;\n    c;\n}\nd");
+        assert!(screen.fold_at(1).unwrap());
+        screen.text_position = TextPosition { row: 5, grapheme: 0, byte: 0 };
+        screen.set_mark('a');
+        screen.buffer.insert_line(0, Line::from_string(""));
+        screen.buffer.insert_line(0, Line::from_string(""));
+        screen.draw();
+        assert_eq!(screen.folds.fold_containing(3), Some(&(3..6)));
+        assert!(screen.jump_to_mark('a').unwrap());
+        assert_eq!(screen.text_position.row, 7);
+        // Deleting lines inside the fold shrinks it
+        screen.buffer.delete_line(4);
+        screen.draw();
+        assert_eq!(screen.folds.fold_containing(3), Some(&(3..5)));
+        assert_eq!(screen.marks[&'a'].row, 6);
+    }
+
+    #[test]
+    fn gutter_width_fits_line_count() {
+        for (lines, width) in [(5, 4), (150, 4), (12000, 6)] {
+            let mut screen = test_screen(&"a\n".repeat(lines));
+            screen.size.width = 80;
+            screen.update_gutter_width();
+            assert_eq!(screen.inner_boundary.left, width);
+            assert_eq!(screen.view_width(), 80 - width);
+        }
+        let mut screen = test_screen("a\nb\nc");
+        screen.settings.gutter_min_width = 1;
+        screen.update_gutter_width();
+        assert_eq!(screen.inner_boundary.left, 2);
+        assert_eq!(screen.render_gutter(2), format!("{}", "3 ".dark_grey()));
+    }
+
+    #[test]
+    fn goto_definition_in_rust() {
+        let mut screen = test_screen("fn main() {\n    helper(1);\n}\n\npub fn helper(x: u8) {}");
+        screen.buffer.extension = Some("rs".to_string());
+        screen.text_position.row = 1;
+        screen.text_position.grapheme = 6;
+        assert!(screen.goto_definition().unwrap());
+        assert_eq!(screen.text_position.row, 4);
+        assert_eq!(screen.text_position.grapheme, 7);
+        assert_eq!(screen.jump_list.len(), 1);
+        assert_eq!(screen.jump_list[0].row, 1);
+        // Words without a definition leave the cursor alone
+        screen.text_position.grapheme = 14;
+        assert!(!screen.goto_definition().unwrap());
+        assert_eq!(screen.text_position.row, 4);
+    }
+
+    #[test]
+    fn paragraph_movement() {
+        let mut screen = test_screen("one\ntwo\n\nthree\n\n\nfour\nfive");
+        screen.text_position.grapheme = 2;
+        screen.move_next_paragraph().unwrap();
+        assert_eq!((screen.text_position.row, screen.text_position.grapheme), (2, 0));
+        screen.move_next_paragraph().unwrap();
+        assert_eq!(screen.text_position.row, 4);
+        // Consecutive blank lines are skipped over
+        screen.move_next_paragraph().unwrap();
+        assert_eq!(screen.text_position.row, 7);
+        screen.move_next_paragraph().unwrap();
+        assert_eq!(screen.text_position.row, 7);
+        screen.move_prev_paragraph().unwrap();
+        assert_eq!(screen.text_position.row, 5);
+        screen.move_prev_paragraph().unwrap();
+        assert_eq!(screen.text_position.row, 2);
+        screen.move_prev_paragraph().unwrap();
+        assert_eq!(screen.text_position.row, 0);
+    }
+
+    #[test]
+    fn paragraph_break_on_whitespace_lines() {
+        let mut screen = test_screen("one\ntwo\n  \nthree\nfour");
+        screen.move_next_paragraph().unwrap();
+        assert_eq!(screen.text_position.row, 2);
+        // Unless only truly empty lines count as blank
+        screen.settings.whitespace_is_blank = false;
+        screen.move_to_row_start(0).unwrap();
+        screen.move_next_paragraph().unwrap();
+        assert_eq!(screen.text_position.row, 4);
+        screen.move_prev_paragraph().unwrap();
+        assert_eq!(screen.text_position.row, 0);
+    }
+
+    #[test]
+    fn search_count_follows_edits() {
+        let mut screen = test_screen("foo bar
+baz foo");
+        screen.start_search("foo").unwrap();
+        assert_eq!((screen.text_position.row, screen.text_position.grapheme), (1, 4));
+        assert_eq!(screen.search_indicator(), Some("match 2 of 2".to_string()));
+        // Type a new match in front of the cursor
+        screen.text_position.grapheme = 0;
+        screen.text_position.byte = 0;
+        for c in "foo".chars() {
+            screen.insert_char(c).unwrap();
+        }
+        assert_eq!(screen.search_indicator(), Some("match 2 of 3".to_string()));
+        screen.search_next().unwrap();
+        assert_eq!(screen.search_indicator(), Some("match 3 of 3".to_string()));
+    }
+
+    #[test]
+    fn search_offsets() {
+        let mut screen = test_screen("a foo\nb foobar");
+        screen.start_search("foo/e").unwrap();
+        assert_eq!((screen.text_position.row, screen.text_position.grapheme), (0, 4));
+        // Repeating continues past the current match
+        screen.search_next().unwrap();
+        assert_eq!((screen.text_position.row, screen.text_position.grapheme), (1, 4));
+        assert_eq!(screen.search_indicator(), Some("match 2 of 2".to_string()));
+        // Going back steps to the previous match, rather than finding the current one again
+        screen.search_prev().unwrap();
+        assert_eq!((screen.text_position.row, screen.text_position.grapheme), (0, 4));
+
+        screen.text_position = TextPosition::default();
+        screen.start_search("foo/b+2").unwrap();
+        assert_eq!((screen.text_position.row, screen.text_position.grapheme), (0, 4));
+        screen.search_next().unwrap();
+        assert_eq!((screen.text_position.row, screen.text_position.grapheme), (1, 4));
+        screen.search_prev().unwrap();
+        assert_eq!((screen.text_position.row, screen.text_position.grapheme), (0, 4));
+    }
+
+    #[test]
+    fn search_from_prompt() {
+        let mut screen = test_screen("Foo bar\nbaz foo\nfoo");
+        screen.size.width = 60;
+        screen.settings.ignore_case = true;
+        let keys = "/fox\u{8}o/e";
+        let mut events: Vec<Event> = keys.chars().map(|c| match c {
+            '\u{8}' => key(KeyCode::Backspace),
+            c => key(KeyCode::Char(c)),
+        }).collect();
+        events.push(key(KeyCode::Enter));
+        events.push(key(KeyCode::Char('q')));
+        let output = Terminal::capture(|| { Terminal::script_input(events, || screen.run()); });
+        // The cursor starts on a match, so the search skips ahead to the end of the next one
+        assert_eq!((screen.text_position.row, screen.text_position.grapheme), (1, 6));
+        assert!(output.contains("/foo/e"));
+        assert!(output.contains("match 2 of 3"));
+
+        let mut screen = test_screen("one");
+        screen.size.width = 60;
+        let events = vec![key(KeyCode::Char('/')), key(KeyCode::Char('x')), key(KeyCode::Enter),
+                          key(KeyCode::Char('q'))];
+        let output = Terminal::capture(|| { Terminal::script_input(events, || screen.run()); });
+        assert!(output.contains("Pattern not found"));
+        assert_eq!(screen.mode, Mode::Normal);
+    }
+
+    #[test]
+    fn cursor_limit_depends_on_mode() {
+        let mut screen = test_screen("abc\nde");
+        screen.move_end_line().unwrap();
+        assert_eq!(screen.text_position.grapheme, 2);
+        screen.move_right().unwrap();
+        assert_eq!(screen.text_position.grapheme, 2);
+        // Insert mode can move one past the end to append
+        screen.set_mode(Mode::Insert);
+        assert_eq!(screen.max_cursor_grapheme(), 3);
+        screen.move_right().unwrap();
+        assert_eq!((screen.text_position.grapheme, screen.text_position.byte), (3, 3));
+        screen.insert_char('d').unwrap();
+        assert_eq!(screen.buffer.text[0].text, "abcd");
+        screen.move_down().unwrap();
+        assert_eq!(screen.text_position.grapheme, 2);
+        // Leaving insert mode pulls the cursor back onto the last grapheme
+        screen.set_mode(Mode::Normal);
+        assert_eq!((screen.text_position.grapheme, screen.text_position.byte), (1, 1));
+    }
+
+    #[test]
+    fn movement_in_empty_buffer() {
+        let mut screen = test_screen("");
+        screen.buffer = Buffer::empty();
+        screen.move_right().unwrap();
+        screen.move_down().unwrap();
+        screen.move_left().unwrap();
+        screen.move_up().unwrap();
+        screen.move_end_line().unwrap();
+        screen.move_start_line().unwrap();
+        screen.move_first_line().unwrap();
+        screen.move_last_line().unwrap();
+        screen.move_next_word().unwrap();
+        screen.move_prev_word().unwrap();
+        screen.move_next_paragraph().unwrap();
+        screen.move_prev_paragraph().unwrap();
+        screen.move_start_visual_row().unwrap();
+        screen.move_end_visual_row().unwrap();
+        screen.delete_grapheme(screen.text_position.clone());
+        screen.backspace().unwrap();
+        screen.delete_forward().unwrap();
+        screen.increment_number(1).unwrap();
+        assert!(!screen.goto_definition().unwrap());
+        assert!(!screen.fold_at(0).unwrap());
+        screen.settings.wrap = true;
+        screen.move_down().unwrap();
+        screen.move_up().unwrap();
+        assert_eq!(screen.text_position, TextPosition::default());
+        assert_eq!(screen.buffer.num_lines, 0);
+        screen.render_rows();
+    }
+
+    #[test]
+    fn status_line_shows_scroll_position() {
+        let mut screen = test_screen("one\ntwo");
+        screen.size.width = 30;
+        assert_eq!(screen.render_status_line(), "[No Name]            1,1   All");
+        let lines: Vec<String> = (0..50).map(|n| n.to_string()).collect();
+        screen.buffer = Buffer::from_string(&lines.join("\n"));
+        screen.buffer.path = PathBuf::from("file.txt");
+        screen.insert_char('x').unwrap();
+        assert_eq!(screen.render_status_line(), "file.txt [+]         1,2   Top");
+        screen.text_position.row = 49;
+        screen.scroll_offset.row = 40;
+        assert!(screen.render_status_line().ends_with("50,2   Bot"));
+    }
+
+    #[test]
+    fn message_shown_until_key_press() {
+        let mut screen = test_screen("one");
+        assert!(screen.render_message().is_none());
+        screen.set_message("Written", MessageKind::Info);
+        assert_eq!(screen.render_message(), Some("Written".to_string()));
+        screen.set_message("Pattern not found", MessageKind::Warning);
+        assert_eq!(screen.render_message(), Some(format!("{}", "Pattern not fo".yellow())));
+        screen.set_message("Failed", MessageKind::Error);
+        assert_eq!(screen.render_message(), Some(format!("{}", "Failed".red())));
+        screen.key_pressed();
+        assert!(screen.message.is_none());
+        assert!(screen.render_message().is_none());
+    }
+
+    #[test]
+    fn count_selection() {
+        let mut screen = test_screen("one two three\nfour  five\nsix");
+        screen.selection_anchor = Some(TextPosition { row: 0, grapheme: 4, byte: 4 });
+        screen.text_position = TextPosition { row: 0, grapheme: 10, byte: 10 };
+        assert_eq!(screen.selection_counts(), (1, 2, 7));
+        // Selected backwards, across lines
+        screen.text_position = TextPosition { row: 1, grapheme: 5, byte: 5 };
+        screen.selection_anchor = Some(TextPosition { row: 0, grapheme: 8, byte: 8 });
+        screen.show_selection_counts();
+        assert_eq!(screen.selection_counts(), (2, 2, 12));
+        assert_eq!(screen.message, Some(("Selected 2 lines, 2 words, 12 characters".to_string(), MessageKind::Info)));
+        screen.selection_anchor = None;
+        assert_eq!(screen.selection_counts(), (3, 6, 28));
+        // Words are counted as the buffer's stats count them, not split on whitespace
+        let mut screen = test_screen("one,two (x+y)");
+        assert_eq!(screen.selection_counts().1, 3);
+        assert_eq!(screen.buffer.stats(&WORD_REGEX).words, 3);
+    }
+
+    #[test]
+    fn select_text_objects() {
+        let mut screen = test_screen("call(bar_baz, x)");
+        screen.text_position = TextPosition { row: 0, grapheme: 6, byte: 6 };
+        let events = vec![key(KeyCode::Char('h')), key(KeyCode::Char('i')), key(KeyCode::Char('w')),
+                          key(KeyCode::Char('y')), key(KeyCode::Char('q'))];
+        Terminal::capture(|| { Terminal::script_input(events, || screen.run()); });
+        assert_eq!(screen.registers.borrow().get(None), Some(&Register::charwise("bar_baz")));
+        assert_eq!(screen.text_position.grapheme, 5);
+
+        // The inside of parentheses spanning lines, found from within a nested pair
+        let mut screen = test_screen("f(a, (b),\n  c)\nend");
+        screen.text_position = TextPosition { row: 1, grapheme: 2, byte: 2 };
+        let events = vec![key(KeyCode::Char('h')), key(KeyCode::Char('i')), key(KeyCode::Char('(')),
+                          key(KeyCode::Char('k')), key(KeyCode::Char('q')), key(KeyCode::Char('n'))];
+        Terminal::capture(|| { Terminal::script_input(events, || screen.run()); });
+        assert_eq!(screen.buffer.text[0].text, "f()");
+        assert_eq!(screen.buffer.text[1].text, "end");
+    }
+
+    #[test]
+    fn count_selection_in_select_mode() {
+        let mut screen = test_screen("one two three");
+        screen.size.width = 60;
+        let events = vec![key(KeyCode::Char('h')), key(KeyCode::Char('d')), key(KeyCode::Char('d')),
+                          key(KeyCode::Char('d')), key(KeyCode::Char('d')),
+                          Event::Key(KeyEvent::new(KeyCode::Char('g'), KeyModifiers::CONTROL)),
+                          key(KeyCode::Esc), key(KeyCode::Char('q'))];
+        let output = Terminal::capture(|| { Terminal::script_input(events, || screen.run()); });
+        assert!(output.contains("Selected 1 line, 2 words, 5 characters"));
+    }
+
+    #[test]
+    fn confirm_substitute_answers() {
+        let mut screen = test_screen("a cat, a cat\ncat\nthe cat");
+        let pattern = Regex::new("cat").unwrap();
+        let mut answers = "nyq".chars();
+        let mut highlighted = Vec::new();
+        let replaced = screen.confirm_substitute(0..3, &pattern, "kitten", true, |screen| {
+            highlighted.push(screen.highlight.clone().unwrap());
+            answers.next()
+        });
+        assert_eq!(replaced, 1);
+        assert_eq!(screen.buffer.text[0].text, "a cat, a kitten");
+        assert_eq!(screen.buffer.text[1].text, "cat");
+        assert_eq!(highlighted, [SearchMatch { row: 0, start: 2, end: 5 }, SearchMatch { row: 0, start: 9, end: 12 },
+                                 SearchMatch { row: 1, start: 0, end: 3 }]);
+        assert!(screen.highlight.is_none());
+
+        // Replacing with text containing the pattern doesn't match it again, and `a` replaces
+        // every remaining match without asking
+        let mut answers = "xya".chars();
+        let mut asked = 0;
+        let replaced = screen.confirm_substitute(0..3, &pattern, "cats", true, |_| {
+            asked += 1;
+            answers.next()
+        });
+        assert_eq!((replaced, asked), (3, 3));
+        let text: Vec<&str> = screen.buffer.text.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(text, ["a cats, a kitten", "cats", "the cats"]);
+
+        // `a` keeps replacing to the last match, however many follow it
+        let mut screen = test_screen("cat cat cat cat");
+        let mut asked = 0;
+        let replaced = screen.confirm_substitute(0..1, &pattern, "dog", true, |_| {
+            asked += 1;
+            Some('a')
+        });
+        assert_eq!((replaced, asked), (4, 1));
+        assert_eq!(screen.buffer.text[0].text, "dog dog dog dog");
+    }
+
+    #[test]
+    fn confirm_quit_of_modified_buffer() {
+        let mut screen = test_screen("one");
+        assert!(matches!(screen.confirm_quit(|_| panic!("nothing to ask")), ScreenAction::QuitScreen));
+        screen.insert_char('x').unwrap();
+        // Esc cancels, keeping the changes
+        let action = screen.confirm_quit(|screen| {
+            assert!(screen.message.is_some());
+            None
+        });
+        assert!(matches!(action, ScreenAction::EnterMode(Mode::Normal)));
+        assert!(screen.buffer.modified);
+        // Other keys ask again, until the changes are discarded
+        let mut answers = "xn".chars();
+        assert!(matches!(screen.confirm_quit(|_| answers.next()), ScreenAction::QuitScreen));
+        assert_eq!(answers.next(), None);
+        assert!(screen.buffer.modified);
+        assert_eq!(screen.message, None);
+
+        // Saving writes the file before quitting
+        let path = std::env::temp_dir().join(format!("trout_confirm_quit_{}", std::process::id()));
+        screen.buffer.path = path.clone();
+        assert!(matches!(screen.confirm_quit(|_| Some('y')), ScreenAction::QuitScreen));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "xone\n");
+        assert!(!screen.buffer.modified);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// A key press event, as read from the terminal
+    fn key(code: KeyCode) -> Event {
+        Event::Key(KeyEvent::from(code))
+    }
+
+    #[test]
+    fn run_until_quit() {
+        let mut screen = test_screen("one\ntwo");
+        let events = vec![key(KeyCode::Char('s')), key(KeyCode::Char('i')), key(KeyCode::Char('x')),
+                          key(KeyCode::Esc), key(KeyCode::Char('q')), key(KeyCode::Char('n'))];
+        let mut action = None;
+        Terminal::capture(|| action = Some(Terminal::script_input(events, || screen.run())));
+        assert!(matches!(action, Some(EditorAction::QuitScreen)));
+        assert!(screen.quit_screen);
+        assert_eq!(screen.buffer.text[1].text, "xtwo");
+        assert_eq!(screen.mode, Mode::Normal);
+    }
+
+    #[test]
+    fn run_returns_commands() {
+        let mut screen = test_screen("one");
+        let events = vec![key(KeyCode::Char('c')), key(KeyCode::Char('w')), key(KeyCode::Enter)];
+        let mut action = None;
+        Terminal::capture(|| action = Some(Terminal::script_input(events, || screen.run())));
+        assert!(matches!(action, Some(EditorAction::ExecuteCommand(Command::Write { path: None, force: false }))));
+        assert_eq!(screen.mode, Mode::Normal);
+    }
+
+    #[test]
+    fn run_uses_configured_bindings() {
+        let mut screen = test_screen("one\ntwo\nthree");
+        screen.key_map = Rc::new(KeyMap::from_toml("[normal]\n\"j\" = \"MoveDown\"\n\"x\" = \"MoveRight\"\n\"i\" = \"Cancel\"\n").unwrap());
+        // `x` moves instead of deleting and `i` no longer enters insert mode
+        let events = vec![key(KeyCode::Char('j')), key(KeyCode::Char('j')), key(KeyCode::Char('x')),
+                          key(KeyCode::Char('i')), key(KeyCode::Char('w')), key(KeyCode::Char('q'))];
+        Terminal::capture(|| { Terminal::script_input(events, || screen.run()); });
+        assert!(screen.quit_screen);
+        assert!(!screen.buffer.modified);
+        assert_eq!((screen.text_position.row, screen.text_position.grapheme), (1, 1));
+    }
+
+    #[test]
+    fn marks_and_jumps() {
+        let mut screen = test_screen("first line\nsecond line\nthird line\nlast");
+        screen.text_position = TextPosition { row: 2, grapheme: 6, byte: 6 };
+        screen.set_mark('a');
+        screen.move_first_line().unwrap();
+        assert!(screen.jump_to_mark('a').unwrap());
+        assert_eq!((screen.text_position.row, screen.text_position.grapheme), (2, 6));
+        assert!(!screen.jump_to_mark('b').unwrap());
+        // Back through the jump to the mark, then the jump to the first line
+        assert!(screen.jump_back().unwrap());
+        assert_eq!((screen.text_position.row, screen.text_position.grapheme), (0, 6));
+        assert!(screen.jump_back().unwrap());
+        assert_eq!(screen.text_position.row, 2);
+        assert!(!screen.jump_back().unwrap());
+
+        // Marks are kept within the buffer after it shrinks
+        screen.text_position = TextPosition { row: 3, grapheme: 3, byte: 3 };
+        screen.set_mark('z');
+        screen.buffer.delete_line(3);
+        screen.buffer.delete_line(2);
+        assert!(screen.jump_to_mark('z').unwrap());
+        assert_eq!(screen.text_position, TextPosition { row: 1, grapheme: 3, byte: 3 });
+
+        for _ in 0..MAX_JUMPS + 5 {
+            screen.move_last_line().unwrap();
+            screen.move_first_line().unwrap();
+        }
+        assert_eq!(screen.jump_list.len(), MAX_JUMPS);
+    }
+
+    #[test]
+    fn move_to_matching_bracket() {
+        let mut screen = test_screen("let x = f(a, (b\n  + c)) * 2;");
+        screen.text_position = TextPosition { row: 0, grapheme: 9, byte: 9 };
+        assert!(screen.move_matching_bracket().unwrap());
+        assert_eq!(screen.text_position, TextPosition { row: 1, grapheme: 6, byte: 6 });
+        assert!(screen.move_matching_bracket().unwrap());
+        assert_eq!(screen.text_position.grapheme, 9);
+        // Off a bracket, the first one later on the line is matched
+        screen.text_position = TextPosition { row: 0, grapheme: 11, byte: 11 };
+        assert!(screen.move_matching_bracket().unwrap());
+        assert_eq!(screen.text_position, TextPosition { row: 1, grapheme: 5, byte: 5 });
+        screen.text_position = TextPosition { row: 1, grapheme: 8, byte: 8 };
+        assert!(!screen.move_matching_bracket().unwrap());
+        assert_eq!(screen.text_position.grapheme, 8);
+    }
+
+    #[test]
+    fn delete_to_line_end() {
+        let mut screen = test_screen("ab日本cd");
+        screen.text_position = TextPosition { row: 0, grapheme: 3, byte: 5 };
+        screen.delete_to_line_end().unwrap();
+        assert_eq!(screen.buffer.text[0].text, "ab日");
+        assert_eq!(screen.text_position, TextPosition { row: 0, grapheme: 2, byte: 2 });
+    }
+
+    #[test]
+    fn delete_yank_and_paste_lines() {
+        let mut screen = test_screen("one\ntwo\nthree");
+        screen.text_position.row = 1;
+        screen.delete_current_line().unwrap();
+        assert_eq!(screen.registers.borrow().get(None), Some(&Register::linewise("two")));
+        assert_eq!(screen.buffer.num_lines, 2);
+        // Pasting a line puts it below the cursor's line, rather than within it
+        screen.text_position = TextPosition { row: 1, grapheme: 2, byte: 2 };
+        screen.paste(false).unwrap();
+        let text: Vec<&str> = screen.buffer.text.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(text, ["one", "three", "two"]);
+        assert_eq!(screen.text_position.row, 2);
+        screen.text_position.row = 0;
+        screen.yank_current_line();
+        screen.paste(true).unwrap();
+        assert_eq!(screen.buffer.text[0].text, "one");
+        assert_eq!(screen.buffer.num_lines, 4);
+
+        // Deleting every line leaves one empty line
+        let mut screen = test_screen("only");
+        screen.delete_current_line().unwrap();
+        screen.delete_current_line().unwrap();
+        assert_eq!(screen.buffer.num_lines, 1);
+        assert_eq!(screen.buffer.text[0].text, "");
+        assert_eq!(screen.registers.borrow().get(None), Some(&Register::linewise("")));
+
+        screen.registers.borrow_mut().yank(None, Register::charwise("ab"));
+        screen.paste(false).unwrap();
+        screen.paste(false).unwrap();
+        assert_eq!(screen.buffer.text[0].text, "abab");
+        assert_eq!(screen.text_position.grapheme, 3);
+    }
+
+    #[test]
+    fn delete_word_within_line() {
+        let mut screen = test_screen("héllo wörld, ünï");
+        screen.text_position = TextPosition { row: 0, grapheme: 6, byte: 7 };
+        screen.delete_word().unwrap();
+        assert_eq!(screen.buffer.text[0].text, "héllo , ünï");
+        assert_eq!(screen.registers.borrow().get(None), Some(&Register::charwise("wörld")));
+        // Punctuation is a word of its own, deleted along with the whitespace after it
+        screen.delete_word().unwrap();
+        assert_eq!(screen.buffer.text[0].text, "héllo ünï");
+        assert_eq!(screen.text_position, TextPosition { row: 0, grapheme: 6, byte: 7 });
+        // The last word is deleted to the end of the line, leaving the cursor on the line
+        screen.delete_word().unwrap();
+        assert_eq!(screen.buffer.text[0].text, "héllo ");
+        assert_eq!(screen.text_position, TextPosition { row: 0, grapheme: 5, byte: 6 });
+    }
+
+    #[test]
+    fn delete_word_at_line_end_joins_lines() {
+        let mut screen = test_screen("añb\nçd\n\nëf");
+        screen.text_position = TextPosition { row: 0, grapheme: 3, byte: 4 };
+        screen.delete_word().unwrap();
+        assert_eq!(screen.buffer.text[0].text, "añbçd");
+        assert_eq!(screen.registers.borrow().get(None), Some(&Register::charwise("\n")));
+        // On an empty line, the next line is pulled up
+        screen.text_position = TextPosition { row: 1, grapheme: 0, byte: 0 };
+        screen.delete_word().unwrap();
+        let text: Vec<&str> = screen.buffer.text.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(text, ["añbçd", "ëf"]);
+        // Nothing is left to join onto the last line
+        screen.text_position = TextPosition { row: 1, grapheme: 2, byte: 3 };
+        screen.delete_word().unwrap();
+        assert_eq!(screen.buffer.num_lines, 2);
+    }
+
+    #[test]
+    fn change_word_before_punctuation() {
+        let mut screen = test_screen("föo.bar");
+        let action = screen.change_word().unwrap();
+        assert!(matches!(action, ScreenAction::EnterMode(Mode::Insert)));
+        assert_eq!(screen.buffer.text[0].text, ".bar");
+        assert_eq!(screen.registers.borrow().get(None), Some(&Register::charwise("föo")));
+        assert_eq!(screen.mode, Mode::Insert);
+        assert_eq!(screen.text_position, TextPosition::default());
+        // The end of a line's last word is past the end of the line once it is deleted
+        let mut screen = test_screen("a wörd");
+        screen.text_position = TextPosition { row: 0, grapheme: 2, byte: 2 };
+        screen.change_word().unwrap();
+        assert_eq!(screen.buffer.text[0].text, "a ");
+        assert_eq!(screen.text_position, TextPosition { row: 0, grapheme: 2, byte: 2 });
+        // On whitespace, only the whitespace is changed
+        let mut screen = test_screen("a   b");
+        screen.text_position = TextPosition { row: 0, grapheme: 1, byte: 1 };
+        screen.change_word().unwrap();
+        assert_eq!(screen.buffer.text[0].text, "ab");
+    }
+
+    #[test]
+    fn repeat_change_word() {
+        let mut screen = test_screen("foo.bar baz");
+        let mut events = vec![Event::Key(KeyEvent::new(KeyCode::Char('C'), KeyModifiers::SHIFT))];
+        events.extend("wxy".chars().map(|c| key(KeyCode::Char(c))));
+        events.extend([key(KeyCode::Esc), key(KeyCode::Char('d')), key(KeyCode::Char('.')),
+                       key(KeyCode::Char('q')), key(KeyCode::Char('n'))]);
+        Terminal::capture(|| { Terminal::script_input(events, || screen.run()); });
+        assert_eq!(screen.buffer.text[0].text, "xy.xy baz");
+        assert_eq!(screen.last_change.len(), 5);
+    }
+
+    #[test]
+    fn open_lines_below_and_above() {
+        let mut screen = test_screen("fn main() {\n    body\n}");
+        screen.text_position = TextPosition { row: 1, grapheme: 6, byte: 6 };
+        let action = screen.open_line(false).unwrap();
+        assert!(matches!(action, ScreenAction::EnterMode(Mode::Insert)));
+        assert_eq!(screen.buffer.num_lines, 4);
+        assert_eq!(screen.buffer.text[2].text, "    ");
+        assert_eq!(screen.text_position, TextPosition { row: 2, grapheme: 4, byte: 4 });
+        assert!(screen.buffer.modified);
+        screen.set_mode(Mode::Normal);
+        screen.text_position = TextPosition::default();
+        screen.open_line(true).unwrap();
+        assert_eq!(screen.buffer.num_lines, 5);
+        assert_eq!(screen.buffer.text[0].text, "");
+        assert_eq!(screen.buffer.text[1].text, "fn main() {");
+        assert_eq!(screen.text_position, TextPosition::default());
+        // Without auto_indent, new lines start empty
+        screen.set_mode(Mode::Normal);
+        screen.settings.auto_indent = false;
+        screen.text_position = TextPosition { row: 2, grapheme: 0, byte: 0 };
+        screen.open_line(false).unwrap();
+        assert_eq!(screen.buffer.text[3].text, "");
+        assert_eq!(screen.text_position.row, 3);
+    }
+
+    #[test]
+    fn insert_entry_positions() {
+        let mut screen = test_screen("  añb\n\nend");
+        screen.text_position = TextPosition { row: 0, grapheme: 3, byte: 3 };
+        let action = screen.insert_after().unwrap();
+        assert!(matches!(action, ScreenAction::EnterMode(Mode::Insert)));
+        assert_eq!(screen.mode, Mode::Insert);
+        assert_eq!(screen.text_position, TextPosition { row: 0, grapheme: 4, byte: 5 });
+        // After the last grapheme is the end of the line
+        screen.set_mode(Mode::Normal);
+        screen.move_end_line().unwrap();
+        screen.insert_after().unwrap();
+        assert_eq!(screen.text_position, TextPosition { row: 0, grapheme: 5, byte: 6 });
+        screen.set_mode(Mode::Normal);
+        screen.move_start_line().unwrap();
+        screen.insert_at_line_end().unwrap();
+        assert_eq!(screen.text_position, TextPosition { row: 0, grapheme: 5, byte: 6 });
+        screen.set_mode(Mode::Normal);
+        screen.insert_at_first_non_blank().unwrap();
+        assert_eq!(screen.text_position, TextPosition { row: 0, grapheme: 2, byte: 2 });
+        // On an empty line, each starts inserting at its start
+        screen.set_mode(Mode::Normal);
+        screen.text_position = TextPosition { row: 1, grapheme: 0, byte: 0 };
+        screen.insert_after().unwrap();
+        assert_eq!(screen.text_position, TextPosition { row: 1, grapheme: 0, byte: 0 });
+        screen.set_mode(Mode::Normal);
+        screen.insert_at_line_end().unwrap();
+        assert_eq!(screen.text_position, TextPosition { row: 1, grapheme: 0, byte: 0 });
+        screen.set_mode(Mode::Normal);
+        screen.insert_at_first_non_blank().unwrap();
+        assert_eq!(screen.text_position, TextPosition { row: 1, grapheme: 0, byte: 0 });
+    }
+
+    #[test]
+    fn replace_and_repeat() {
+        let mut screen = test_screen("añb\n\nend");
+        let events = ['d', 'r', 'x', 'd', 'r', 'é', 's', 'r', 'z', 's', 'a', '.']
+            .map(|c| key(KeyCode::Char(c))).into_iter()
+            .chain([key(KeyCode::Char('q')), key(KeyCode::Char('n'))]).collect();
+        Terminal::capture(|| { Terminal::script_input(events, || screen.run()); });
+        assert_eq!(screen.buffer.text[0].text, "axé");
+        // Replacing on an empty line does nothing, but can still be repeated elsewhere
+        assert_eq!(screen.buffer.text[1].text, "");
+        assert_eq!(screen.buffer.text[2].text, "ezd");
+        assert_eq!(screen.text_position, TextPosition { row: 2, grapheme: 1, byte: 1 });
+    }
+
+    #[test]
+    fn selected_register() {
+        let mut screen = test_screen("one\ntwo");
+        screen.selected_register = Some('a');
+        screen.yank_current_line();
+        assert!(screen.selected_register.is_none());
+        screen.text_position.row = 1;
+        screen.delete_current_line().unwrap();
+        // Pasting from the named register gets the yank rather than the later delete
+        screen.selected_register = Some('a');
+        screen.paste(false).unwrap();
+        let text: Vec<&str> = screen.buffer.text.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(text, ["one", "one"]);
+        assert_eq!(screen.registers.borrow().get(Some('1')), Some(&Register::linewise("two")));
+    }
+
+    #[test]
+    fn line_end_sticks_with_virtual_edit() {
+        let mut screen = test_screen("a long line\nab\nanother line");
+        screen.settings.virtual_edit = true;
+        screen.update_gutter_width();
+        screen.move_end_line().unwrap();
+        screen.move_down().unwrap();
+        assert_eq!(screen.text_position, TextPosition { row: 1, grapheme: 1, byte: 1 });
+        screen.move_down().unwrap();
+        assert_eq!(screen.text_position, TextPosition { row: 2, grapheme: 11, byte: 11 });
+        screen.move_up().unwrap();
+        screen.move_up().unwrap();
+        assert_eq!(screen.text_position, TextPosition { row: 0, grapheme: 10, byte: 10 });
+        // Appending at the end of the line stays just past the end of each line
+        screen.insert_at_line_end().unwrap();
+        screen.move_down().unwrap();
+        assert_eq!(screen.text_position, TextPosition { row: 1, grapheme: 2, byte: 2 });
+    }
+
+    #[test]
+    fn vertical_moves_keep_column() {
+        let mut screen = test_screen("a long line\nab\nanother line\n\nend of text");
+        for _ in 0..7 {
+            screen.move_right().unwrap();
+        }
+        screen.move_down().unwrap();
+        assert_eq!(screen.text_position.grapheme, 1);
+        screen.move_down().unwrap();
+        assert_eq!(screen.text_position, TextPosition { row: 2, grapheme: 7, byte: 7 });
+        screen.move_down().unwrap();
+        screen.move_up().unwrap();
+        assert_eq!(screen.text_position.grapheme, 7);
+        // A horizontal motion sets a new column
+        screen.move_left().unwrap();
+        screen.move_up().unwrap();
+        screen.move_up().unwrap();
+        assert_eq!(screen.text_position.grapheme, 6);
+        // The end of line motion sticks to line ends
+        screen.move_end_line().unwrap();
+        screen.move_down().unwrap();
+        screen.move_down().unwrap();
+        assert_eq!(screen.text_position.grapheme, 11);
+        // Moving the cursor some other way takes its new column
+        screen.text_position.grapheme = 3;
+        screen.text_position.byte = 3;
+        screen.move_down().unwrap();
+        screen.move_down().unwrap();
+        assert_eq!(screen.text_position.grapheme, 3);
+    }
+
+    #[test]
+    fn delete_to_line_start() {
+        let mut screen = test_screen("    let x\nabc def");
+        screen.set_mode(Mode::Insert);
+        screen.move_end_line().unwrap();
+        screen.delete_to_line_start().unwrap();
+        assert_eq!(screen.buffer.text[0].text, "    ");
+        assert_eq!(screen.text_position.grapheme, 4);
+        // Within the indentation, delete the rest
+        screen.delete_to_line_start().unwrap();
+        assert_eq!(screen.buffer.text[0].text, "");
+        assert_eq!(screen.text_position.grapheme, 0);
+
+        screen.text_position.row = 1;
+        screen.text_position.grapheme = 4;
+        screen.delete_to_line_start().unwrap();
+        assert_eq!(screen.buffer.text[1].text, "def");
+        assert_eq!((screen.text_position.grapheme, screen.text_position.byte), (0, 0));
+    }
+
+    #[test]
+    fn scroll_offset_clamped_when_buffer_shrinks() {
+        let lines: Vec<String> = (0..100).map(|n| format!("line {n}")).collect();
+        let mut screen = test_screen(&lines.join("\n"));
+        screen.text_position.row = 95;
+        screen.scroll_offset.row = 90;
+        while screen.buffer.num_lines > 3 {
+            screen.buffer.delete_line(3);
+        }
+        screen.text_position.row = 2;
+        screen.scroll_into_view().unwrap();
+        assert_eq!(screen.scroll_offset.row, 0);
+        assert!(screen.render_rows()[0].contains("line 0"));
+
+        // With more lines than fit, the last page is shown
+        let mut screen = test_screen(&lines.join("\n"));
+        screen.scroll_offset.row = 90;
+        while screen.buffer.num_lines > 30 {
+            screen.buffer.delete_line(30);
+        }
+        screen.text_position.row = 29;
+        screen.scroll_into_view().unwrap();
+        assert_eq!(screen.scroll_offset.row, 20);
+    }
+
+    #[test]
+    fn draw_output() {
+        let mut screen = test_screen("one\ntwo");
+        screen.size = Size { height: 5, width: 20 };
+        screen.scroll_into_view().unwrap();
+        let output = Terminal::capture(|| screen.draw());
+        let gutter = |n: usize| format!("{}", format!("{n:>3} ").dark_grey());
+        let expected = [
+            "\x1b[?25l".to_string(),
+            format!("\x1b[1;1H\x1b[K{}one", gutter(1)),
+            format!("\x1b[2;1H\x1b[K{}two", gutter(2)),
+            "\x1b[3;1H\x1b[K    ~".to_string(),
+            format!("\x1b[4;1H\x1b[K{}", "[No Name]  1,1   All".reverse()),
+            "\x1b[5;1H\x1b[K".to_string(),
+            "\x1b[1;5H\x1b[?25h".to_string(),
+        ].concat();
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn reflow_at_cursor() {
+        let mut screen = test_screen("one two three four five six\n\nseven");
+        screen.settings.text_width = 10;
+        screen.text_position.grapheme = 5;
+        screen.reflow_paragraph().unwrap();
+        assert_eq!(screen.buffer.num_lines, 5);
+        assert_eq!(screen.buffer.text[2].text, "five six");
+        assert_eq!((screen.text_position.row, screen.text_position.grapheme), (2, 0));
+    }
+
+    #[test]
+    fn trailing_whitespace_highlight() {
+        let mut screen = test_screen("ab  \ncd \t");
+        assert_eq!(screen.render_line(1), "cd  ");
+        screen.settings.highlight_trailing_whitespace = true;
+        assert_eq!(screen.render_line(1), format!("cd{}", "  ".on(Color::Red)));
+        // Not on the cursor's line
+        assert_eq!(screen.render_line(0), "ab  ");
+        screen.text_position.row = 1;
+        assert_eq!(screen.render_line(0), format!("ab{}", "  ".on(Color::Red)));
+    }
+
+    #[test]
+    fn virtual_edit_pads_line() {
+        let mut screen = test_screen("ab\nlonger line\nc");
+        screen.move_right().unwrap();
+        screen.move_right().unwrap();
+        assert_eq!(screen.text_position.grapheme, 1);
+        screen.settings.virtual_edit = true;
+        for _ in 0..4 {
+            screen.move_right().unwrap();
+        }
+        assert_eq!((screen.text_position.grapheme, screen.text_position.byte), (5, 2));
+        screen.insert_char('x').unwrap();
+        assert_eq!(screen.buffer.text[0].text, "ab   x");
+        assert_eq!((screen.text_position.grapheme, screen.text_position.byte), (6, 6));
+        // Moving onto a shorter line keeps the column
+        screen.move_down().unwrap();
+        screen.move_down().unwrap();
+        assert_eq!((screen.text_position.row, screen.text_position.grapheme), (2, 6));
+        screen.insert_char('y').unwrap();
+        assert_eq!(screen.buffer.text[2].text, "c     y");
+    }
+
+    #[test]
+    fn block_insert_comments_lines() {
+        let mut screen = test_screen("one\ntwo\nthree\nfour");
+        screen.start_block_insert(0..3, 0).unwrap();
+        assert_eq!(screen.mode, Mode::Insert);
+        for c in "// ".chars() {
+            screen.insert_char(c).unwrap();
+        }
+        screen.set_mode(Mode::Normal);
+        let text: Vec<&str> = screen.buffer.text.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(text, ["// one", "// two", "// three", "four"]);
+        assert_eq!(screen.block_insert, None);
+
+        // Rows which don't reach the column are left alone
+        let mut screen = test_screen("abcd\nab\nabcd");
+        screen.start_block_insert(0..3, 3).unwrap();
+        screen.insert_char('|').unwrap();
+        screen.set_mode(Mode::Normal);
+        let text: Vec<&str> = screen.buffer.text.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(text, ["abc|d", "ab", "abc|d"]);
+    }
+
+    #[test]
+    fn sign_column_marks_changes() {
+        let mut screen = test_screen("one\ntwo");
+        screen.settings.sign_column = true;
+        screen.update_gutter_width();
+        assert_eq!(screen.inner_boundary.left, 5);
+        screen.insert_char('x').unwrap();
+        assert_eq!(screen.render_gutter(0), format!("{}{}", "~".yellow(), "  1 ".dark_grey()));
+        assert_eq!(screen.render_gutter(1), format!("{}{}", " ".stylize(), "  2 ".dark_grey()));
+    }
+
+    #[test]
+    fn autosave_on_focus_lost() {
+        let path = std::env::temp_dir().join(format!("trout_autosave_{}", std::process::id()));
+        std::fs::write(&path, "one\n").unwrap();
+        let mut screen = test_screen("");
+        screen.buffer = Buffer::from_file(path.clone());
+        screen.insert_char('x').unwrap();
+        // Off by default, so nothing is written
+        assert!(!screen.focus_lost().unwrap());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "one\n");
+        screen.settings.autosave_on_focus_lost = true;
+        assert!(screen.focus_lost().unwrap());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "xone\n");
+        assert!(!screen.buffer.modified);
+        // Unmodified buffers aren't written again
+        assert!(!screen.focus_lost().unwrap());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn focus_gained_reloads_changed_file() {
+        let path = std::env::temp_dir().join(format!("trout_focus_gained_{}", std::process::id()));
+        std::fs::write(&path, "one\n").unwrap();
+        let mut screen = test_screen("");
+        screen.buffer = Buffer::from_file(path.clone());
+        assert!(!screen.focus_gained().unwrap());
+        std::fs::write(&path, "two\nthree\n").unwrap();
+        // Make sure the modification time differs even on coarse grained file systems
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(5)).unwrap();
+        assert!(screen.focus_gained().unwrap());
+        assert_eq!(screen.buffer.num_lines, 2);
+        assert_eq!(screen.buffer.text[1].text, "three");
+        assert!(!screen.focus_gained().unwrap());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn empty_rows_past_end_of_buffer() {
+        let mut screen = test_screen("one\ntwo\nthree");
+        screen.update_gutter_width();
+        let rows = screen.render_rows();
+        // 12 rows, less 2 for the bottom boundary, less 3 lines of text
+        assert_eq!(rows.len(), 10);
+        assert_eq!(rows.iter().filter(|row| row.as_str() == "    ~").count(), 7);
+        assert!(rows[2].contains("three"));
+
+        screen.settings.eof_marker = true;
+        screen.settings.empty_line_char = '.';
+        let rows = screen.render_rows();
+        assert_eq!(rows[3], format!("    {}", "[EOF]".dark_blue()));
+        assert_eq!(rows.iter().filter(|row| row.as_str() == "    .").count(), 6);
+    }
+
+    #[test]
+    fn scroll_pages_near_end() {
+        let text: Vec<String> = (0..30).map(|idx| format!("line {idx}")).collect();
+        let mut screen = test_screen(&text.join("\n"));
+        let height = screen.view_height();
+        screen.text_position.row = 2;
+        screen.scroll_half_page_down().unwrap();
+        assert_eq!(screen.scroll_offset.row, height / 2);
+        assert_eq!(screen.text_position.row, 2 + height / 2);
+
+        // Paging down stops with the last line at the bottom, then the cursor at the end
+        screen.scroll_page_down().unwrap();
+        screen.scroll_page_down().unwrap();
+        assert_eq!(screen.scroll_offset.row, 30 - height);
+        assert_eq!(screen.text_position.row, min(2 + height / 2 + 2 * height, 29));
+        screen.scroll_page_down().unwrap();
+        assert_eq!(screen.scroll_offset.row, 30 - height);
+        assert_eq!(screen.text_position.row, 29);
+
+        screen.scroll_half_page_up().unwrap();
+        assert_eq!(screen.scroll_offset.row, 30 - height - height / 2);
+        assert_eq!(screen.text_position.row, 29 - height / 2);
+        screen.scroll_page_up().unwrap();
+        screen.scroll_page_up().unwrap();
+        assert_eq!(screen.scroll_offset.row, 0);
+        assert_eq!(screen.text_position.row, (29 - height / 2).saturating_sub(2 * height));
+        screen.scroll_page_up().unwrap();
+        assert_eq!(screen.text_position.row, 0);
+    }
+
+    #[test]
+    fn reposition_cursor_line() {
+        let text: Vec<String> = (0..100).map(|idx| format!("line {idx}")).collect();
+        let mut screen = test_screen(&text.join("\n"));
+        let height = screen.view_height();
+        screen.text_position.row = 60;
+        screen.center_cursor().unwrap();
+        assert_eq!(screen.scroll_offset.row, 60 - height / 2);
+        screen.cursor_to_top().unwrap();
+        assert_eq!(screen.scroll_offset.row, 60);
+        screen.cursor_to_bottom().unwrap();
+        assert_eq!(screen.scroll_offset.row, 60 - (height - 1));
+        assert_eq!(screen.screen_location.row, height - 1);
+
+        // Near the start the view can't scroll far enough, so it stays at the first line
+        screen.text_position.row = 2;
+        screen.center_cursor().unwrap();
+        assert_eq!(screen.scroll_offset.row, 0);
+        screen.cursor_to_bottom().unwrap();
+        assert_eq!(screen.scroll_offset.row, 0);
+    }
+
+    #[test]
+    fn find_char_on_line() {
+        let mut screen = test_screen("añb, çd, ñe");
+        let find = |target: &str, forward, kind| CharFind { target: target.to_string(), motion: FindMotion { forward, kind } };
+        screen.find_char(find("ñ", true, FindKind::To)).unwrap();
+        assert_eq!((screen.text_position.grapheme, screen.text_position.byte), (1, 1));
+        // Repeating moves on to the next one, past the multibyte graphemes before it
+        screen.repeat_find(false).unwrap();
+        assert_eq!((screen.text_position.grapheme, screen.text_position.byte), (9, 11));
+        screen.repeat_find(true).unwrap();
+        assert_eq!(screen.text_position.grapheme, 1);
+
+        screen.find_char(find(",", true, FindKind::Till)).unwrap();
+        assert_eq!(screen.text_position.grapheme, 2);
+        screen.repeat_find(false).unwrap();
+        assert_eq!(screen.text_position.grapheme, 6);
+        screen.find_char(find("a", false, FindKind::Till)).unwrap();
+        assert_eq!(screen.text_position.grapheme, 1);
+
+        // A grapheme which isn't on the line leaves the cursor
+        screen.find_char(find("z", true, FindKind::To)).unwrap();
+        assert_eq!(screen.text_position.grapheme, 1);
+    }
+
+    #[test]
+    fn yank_selection_across_lines() {
+        let mut screen = test_screen("first line\nsecond line\n");
+        screen.text_position = TextPosition { row: 1, grapheme: 5, byte: 5 };
+        screen.selection_anchor = Some(TextPosition { row: 0, grapheme: 6, byte: 6 });
+        screen.yank_selection().unwrap();
+        assert_eq!(screen.registers.borrow().get(None).unwrap().text, "line\nsecond");
+        assert!(screen.selection_anchor.is_none());
+        assert_eq!(screen.text_position.row, 0);
+        assert_eq!(screen.text_position.grapheme, 6);
+    }
+
+    #[test]
+    fn selection_drawn_either_way() {
+        let mut screen = test_screen("abcd\nef\n");
+        screen.selection_anchor = Some(TextPosition { row: 1, grapheme: 0, byte: 0 });
+        screen.text_position = TextPosition { row: 0, grapheme: 2, byte: 2 };
+        assert_eq!(screen.render_line(0), format!("ab{}", "cd ".reverse()));
+        assert_eq!(screen.render_line(1), format!("{}f", "e".reverse()));
+        // Swapping the ends selects the same text
+        screen.selection_anchor = Some(TextPosition { row: 0, grapheme: 2, byte: 2 });
+        screen.text_position = TextPosition { row: 1, grapheme: 0, byte: 0 };
+        assert_eq!(screen.render_line(0), format!("ab{}", "cd ".reverse()));
+        assert_eq!(screen.render_line(1), format!("{}f", "e".reverse()));
+    }
+
+    #[test]
+    fn delete_selection_across_lines() {
+        let mut screen = test_screen("one two\nthree\nfour five\n");
+        screen.selection_anchor = Some(TextPosition { row: 0, grapheme: 4, byte: 4 });
+        screen.text_position = TextPosition { row: 2, grapheme: 4, byte: 4 };
+        screen.delete_selection().unwrap();
+        assert_eq!(screen.buffer.text[0].text, "one five");
+        assert_eq!(screen.buffer.num_lines, 1);
+        assert_eq!(screen.registers.borrow().get(None).unwrap().text, "two\nthree\nfour ");
+        assert_eq!(screen.text_position.grapheme, 4);
+    }
+
+    #[test]
+    fn block_selection() {
+        let mut screen = test_screen("abcdef\nab\nuvwxyz\n");
+        screen.selection_kind = SelectionKind::Block;
+        screen.selection_anchor = Some(TextPosition { row: 0, grapheme: 3, byte: 3 });
+        screen.text_position = TextPosition { row: 2, grapheme: 1, byte: 1 };
+        assert_eq!(screen.selected_block(), Some((0..3, 1..=3)));
+        // Only the block's columns are inverted, with short lines padded across it
+        assert_eq!(screen.render_line(0), format!("a{}ef", "bcd".reverse()));
+        assert_eq!(screen.render_line(1), format!("a{}", "b  ".reverse()));
+
+        // Yanking gives the rows of the block, short ones cut off, which paste as a block
+        screen.yank_selection().unwrap();
+        let register = screen.registers.borrow().get(None).unwrap().clone();
+        assert_eq!(register, Register { text: "bcd\nb\nvwx".to_string(), kind: RegisterKind::Blockwise });
+        assert_eq!((screen.text_position.row, screen.text_position.grapheme), (0, 1));
+        screen.text_position = TextPosition { row: 1, grapheme: 1, byte: 1 };
+        screen.paste(false).unwrap();
+        let text: Vec<&str> = screen.buffer.text.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(text, ["abcdef", "abbcd", "uvbwxyz", "  vwx"]);
+    }
+
+    #[test]
+    fn indent_and_dedent_lines() {
+        let mut screen = test_screen("one\n  two\n\nfour");
+        screen.settings.shift_width = 4;
+        screen.indent_lines(0..1).unwrap();
+        assert_eq!(screen.buffer.text[0].text, "    one");
+        assert_eq!(screen.text_position.grapheme, 4);
+        // A line with less indentation than the shift width loses all of it
+        screen.dedent_lines(1..2).unwrap();
+        assert_eq!(screen.buffer.text[1].text, "two");
+        assert_eq!((screen.text_position.row, screen.text_position.grapheme), (1, 0));
+        // Every selected line is shifted, except empty ones
+        screen.selection_anchor = Some(TextPosition { row: 1, grapheme: 1, byte: 1 });
+        screen.text_position = TextPosition { row: 3, grapheme: 0, byte: 0 };
+        screen.settings.expand_tab = false;
+        screen.shift_selection(false).unwrap();
+        let text: Vec<&str> = screen.buffer.text.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(text, ["    one", "\ttwo", "", "\tfour"]);
+        assert_eq!(screen.selection_anchor, None);
+        assert!(screen.buffer.modified);
+    }
+
+    #[test]
+    fn first_non_blank_toggles_with_line_start() {
+        let mut screen = test_screen("    indented\n   \nflush");
+        screen.text_position = TextPosition { row: 0, grapheme: 8, byte: 8 };
+        screen.move_first_non_blank().unwrap();
+        assert_eq!(screen.text_position.grapheme, 4);
+        assert_eq!(screen.text_position.byte, 4);
+        screen.move_first_non_blank().unwrap();
+        assert_eq!(screen.text_position.grapheme, 0);
+        screen.move_first_non_blank().unwrap();
+        assert_eq!(screen.text_position.grapheme, 4);
+        // A blank line has no non-blank grapheme, so its last one is used
+        screen.text_position = TextPosition { row: 1, grapheme: 0, byte: 0 };
+        screen.move_first_non_blank().unwrap();
+        assert_eq!(screen.text_position.grapheme, 2);
+        screen.move_first_non_blank().unwrap();
+        assert_eq!(screen.text_position.grapheme, 0);
+        // Without indentation, the start of the line is the first non-blank
+        screen.text_position = TextPosition { row: 2, grapheme: 3, byte: 3 };
+        screen.move_first_non_blank().unwrap();
+        assert_eq!(screen.text_position.grapheme, 0);
+        screen.move_first_non_blank().unwrap();
+        assert_eq!(screen.text_position.grapheme, 0);
+    }
+
+    #[test]
+    fn insert_on_block() {
+        let mut screen = test_screen("one\ntwo\nthree");
+        screen.selection_kind = SelectionKind::Block;
+        screen.selection_anchor = Some(TextPosition { row: 0, grapheme: 0, byte: 0 });
+        screen.text_position = TextPosition { row: 1, grapheme: 2, byte: 2 };
+        screen.insert_on_block().unwrap();
+        screen.insert_char('#').unwrap();
+        screen.set_mode(Mode::Normal);
+        let text: Vec<&str> = screen.buffer.text.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(text, ["#one", "#two", "three"]);
+    }
+
+    #[test]
+    fn record_and_replay_movements() {
+        let mut screen = test_screen("abcdef\nabcdef\nabcdef\nabcdef\nabcdef");
+        let keys = ['Q', 'a', 's', 'd', 'Q', '2', '@', 'a', '@', '@', 'q'];
+        // Terminals report upper case letters with shift held
+        let events = keys.iter().map(|c| if c.is_ascii_uppercase() {
+            Event::Key(KeyEvent::new(KeyCode::Char(*c), KeyModifiers::SHIFT))
+        } else {
+            key(KeyCode::Char(*c))
+        }).collect();
+        Terminal::capture(|| { Terminal::script_input(events, || screen.run()); });
+        // Recording moved down and right once, then it was played twice, then once more
+        assert_eq!(screen.text_position.row, 4);
+        assert_eq!(screen.text_position.grapheme, 4);
+        assert!(screen.macros.borrow().recording().is_none());
+    }
+
+    #[test]
+    fn scroll_off_keeps_context() {
+        let text: Vec<String> = (0..40).map(|idx| format!("line {idx}")).collect();
+        let mut screen = test_screen(&text.join("\n"));
+        screen.settings.set("scrolloff=3").unwrap();
+        let height = screen.view_height();
+        // Moving down scrolls once fewer than three lines are left below the cursor
+        for _ in 0..height - 4 {
+            screen.move_down().unwrap();
+        }
+        assert_eq!(screen.scroll_offset.row, 0);
+        screen.move_down().unwrap();
+        assert_eq!(screen.scroll_offset.row, 1);
+        assert_eq!(screen.scroll_offset.row + height - 1 - screen.text_position.row, 3);
+
+        // Near the end of the buffer the cursor can go all the way down
+        screen.move_last_line().unwrap();
+        assert_eq!(screen.scroll_offset.row, 40 - height);
+        screen.move_up().unwrap();
+        assert_eq!(screen.scroll_offset.row, 40 - height);
+        // Moving back up keeps three lines above the cursor
+        for _ in 0..height {
+            screen.move_up().unwrap();
+        }
+        assert_eq!(screen.text_position.row - screen.scroll_offset.row, 3);
+    }
+
+    #[test]
+    fn scroll_right_along_long_line() {
+        let mut screen = test_screen(&"abcdefghij".repeat(4));
+        let width = screen.view_width();
+        for _ in 0..39 {
+            screen.move_right().unwrap();
+            let col = screen.scroll_offset.col;
+            assert!((col..col + width).contains(&screen.text_position.grapheme));
+        }
+        assert_eq!(screen.scroll_offset.col, 40 - width);
+        assert_eq!(screen.screen_location.col, screen.inner_boundary.left + width - 1);
+        screen.move_start_line().unwrap();
+        assert_eq!(screen.scroll_offset.col, 0);
+
+        // The cursor's grapheme fits on screen when the line is made of wide ones
+        let mut screen = test_screen(&"界".repeat(20));
+        for _ in 0..19 {
+            screen.move_right().unwrap();
+        }
+        let line = &screen.buffer.text[0];
+        assert!(line.display_width(screen.scroll_offset.col..20, screen.settings.tab_width) <= width);
+    }
+}